@@ -0,0 +1,30 @@
+-- Accounts and feedback, as created by the init tool before migrations existed.
+CREATE TABLE IF NOT EXISTS Account (
+    id              SERIAL,
+    nick_name       VARCHAR NOT NULL,
+    password_salt   VARCHAR NOT NULL,
+    password_hash   VARCHAR NOT NULL,
+    email           VARCHAR PRIMARY KEY NOT NULL,
+    contribution    SMALLINT NOT NULL,
+    available       BOOLEAN NOT NULL,
+    permissions     SMALLINT NOT NULL
+);
+
+-- Trainable feedback.
+CREATE TABLE IF NOT EXISTS TFeedback (
+    id              SERIAL PRIMARY KEY,
+    time_stamp      BIGINT NOT NULL,
+    from_user_email VARCHAR NOT NULL,
+    time_out        BIGINT NOT NULL,
+    pic_link        TEXT NOT NULL,
+    real_label      VARCHAR NOT NULL,
+    submit_count    BIGINT NOT NULL
+);
+
+-- Untrainable feedback.
+CREATE TABLE IF NOT EXISTS UFeedback (
+    id              SERIAL PRIMARY KEY,
+    time_stamp      BIGINT NOT NULL,
+    from_user_email VARCHAR NOT NULL,
+    pic_link        TEXT NOT NULL
+);
@@ -0,0 +1,20 @@
+-- Task run history, times are Unix epoch milliseconds.
+CREATE TABLE IF NOT EXISTS TaskRun (
+    id              BIGSERIAL PRIMARY KEY,
+    task_name       VARCHAR NOT NULL,
+    started_at      BIGINT NOT NULL,
+    finished_at     BIGINT NOT NULL,
+    outcome         VARCHAR NOT NULL,
+    attempts        INTEGER NOT NULL,
+    error           TEXT
+);
+CREATE INDEX IF NOT EXISTS TaskRun_task_name_started_at ON TaskRun (task_name, started_at DESC);
+
+-- One row per task holding the latest scheduled tick and the instance running it.
+-- Times are Unix epoch milliseconds.
+CREATE TABLE IF NOT EXISTS TaskLease (
+    task_name       VARCHAR PRIMARY KEY,
+    holder          VARCHAR NOT NULL,
+    tick            BIGINT NOT NULL,
+    acquired_at     BIGINT NOT NULL
+);
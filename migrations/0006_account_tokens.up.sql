@@ -0,0 +1,13 @@
+-- Accounts from before are taken as verified, sign-ups verify their email when mail is enabled.
+ALTER TABLE Account ADD COLUMN IF NOT EXISTS email_verified BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Single-use tokens mailed to the accounts, by their SHA-256. Times are Unix epoch seconds.
+CREATE TABLE IF NOT EXISTS AccountToken (
+    token_hash      VARCHAR PRIMARY KEY,
+    user_email      VARCHAR NOT NULL,
+    purpose         VARCHAR NOT NULL,
+    issued_at       BIGINT NOT NULL,
+    expires_at      BIGINT NOT NULL,
+    used_at         BIGINT
+);
+CREATE INDEX IF NOT EXISTS AccountToken_user_email_purpose ON AccountToken (user_email, purpose);
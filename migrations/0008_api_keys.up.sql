@@ -0,0 +1,14 @@
+-- Keys of devices and scripts acting for an account, by the SHA-256 of the key.
+-- `scopes` holds `Permission` bits, `allowed_ips` comma separated addresses or CIDR ranges, empty for any.
+CREATE TABLE IF NOT EXISTS ApiKey (
+    key_id          VARCHAR PRIMARY KEY,
+    key_hash        VARCHAR NOT NULL UNIQUE,
+    user_email      VARCHAR NOT NULL,
+    name            VARCHAR NOT NULL,
+    scopes          SMALLINT NOT NULL,
+    allowed_ips     VARCHAR NOT NULL DEFAULT '',
+    created_at      BIGINT NOT NULL,
+    expires_at      BIGINT,
+    last_used_at    BIGINT
+);
+CREATE INDEX IF NOT EXISTS ApiKey_user_email ON ApiKey (user_email);
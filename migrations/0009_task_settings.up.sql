@@ -0,0 +1,9 @@
+-- Settings of a task changed through the admin API, shared by every instance and read before leasing a tick.
+-- A schedule of NULLs is the one configured in `daemon.tasks`. Times are Unix epoch milliseconds.
+CREATE TABLE IF NOT EXISTS TaskSetting (
+    task_name           VARCHAR PRIMARY KEY,
+    paused              BOOLEAN NOT NULL DEFAULT FALSE,
+    interval_seconds    BIGINT,
+    cron_expression     VARCHAR,
+    updated_at          BIGINT NOT NULL
+);
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS UFeedback;
+DROP TABLE IF EXISTS TFeedback;
+DROP TABLE IF EXISTS Account;
@@ -0,0 +1,30 @@
+-- Accounts and feedback, as migrations/0001_initial.up.sql.
+-- Account has no `id`: SQLite only numbers primary keys and nothing reads it.
+CREATE TABLE IF NOT EXISTS Account (
+    nick_name       VARCHAR NOT NULL,
+    password_salt   VARCHAR NOT NULL,
+    password_hash   VARCHAR NOT NULL,
+    email           VARCHAR PRIMARY KEY NOT NULL,
+    contribution    SMALLINT NOT NULL,
+    available       BOOLEAN NOT NULL,
+    permissions     SMALLINT NOT NULL
+);
+
+-- Trainable feedback.
+CREATE TABLE IF NOT EXISTS TFeedback (
+    id              INTEGER PRIMARY KEY,
+    time_stamp      BIGINT NOT NULL,
+    from_user_email VARCHAR NOT NULL,
+    time_out        BIGINT NOT NULL,
+    pic_link        TEXT NOT NULL,
+    real_label      VARCHAR NOT NULL,
+    submit_count    BIGINT NOT NULL
+);
+
+-- Untrainable feedback.
+CREATE TABLE IF NOT EXISTS UFeedback (
+    id              INTEGER PRIMARY KEY,
+    time_stamp      BIGINT NOT NULL,
+    from_user_email VARCHAR NOT NULL,
+    pic_link        TEXT NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Species catalog, one row per label of the model (see species_vector.rs).
+CREATE TABLE Wiki (
+    label           INTEGER PRIMARY KEY,
+    english_name    VARCHAR NOT NULL,
+    specie_name     VARCHAR NOT NULL,
+    content         TEXT NOT NULL
+);
@@ -0,0 +1,10 @@
+-- One row per identified picture, times are Unix epoch milliseconds.
+CREATE TABLE IF NOT EXISTS InferenceHistory (
+    id              INTEGER PRIMARY KEY,
+    user_email      VARCHAR NOT NULL,
+    file_name       TEXT NOT NULL,
+    label           INTEGER NOT NULL,
+    specie_name     VARCHAR NOT NULL,
+    inferred_at     BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS InferenceHistory_user_email_inferred_at ON InferenceHistory (user_email, inferred_at DESC);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS RevokedSession;
+DROP TABLE IF EXISTS RefreshToken;
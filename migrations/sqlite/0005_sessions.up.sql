@@ -0,0 +1,20 @@
+-- Refresh tokens by their SHA-256, a session keeps one while it is rotated.
+-- Times are Unix epoch seconds, like the claims of the access tokens.
+CREATE TABLE IF NOT EXISTS RefreshToken (
+    token_hash      VARCHAR PRIMARY KEY,
+    session_id      VARCHAR NOT NULL,
+    user_email      VARCHAR NOT NULL,
+    issued_at       BIGINT NOT NULL,
+    expires_at      BIGINT NOT NULL,
+    used_at         BIGINT
+);
+CREATE INDEX IF NOT EXISTS RefreshToken_session_id ON RefreshToken (session_id);
+CREATE INDEX IF NOT EXISTS RefreshToken_user_email ON RefreshToken (user_email);
+
+-- Sessions signed out or revoked, kept until their last access token expires.
+CREATE TABLE IF NOT EXISTS RevokedSession (
+    session_id      VARCHAR PRIMARY KEY,
+    user_email      VARCHAR NOT NULL,
+    revoked_at      BIGINT NOT NULL,
+    expires_at      BIGINT NOT NULL
+);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS AccountToken;
+ALTER TABLE Account DROP COLUMN email_verified;
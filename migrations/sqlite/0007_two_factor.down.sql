@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS RecoveryCode;
+DROP TABLE IF EXISTS TwoFactor;
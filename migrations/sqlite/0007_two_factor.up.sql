@@ -0,0 +1,17 @@
+-- TOTP secrets of the accounts, base32. The secret is pending until a first code enables it.
+-- `last_step` is the latest 30 second step accepted, so a code works once.
+CREATE TABLE IF NOT EXISTS TwoFactor (
+    user_email      VARCHAR PRIMARY KEY,
+    secret          VARCHAR NOT NULL,
+    enabled         BOOLEAN NOT NULL DEFAULT FALSE,
+    enrolled_at     BIGINT NOT NULL,
+    last_step       BIGINT NOT NULL DEFAULT 0
+);
+
+-- Single-use codes replacing a lost authenticator, by their SHA-256.
+CREATE TABLE IF NOT EXISTS RecoveryCode (
+    code_hash       VARCHAR PRIMARY KEY,
+    user_email      VARCHAR NOT NULL,
+    used_at         BIGINT
+);
+CREATE INDEX IF NOT EXISTS RecoveryCode_user_email ON RecoveryCode (user_email);
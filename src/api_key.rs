@@ -0,0 +1,316 @@
+use std::net::IpAddr;
+
+use axum::{extract::{Path, State}, http::HeaderMap, Form, Json};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    authenticator::{generate_token, hash_token, AuthUser, Permission},
+    config::app_config,
+    error::{parse_json_field, AppError, ErrorResponses},
+    repository::{ApiKeyRecord, Repositories},
+    MultiState
+};
+
+// Start of every key, so leaked keys are easy to search for.
+const KEY_PREFIX: &str = "iik_";
+const NAME_MAX_LENGTH: usize = 64;
+// Scopes by the names clients send, the `Permission` bits a key may hold.
+const SCOPES: [(&str, Permission); 4] = [
+    ("Common", Permission::Common),
+    ("MngModel", Permission::MngModel),
+    ("MngUsr", Permission::MngUsr),
+    ("MngFeedBack", Permission::MngFeedBack),
+];
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RequestApiKeyCreate {
+    name: String,
+    scopes: String, // Json String
+    expires_in: Option<i64>, // seconds, none for a key living until revoked
+    allowed_ips: Option<String>, // Json String
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResponseApiKey {
+    key_id: String,
+    name: String,
+    scopes: Vec<String>,
+    allowed_ips: Vec<String>,
+    created_at: i64,
+    expires_at: Option<i64>,
+    last_used_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResponseApiKeyCreated {
+    // The key itself, shown this time only.
+    api_key: String,
+    details: ResponseApiKey,
+}
+
+impl From<ApiKeyRecord> for ResponseApiKey {
+    fn from(record: ApiKeyRecord) -> Self {
+        ResponseApiKey {
+            key_id: record.key_id,
+            name: record.name,
+            scopes: SCOPES.iter()
+                .filter(|(_, permission)| record.scopes & *permission as i16 != 0)
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            allowed_ips: __split_allowed_ips(&record.allowed_ips).map(str::to_string).collect(),
+            created_at: record.created_at,
+            expires_at: record.expires_at,
+            last_used_at: record.last_used_at,
+        }
+    }
+}
+
+/// The key of an `Authorization: ApiKey <key>` header.
+pub fn get_api_key(headers: &HeaderMap) -> Option<String> {
+    let authorization = headers.get("authorization")?.to_str().ok()?;
+    Some(authorization.strip_prefix("ApiKey ")?.trim().to_string())
+}
+
+/// The key of the header if it exists, hasn't expired and is used from an allowed address.
+pub async fn authenticate_api_key(repositories: &Repositories, api_key: &str, ip: Option<IpAddr>) -> Result<ApiKeyRecord, AppError> {
+    let now = Local::now().timestamp();
+    let record = repositories.api_keys
+        .find_api_key(&hash_token(api_key))
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("API key is invalid!".to_string()))?;
+    if record.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(AppError::Unauthorized("API key has expired!".to_string()));
+    }
+    if !__ip_allowed(&record.allowed_ips, ip) {
+        tracing::warn!("API key {} of {} used from {ip:?}.", record.key_id, record.user_email);
+        return Err(AppError::Forbidden("API key isn't allowed from this address!".to_string()));
+    }
+    repositories.api_keys.touch_api_key(&record.key_id, now).await?;
+    Ok(record)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api_keys",
+    tag = "api keys",
+    responses(
+        (status = 200, description = "API keys of the caller, newest first", body = Vec<ResponseApiKey>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_api_keys(
+    State(multi_state): State<MultiState>,
+    user: AuthUser
+) -> Result<Json<Vec<ResponseApiKey>>, AppError> {
+    Ok(Json(_fetch_api_keys(&multi_state.repositories, &user).await?))
+}
+
+pub async fn _fetch_api_keys(repositories: &Repositories, user: &AuthUser) -> Result<Vec<ResponseApiKey>, AppError> {
+    Ok(repositories.api_keys
+        .list_api_keys(&user.email)
+        .await?
+        .into_iter()
+        .map(ResponseApiKey::from)
+        .collect())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api_keys",
+    tag = "api keys",
+    request_body(content = RequestApiKeyCreate, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The new key, sent back in the `Authorization: ApiKey <key>` header", body = ResponseApiKeyCreated),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_create_api_key(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    Form(api_key_form): Form<RequestApiKeyCreate>
+) -> Result<Json<ResponseApiKeyCreated>, AppError> {
+    let scopes = parse_json_field("scopes", &api_key_form.scopes)?;
+    let allowed_ips = match &api_key_form.allowed_ips {
+        Some(allowed_ips) => parse_json_field("allowed_ips", allowed_ips)?,
+        None => Vec::new(),
+    };
+    let created = _create_api_key(
+        &multi_state.repositories, &user, api_key_form.name, scopes, api_key_form.expires_in, allowed_ips
+    ).await?;
+    Ok(Json(created))
+}
+
+/// A key acting for the caller with some of its permissions. Only signed-in sessions create keys.
+pub async fn _create_api_key(
+    repositories: &Repositories,
+    user: &AuthUser,
+    name: String,
+    scopes: Vec<String>,
+    expires_in: Option<i64>,
+    allowed_ips: Vec<String>
+) -> Result<ResponseApiKeyCreated, AppError> {
+    user.require_session()?;
+    let name = name.trim().to_string();
+    if name.is_empty() || name.chars().count() > NAME_MAX_LENGTH {
+        return Err(AppError::BadRequest(format!("The name should have 1 to {NAME_MAX_LENGTH} characters!")));
+    }
+    let scopes = __scopes_from_names(&scopes)?;
+    let proof = repositories.accounts
+        .find_proof(&user.email)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Couldn't find account: {:?}", user.email)))?;
+    if proof.permissions & scopes != scopes {
+        return Err(AppError::Forbidden("A key can't have scopes the account doesn't have!".to_string()));
+    }
+    let max_expiration = app_config().auth.api_key_max_expiration;
+    match expires_in {
+        Some(expires_in) if expires_in <= 0 =>
+            return Err(AppError::BadRequest("expires_in should be greater than 0!".to_string())),
+        Some(expires_in) if max_expiration > 0 && expires_in > max_expiration =>
+            return Err(AppError::BadRequest(format!("expires_in should be at most {max_expiration}!"))),
+        None if max_expiration > 0 =>
+            return Err(AppError::BadRequest(format!("expires_in is required, at most {max_expiration}!"))),
+        _ => {},
+    }
+    for entry in &allowed_ips {
+        if __parse_ip_range(entry).is_none() {
+            return Err(AppError::BadRequest(format!("{entry:?} is no IP address nor CIDR range!")));
+        }
+    }
+
+    let key_id = generate_token(6)?;
+    let api_key = format!("{KEY_PREFIX}{key_id}_{}", generate_token(32)?);
+    let now = Local::now().timestamp();
+    let record = ApiKeyRecord {
+        key_id,
+        key_hash: hash_token(&api_key),
+        user_email: user.email.clone(),
+        name,
+        scopes,
+        allowed_ips: allowed_ips.iter().map(|entry| entry.trim()).collect::<Vec<&str>>().join(","),
+        created_at: now,
+        expires_at: expires_in.map(|expires_in| now + expires_in),
+        last_used_at: None,
+    };
+    repositories.api_keys.insert_api_key(&record).await?;
+    tracing::info!("{} created API key {}.", user.email, record.key_id);
+    Ok(ResponseApiKeyCreated { api_key, details: ResponseApiKey::from(record) })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api_keys/{key_id}/revoke",
+    tag = "api keys",
+    params(("key_id" = String, Path, description = "Id of the key")),
+    responses(
+        (status = 200, description = "Revoked, the key no longer works", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_revoke_api_key(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    Path(key_id): Path<String>
+) -> Result<String, AppError> {
+    _revoke_api_key(&multi_state.repositories, &user, &key_id).await
+}
+
+pub async fn _revoke_api_key(repositories: &Repositories, user: &AuthUser, key_id: &str) -> Result<String, AppError> {
+    user.require_session()?;
+    if !repositories.api_keys.revoke_api_key(&user.email, key_id).await? {
+        return Err(AppError::NotFound(format!("Couldn't find API key: {key_id:?}")));
+    }
+    tracing::info!("{} revoked API key {key_id}.", user.email);
+    Ok("Succeeded to revoke the API key!".to_string())
+}
+
+fn __scopes_from_names(names: &[String]) -> Result<i16, AppError> {
+    if names.is_empty() {
+        return Err(AppError::BadRequest("A key needs at least one scope!".to_string()));
+    }
+    let mut scopes = 0;
+    for name in names {
+        let (_, permission) = SCOPES.iter()
+            .find(|(scope, _)| scope == name)
+            .ok_or_else(|| AppError::BadRequest(format!("Unknown scope {name:?}, expected Common, MngModel, MngUsr or MngFeedBack!")))?;
+        scopes |= *permission as i16;
+    }
+    Ok(scopes)
+}
+
+fn __split_allowed_ips(allowed_ips: &str) -> impl Iterator<Item = &str> {
+    allowed_ips.split(',').filter(|entry| !entry.is_empty())
+}
+
+/// Any address without an allowlist, else one in a range of it. Unknown addresses only without one.
+fn __ip_allowed(allowed_ips: &str, ip: Option<IpAddr>) -> bool {
+    let mut ranges = __split_allowed_ips(allowed_ips).filter_map(__parse_ip_range).peekable();
+    if ranges.peek().is_none() {
+        return true;
+    }
+    match ip {
+        Some(ip) => ranges.any(|range| __ip_in_range(ip.to_canonical(), range)),
+        None => false,
+    }
+}
+
+/// An address, or a CIDR range `address/prefix`.
+fn __parse_ip_range(entry: &str) -> Option<(IpAddr, u32)> {
+    let (address, prefix) = match entry.trim().split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (entry.trim(), None),
+    };
+    let address: IpAddr = address.parse().ok()?;
+    let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= max_prefix)?,
+        None => max_prefix,
+    };
+    Some((address, prefix))
+}
+
+fn __ip_in_range(ip: IpAddr, (network, prefix): (IpAddr, u32)) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        },
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{__ip_allowed, __parse_ip_range};
+
+    #[test]
+    fn allowlists_match_addresses_and_ranges() {
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        assert!(__ip_allowed("", ip("203.0.113.9")));
+        assert!(__ip_allowed("", None));
+
+        let allowed_ips = "10.0.0.0/8,203.0.113.9,fd00::/8";
+        assert!(__ip_allowed(allowed_ips, ip("10.20.30.40")));
+        assert!(__ip_allowed(allowed_ips, ip("203.0.113.9")));
+        assert!(__ip_allowed(allowed_ips, ip("::ffff:203.0.113.9")));
+        assert!(__ip_allowed(allowed_ips, ip("fd12::1")));
+        assert!(!__ip_allowed(allowed_ips, ip("203.0.113.10")));
+        assert!(!__ip_allowed(allowed_ips, ip("11.0.0.1")));
+        assert!(!__ip_allowed(allowed_ips, None));
+        assert!(__ip_allowed("0.0.0.0/0", ip("198.51.100.1")));
+
+        assert!(__parse_ip_range("10.0.0.0/33").is_none());
+        assert!(__parse_ip_range("example.com").is_none());
+    }
+}
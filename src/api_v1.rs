@@ -0,0 +1,1146 @@
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Multipart, Path, Query, Request, State},
+    http::{header, HeaderMap},
+    response::Response,
+    middleware,
+    routing::{get, post},
+    Form, Json, Router
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    authenticator::{
+        _fetch_role, _forgot_password, _refresh, _resend_verification, _reset_password, _sign_in, _sign_in_two_factor,
+        _sign_out, _sign_up, _verify_email, middleware_authorize, role_to_string, string_to_role, AuthUser, Permission,
+        RequestAccountEmail, RequestAccountForSignIn, RequestAccountForSignUp, RequestAccountToken,
+        RequestPasswordReset, RequestTwoFactorSignIn, RequirePermission
+    },
+    daemon::TaskStatus,
+    dl_svc::{
+        _fetch_inference_history, _infer, _ssh_host,
+        RequestInferenceHistory, ResponseInferResult, ResponseInferResultUnit, ResponseInferenceRecord
+    },
+    error::{json_field_error, AppError, ErrorResponses, FieldError},
+    feedback::{
+        self, _accept_or_reject_feedback, _label_picture, _submit_feedback,
+        AccRejFeedbackUnit, FeedbackFileUnit, RequestLabelImage, ResponseFeedback, ResponseFeedbackUnit
+    },
+    health::{self, ResponseDiagnostics},
+    io_agent::{self, _path_is_valid, RequestImageFetch, UploadPicture},
+    mailer::_address_is_valid,
+    model_manager::{self, _operate_files, FileMetadata, RequestFetchModels},
+    task_manager::{self, RequestTaskSchedule, ResponseTaskAction},
+    api_key::{_create_api_key, _fetch_api_keys, _revoke_api_key, ResponseApiKey, ResponseApiKeyCreated},
+    two_factor::{
+        _disable_two_factor, _enable_two_factor, _enroll_two_factor, _regenerate_recovery_codes, _two_factor_status,
+        RequestTwoFactorCode, ResponseRecoveryCodes, ResponseTwoFactorEnrollment, ResponseTwoFactorStatus
+    },
+    user_manager::{
+        self, _add_admin, _suspend_or_unsuspend_users,
+        RequestAdminAdd, ResponseUserInfo, ResponseUserManageUnit
+    },
+    MultiState
+};
+
+/// Routes of `/api/v1`: the same operations as the legacy routes, with JSON bodies in and out.
+pub fn router(multi_state: MultiState) -> Router<MultiState> {
+    Router::new()
+        .route("/sign_out", post(handler_sign_out))
+        .route("/users/:useremail", get(handler_fetch_user_info).require(&multi_state, Permission::Common))
+        .route("/users/:useremail/role", get(handler_fetch_role).require(&multi_state, Permission::Common))
+        .route("/users/:useremail/inferences", get(handler_fetch_inference_history).require(&multi_state, Permission::Common))
+        .route("/users/:useremail/pictures", post(handler_upload_picture).require(&multi_state, Permission::Common))
+        .route("/images", get(handler_fetch_image).require(&multi_state, Permission::Common))
+        .route("/infer", post(handler_infer).require(&multi_state, Permission::Common))
+        .route("/two_factor", get(handler_two_factor_status).require(&multi_state, Permission::Common))
+        .route("/two_factor/enroll", post(handler_enroll_two_factor).require(&multi_state, Permission::Common))
+        .route("/two_factor/enable", post(handler_enable_two_factor).require(&multi_state, Permission::Common))
+        .route("/two_factor/disable", post(handler_disable_two_factor).require(&multi_state, Permission::Common))
+        .route("/two_factor/recovery_codes", post(handler_regenerate_recovery_codes).require(&multi_state, Permission::Common))
+        .route("/api_keys", get(handler_fetch_api_keys).post(handler_create_api_key).require(&multi_state, Permission::Common))
+        .route("/api_keys/:key_id/revoke", post(handler_revoke_api_key).require(&multi_state, Permission::Common))
+        .route("/feedback", post(handler_submit_feedback).require(&multi_state, Permission::Common))
+        .route("/feedback/unlabelled", get(handler_fetch_unlabelled_feedback).require(&multi_state, Permission::Common))
+        .route("/feedback/labels", post(handler_label_picture).require(&multi_state, Permission::Common))
+
+        .route("/admin/feedback", get(handler_fetch_trainable_feedback).post(handler_accept_or_reject_feedback)
+            .require(&multi_state, Permission::MngFeedBack))
+        .route("/admin/users", get(handler_fetch_users).post(handler_add_admin).require(&multi_state, Permission::MngUsr))
+        .route("/admin/users/availability", post(handler_suspend_or_unsuspend_users).require(&multi_state, Permission::MngUsr))
+        .route("/admin/models", get(handler_fetch_models).require(&multi_state, Permission::MngModel))
+        .route("/admin/models/operations", post(handler_operate_files).require(&multi_state, Permission::MngModel))
+        .route("/admin/ssh/:useremail", get(handler_ssh_host).require(&multi_state, Permission::MngModel))
+        .route("/admin/diagnostics", get(handler_diagnostics).require(&multi_state, Permission::MngModel))
+        .route("/admin/tasks", get(handler_fetch_tasks).require(&multi_state, Permission::MngModel))
+        .route("/admin/tasks/:task_name/trigger", post(handler_task_trigger).require(&multi_state, Permission::MngModel))
+        .route("/admin/tasks/:task_name/pause", post(handler_task_pause).require(&multi_state, Permission::MngModel))
+        .route("/admin/tasks/:task_name/resume", post(handler_task_resume).require(&multi_state, Permission::MngModel))
+        .route("/admin/tasks/:task_name/schedule", post(handler_task_schedule).require(&multi_state, Permission::MngModel))
+        .route_layer(middleware::from_fn_with_state(multi_state, middleware_authorize))
+        .route("/sign_in", post(handler_sign_in))
+        .route("/sign_in/two_factor", post(handler_sign_in_two_factor))
+        .route("/sign_up", post(handler_sign_up))
+        .route("/refresh", post(handler_refresh))
+        .route("/verify_email", post(handler_verify_email))
+        .route("/resend_verification", post(handler_resend_verification))
+        .route("/forgot_password", post(handler_forgot_password))
+        .route("/reset_password", post(handler_reset_password))
+}
+
+/// JSON body which is deserialized, then validated field by field.
+/// Every failure is answered as `AppError::Validation` naming the offending fields.
+pub struct ApiJson<T>(pub T);
+
+/// Checks on a deserialized request which serde can't express.
+pub trait Validate {
+    fn validate(&self) -> Vec<FieldError>;
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+    where T: DeserializeOwned + Validate,
+          S: Send + Sync
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !__is_json_content(request.headers()) {
+            return Err(AppError::BadRequest("Expected a request body with `Content-Type: application/json`!".to_string()));
+        }
+        let bytes = Bytes::from_request(request, state).await
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+        let deserializer = &mut serde_json::Deserializer::from_slice(&bytes);
+        let value: T = serde_path_to_error::deserialize(deserializer)
+            .map_err(|err| AppError::Validation(vec![json_field_error("", &err)]))?;
+        let errors = value.validate();
+        if !errors.is_empty() {
+            return Err(AppError::Validation(errors));
+        }
+        Ok(ApiJson(value))
+    }
+}
+
+fn __is_json_content(headers: &HeaderMap) -> bool {
+    headers.get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|mime| {
+            let mime = mime.trim();
+            mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+        })
+}
+
+fn __check_not_empty(errors: &mut Vec<FieldError>, field: &str, value: &str) {
+    if value.trim().is_empty() {
+        errors.push(FieldError::new(field, "should not be empty"));
+    }
+}
+
+fn __check_email(errors: &mut Vec<FieldError>, field: &str, value: &str) {
+    if !_address_is_valid(value) {
+        errors.push(FieldError::new(field, "should be an email address"));
+    }
+}
+
+fn __check_file_name(errors: &mut Vec<FieldError>, field: &str, value: &str) {
+    if !_path_is_valid(value) {
+        errors.push(FieldError::new(field, "should be a file name without directories"));
+    }
+}
+
+fn __check_list_not_empty<T>(errors: &mut Vec<FieldError>, field: &str, list: &[T]) {
+    if list.is_empty() {
+        errors.push(FieldError::new(field, "should hold at least one item"));
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ResponseMessage {
+    message: String
+}
+
+impl ResponseMessage {
+    fn new(message: impl Into<String>) -> Json<Self> {
+        Json(ResponseMessage { message: message.into() })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ResponseRole {
+    role: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ResponseSshHost {
+    host: String
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RequestInferV1 {
+    file_list: Vec<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RequestFeedbackV1 {
+    file_with_label_list: Vec<FeedbackFileUnit>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RequestAccRejFeedbackV1 {
+    files_to_operate: Vec<AccRejFeedbackUnit>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RequestUserManagementV1 {
+    user_emails: Vec<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RequestApiKeyCreateV1 {
+    name: String,
+    scopes: Vec<String>, // Common, MngModel, MngUsr or MngFeedBack
+    expires_in: Option<i64>, // seconds, none for a key living until revoked
+    #[serde(default)]
+    allowed_ips: Vec<String>, // addresses or CIDR ranges, empty for any
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RequestFileOperationV1 {
+    operation_type: String, // backup or remove
+    files2operate: Vec<String>
+}
+
+impl Validate for RequestAccountForSignIn {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_email(&mut errors, "useremail", &self.useremail);
+        __check_not_empty(&mut errors, "password", &self.password);
+        errors
+    }
+}
+
+impl Validate for RequestAccountForSignUp {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_not_empty(&mut errors, "username", &self.username);
+        __check_email(&mut errors, "email", &self.email);
+        __check_not_empty(&mut errors, "password", &self.password);
+        if self.password != self.repassword {
+            errors.push(FieldError::new("repassword", "should be the same as password"));
+        }
+        errors
+    }
+}
+
+impl Validate for RequestAccountToken {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_not_empty(&mut errors, "token", &self.token);
+        errors
+    }
+}
+
+impl Validate for RequestAccountEmail {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_email(&mut errors, "useremail", &self.useremail);
+        errors
+    }
+}
+
+impl Validate for RequestPasswordReset {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_not_empty(&mut errors, "token", &self.token);
+        __check_not_empty(&mut errors, "password", &self.password);
+        if self.password != self.repassword {
+            errors.push(FieldError::new("repassword", "should be the same as password"));
+        }
+        errors
+    }
+}
+
+impl Validate for RequestTwoFactorSignIn {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_not_empty(&mut errors, "challenge", &self.challenge);
+        __check_not_empty(&mut errors, "code", &self.code);
+        errors
+    }
+}
+
+impl Validate for RequestTwoFactorCode {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_not_empty(&mut errors, "code", &self.code);
+        errors
+    }
+}
+
+impl Validate for RequestApiKeyCreateV1 {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_not_empty(&mut errors, "name", &self.name);
+        __check_list_not_empty(&mut errors, "scopes", &self.scopes);
+        for (index, entry) in self.allowed_ips.iter().enumerate() {
+            __check_not_empty(&mut errors, &format!("allowed_ips[{index}]"), entry);
+        }
+        errors
+    }
+}
+
+impl Validate for RequestInferV1 {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_list_not_empty(&mut errors, "file_list", &self.file_list);
+        for (index, file_name) in self.file_list.iter().enumerate() {
+            __check_file_name(&mut errors, &format!("file_list[{index}]"), file_name);
+        }
+        errors
+    }
+}
+
+impl Validate for RequestFeedbackV1 {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_list_not_empty(&mut errors, "file_with_label_list", &self.file_with_label_list);
+        for (index, item) in self.file_with_label_list.iter().enumerate() {
+            __check_file_name(&mut errors, &format!("file_with_label_list[{index}].filename"), &item.filename);
+            if let Some(label) = &item.label {
+                __check_not_empty(&mut errors, &format!("file_with_label_list[{index}].label"), label);
+            }
+        }
+        errors
+    }
+}
+
+impl Validate for RequestLabelImage {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_file_name(&mut errors, "image_name", &self.image_name);
+        __check_not_empty(&mut errors, "image_label", &self.image_label);
+        errors
+    }
+}
+
+impl Validate for RequestAccRejFeedbackV1 {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_list_not_empty(&mut errors, "files_to_operate", &self.files_to_operate);
+        for (index, item) in self.files_to_operate.iter().enumerate() {
+            __check_not_empty(&mut errors, &format!("files_to_operate[{index}].pic_path"), &item.pic_path);
+            __check_not_empty(&mut errors, &format!("files_to_operate[{index}].real_label"), &item.real_label);
+        }
+        errors
+    }
+}
+
+impl Validate for RequestUserManagementV1 {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_list_not_empty(&mut errors, "user_emails", &self.user_emails);
+        for (index, useremail) in self.user_emails.iter().enumerate() {
+            __check_email(&mut errors, &format!("user_emails[{index}]"), useremail);
+        }
+        errors
+    }
+}
+
+impl Validate for RequestAdminAdd {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        __check_not_empty(&mut errors, "username", &self.username);
+        __check_email(&mut errors, "useremail", &self.useremail);
+        __check_not_empty(&mut errors, "password", &self.password);
+        if self.password != self.repassword {
+            errors.push(FieldError::new("repassword", "should be the same as password"));
+        }
+        // Unknown roles would silently become Common User.
+        if role_to_string(string_to_role(self.role.clone()) as i16) != self.role {
+            errors.push(FieldError::new(
+                "role",
+                "should be one of Common User, User Administrator, Model Administrator and Super Root"
+            ));
+        }
+        errors
+    }
+}
+
+impl Validate for RequestFileOperationV1 {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if !matches!(self.operation_type.as_str(), "backup" | "remove") {
+            errors.push(FieldError::new("operation_type", "should be backup or remove"));
+        }
+        __check_list_not_empty(&mut errors, "files2operate", &self.files2operate);
+        for (index, file_name) in self.files2operate.iter().enumerate() {
+            __check_file_name(&mut errors, &format!("files2operate[{index}]"), file_name);
+        }
+        errors
+    }
+}
+
+impl Validate for RequestTaskSchedule {
+    fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        match (self.interval, &self.cron) {
+            (Some(0), None) => errors.push(FieldError::new("interval", "should be greater than 0")),
+            (Some(_), None) | (None, Some(_)) => {},
+            _ => errors.push(FieldError::new("interval", "either interval or cron should be given")),
+        }
+        errors
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/sign_in",
+    tag = "v1 auth",
+    operation_id = "v1_sign_in",
+    request_body = RequestAccountForSignIn,
+    responses(
+        (status = 200, description = "Signed in, the tokens are in the `auth-token` and `refresh-token` headers. With two-factor authentication, only a `two-factor-challenge` header is returned, to send to `/api/v1/sign_in/two_factor` with the code", body = ResponseMessage, headers(("auth-token" = String, description = "JWT to send back in the `auth-token` header"), ("refresh-token" = String, description = "Token to send to `/api/v1/refresh` for a new JWT"), ("two-factor-challenge" = String, description = "Challenge of the second step of the sign in"))),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_sign_in(
+    State(multi_state): State<MultiState>,
+    ApiJson(request): ApiJson<RequestAccountForSignIn>
+) -> Result<(HeaderMap, Json<ResponseMessage>), AppError> {
+    let (headers, message) = _sign_in(&multi_state.repositories, request).await?;
+    Ok((headers, ResponseMessage::new(message)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/sign_in/two_factor",
+    tag = "v1 auth",
+    operation_id = "v1_sign_in_two_factor",
+    request_body = RequestTwoFactorSignIn,
+    responses(
+        (status = 200, description = "Signed in, the tokens are in the `auth-token` and `refresh-token` headers", body = ResponseMessage, headers(("auth-token" = String, description = "JWT to send back in the `auth-token` header"), ("refresh-token" = String, description = "Token to send to `/api/v1/refresh` for a new JWT"))),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_sign_in_two_factor(
+    State(multi_state): State<MultiState>,
+    ApiJson(request): ApiJson<RequestTwoFactorSignIn>
+) -> Result<(HeaderMap, Json<ResponseMessage>), AppError> {
+    let headers = _sign_in_two_factor(&multi_state.repositories, request).await?;
+    Ok((headers, ResponseMessage::new("Succeeded to sign in!")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/refresh",
+    tag = "v1 auth",
+    operation_id = "v1_refresh",
+    params(("refresh-token" = String, Header, description = "Refresh token of the session, used once")),
+    responses(
+        (status = 200, description = "Refreshed, the new tokens are in the `auth-token` and `refresh-token` headers", body = ResponseMessage, headers(("auth-token" = String, description = "JWT to send back in the `auth-token` header"), ("refresh-token" = String, description = "Token to send to `/api/v1/refresh` next time"))),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_refresh(
+    State(multi_state): State<MultiState>,
+    headers: HeaderMap
+) -> Result<(HeaderMap, Json<ResponseMessage>), AppError> {
+    let headers = _refresh(&multi_state.repositories, &headers).await?;
+    Ok((headers, ResponseMessage::new("Succeeded to refresh the token!")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/sign_out",
+    tag = "v1 auth",
+    operation_id = "v1_sign_out",
+    responses(
+        (status = 200, description = "Signed out, every token of the session is revoked", body = ResponseMessage),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_sign_out(
+    State(multi_state): State<MultiState>,
+    user: AuthUser
+) -> Result<Json<ResponseMessage>, AppError> {
+    Ok(ResponseMessage::new(_sign_out(&multi_state.repositories, &user).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/sign_up",
+    tag = "v1 auth",
+    operation_id = "v1_sign_up",
+    request_body = RequestAccountForSignUp,
+    responses(
+        (status = 200, description = "Signed up a common user", body = ResponseMessage),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_sign_up(
+    State(multi_state): State<MultiState>,
+    ApiJson(request): ApiJson<RequestAccountForSignUp>
+) -> Result<Json<ResponseMessage>, AppError> {
+    Ok(ResponseMessage::new(_sign_up(&multi_state.repositories, multi_state.mailer.as_ref(), request).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/verify_email",
+    tag = "v1 auth",
+    operation_id = "v1_verify_email",
+    request_body = RequestAccountToken,
+    responses(
+        (status = 200, description = "Verified the email of the account", body = ResponseMessage),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_verify_email(
+    State(multi_state): State<MultiState>,
+    ApiJson(request): ApiJson<RequestAccountToken>
+) -> Result<Json<ResponseMessage>, AppError> {
+    Ok(ResponseMessage::new(_verify_email(&multi_state.repositories, request).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/resend_verification",
+    tag = "v1 auth",
+    operation_id = "v1_resend_verification",
+    request_body = RequestAccountEmail,
+    responses(
+        (status = 200, description = "Mailed a new link if the account is waiting for one", body = ResponseMessage),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_resend_verification(
+    State(multi_state): State<MultiState>,
+    ApiJson(request): ApiJson<RequestAccountEmail>
+) -> Result<Json<ResponseMessage>, AppError> {
+    Ok(ResponseMessage::new(_resend_verification(&multi_state.repositories, multi_state.mailer.as_ref(), request).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/forgot_password",
+    tag = "v1 auth",
+    operation_id = "v1_forgot_password",
+    request_body = RequestAccountEmail,
+    responses(
+        (status = 200, description = "Mailed a link to reset the password if the account exists", body = ResponseMessage),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_forgot_password(
+    State(multi_state): State<MultiState>,
+    ApiJson(request): ApiJson<RequestAccountEmail>
+) -> Result<Json<ResponseMessage>, AppError> {
+    Ok(ResponseMessage::new(_forgot_password(&multi_state.repositories, multi_state.mailer.as_ref(), request).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/reset_password",
+    tag = "v1 auth",
+    operation_id = "v1_reset_password",
+    request_body = RequestPasswordReset,
+    responses(
+        (status = 200, description = "Set the new password and signed the account out everywhere", body = ResponseMessage),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_reset_password(
+    State(multi_state): State<MultiState>,
+    ApiJson(request): ApiJson<RequestPasswordReset>
+) -> Result<Json<ResponseMessage>, AppError> {
+    Ok(ResponseMessage::new(_reset_password(&multi_state.repositories, request).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/two_factor",
+    tag = "v1 two factor",
+    operation_id = "v1_two_factor_status",
+    responses(
+        (status = 200, description = "Two-factor authentication of the caller", body = ResponseTwoFactorStatus),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_two_factor_status(
+    State(multi_state): State<MultiState>,
+    user: AuthUser
+) -> Result<Json<ResponseTwoFactorStatus>, AppError> {
+    Ok(Json(_two_factor_status(&multi_state.repositories, &user).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/two_factor/enroll",
+    tag = "v1 two factor",
+    operation_id = "v1_enroll_two_factor",
+    responses(
+        (status = 200, description = "A new secret, enabled by `/api/v1/two_factor/enable` with a first code", body = ResponseTwoFactorEnrollment),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_enroll_two_factor(
+    State(multi_state): State<MultiState>,
+    user: AuthUser
+) -> Result<Json<ResponseTwoFactorEnrollment>, AppError> {
+    Ok(Json(_enroll_two_factor(&multi_state.repositories, &user).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/two_factor/enable",
+    tag = "v1 two factor",
+    operation_id = "v1_enable_two_factor",
+    request_body = RequestTwoFactorCode,
+    responses(
+        (status = 200, description = "Enabled, the recovery codes are shown this time only", body = ResponseRecoveryCodes),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_enable_two_factor(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    ApiJson(request): ApiJson<RequestTwoFactorCode>
+) -> Result<Json<ResponseRecoveryCodes>, AppError> {
+    Ok(Json(_enable_two_factor(&multi_state.repositories, &user, request).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/two_factor/disable",
+    tag = "v1 two factor",
+    operation_id = "v1_disable_two_factor",
+    request_body = RequestTwoFactorCode,
+    responses(
+        (status = 200, description = "Disabled, signing in takes the password only", body = ResponseMessage),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_disable_two_factor(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    ApiJson(request): ApiJson<RequestTwoFactorCode>
+) -> Result<Json<ResponseMessage>, AppError> {
+    Ok(ResponseMessage::new(_disable_two_factor(&multi_state.repositories, &user, request).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/two_factor/recovery_codes",
+    tag = "v1 two factor",
+    operation_id = "v1_regenerate_recovery_codes",
+    request_body = RequestTwoFactorCode,
+    responses(
+        (status = 200, description = "New recovery codes, the earlier ones no longer work", body = ResponseRecoveryCodes),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_regenerate_recovery_codes(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    ApiJson(request): ApiJson<RequestTwoFactorCode>
+) -> Result<Json<ResponseRecoveryCodes>, AppError> {
+    Ok(Json(_regenerate_recovery_codes(&multi_state.repositories, &user, request).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/api_keys",
+    tag = "v1 api keys",
+    operation_id = "v1_fetch_api_keys",
+    responses(
+        (status = 200, description = "API keys of the caller, newest first", body = Vec<ResponseApiKey>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_api_keys(
+    State(multi_state): State<MultiState>,
+    user: AuthUser
+) -> Result<Json<Vec<ResponseApiKey>>, AppError> {
+    Ok(Json(_fetch_api_keys(&multi_state.repositories, &user).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/api_keys",
+    tag = "v1 api keys",
+    operation_id = "v1_create_api_key",
+    request_body = RequestApiKeyCreateV1,
+    responses(
+        (status = 200, description = "The new key, sent back in the `Authorization: ApiKey <key>` header", body = ResponseApiKeyCreated),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_create_api_key(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    ApiJson(request): ApiJson<RequestApiKeyCreateV1>
+) -> Result<Json<ResponseApiKeyCreated>, AppError> {
+    let created = _create_api_key(
+        &multi_state.repositories, &user, request.name, request.scopes, request.expires_in, request.allowed_ips
+    ).await?;
+    Ok(Json(created))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/api_keys/{key_id}/revoke",
+    tag = "v1 api keys",
+    operation_id = "v1_revoke_api_key",
+    params(("key_id" = String, Path, description = "Id of the key")),
+    responses(
+        (status = 200, description = "Revoked, the key no longer works", body = ResponseMessage),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_revoke_api_key(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    Path(key_id): Path<String>
+) -> Result<Json<ResponseMessage>, AppError> {
+    Ok(ResponseMessage::new(_revoke_api_key(&multi_state.repositories, &user, &key_id).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{useremail}",
+    tag = "v1 user",
+    params(("useremail" = String, Path, description = "Email of the account")),
+    responses(
+        (status = 200, description = "Profile of the account", body = ResponseUserInfo),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_user_info(
+    multi_state: State<MultiState>,
+    user: AuthUser,
+    useremail: Path<String>
+) -> Result<Json<ResponseUserInfo>, AppError> {
+    user_manager::handler_user_info(multi_state, user, useremail).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{useremail}/role",
+    tag = "v1 user",
+    params(("useremail" = String, Path, description = "Email of the account")),
+    responses(
+        (status = 200, description = "Role of the account", body = ResponseRole),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_role(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    Path(useremail): Path<String>
+) -> Result<Json<ResponseRole>, AppError> {
+    user.require_self_or(&multi_state.repositories, &useremail, Permission::MngUsr).await?;
+    let role = _fetch_role(&multi_state.repositories, &useremail).await?;
+    Ok(Json(ResponseRole { role }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/{useremail}/pictures",
+    tag = "v1 user",
+    params(("useremail" = String, Path, description = "Email of the account")),
+    request_body(content = UploadPicture, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Name and size of the stored picture", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_upload_picture(
+    user: AuthUser,
+    useremail: Path<String>,
+    multipart: Multipart
+) -> Result<String, AppError> {
+    io_agent::handler_upload_pic(user, useremail, multipart).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/images",
+    tag = "v1 user",
+    operation_id = "v1_fetch_image",
+    params(RequestImageFetch),
+    responses(
+        (status = 200, description = "Content of the picture", body = Vec<u8>, content_type = "application/octet-stream"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_image(
+    request: Query<RequestImageFetch>
+) -> Result<Response, AppError> {
+    io_agent::handler_fetch_image(request).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/infer",
+    tag = "v1 user",
+    operation_id = "v1_infer",
+    request_body = RequestInferV1,
+    responses(
+        (status = 200, description = "Species on every picture", body = Vec<ResponseInferResultUnit>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_infer(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    ApiJson(request): ApiJson<RequestInferV1>
+) -> Result<Json<ResponseInferResult>, AppError> {
+    Ok(Json(_infer(&multi_state.repositories, &user.email, request.file_list).await?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{useremail}/inferences",
+    tag = "v1 user",
+    params(("useremail" = String, Path, description = "Email of the account"), RequestInferenceHistory),
+    responses(
+        (status = 200, description = "Latest identified pictures, newest first", body = Vec<ResponseInferenceRecord>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_inference_history(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    Path(useremail): Path<String>,
+    Query(request): Query<RequestInferenceHistory>
+) -> Result<Json<Vec<ResponseInferenceRecord>>, AppError> {
+    Ok(Json(_fetch_inference_history(&multi_state.repositories, &user, &useremail, request.limit).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/feedback",
+    tag = "v1 feedback",
+    request_body = RequestFeedbackV1,
+    responses(
+        (status = 200, description = "Feedback recorded", body = ResponseMessage),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_submit_feedback(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    ApiJson(request): ApiJson<RequestFeedbackV1>
+) -> Result<Json<ResponseMessage>, AppError> {
+    let message = _submit_feedback(&multi_state.repositories, &user.email, &request.file_with_label_list).await?;
+    Ok(ResponseMessage::new(message))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/feedback/unlabelled",
+    tag = "v1 feedback",
+    responses(
+        (status = 200, description = "Pictures waiting for a label", body = Vec<ResponseFeedbackUnit>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_unlabelled_feedback(
+    multi_state: State<MultiState>
+) -> Result<Response, AppError> {
+    feedback::handler_fetch_ufb(multi_state).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/feedback/labels",
+    tag = "v1 feedback",
+    request_body = RequestLabelImage,
+    responses(
+        (status = 200, description = "Label recorded", body = ResponseMessage),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_label_picture(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    ApiJson(request): ApiJson<RequestLabelImage>
+) -> Result<Json<ResponseMessage>, AppError> {
+    _label_picture(&multi_state.repositories, user.email, request).await?;
+    Ok(ResponseMessage::new("Succeeded to label the picture!"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/feedback",
+    tag = "v1 feedback",
+    responses(
+        (status = 200, description = "Labelled feedback waiting for review", body = Vec<ResponseFeedback>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_trainable_feedback(
+    multi_state: State<MultiState>
+) -> Result<Response, AppError> {
+    feedback::handler_fetch_trainable_fb(multi_state).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/feedback",
+    tag = "v1 feedback",
+    request_body = RequestAccRejFeedbackV1,
+    responses(
+        (status = 200, description = "Feedback accepted or rejected", body = ResponseMessage),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_accept_or_reject_feedback(
+    State(multi_state): State<MultiState>,
+    ApiJson(request): ApiJson<RequestAccRejFeedbackV1>
+) -> Result<Json<ResponseMessage>, AppError> {
+    _accept_or_reject_feedback(&multi_state.repositories, &request.files_to_operate).await?;
+    Ok(ResponseMessage::new("Feedback operations finished!"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/users",
+    tag = "v1 user management",
+    responses(
+        (status = 200, description = "Every other account", body = Vec<ResponseUserManageUnit>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_users(
+    multi_state: State<MultiState>,
+    user: AuthUser
+) -> Result<Json<Vec<ResponseUserManageUnit>>, AppError> {
+    user_manager::handler_fetch_all_users(multi_state, user).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users",
+    tag = "v1 user management",
+    operation_id = "v1_add_admin",
+    request_body = RequestAdminAdd,
+    responses(
+        (status = 200, description = "Administrator signed up", body = ResponseMessage),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_add_admin(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    ApiJson(request): ApiJson<RequestAdminAdd>
+) -> Result<Json<ResponseMessage>, AppError> {
+    Ok(ResponseMessage::new(_add_admin(&multi_state.repositories, &user, request).await?))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/availability",
+    tag = "v1 user management",
+    request_body = RequestUserManagementV1,
+    responses(
+        (status = 200, description = "Availability of the accounts flipped", body = ResponseMessage),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_suspend_or_unsuspend_users(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    ApiJson(request): ApiJson<RequestUserManagementV1>
+) -> Result<Json<ResponseMessage>, AppError> {
+    let message = _suspend_or_unsuspend_users(&multi_state.repositories, &user, request.user_emails).await?;
+    Ok(ResponseMessage::new(message))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/models",
+    tag = "v1 model",
+    params(RequestFetchModels),
+    responses(
+        (status = 200, description = "Files in the directory", body = Vec<FileMetadata>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_models(
+    Query(request): Query<RequestFetchModels>
+) -> Result<Json<Vec<FileMetadata>>, AppError> {
+    model_manager::handler_fetch_all_models(Form(request)).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/models/operations",
+    tag = "v1 model",
+    request_body = RequestFileOperationV1,
+    responses(
+        (status = 200, description = "Bytes written or files removed", body = ResponseMessage),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_operate_files(
+    ApiJson(request): ApiJson<RequestFileOperationV1>
+) -> Result<Json<ResponseMessage>, AppError> {
+    let message = _operate_files(&request.operation_type, &request.files2operate).await?;
+    Ok(ResponseMessage::new(message))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/ssh/{useremail}",
+    tag = "v1 model",
+    params(("useremail" = String, Path, description = "Email of the account")),
+    responses(
+        (status = 200, description = "Address of the SSH host of the model server", body = ResponseSshHost),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_ssh_host(
+    user: AuthUser,
+    Path(useremail): Path<String>
+) -> Result<Json<ResponseSshHost>, AppError> {
+    user.require_self(&useremail)?;
+    Ok(Json(ResponseSshHost { host: _ssh_host() }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/diagnostics",
+    tag = "v1 health",
+    operation_id = "v1_diagnostics",
+    responses(
+        (status = 200, description = "Versions, uptime, pool and storage usage", body = ResponseDiagnostics),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_diagnostics(
+    multi_state: State<MultiState>
+) -> Result<Json<ResponseDiagnostics>, AppError> {
+    health::handler_diagnostics(multi_state).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/tasks",
+    tag = "v1 tasks",
+    responses(
+        (status = 200, description = "Every registered daemon task", body = Vec<TaskStatus>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_tasks(
+    multi_state: State<MultiState>
+) -> Result<Json<Vec<TaskStatus>>, AppError> {
+    task_manager::handler_fetch_all_tasks(multi_state).await
+}
+
+// The task handlers already answer JSON, only the body of a schedule differs.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/tasks/{task_name}/trigger",
+    tag = "v1 tasks",
+    params(("task_name" = String, Path, description = "Name of the daemon task")),
+    responses(
+        (status = 200, description = "Run started", body = ResponseTaskAction),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_task_trigger(
+    multi_state: State<MultiState>,
+    task_name: Path<String>
+) -> Result<Json<ResponseTaskAction>, AppError> {
+    task_manager::handler_trigger_task(multi_state, task_name).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/tasks/{task_name}/pause",
+    tag = "v1 tasks",
+    params(("task_name" = String, Path, description = "Name of the daemon task")),
+    responses(
+        (status = 200, description = "Task paused", body = ResponseTaskAction),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_task_pause(
+    multi_state: State<MultiState>,
+    task_name: Path<String>
+) -> Result<Json<ResponseTaskAction>, AppError> {
+    task_manager::handler_pause_task(multi_state, task_name).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/tasks/{task_name}/resume",
+    tag = "v1 tasks",
+    params(("task_name" = String, Path, description = "Name of the daemon task")),
+    responses(
+        (status = 200, description = "Task resumed", body = ResponseTaskAction),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_task_resume(
+    multi_state: State<MultiState>,
+    task_name: Path<String>
+) -> Result<Json<ResponseTaskAction>, AppError> {
+    task_manager::handler_resume_task(multi_state, task_name).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/tasks/{task_name}/schedule",
+    tag = "v1 tasks",
+    params(("task_name" = String, Path, description = "Name of the daemon task")),
+    request_body = RequestTaskSchedule,
+    responses(
+        (status = 200, description = "Schedule replaced", body = ResponseTaskAction),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_task_schedule(
+    multi_state: State<MultiState>,
+    task_name: Path<String>,
+    ApiJson(request): ApiJson<RequestTaskSchedule>
+) -> Result<Json<ResponseTaskAction>, AppError> {
+    task_manager::handler_update_task_schedule(multi_state, task_name, Form(request)).await
+}
@@ -1,35 +1,35 @@
-use std::env;
-use std::ops::BitAnd;
+use std::{ops::BitAnd, sync::{Arc, OnceLock}};
 use axum::extract::Path;
 use axum::http::HeaderValue;
-use sha2::Sha384;
-use chrono::{TimeZone, Local};
-use deadpool_postgres::Pool;
-use hmac::{digest::KeyInit, Hmac};
+use ring::rand::{SecureRandom, SystemRandom};
+use sha2::{Digest, Sha256};
+use chrono::Local;
 use serde::{Deserialize, Serialize};
-use tokio_pg_mapper::FromTokioPostgresRow;
+use utoipa::ToSchema;
 use tokio_pg_mapper_derive::PostgresMapper;
-use jwt::{AlgorithmType, Error, Header, SignWithKey, Token, VerifyWithKey};
-use crate::config::{JWT_EXPIRATION, JWT_REFRESH_PERIOD};
+use crate::api_key::{authenticate_api_key, get_api_key};
+use crate::config::app_config;
+use crate::error::{AppError, ErrorResponses};
+use crate::keyring::keyring;
+use crate::mailer::{_address_is_valid, Mail, Mailer};
+use crate::repository::{Account, AccountTokenRecord, ApiKeyRecord, ProofAccount, RefreshTokenRecord, Repositories};
+use crate::two_factor::{check_second_factor, two_factor_required_for};
+use crate::rate_limit::client_ip;
 use crate::MultiState;
 
-use data_encoding::HEXUPPER;
-use ring::error::Unspecified;
-use ring::rand::SecureRandom;
-use ring::{digest, pbkdf2, rand};
-use std::num::NonZeroU32;
+use crate::password::{hash_password, verify_password};
 
 use axum::{
+    async_trait,
     Form,
-    middleware::Next,
-    extract::Request,
+    middleware::{self, Next},
+    extract::{FromRequestParts, Request},
     response::Response,
     extract::State,
-    http::{HeaderMap, StatusCode},
+    http::{request::Parts, HeaderMap},
+    routing::MethodRouter,
 };
 
-const CREDENTIAL_LEN: usize = digest::SHA512_OUTPUT_LEN;
-
 #[macro_export]
 macro_rules! back_to_enum {
     ($(#[$meta:meta])* $vis:vis enum $name:ident {
@@ -69,12 +69,13 @@ back_to_enum! {
 }
 
 pub fn role_to_string(permissions: i16) -> String {
-    let role: Role = permissions.try_into().unwrap();
+    let role: Result<Role, ()> = permissions.try_into();
     return match role {
-        Role::UserAdmin => "User Administrator".to_string(),
-        Role::CommonUser => "Common User".to_string(),
-        Role::ModelAdmin => "Model Administrator".to_string(),
-        Role::SuperRoot => "Super Root".to_string(),
+        Ok(Role::UserAdmin) => "User Administrator".to_string(),
+        Ok(Role::CommonUser) => "Common User".to_string(),
+        Ok(Role::ModelAdmin) => "Model Administrator".to_string(),
+        Ok(Role::SuperRoot) => "Super Root".to_string(),
+        Err(_) => "Unknown".to_string(),
     }
 }
 
@@ -89,6 +90,7 @@ pub fn string_to_role(role_string: String) -> Role {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub enum Permission {
     MngFeedBack =   0b1000isize,
     MngUsr      =   0b0100isize,
@@ -123,343 +125,791 @@ pub struct Claims {
     user_email: String,
     user_name: String,
     expire_on: usize,
+    // Every token of a sign in and its refreshes, revoked together.
+    session_id: String,
+    // Unix seconds, 0 in the tokens signed before it was claimed.
+    #[serde(default)]
+    issued_at: usize,
+    // With `auth.permissions_in_token` only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    permissions: Option<i16>,
 }
 
-#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
-#[pg_mapper(table = "Account")]
-struct AuthenAccount {
-    nick_name: String,
-    password_salt: String,
-    password_hash: String,
-    email: String,
-    available: bool,
+impl Claims {
+    pub fn user_email(&self) -> &str {
+        &self.user_email
+    }
+
+    /// Extend the expiry from now, claiming the permissions of the proof if configured to.
+    fn renew(&mut self, proof: ProofAccount) {
+        let now = Local::now().timestamp();
+        self.issued_at = now as usize;
+        self.expire_on = (now + app_config().auth.jwt_expiration) as usize;
+        self.permissions = app_config().auth.permissions_in_token.then_some(proof.permissions);
+    }
+
+    /// Claims of a request with an API key, never signed into a token.
+    fn for_api_key(record: &ApiKeyRecord, account: &Account) -> Self {
+        Claims {
+            user_email: account.email.clone(),
+            user_name: account.nick_name.clone(),
+            expire_on: record.expires_at.unwrap_or(i64::MAX) as usize,
+            session_id: format!("api_key:{}", record.key_id),
+            issued_at: record.created_at as usize,
+            permissions: None,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
-#[pg_mapper(table = "Account")]
-pub struct ProofAccount {
+/// `Permission` bits of the API key a request came with, next to its `Claims`.
+#[derive(Clone, Copy, Debug)]
+struct ApiKeyScopes(i16);
+
+/// The signed-in account, from the claims `middleware_authorize` verified.
+/// Handlers act as it instead of trusting emails sent by the client.
+#[derive(Clone, Debug)]
+pub struct AuthUser {
     pub email: String,
-    pub permissions: i16,
-    pub available: bool,
+    pub nick_name: String,
+    pub session_id: String,
+    // Scopes of the API key the request came with, none for signed-in sessions.
+    pub scopes: Option<i16>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Only missing on routes outside of `middleware_authorize`.
+        let claims = parts.extensions.get::<Claims>()
+            .ok_or_else(|| AppError::Unauthorized("Token is invalid!".to_string()))?;
+        Ok(AuthUser {
+            email: claims.user_email.clone(),
+            nick_name: claims.user_name.clone(),
+            session_id: claims.session_id.clone(),
+            scopes: parts.extensions.get::<ApiKeyScopes>().map(|ApiKeyScopes(scopes)| *scopes),
+        })
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+impl AuthUser {
+    /// Fail with `AppError::Forbidden` unless the email is the caller's own.
+    pub fn require_self(&self, useremail: &str) -> Result<(), AppError> {
+        if self.email != useremail {
+            return Err(AppError::forbidden());
+        }
+        Ok(())
+    }
+
+    /// Fail with `AppError::Forbidden` unless the email is the caller's own or the caller has the permission.
+    pub async fn require_self_or(&self, repositories: &Repositories, useremail: &str, needed_permission: Permission) -> Result<(), AppError> {
+        if self.email == useremail {
+            return Ok(());
+        }
+        self.require_scope(needed_permission)?;
+        require_permission(repositories, &self.email, needed_permission).await
+    }
+
+    /// Fail with `AppError::Forbidden` unless the API key the request came with, if any, has the scope.
+    pub fn require_scope(&self, needed_permission: Permission) -> Result<(), AppError> {
+        match self.scopes {
+            Some(scopes) if scopes & needed_permission as i16 == 0 =>
+                Err(AppError::Forbidden("The API key hasn't the scope for this!".to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail with `AppError::Forbidden` for requests with an API key, for what takes a signed-in session.
+    pub fn require_session(&self) -> Result<(), AppError> {
+        if self.scopes.is_some() {
+            return Err(AppError::Forbidden("Sign in for this, API keys can't do it!".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct RequestAccountForSignIn {
-    useremail: String,
-    password: String,
+    pub useremail: String,
+    pub password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct RequestAccountForSignUp {
-    username: String,
-    password: String,
-    repassword: String,
-    email: String,
+    pub username: String,
+    pub password: String,
+    pub repassword: String,
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RequestTwoFactorSignIn {
+    pub challenge: String, // from the `two-factor-challenge` header of the sign in
+    pub code: String, // of the authenticator app, or a recovery code
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RequestAccountToken {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RequestAccountEmail {
+    pub useremail: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RequestPasswordReset {
+    pub token: String,
+    pub password: String,
+    pub repassword: String,
 }
 
+// Purposes of the mailed tokens, also the names of their templates and of the front end pages of their links.
+const VERIFY_EMAIL: &str = "verify_email";
+const RESET_PASSWORD: &str = "reset_password";
+// Purpose of the challenges between the password and the second factor of a sign in.
+const TWO_FACTOR_SIGN_IN: &str = "two_factor_sign_in";
+
 #[derive(Serialize, Deserialize, PostgresMapper)]
 #[pg_mapper (table = "Account")]
 pub struct AccountUnit {
     email: String
 }
 
-pub async fn check_permission (connection: &Pool, useremail: &String, needed_permission: Permission) -> Result<bool, (StatusCode, String)> {
-    let client = connection.get().await.unwrap();
-
-    let auth_statement = client
-    .prepare("
-        SELECT email, permissions, available FROM account WHERE email=$1;
-    ").await.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
-    let current_user = client
-    .query(&auth_statement, &[&useremail])
-    .await
-    .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?
-    .iter()
-    .map(|row| ProofAccount::from_row_ref(row).unwrap())
-    .collect::<Vec<ProofAccount>>()
-    .pop()
-    .ok_or((StatusCode::NOT_FOUND, format!("Couldn't find account: {:?}", useremail)))?;
-
-    // if !check_permission(&current_user, Permission::Common) {
-    //     return  Err(
-    //         (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-    //     );
-    // }
-    let role: Role = current_user.permissions.try_into().unwrap();
+/// Suspended accounts have no permission at all.
+pub async fn check_permission (repositories: &Repositories, useremail: &str, needed_permission: Permission) -> Result<bool, AppError> {
+    let proof = __find_proof(repositories, useremail).await?;
+    __proof_allows(useremail, proof, needed_permission)
+}
+
+/// Fail with `AppError::Forbidden` unless the account has the permission.
+/// With `auth.two_factor_required`, administrators also need two-factor authentication for any but common permissions.
+pub async fn require_permission(repositories: &Repositories, useremail: &str, needed_permission: Permission) -> Result<(), AppError> {
+    let proof = __find_proof(repositories, useremail).await?;
+    if !__proof_allows(useremail, proof, needed_permission)? {
+        return Err(AppError::forbidden());
+    }
+    if app_config().auth.two_factor_required && !matches!(needed_permission, Permission::Common) {
+        let enabled = repositories.two_factor.find_two_factor(useremail).await?.is_some_and(|two_factor| two_factor.enabled);
+        if two_factor_required_for(proof.permissions) && !enabled {
+            return Err(AppError::Forbidden("Enable two-factor authentication to manage users or models!".to_string()));
+        }
+    }
+    Ok(())
+}
+
+async fn __find_proof(repositories: &Repositories, useremail: &str) -> Result<ProofAccount, AppError> {
+    repositories.accounts
+    .find_proof(useremail)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Couldn't find account: {:?}", useremail)))
+}
+
+fn __proof_allows(useremail: &str, proof: ProofAccount, needed_permission: Permission) -> Result<bool, AppError> {
+    if !proof.available {
+        return Err(AppError::Forbidden("The account has been forbidden!".to_string()));
+    }
+    let role: Role = proof.permissions.try_into()
+        .map_err(|_| AppError::Internal(format!("Account {useremail} has unknown permissions {}!", proof.permissions)))?;
     Ok(role & needed_permission)
 }
 
-// Possible to create struct Config to maintain envs.
-fn key_from_secret() -> Result<Hmac<Sha384>, Error> {
-    let secret = env::var("JWT_SECRET")
-        .unwrap_or("Usagi Peropero!".to_string());
-    let key = Hmac::new_from_slice(secret.as_bytes()).unwrap();
-    Ok(key)
+/// Guard of the routes of a method router, declared next to the route.
+pub trait RequirePermission {
+    /// Answer 403 unless the signed-in account has the permission. The route must be behind `middleware_authorize`.
+    fn require(self, multi_state: &MultiState, needed_permission: Permission) -> Self;
 }
 
-fn generate_jwt(claims: Claims)
-    -> Result<String, Error> {
-    let key = key_from_secret().unwrap();
-    let header = Header {
-        algorithm: AlgorithmType::Hs384,
-        ..Default::default()
-    };
-    let token = Token::new(header, claims).sign_with_key(&key)?;
-
-    Ok(token.as_str().to_string())
-}
-
-pub fn verify_jwt(token: &String) -> Result<Claims, Error> {
-    let key = key_from_secret().unwrap();
-    let verify: Result<Token<Header, Claims, _>, _>
-        = token.verify_with_key(&key);
-    match verify {
-        Ok(token) => {
-            let claims: Claims = token.claims().clone();
-            let expiry =
-                Local.timestamp_opt(claims.expire_on as i64, 0).unwrap();
-            let now = Local::now();
-            if now > expiry {
-                return Err(Error::InvalidSignature);
-            }
-            Ok(claims)
-        }
-        Err(err) => Err(err),
+impl RequirePermission for MethodRouter<MultiState> {
+    fn require(self, multi_state: &MultiState, needed_permission: Permission) -> Self {
+        let guard = PermissionGuard { repositories: multi_state.repositories.clone(), needed_permission };
+        self.route_layer(middleware::from_fn_with_state(guard, middleware_require_permission))
+    }
+}
+
+#[derive(Clone)]
+struct PermissionGuard {
+    repositories: Repositories,
+    needed_permission: Permission,
+}
+
+async fn middleware_require_permission(
+    State(guard): State<PermissionGuard>,
+    user: AuthUser,
+    request: Request,
+    next: Next
+) -> Result<Response, AppError> {
+    user.require_scope(guard.needed_permission)?;
+    require_permission(&guard.repositories, &user.email, guard.needed_permission).await?;
+    Ok(next.run(request).await)
+}
+
+/// Hex of `bytes` random bytes, for the tokens handed to clients and the ids of sessions.
+pub fn generate_token(bytes: usize) -> Result<String, AppError> {
+    let mut token = vec![0u8; bytes];
+    SystemRandom::new().fill(&mut token)
+        .map_err(|_| AppError::Internal("Failed to generate a token!".to_string()))?;
+    Ok(hex::encode(token))
+}
+
+/// What is stored of a token, it can't be used if the database leaks.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn generate_jwt(claims: Claims) -> Result<String, String> {
+    keyring().sign(&claims)
+}
+
+/// Claims of a token signed by a key of the keyring and not expired.
+pub fn verify_jwt(token: &str) -> Result<Claims, String> {
+    let claims: Claims = keyring().verify(token)?;
+    if Local::now().timestamp() > claims.expire_on as i64 {
+        return Err("expired".to_string());
     }
+    Ok(claims)
 }
 
-pub async fn handler_sign_in<'a>(
+#[utoipa::path(
+    post,
+    path = "/sign_in",
+    tag = "auth",
+    request_body(content = RequestAccountForSignIn, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Signed in, the tokens are in the `auth-token` and `refresh-token` headers. With two-factor authentication, only a `two-factor-challenge` header is returned, to send to `/sign_in/two_factor` with the code", body = String, content_type = "text/plain", headers(("auth-token" = String, description = "JWT to send back in the `auth-token` header"), ("refresh-token" = String, description = "Token to send to `/refresh` for a new JWT"), ("two-factor-challenge" = String, description = "Challenge of the second step of the sign in"))),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_sign_in(
     State(multi_state): State<MultiState>,
     Form(sign_in_form): Form<RequestAccountForSignIn>
-) -> Result<(HeaderMap, &'a str), (StatusCode, String)> {
-    let client = multi_state.db_pool.get().await.unwrap();
-    let user_request: RequestAccountForSignIn = sign_in_form;
-
-    let query_statement = client
-    .prepare("
-        SELECT nick_name, password_salt, password_hash, email, available FROM account WHERE email=$1;
-    ")
-    .await.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
-
-    let account: AuthenAccount = client
-    .query(&query_statement, &[&user_request.useremail])
-    .await
-    .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?
-    .iter()
-    .map(|row| AuthenAccount::from_row_ref(row).unwrap())
-    // .map(|row: &tokio_postgres::Row| Account {
-    //     id: row.get("id"),
-    //     nick_name: row.get("nick_name"),
-    //     email: row.get("email"),
-    //     contribution: row.get("contribution"),
-    //     available: row.get("available"),
-    // })
-    .collect::<Vec<AuthenAccount>>()
-    .pop()
-    .ok_or((StatusCode::NOT_FOUND, format!("Couldn't find account #{}", user_request.useremail)))?;
-
-    if !account.available {
-        return Err((StatusCode::FORBIDDEN, "The account has been forbidden!".to_string()));
-    }
-
-    if !password_authentificate(
-            user_request.password,
-            account.password_salt,
-            account.password_hash
-    ) {
-        return Err((StatusCode::FORBIDDEN, "Wrong email or password!".to_string()));
-    }
-
-    let claims = Claims {
+) -> Result<(HeaderMap, &'static str), AppError> {
+    _sign_in(&multi_state.repositories, sign_in_form).await
+}
+
+/// Check the credentials, start a session and return the headers carrying its tokens.
+/// Accounts with two-factor authentication get the header of a challenge instead, see `_sign_in_two_factor`.
+/// Unknown emails are answered like wrong passwords, after as long, so accounts can't be told apart.
+pub async fn _sign_in(repositories: &Repositories, user_request: RequestAccountForSignIn) -> Result<(HeaderMap, &'static str), AppError> {
+    let account = repositories.accounts.find(&user_request.useremail).await?;
+    let (password_salt, password_hash) = match &account {
+        Some(account) => (account.password_salt.clone(), account.password_hash.clone()),
+        None => __unknown_account_credentials().clone(),
+    };
+    let account = match (password_authentificate(user_request.password, password_salt, password_hash), account) {
+        (true, Some(account)) => account,
+        _ => return Err(AppError::Unauthorized("Wrong email or password!".to_string())),
+    };
+
+    let proof = ProofAccount::from(&account);
+    if !proof.available {
+        return Err(AppError::Forbidden("The account has been forbidden!".to_string()));
+    }
+    if !account.email_verified {
+        return Err(AppError::Forbidden("The email hasn't been verified yet!".to_string()));
+    }
+
+    if repositories.two_factor.find_two_factor(&account.email).await?.is_some_and(|two_factor| two_factor.enabled) {
+        let challenge = generate_token(32)?;
+        let now = Local::now().timestamp();
+        repositories.account_tokens.issue(&AccountTokenRecord {
+            token_hash: hash_token(&challenge),
+            user_email: account.email,
+            purpose: TWO_FACTOR_SIGN_IN.to_string(),
+            issued_at: now,
+            expires_at: now + app_config().auth.two_factor_challenge_expiration,
+            used_at: None,
+        }).await?;
+        let mut headers = HeaderMap::new();
+        headers.insert("two-factor-challenge", __header_value(&challenge)?);
+        return Ok((headers, "Enter the code of your authenticator app to finish signing in!"));
+    }
+    Ok((__start_session(repositories, account, proof).await?, "Succeeded to sign in!"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sign_in/two_factor",
+    tag = "auth",
+    request_body(content = RequestTwoFactorSignIn, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Signed in, the tokens are in the `auth-token` and `refresh-token` headers", body = String, content_type = "text/plain", headers(("auth-token" = String, description = "JWT to send back in the `auth-token` header"), ("refresh-token" = String, description = "Token to send to `/refresh` for a new JWT"))),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_sign_in_two_factor(
+    State(multi_state): State<MultiState>,
+    Form(sign_in_form): Form<RequestTwoFactorSignIn>
+) -> Result<(HeaderMap, &'static str), AppError> {
+    let headers = _sign_in_two_factor(&multi_state.repositories, sign_in_form).await?;
+    Ok((headers, "Succeeded to sign in!"))
+}
+
+/// Second step of a sign in: the code of the authenticator app, or a recovery code, for the challenge.
+/// A challenge is used once, a wrong code means signing in with the password again.
+pub async fn _sign_in_two_factor(repositories: &Repositories, user_request: RequestTwoFactorSignIn) -> Result<HeaderMap, AppError> {
+    let invalid = || AppError::Unauthorized("The sign in is invalid or expired, please sign in again!".to_string());
+    let useremail = repositories.account_tokens
+    .consume(&hash_token(&user_request.challenge), TWO_FACTOR_SIGN_IN, Local::now().timestamp())
+    .await?
+    .ok_or_else(invalid)?;
+    let account = repositories.accounts
+    .find(&useremail)
+    .await?
+    .ok_or_else(invalid)?;
+    let proof = ProofAccount::from(&account);
+    if !proof.available {
+        return Err(AppError::Forbidden("The account has been forbidden!".to_string()));
+    }
+
+    let two_factor = repositories.two_factor
+    .find_two_factor(&useremail)
+    .await?
+    .filter(|two_factor| two_factor.enabled)
+    .ok_or_else(invalid)?;
+    if !check_second_factor(repositories, &two_factor, &user_request.code, true).await? {
+        tracing::warn!("Wrong two-factor code for {useremail}.");
+        return Err(AppError::Unauthorized("Wrong two-factor code, please sign in again!".to_string()));
+    }
+    __start_session(repositories, account, proof).await
+}
+
+/// A new session of the account, the headers carrying its tokens.
+async fn __start_session(repositories: &Repositories, account: Account, proof: ProofAccount) -> Result<HeaderMap, AppError> {
+    let mut claims = Claims {
         user_email: account.email,
         user_name: account.nick_name,
-        // permissions: account.permissions,
-        expire_on: (Local::now().timestamp() + JWT_EXPIRATION) as usize
+        expire_on: 0,
+        session_id: generate_token(16)?,
+        issued_at: 0,
+        permissions: None,
     };
+    claims.renew(proof);
 
-    let token = generate_jwt(claims).unwrap();
-    let mut headers = HeaderMap::new();
-    headers.insert("auth-token",
-        HeaderValue::from_str(token.as_str()).unwrap());
-    Ok((headers, "Succeeded to sign in!"))
+    let refresh_token = __issue_refresh_token(repositories, &claims.session_id, &claims.user_email).await?;
+    let mut headers = __token_headers(claims)?;
+    headers.insert("refresh-token", __header_value(&refresh_token)?);
+    Ok(headers)
 }
 
+#[utoipa::path(
+    post,
+    path = "/refresh",
+    tag = "auth",
+    params(("refresh-token" = String, Header, description = "Refresh token of the session, used once")),
+    responses(
+        (status = 200, description = "Refreshed, the new tokens are in the `auth-token` and `refresh-token` headers", body = String, content_type = "text/plain", headers(("auth-token" = String, description = "JWT to send back in the `auth-token` header"), ("refresh-token" = String, description = "Token to send to `/refresh` next time"))),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_refresh(
+    State(multi_state): State<MultiState>,
+    headers: HeaderMap
+) -> Result<(HeaderMap, &'static str), AppError> {
+    let headers = _refresh(&multi_state.repositories, &headers).await?;
+    Ok((headers, "Succeeded to refresh the token!"))
+}
+
+/// Swap the refresh token of the headers for a new one and a new JWT of the same session.
+/// A refresh token used twice was stolen from one of its users, so the session is revoked.
+pub async fn _refresh(repositories: &Repositories, headers: &HeaderMap) -> Result<HeaderMap, AppError> {
+    let invalid = || AppError::Unauthorized("Refresh token is invalid or expired!".to_string());
+    let refresh_token = headers.get("refresh-token")
+        .and_then(|refresh_token| refresh_token.to_str().ok())
+        .ok_or_else(invalid)?;
+    let now = Local::now().timestamp();
+    let sessions = &repositories.sessions;
+    let record = sessions.find_refresh_token(&hash_token(refresh_token))
+        .await?
+        .filter(|record| record.expires_at > now)
+        .ok_or_else(invalid)?;
+
+    if !sessions.use_refresh_token(&record.token_hash, now).await? {
+        let expires_at = now + app_config().auth.jwt_expiration;
+        sessions.revoke_session(&record.session_id, &record.user_email, now, expires_at).await?;
+        tracing::warn!("Refresh token of {} reused, revoked the session.", record.user_email);
+        return Err(AppError::Unauthorized("Refresh token has been used already, signed out!".to_string()));
+    }
+    // Signed out while the token was read.
+    if sessions.is_revoked(&record.session_id).await? {
+        return Err(invalid());
+    }
+
+    let account = repositories.accounts
+    .find(&record.user_email)
+    .await?
+    .ok_or_else(invalid)?;
+    let proof = ProofAccount::from(&account);
+    if !proof.available {
+        return Err(AppError::Forbidden("The account has been forbidden!".to_string()));
+    }
+
+    let mut claims = Claims {
+        user_email: account.email,
+        user_name: account.nick_name,
+        expire_on: 0,
+        session_id: record.session_id,
+        issued_at: 0,
+        permissions: None,
+    };
+    claims.renew(proof);
+
+    let refresh_token = __issue_refresh_token(repositories, &claims.session_id, &claims.user_email).await?;
+    let mut headers = __token_headers(claims)?;
+    headers.insert("refresh-token", __header_value(&refresh_token)?);
+    Ok(headers)
+}
+
+#[utoipa::path(
+    post,
+    path = "/sign_out",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Signed out, every token of the session is revoked", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_sign_out(
+    State(multi_state): State<MultiState>,
+    user: AuthUser
+) -> Result<String, AppError> {
+    _sign_out(&multi_state.repositories, &user).await
+}
+
+/// Revoke the session of the caller, until its last JWT would have expired.
+pub async fn _sign_out(repositories: &Repositories, user: &AuthUser) -> Result<String, AppError> {
+    user.require_session()?;
+    let now = Local::now().timestamp();
+    let expires_at = now + app_config().auth.jwt_expiration;
+    repositories.sessions.revoke_session(&user.session_id, &user.email, now, expires_at).await?;
+    Ok("Succeeded to sign out!".to_string())
+}
+
+/// Revoke every session of the account, for when it is suspended.
+pub async fn revoke_sessions_of(repositories: &Repositories, useremail: &str) -> Result<u64, AppError> {
+    let now = Local::now().timestamp();
+    let expires_at = now + app_config().auth.jwt_expiration;
+    repositories.sessions.revoke_sessions_of(useremail, now, expires_at).await
+}
+
+async fn __issue_refresh_token(repositories: &Repositories, session_id: &str, useremail: &str) -> Result<String, AppError> {
+    let refresh_token = generate_token(32)?;
+    let now = Local::now().timestamp();
+    repositories.sessions.insert_refresh_token(&RefreshTokenRecord {
+        token_hash: hash_token(&refresh_token),
+        session_id: session_id.to_string(),
+        user_email: useremail.to_string(),
+        issued_at: now,
+        expires_at: now + app_config().auth.refresh_token_expiration,
+        used_at: None,
+    }).await?;
+    Ok(refresh_token)
+}
+
+#[utoipa::path(
+    post,
+    path = "/sign_up",
+    tag = "auth",
+    request_body(content = RequestAccountForSignUp, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Signed up a common user", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    )
+)]
 pub async fn handler_sign_up(
     State(multi_state): State<MultiState>,
     Form(sign_up_form): Form<RequestAccountForSignUp>
-) -> Result<String, (StatusCode, String)> {
-    let client = multi_state.db_pool.get().await.unwrap();
+) -> Result<String, AppError> {
+    _sign_up(&multi_state.repositories, multi_state.mailer.as_ref(), sign_up_form).await
+}
 
-    let user_request = sign_up_form;
+/// With a mailer the account can't sign in until the link mailed to it is opened.
+pub async fn _sign_up(repositories: &Repositories, mailer: Option<&Arc<dyn Mailer>>, user_request: RequestAccountForSignUp) -> Result<String, AppError> {
     if user_request.password != user_request.repassword {
-        return Err((StatusCode::NOT_ACCEPTABLE, "The passwords should be the same!".to_string()))
-    }
-
-    let query_statement = client
-    .prepare("
-        SELECT email, available, permissions FROM account WHERE email=$1;
-    ")
-    .await.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
-
-    let account = client
-    .query(&query_statement, &[&user_request.email])
-    .await
-    .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?
-    .iter()
-    .map(|row| ProofAccount::from_row_ref(row).unwrap())
-    .collect::<Vec<ProofAccount>>()
-    .pop();
-
-    match account {
-        None => {
-            let contribute: i16 = 0;
-            let available = true;
-            let permissions = Role::CommonUser as i16;
-            let (passwd_salt, passwd_hash) =
-                encrypt_password(user_request.password);
-
-            let insert_statement = client
-            .prepare("
-                INSERT INTO account (nick_name, password_salt, password_hash, email, contribution, available, permissions)
-                VALUES
-                ($1, $2, $3, $4, $5, $6, $7)
-            ").await.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
-
-            // let rows = client
-            // .execute(&insert_statement,
-            //     &[ &user_request.username, &passwd_salt, &passwd_hash, &user_request.email,
-            //         &contribute, &available, &permissions]
-            // )
-            // .await
-            // .map_err(|err| (StatusCode::NOT_MODIFIED, err.to_string()))?;
-            let rows = client
-            .execute(&insert_statement,
-                &[ &user_request.username, &passwd_salt, &passwd_hash, &user_request.email,
-                    &contribute, &available, &permissions]
-            )
-            .await
-            .map_err(|err| (StatusCode::NOT_MODIFIED, err.to_string()))?;
-
-            if rows < 1 {
-                return Err((StatusCode::NOT_MODIFIED, "Register account failed".to_string()));
-            }
-            Ok("Succeeded to sign up!".to_string())
+        return Err(AppError::BadRequest("The passwords should be the same!".to_string()))
+    }
+    if !_address_is_valid(&user_request.email) {
+        return Err(AppError::BadRequest("The email should be an email address!".to_string()))
+    }
+
+    if repositories.accounts.find(&user_request.email).await?.is_some() {
+        return Err(AppError::Conflict("The email has been used!".to_string()));
+    }
+    let (passwd_salt, passwd_hash) =
+        encrypt_password(user_request.password)?;
+    let account = Account {
+        nick_name: user_request.username,
+        password_salt: passwd_salt,
+        password_hash: passwd_hash,
+        email: user_request.email,
+        contribution: 0,
+        available: true,
+        permissions: Role::CommonUser as i16,
+        email_verified: mailer.is_none(),
+    };
+    // Someone else may have taken the email since.
+    if !repositories.accounts.insert(&account).await? {
+        return Err(AppError::Conflict("The email has been used!".to_string()));
+    }
+    match mailer {
+        Some(mailer) => {
+            __mail_token(repositories, mailer, &account, VERIFY_EMAIL, app_config().mail.verification_expiration).await?;
+            Ok("Succeeded to sign up, please verify the email!".to_string())
         },
-        Some(_) => {
-            Err((StatusCode::CONFLICT, "The email has been used!".to_string()))
-        }
+        None => Ok("Succeeded to sign up!".to_string()),
     }
 }
 
-// pub async fn handler_sign_out() -> Result<axum::Json<String>, (StatusCode, String)> {
-//     Err((StatusCode::NOT_IMPLEMENTED, "Not implemented API!".to_string()))
-// }
+#[utoipa::path(
+    post,
+    path = "/verify_email",
+    tag = "auth",
+    request_body(content = RequestAccountToken, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Verified the email of the account", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_verify_email(
+    State(multi_state): State<MultiState>,
+    Form(verify_form): Form<RequestAccountToken>
+) -> Result<String, AppError> {
+    _verify_email(&multi_state.repositories, verify_form).await
+}
 
-pub async fn middleware_authorize(
-    headers: HeaderMap,
-    request: Request,
-    next: Next
-) -> Result<Response, (StatusCode, String)> {
-    let token_opt = get_token(&headers);
-    if let None = token_opt {
-        return Err((StatusCode::UNAUTHORIZED, "Token is invalid!".to_string()))
-    }
-    let token = token_opt.unwrap();
-    let parse_result = verify_jwt(&token);
-    if let Err(err) = parse_result {
-        return Err(
-            (StatusCode::UNAUTHORIZED,
-                format!("Token is invalid or expired! Error: {err}")));
+pub async fn _verify_email(repositories: &Repositories, user_request: RequestAccountToken) -> Result<String, AppError> {
+    let useremail = __consume_token(repositories, &user_request.token, VERIFY_EMAIL).await?;
+    repositories.accounts.set_email_verified(&useremail, true).await?;
+    Ok("Succeeded to verify the email!".to_string())
+}
+
+#[utoipa::path(
+    post,
+    path = "/resend_verification",
+    tag = "auth",
+    request_body(content = RequestAccountEmail, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Mailed a new link if the account is waiting for one", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_resend_verification(
+    State(multi_state): State<MultiState>,
+    Form(resend_form): Form<RequestAccountEmail>
+) -> Result<String, AppError> {
+    _resend_verification(&multi_state.repositories, multi_state.mailer.as_ref(), resend_form).await
+}
+
+/// Answers the same whether the account exists or not, so it can't be used to find accounts.
+pub async fn _resend_verification(repositories: &Repositories, mailer: Option<&Arc<dyn Mailer>>, user_request: RequestAccountEmail) -> Result<String, AppError> {
+    let mailer = mailer.ok_or_else(|| AppError::Unavailable("Mail is disabled!".to_string()))?;
+    let account = repositories.accounts.find(&user_request.useremail).await?;
+    if let Some(account) = account.filter(|account| !account.email_verified) {
+        __mail_token(repositories, mailer, &account, VERIFY_EMAIL, app_config().mail.verification_expiration).await?;
     }
+    Ok("If the email waits for verification, a new link has been sent to it.".to_string())
+}
 
-    let mut response = next.run(request).await;
+#[utoipa::path(
+    post,
+    path = "/forgot_password",
+    tag = "auth",
+    request_body(content = RequestAccountEmail, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Mailed a link to reset the password if the account exists", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_forgot_password(
+    State(multi_state): State<MultiState>,
+    Form(forgot_form): Form<RequestAccountEmail>
+) -> Result<String, AppError> {
+    _forgot_password(&multi_state.repositories, multi_state.mailer.as_ref(), forgot_form).await
+}
 
-    let mut claims = parse_result.unwrap();
-    if claims.expire_on as i64 - Local::now().timestamp() <= JWT_REFRESH_PERIOD {
-        claims.expire_on = (Local::now().timestamp() + JWT_EXPIRATION) as usize;
-        let new_token = generate_jwt(claims).unwrap();
-        response.headers_mut()
-        .insert("auth-token",
-            HeaderValue::from_str(new_token.as_str()).unwrap());
+/// Answers the same whether the account exists or not, so it can't be used to find accounts.
+pub async fn _forgot_password(repositories: &Repositories, mailer: Option<&Arc<dyn Mailer>>, user_request: RequestAccountEmail) -> Result<String, AppError> {
+    let mailer = mailer.ok_or_else(|| AppError::Unavailable("Mail is disabled!".to_string()))?;
+    if let Some(account) = repositories.accounts.find(&user_request.useremail).await? {
+        __mail_token(repositories, mailer, &account, RESET_PASSWORD, app_config().mail.reset_expiration).await?;
     }
+    Ok("If the account exists, a link to reset the password has been sent to its email.".to_string())
+}
 
-    Ok(response)
+#[utoipa::path(
+    post,
+    path = "/reset_password",
+    tag = "auth",
+    request_body(content = RequestPasswordReset, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Set the new password and signed the account out everywhere", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_reset_password(
+    State(multi_state): State<MultiState>,
+    Form(reset_form): Form<RequestPasswordReset>
+) -> Result<String, AppError> {
+    _reset_password(&multi_state.repositories, reset_form).await
 }
 
-fn get_token(headers: &HeaderMap) -> Option<String> {
-    let __token_header_value = headers.get("auth-token");
-    if let None = __token_header_value {
-        return None;
+/// Set the password of the account the token was mailed to and revoke its sessions.
+/// Opening the link proves the email as well.
+pub async fn _reset_password(repositories: &Repositories, user_request: RequestPasswordReset) -> Result<String, AppError> {
+    if user_request.password != user_request.repassword {
+        return Err(AppError::BadRequest("The passwords should be the same!".to_string()))
     }
-    let __token_str = __token_header_value.unwrap().to_str().unwrap();
-    let __token = __token_str.to_string();
-    Some(__token)
+    let useremail = __consume_token(repositories, &user_request.token, RESET_PASSWORD).await?;
+    let (passwd_salt, passwd_hash) = encrypt_password(user_request.password)?;
+    repositories.accounts.set_password(&useremail, &passwd_salt, &passwd_hash).await?;
+    repositories.accounts.set_email_verified(&useremail, true).await?;
+    revoke_sessions_of(repositories, &useremail).await?;
+    tracing::info!("Reset the password of {useremail}.");
+    Ok("Succeeded to reset the password, please sign in again!".to_string())
+}
+
+/// Issue a token of the purpose and mail its link with the template of the same name.
+/// Mail failures are logged only, the client can ask for another link.
+async fn __mail_token(repositories: &Repositories, mailer: &Arc<dyn Mailer>, account: &Account, purpose: &str, expiration: i64) -> Result<(), AppError> {
+    let token = generate_token(32)?;
+    let now = Local::now();
+    repositories.account_tokens.issue(&AccountTokenRecord {
+        token_hash: hash_token(&token),
+        user_email: account.email.clone(),
+        purpose: purpose.to_string(),
+        issued_at: now.timestamp(),
+        expires_at: now.timestamp() + expiration,
+        used_at: None,
+    }).await?;
+
+    let mail_config = &app_config().mail;
+    let link = format!("{}/{purpose}?token={token}", mail_config.public_url.trim_end_matches('/'));
+    let expires_at = (now + chrono::Duration::seconds(expiration)).format("%Y-%m-%d %H:%M (UTC%:z)").to_string();
+    let values = [("nick_name", account.nick_name.as_str()), ("link", link.as_str()), ("expires_at", expires_at.as_str())];
+    let mail = match Mail::from_template(mail_config, purpose, &account.email, &values) {
+        Ok(mail) => mail,
+        Err(err) => {
+            tracing::error!("Failed to mail the {purpose} link to {}: {err}", account.email);
+            return Ok(());
+        },
+    };
+    // Sent in the background, the answer comes as soon for accounts which exist as for those which don't.
+    let mailer = mailer.clone();
+    let purpose = purpose.to_string();
+    tokio::spawn(async move {
+        if let Err(err) = mailer.send(&mail).await {
+            tracing::error!("Failed to mail the {purpose} link to {}: {err}", mail.to);
+        }
+    });
+    Ok(())
+}
+
+/// The account of an unused and unexpired token of the purpose, which is used up.
+async fn __consume_token(repositories: &Repositories, token: &str, purpose: &str) -> Result<String, AppError> {
+    repositories.account_tokens.consume(&hash_token(token), purpose, Local::now().timestamp())
+        .await?
+        .ok_or_else(|| AppError::BadRequest("The link is invalid, expired or used already!".to_string()))
 }
 
-pub fn encrypt_password(password_string: String) -> (String, String) {
-    let n_iter = NonZeroU32::new(100_000).unwrap();
-    let rng = rand::SystemRandom::new();
+pub async fn middleware_authorize(
+    State(multi_state): State<MultiState>,
+    headers: HeaderMap,
+    mut request: Request,
+    next: Next
+) -> Result<Response, AppError> {
+    if let Some(api_key) = get_api_key(&headers) {
+        let ip = client_ip(&request, app_config().rate_limit.trust_forwarded_for);
+        let record = authenticate_api_key(&multi_state.repositories, &api_key, ip).await?;
+        let account = multi_state.repositories.accounts
+            .find(&record.user_email)
+            .await?
+            .filter(|account| account.available)
+            .ok_or_else(|| AppError::Unauthorized("The account of the API key is unavailable!".to_string()))?;
+        request.extensions_mut().insert(Claims::for_api_key(&record, &account));
+        request.extensions_mut().insert(ApiKeyScopes(record.scopes));
+        return Ok(next.run(request).await);
+    }
 
-    let mut salt = [0u8; CREDENTIAL_LEN];
-    rng.fill(&mut salt).unwrap();
+    let token = get_token(&headers)
+        .ok_or_else(|| AppError::Unauthorized("Token is invalid!".to_string()))?;
+    let claims = verify_jwt(&token)
+        .map_err(|err| AppError::Unauthorized(format!("Token is invalid or expired! Error: {err}")))?;
+    if multi_state.repositories.sessions.is_revoked(&claims.session_id).await? {
+        return Err(AppError::Unauthorized("Token has been revoked!".to_string()));
+    }
 
-    let password = password_string.as_str();
-    let mut pbkdf2_hash = [0u8; CREDENTIAL_LEN];
-    pbkdf2::derive(
-        pbkdf2::PBKDF2_HMAC_SHA512,
-        n_iter,
-        &salt,
-        password.as_bytes(),
-        &mut pbkdf2_hash,
-    );
+    let accounts = &multi_state.repositories.accounts;
+    if let (true, Some(permissions)) = (app_config().auth.permissions_in_token, claims.permissions) {
+        // Only an account available at sign in got a token.
+        accounts.remember_proof(&claims.user_email, ProofAccount { permissions, available: true }, claims.issued_at as i64);
+    }
 
-    (
-        HEXUPPER.encode(&salt),
-        HEXUPPER.encode(&pbkdf2_hash)
-    )
+    // Tokens are renewed by `/refresh` only, so the refresh token of the session rotates with them.
+    request.extensions_mut().insert(claims);
+    Ok(next.run(request).await)
 }
 
-fn password_authentificate(password_string: String, salt_string: String, pbkdf2_hash_string: String) -> bool {
-    let n_iter = NonZeroU32::new(100_000).unwrap();
-    let salt = HEXUPPER.decode(salt_string.as_bytes()).unwrap();
-    let pbkdf2_hash = HEXUPPER.decode(pbkdf2_hash_string.as_bytes()).unwrap();
+pub fn get_token(headers: &HeaderMap) -> Option<String> {
+    let __token_header_value = headers.get("auth-token")?;
+    let __token_str = __token_header_value.to_str().ok()?;
+    Some(__token_str.to_string())
+}
 
-    let password = password_string.as_str();
+/// Headers handing a freshly signed token to the client.
+fn __token_headers(claims: Claims) -> Result<HeaderMap, AppError> {
+    let token = generate_jwt(claims)
+        .map_err(|err| AppError::Internal(format!("Failed to sign the token! {err}")))?;
+    let mut headers = HeaderMap::new();
+    headers.insert("auth-token", __header_value(&token)?);
+    Ok(headers)
+}
 
-    let authentification_result: Result<(), Unspecified> = pbkdf2::verify(
-        pbkdf2::PBKDF2_HMAC_SHA512,
-        n_iter,
-        &salt,
-        password.as_bytes(),
-        &pbkdf2_hash
-    );
+fn __header_value(token: &str) -> Result<HeaderValue, AppError> {
+    HeaderValue::from_str(token).map_err(|err| AppError::Internal(err.to_string()))
+}
 
-    match authentification_result {
-        Ok(_) => true,
-        Err(_) => false
-    }
+/// Salt and hash checked for unknown emails, taking as long as checking a real password.
+fn __unknown_account_credentials() -> &'static (String, String) {
+    static CREDENTIALS: OnceLock<(String, String)> = OnceLock::new();
+    CREDENTIALS.get_or_init(|| hash_password("").unwrap_or_default())
+}
+
+pub fn encrypt_password(password_string: String) -> Result<(String, String), AppError> {
+    hash_password(&password_string).map_err(AppError::Internal)
 }
 
+fn password_authentificate(password_string: String, salt_string: String, pbkdf2_hash_string: String) -> bool {
+    verify_password(&password_string, &salt_string, &pbkdf2_hash_string)
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/check_role/{useremail}",
+    tag = "user",
+    params(("useremail" = String, Path, description = "Email of the account")),
+    responses(
+        (status = 200, description = "Role of the account", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_transfer_permission_to_role(
     State(multi_state): State<MultiState>,
+    user: AuthUser,
     Path(useremail): Path<String>
-) -> Result<String, (StatusCode, String)> {
-    let client = multi_state.db_pool.get().await.unwrap();
-
-    let query_statement = client
-    .prepare("
-        SELECT email, permissions, available FROM account WHERE email=$1;
-    ")
-    .await.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
-
-    let account = client
-    .query(&query_statement, &[&useremail])
-    .await
-    .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?
-    .iter()
-    .map(|row| ProofAccount::from_row_ref(row).unwrap())
-    .collect::<Vec<ProofAccount>>()
-    .pop().unwrap();
+) -> Result<String, AppError> {
+    user.require_self_or(&multi_state.repositories, &useremail, Permission::MngUsr).await?;
+    _fetch_role(&multi_state.repositories, &useremail).await
+}
+
+pub async fn _fetch_role(repositories: &Repositories, useremail: &str) -> Result<String, AppError> {
+    let account = repositories.accounts
+    .find(useremail)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Couldn't find account: {:?}", useremail)))?;
 
     let role = role_to_string(account.permissions);
     return Ok(role);
@@ -1,29 +1,981 @@
-// io_agent.rs
-pub const USER_PIC_PATH: &str = "./data_src/";
-pub const DATASETS_DIRECTORY: &str = "./datasets/";
-pub const TFEEDBACK_STORED_DIRECTORY: &str = "./tfeedback/";
-pub const UFEEDBACK_STORED_DIRECTORY: &str = "./ufeedback/";
-pub const DATA_TO_TRAIN_DIRECTORY: &str = "./data2train/";
+use std::{collections::HashMap, env, fmt, fs, net::SocketAddr, path::{Path, PathBuf}, str::FromStr, sync::OnceLock};
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
 
 // doc_database.rs
-pub const QUEUE_STORED_PATH: &str = "./queue.db";
+// The queue is a fixed size array, so its length has to stay a compile time constant.
 pub const QUEUE_MAX_LENGTH: usize = 10;
 
-pub const DATASETS_STORED_PATH: &str = "./datasets.db";
+/// Path of the configuration file used when neither `--config` nor `INSECTSYS_CONFIG` is given.
+pub const DEFAULT_CONFIG_PATH: &str = "./insectsys.toml";
+/// Environment variable holding the path of the configuration file.
+pub const CONFIG_PATH_ENV: &str = "INSECTSYS_CONFIG";
+/// Prefix of the environment variables overriding single keys,
+/// e.g. `INSECTSYS__DATABASE__HOST=db.local` overrides `database.host`.
+pub const ENV_OVERRIDE_PREFIX: &str = "INSECTSYS__";
+
+static APP_CONFIG: OnceLock<AppConfig> = OnceLock::new();
+
+/// Layered configuration of the service.
+///
+/// Layers from lowest to highest priority:
+/// built-in defaults < TOML file < `INSECTSYS__*` environment variables < `--set key=value` flags.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub cors: CorsConfig,
+    pub tls: TlsConfig,
+    pub database: DatabaseConfig,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+    pub feedback: FeedbackConfig,
+    pub daemon: DaemonConfig,
+    pub dl_svc: DlSvcConfig,
+    pub log: LogConfig,
+    pub rate_limit: RateLimitConfig,
+    pub mail: MailConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub body_limit: usize, // bytes
+}
+
+/// Cross-origin requests accepted from browsers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>, // e.g. "https://insects.example.org", "*" for any
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub max_age: u64, // seconds browsers may cache a preflight response
+}
+
+/// Built-in HTTPS. The PEM files are read again on SIGHUP and when they change.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    pub cert_path: String, // certificate chain
+    pub key_path: String, // private key, PKCS#8, PKCS#1 or SEC1
+    pub reload_interval: u64, // seconds between checks for changed files, 0 to reload on SIGHUP only
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub backend: String, // "postgres" or "sqlite", the latter needs the `sqlite` cargo feature
+    pub sqlite_path: String, // database file of the sqlite backend
+    // postgres backend
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub dbname: String,
+    pub pool_max_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    // io_agent.rs
+    pub user_pic_path: String,
+    pub datasets_directory: String,
+    pub tfeedback_stored_directory: String,
+    pub ufeedback_stored_directory: String,
+    pub data_to_train_directory: String,
+    // doc_database.rs
+    pub queue_stored_path: String,
+    pub datasets_stored_path: String,
+    // main.rs + model_manage.rs
+    pub model_stored_path: String,
+    pub model_backup_stored_path: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub jwt_expiration: i64, // seconds
+    // Seconds a refresh token lives unused, each refresh starts the period again.
+    pub refresh_token_expiration: i64,
+    // Seconds the permissions and availability of an account, and the revoked sessions, are cached for,
+    // 0 to look them up every request.
+    pub permission_cache_ttl: u64,
+    // Carry the permissions in the tokens, trusted in place of a lookup while younger than the cache TTL.
+    pub permissions_in_token: bool,
+    // Id of the key in `jwt_keys` signing new tokens, the first one when empty.
+    pub jwt_signing_key: String,
+    // Keys verifying tokens by their `kid`. Retired keys stay until the tokens they signed have expired.
+    pub jwt_keys: Vec<JwtKeyConfig>,
+    // Accounts whose role manages users or models use the admin routes only once two-factor authentication is enabled.
+    pub two_factor_required: bool,
+    pub two_factor_issuer: String, // name authenticator apps show next to the account
+    pub two_factor_challenge_expiration: i64, // seconds between the password and the code of a sign in
+    // Longest lifetime of an API key in seconds, 0 lets keys live until revoked.
+    pub api_key_max_expiration: i64,
+}
+
+/// One key of the token keyring, read at startup.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtKeyConfig {
+    pub id: String,
+    pub algorithm: String, // "HS384" or "EdDSA"
+    // HS384 only, at least 48 bytes.
+    pub secret: String,
+    // HS384: file holding the secret instead; EdDSA: PEM or DER file of the PKCS#8 Ed25519 private key.
+    pub key_file: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedbackConfig {
+    pub expiration: i64, // seconds
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub timer_duration: u64, // seconds, used by tasks without their own interval
+    pub shutdown_grace_period: u64, // seconds
+    pub instance_id: String, // name of this instance in the task leases, empty for "<hostname>-<pid>"
+    pub tasks: HashMap<String, TaskConfig>,
+}
+
+/// Schedule and retry policy of one daemon task.
+/// `interval` and `cron` are exclusive; without both the task runs every `timer_duration`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaskConfig {
+    pub interval: Option<u64>, // seconds
+    pub cron: Option<String>, // e.g. "0 3 * * *", seconds field optional
+    pub max_retries: u32,
+    pub retry_backoff: u64, // seconds before the first retry, doubled on every retry
+    pub retry_backoff_max: u64, // seconds
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DlSvcConfig {
+    pub host: String,
+    // inference entrypoint, run as `<python> <infer_script> <model_prefix> <target> <image>`
+    pub python: String,
+    pub infer_script: String,
+    pub compiled_model_directory: String,
+    pub model_prefix: String,
+    pub target: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: String, // "pretty" or "json"
+    pub filter: String, // EnvFilter directives, `RUST_LOG` takes precedence
+    pub stdout: bool,
+    pub directory: String, // daily rolling files, empty to disable
+    pub file_prefix: String,
+}
+
+/// Token buckets of the rate limited route groups, see rate_limit.rs.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub trust_forwarded_for: bool, // take the client address from the last X-Forwarded-For entry, only behind a proxy
+    pub sign_in: RateLimitRule,
+    pub sign_up: RateLimitRule,
+    pub upload: RateLimitRule,
+    pub infer: RateLimitRule,
+    pub mail: RateLimitRule, // requests sending mail, such as password resets
+}
+
+/// Mail to the accounts, for verifying their email and resetting their password.
+/// Disabled, sign-ups need no verification and passwords can't be reset.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    pub enabled: bool,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub security: String, // "none", "starttls" or "tls"
+    pub username: String, // no authentication when empty
+    pub password: String,
+    pub ca_file: String, // PEM roots trusted for "starttls" and "tls"
+    pub timeout: u64, // seconds for a whole delivery
+    pub from_address: String,
+    pub from_name: String,
+    // Front end the links in the mail point to, as `<public_url>/verify_email?token=...`.
+    pub public_url: String,
+    // Directory of templates replacing the built-in ones, named as them; empty for the built-in ones.
+    pub template_directory: String,
+    pub verification_expiration: i64, // seconds
+    pub reset_expiration: i64, // seconds
+}
+
+/// Budget of one route group, counted per client address and per signed-in account.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitRule {
+    pub ip_per_minute: u32, // 0 for unlimited
+    pub identity_per_minute: u32, // 0 for unlimited
+    pub burst: u32, // requests allowed at once, 0 for the per minute rate
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "127.0.0.1:8080".to_string(),
+            body_limit: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec!["http://localhost:3000".to_string(), "http://127.0.0.1:3000".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string(), "OPTIONS".to_string()],
+            allowed_headers: vec!["content-type".to_string(), "auth-token".to_string(), "refresh-token".to_string(),
+                "x-request-id".to_string()],
+            max_age: 600,
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            enabled: false,
+            cert_path: "./cert/cert.pem".to_string(),
+            key_path: "./cert/cert.key".to_string(),
+            reload_interval: 60,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            backend: "postgres".to_string(),
+            sqlite_path: "./insectsys.sqlite3".to_string(),
+            host: "localhost".to_string(),
+            port: 5432,
+            user: "postgres".to_string(),
+            password: "postgres".to_string(),
+            dbname: "insectsys".to_string(),
+            pool_max_size: 16,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            user_pic_path: "./data_src/".to_string(),
+            datasets_directory: "./datasets/".to_string(),
+            tfeedback_stored_directory: "./tfeedback/".to_string(),
+            ufeedback_stored_directory: "./ufeedback/".to_string(),
+            data_to_train_directory: "./data2train/".to_string(),
+            queue_stored_path: "./queue.db".to_string(),
+            datasets_stored_path: "./datasets.db".to_string(),
+            model_stored_path: "./models/".to_string(),
+            model_backup_stored_path: "./.modbak/".to_string(),
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            jwt_expiration: 900, // 15min
+            refresh_token_expiration: 30 * 24 * 3600, // 30 days
+            permission_cache_ttl: 30,
+            permissions_in_token: false,
+            jwt_signing_key: String::new(),
+            jwt_keys: Vec::new(),
+            two_factor_required: false,
+            two_factor_issuer: "Insect Identifier".to_string(),
+            two_factor_challenge_expiration: 300,
+            api_key_max_expiration: 0,
+        }
+    }
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        FeedbackConfig {
+            expiration: 3600 * 24 * 7, // 7 days
+        }
+    }
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            timer_duration: 3600, // 1h
+            shutdown_grace_period: 30,
+            instance_id: String::new(),
+            tasks: HashMap::new(),
+        }
+    }
+}
+
+impl Default for TaskConfig {
+    fn default() -> Self {
+        TaskConfig {
+            interval: None,
+            cron: None,
+            max_retries: 0,
+            retry_backoff: 5,
+            retry_backoff_max: 300,
+        }
+    }
+}
+
+impl Default for DlSvcConfig {
+    fn default() -> Self {
+        DlSvcConfig {
+            host: "https://localhost:8182".to_string(),
+            python: "python".to_string(),
+            infer_script: "./dl_svc/TransferProcedures/infer_by_tvm.py".to_string(),
+            compiled_model_directory: "./models/compiled/".to_string(),
+            model_prefix: "optimized".to_string(),
+            target: "llvm".to_string(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: "pretty".to_string(),
+            filter: "info".to_string(),
+            stdout: true,
+            directory: "./logs/".to_string(),
+            file_prefix: "insectsys.log".to_string(),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: true,
+            trust_forwarded_for: false,
+            sign_in: RateLimitRule { ip_per_minute: 10, identity_per_minute: 0, burst: 5 },
+            sign_up: RateLimitRule { ip_per_minute: 3, identity_per_minute: 0, burst: 3 },
+            upload: RateLimitRule { ip_per_minute: 60, identity_per_minute: 30, burst: 10 },
+            infer: RateLimitRule { ip_per_minute: 20, identity_per_minute: 10, burst: 5 },
+            mail: RateLimitRule { ip_per_minute: 3, identity_per_minute: 0, burst: 3 },
+        }
+    }
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            enabled: false,
+            smtp_host: "localhost".to_string(),
+            smtp_port: 1025, // MailHog
+            security: "none".to_string(),
+            username: String::new(),
+            password: String::new(),
+            ca_file: "/etc/ssl/certs/ca-certificates.crt".to_string(),
+            timeout: 10,
+            from_address: "no-reply@localhost".to_string(),
+            from_name: "Insect Identifier".to_string(),
+            public_url: "http://localhost:3000".to_string(),
+            template_directory: String::new(),
+            verification_expiration: 24 * 3600, // 1 day
+            reset_expiration: 3600, // 1h
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(String),
+    Args(String),
+    Invalid(String, String), // (key, reason)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "couldn't read config file {}: {err}", path.display()),
+            ConfigError::Parse(err) => write!(f, "couldn't parse config: {err}"),
+            ConfigError::Args(err) => write!(f, "bad command line arguments: {err}"),
+            ConfigError::Invalid(key, reason) => write!(f, "invalid value for `{key}`: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl AppConfig {
+    /// Load the configuration from the process environment and the given command line arguments.
+    /// Returns the config and the arguments which are not config flags (e.g. subcommands).
+    pub fn load(args: &[String]) -> Result<(AppConfig, Vec<String>), ConfigError> {
+        let env_vars: HashMap<String, String> = env::vars().collect();
+        Self::load_from(args, &env_vars)
+    }
+
+    pub fn load_from(args: &[String], env_vars: &HashMap<String, String>) -> Result<(AppConfig, Vec<String>), ConfigError> {
+        let (config_path, overrides, rest) = parse_args(args)?;
+
+        let (config_path, explicit) = match config_path.or_else(|| env_vars.get(CONFIG_PATH_ENV).cloned()) {
+            Some(path) => (PathBuf::from(path), true),
+            None => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+
+        let mut table = Table::new();
+        if explicit || config_path.exists() {
+            let content = fs::read_to_string(&config_path)
+                .map_err(|err| ConfigError::Io(config_path.clone(), err))?;
+            table = content.parse::<Table>()
+                .map_err(|err| ConfigError::Parse(format!("{}: {err}", config_path.display())))?;
+        }
+
+        let defaults = Value::try_from(AppConfig::default())
+            .map_err(|err| ConfigError::Parse(err.to_string()))?;
+        let mut env_overrides: Vec<(&String, &String)> = env_vars.iter()
+            .filter(|(key, _)| key.starts_with(ENV_OVERRIDE_PREFIX))
+            .collect();
+        env_overrides.sort();
+        for (key, value) in env_overrides {
+            let dotted_key = key[ENV_OVERRIDE_PREFIX.len()..].to_lowercase().replace("__", ".");
+            set_dotted(&mut table, &defaults, &dotted_key, value)?;
+        }
+        for (key, value) in overrides.iter() {
+            set_dotted(&mut table, &defaults, key, value)?;
+        }
+
+        let config: AppConfig = Value::Table(table).try_into()
+            .map_err(|err: toml::de::Error| ConfigError::Parse(err.message().to_string()))?;
+        config.validate()?;
+        Ok((config, rest))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key: &str, reason: &str| Err(ConfigError::Invalid(key.to_string(), reason.to_string()));
+
+        if self.server.bind_address.parse::<SocketAddr>().is_err() {
+            return invalid("server.bind_address", "expected an address such as 127.0.0.1:8080");
+        }
+        if self.server.body_limit == 0 {
+            return invalid("server.body_limit", "must be greater than 0");
+        }
+        for origin in self.cors.allowed_origins.iter() {
+            if origin == "*" {
+                if self.cors.allowed_origins.len() > 1 {
+                    return invalid("cors.allowed_origins", "\"*\" can't be combined with other origins");
+                }
+            } else if !(origin.starts_with("http://") || origin.starts_with("https://")) || origin.ends_with('/')
+                || HeaderValue::from_str(origin).is_err() {
+                return invalid("cors.allowed_origins", &format!("expected an origin such as https://example.org, got `{origin}`"));
+            }
+        }
+        for method in self.cors.allowed_methods.iter() {
+            if Method::from_bytes(method.as_bytes()).is_err() {
+                return invalid("cors.allowed_methods", &format!("`{method}` is not an HTTP method"));
+            }
+        }
+        for header in self.cors.allowed_headers.iter() {
+            if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                return invalid("cors.allowed_headers", &format!("`{header}` is not a header name"));
+            }
+        }
+        if self.tls.enabled {
+            for (key, path) in [("tls.cert_path", &self.tls.cert_path), ("tls.key_path", &self.tls.key_path)] {
+                if path.is_empty() {
+                    return invalid(key, "must not be empty when tls.enabled is set");
+                }
+            }
+        }
+        match self.database.backend.as_str() {
+            "postgres" => {},
+            "sqlite" if !cfg!(feature = "sqlite") => {
+                return invalid("database.backend", "\"sqlite\" needs a build with the `sqlite` feature");
+            },
+            "sqlite" if self.database.sqlite_path.is_empty() => {
+                return invalid("database.sqlite_path", "must not be empty with the sqlite backend");
+            },
+            "sqlite" => {},
+            _ => return invalid("database.backend", "expected \"postgres\" or \"sqlite\""),
+        }
+        if self.database.host.is_empty() {
+            return invalid("database.host", "must not be empty");
+        }
+        if self.database.user.is_empty() {
+            return invalid("database.user", "must not be empty");
+        }
+        if self.database.dbname.is_empty() {
+            return invalid("database.dbname", "must not be empty");
+        }
+        if self.database.pool_max_size == 0 {
+            return invalid("database.pool_max_size", "must be greater than 0");
+        }
+        for (key, path) in self.storage.entries() {
+            if path.is_empty() {
+                return invalid(&format!("storage.{key}"), "must not be empty");
+            }
+        }
+        if self.auth.jwt_expiration <= 0 {
+            return invalid("auth.jwt_expiration", "must be greater than 0");
+        }
+        if self.auth.refresh_token_expiration <= self.auth.jwt_expiration {
+            return invalid("auth.refresh_token_expiration", "must be greater than auth.jwt_expiration");
+        }
+        for (index, key) in self.auth.jwt_keys.iter().enumerate() {
+            let name = format!("auth.jwt_keys[{index}]");
+            if key.id.is_empty() {
+                return invalid(&format!("{name}.id"), "must not be empty");
+            }
+            if self.auth.jwt_keys[..index].iter().any(|other| other.id == key.id) {
+                return invalid(&format!("{name}.id"), &format!("`{}` is used by another key", key.id));
+            }
+            match key.algorithm.as_str() {
+                "HS384" if key.secret.is_empty() == key.key_file.is_empty() =>
+                    return invalid(&name, "expected either secret or key_file"),
+                "EdDSA" if key.key_file.is_empty() || !key.secret.is_empty() =>
+                    return invalid(&name, "expected key_file and no secret"),
+                "HS384" | "EdDSA" => {},
+                _ => return invalid(&format!("{name}.algorithm"), "expected HS384 or EdDSA"),
+            }
+        }
+        if !self.auth.jwt_signing_key.is_empty()
+            && !self.auth.jwt_keys.iter().any(|key| key.id == self.auth.jwt_signing_key) {
+            return invalid("auth.jwt_signing_key", "must be the id of a key in auth.jwt_keys");
+        }
+        if self.auth.two_factor_issuer.is_empty() || self.auth.two_factor_issuer.contains(':') {
+            return invalid("auth.two_factor_issuer", "must not be empty nor contain `:`");
+        }
+        if self.auth.two_factor_challenge_expiration <= 0 {
+            return invalid("auth.two_factor_challenge_expiration", "must be greater than 0");
+        }
+        if self.auth.api_key_max_expiration < 0 {
+            return invalid("auth.api_key_max_expiration", "must not be negative");
+        }
+        if self.mail.enabled {
+            if !["none", "starttls", "tls"].contains(&self.mail.security.as_str()) {
+                return invalid("mail.security", "expected none, starttls or tls");
+            }
+            for (key, value) in [("mail.smtp_host", &self.mail.smtp_host), ("mail.from_address", &self.mail.from_address)] {
+                if value.is_empty() {
+                    return invalid(key, "must not be empty when mail.enabled is set");
+                }
+            }
+            if self.mail.timeout == 0 {
+                return invalid("mail.timeout", "must be greater than 0");
+            }
+        }
+        if self.mail.verification_expiration <= 0 {
+            return invalid("mail.verification_expiration", "must be greater than 0");
+        }
+        if self.mail.reset_expiration <= 0 {
+            return invalid("mail.reset_expiration", "must be greater than 0");
+        }
+        if self.feedback.expiration <= 0 {
+            return invalid("feedback.expiration", "must be greater than 0");
+        }
+        if self.daemon.timer_duration == 0 {
+            return invalid("daemon.timer_duration", "must be greater than 0");
+        }
+        for (task_name, task) in self.daemon.tasks.iter() {
+            if task.interval == Some(0) {
+                return invalid(&format!("daemon.tasks.{task_name}.interval"), "must be greater than 0");
+            }
+            if let Some(expression) = &task.cron {
+                if task.interval.is_some() {
+                    return invalid(&format!("daemon.tasks.{task_name}"), "set either `interval` or `cron`, not both");
+                }
+                if let Err(err) = parse_cron_expression(expression) {
+                    return invalid(&format!("daemon.tasks.{task_name}.cron"), &err.to_string());
+                }
+            }
+            if task.retry_backoff == 0 || task.retry_backoff_max < task.retry_backoff {
+                return invalid(&format!("daemon.tasks.{task_name}.retry_backoff"), "must be greater than 0 and at most retry_backoff_max");
+            }
+        }
+        if !(self.dl_svc.host.starts_with("http://") || self.dl_svc.host.starts_with("https://")) {
+            return invalid("dl_svc.host", "expected an http:// or https:// URL");
+        }
+        for (key, value) in [("dl_svc.python", &self.dl_svc.python), ("dl_svc.infer_script", &self.dl_svc.infer_script),
+                             ("dl_svc.model_prefix", &self.dl_svc.model_prefix), ("dl_svc.target", &self.dl_svc.target)] {
+            if value.trim().is_empty() {
+                return invalid(key, "must not be empty");
+            }
+        }
+        if !["pretty", "json"].contains(&self.log.format.as_str()) {
+            return invalid("log.format", "expected \"pretty\" or \"json\"");
+        }
+        if let Err(err) = tracing_subscriber::EnvFilter::try_new(&self.log.filter) {
+            return invalid("log.filter", &err.to_string());
+        }
+        if !self.log.stdout && self.log.directory.is_empty() {
+            return invalid("log.stdout", "logs need stdout or a directory");
+        }
+        if !self.log.directory.is_empty() && self.log.file_prefix.is_empty() {
+            return invalid("log.file_prefix", "must not be empty");
+        }
+        for (group, rule) in self.rate_limit.groups() {
+            if rule.burst > 0 && rule.ip_per_minute == 0 && rule.identity_per_minute == 0 {
+                return invalid(&format!("rate_limit.{group}.burst"), "needs ip_per_minute or identity_per_minute");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DatabaseConfig {
+    /// Connection parameters in the `key=value` format accepted by both
+    /// `postgres::Client::connect` and `tokio_postgres::Config::from_str`.
+    pub fn connection_params(&self) -> String {
+        format!(
+            "host='{}' port={} user='{}' password='{}' dbname='{}'",
+            escape_param(&self.host), self.port, escape_param(&self.user),
+            escape_param(&self.password), escape_param(&self.dbname)
+        )
+    }
+}
+
+impl DaemonConfig {
+    /// Settings of the named task, the interval falling back to `timer_duration`.
+    pub fn task(&self, task_name: &str) -> TaskConfig {
+        let mut task = self.tasks.get(task_name).cloned().unwrap_or_default();
+        if task.cron.is_none() && task.interval.is_none() {
+            task.interval = Some(self.timer_duration);
+        }
+        task
+    }
+
+    /// Name this instance holds task leases under.
+    pub fn instance_name(&self) -> String {
+        if !self.instance_id.is_empty() {
+            return self.instance_id.clone();
+        }
+        let hostname = std::fs::read_to_string("/etc/hostname")
+            .ok()
+            .map(|hostname| hostname.trim().to_string())
+            .filter(|hostname| !hostname.is_empty())
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .unwrap_or_else(|| "localhost".to_string());
+        format!("{hostname}-{}", std::process::id())
+    }
+}
+
+/// Parse a cron expression. The classic five fields (minute to weekday) are accepted
+/// as well as the six or seven field form starting with seconds.
+pub fn parse_cron_expression(expression: &str) -> Result<cron::Schedule, cron::error::Error> {
+    let expression = expression.trim();
+    if expression.split_whitespace().count() == 5 {
+        return cron::Schedule::from_str(&format!("0 {expression}"));
+    }
+    cron::Schedule::from_str(expression)
+}
+
+impl RateLimitConfig {
+    /// Every route group with its name, as used in the metrics.
+    pub fn groups(&self) -> Vec<(&'static str, &RateLimitRule)> {
+        vec![
+            ("sign_in", &self.sign_in),
+            ("sign_up", &self.sign_up),
+            ("upload", &self.upload),
+            ("infer", &self.infer),
+            ("mail", &self.mail),
+        ]
+    }
+}
+
+impl StorageConfig {
+    /// Every storage location with its key, used for validation and initialization.
+    pub fn entries(&self) -> Vec<(&'static str, &String)> {
+        vec![
+            ("user_pic_path", &self.user_pic_path),
+            ("datasets_directory", &self.datasets_directory),
+            ("tfeedback_stored_directory", &self.tfeedback_stored_directory),
+            ("ufeedback_stored_directory", &self.ufeedback_stored_directory),
+            ("data_to_train_directory", &self.data_to_train_directory),
+            ("queue_stored_path", &self.queue_stored_path),
+            ("datasets_stored_path", &self.datasets_stored_path),
+            ("model_stored_path", &self.model_stored_path),
+            ("model_backup_stored_path", &self.model_backup_stored_path),
+        ]
+    }
+
+    /// Directories which have to exist before the service starts.
+    pub fn directories(&self) -> Vec<&Path> {
+        vec![
+            Path::new(&self.user_pic_path),
+            Path::new(&self.datasets_directory),
+            Path::new(&self.model_stored_path),
+            Path::new(&self.model_backup_stored_path),
+            Path::new(&self.tfeedback_stored_directory),
+            Path::new(&self.ufeedback_stored_directory),
+            Path::new(&self.data_to_train_directory),
+        ]
+    }
+}
+
+/// Install the process wide configuration. Must be called once at startup.
+pub fn init_app_config(config: AppConfig) {
+    if APP_CONFIG.set(config).is_err() {
+        panic!("The configuration has already been initialized!");
+    }
+}
+
+pub fn app_config() -> &'static AppConfig {
+    APP_CONFIG.get().expect("The configuration is not initialized!")
+}
+
+fn escape_param(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+type ParsedArgs = (Option<String>, Vec<(String, String)>, Vec<String>);
+
+fn parse_args(args: &[String]) -> Result<ParsedArgs, ConfigError> {
+    let mut config_path = None;
+    let mut overrides = Vec::new();
+    let mut rest = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--config" {
+            let path = iter.next().ok_or(ConfigError::Args("--config expects a path".to_string()))?;
+            config_path = Some(path.clone());
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config_path = Some(path.to_string());
+        } else if arg == "--set" || arg.starts_with("--set=") {
+            let pair = match arg.strip_prefix("--set=") {
+                Some(pair) => pair.to_string(),
+                None => iter.next().ok_or(ConfigError::Args("--set expects key=value".to_string()))?.clone(),
+            };
+            let (key, value) = pair.split_once('=')
+                .ok_or(ConfigError::Args(format!("--set expects key=value, got `{pair}`")))?;
+            overrides.push((key.trim().to_string(), value.to_string()));
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    Ok((config_path, overrides, rest))
+}
+
+/// Set `section.key` in the table, converting the raw string to the type of the default value.
+fn set_dotted(table: &mut Table, defaults: &Value, dotted_key: &str, raw_value: &str) -> Result<(), ConfigError> {
+    let mut parts: Vec<&str> = dotted_key.split('.').collect();
+    let last = parts.pop().filter(|key| !key.is_empty())
+        .ok_or(ConfigError::Args(format!("bad config key `{dotted_key}`")))?;
+
+    let mut current = table;
+    let mut current_default = Some(defaults);
+    for part in parts {
+        current_default = current_default.and_then(|value| value.get(part));
+        let entry = current.entry(part.to_string()).or_insert_with(|| Value::Table(Table::new()));
+        current = entry.as_table_mut()
+            .ok_or(ConfigError::Args(format!("`{part}` in `{dotted_key}` is not a table")))?;
+    }
+    // Keys without a default (e.g. entries of `daemon.tasks`) are typed by guessing,
+    // unknown ones are rejected when the table is deserialized.
+    let value = match current_default.and_then(|value| value.get(last)) {
+        Some(default_value) => convert_value(default_value, dotted_key, raw_value)?,
+        None => guess_value(raw_value),
+    };
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+fn guess_value(raw_value: &str) -> Value {
+    if let Ok(integer) = raw_value.parse::<i64>() {
+        return Value::Integer(integer);
+    }
+    if let Ok(boolean) = raw_value.parse::<bool>() {
+        return Value::Boolean(boolean);
+    }
+    Value::String(raw_value.to_string())
+}
+
+fn convert_value(default_value: &Value, dotted_key: &str, raw_value: &str) -> Result<Value, ConfigError> {
+    let invalid = || ConfigError::Invalid(dotted_key.to_string(), format!("`{raw_value}` is not a valid {}", default_value.type_str()));
+    let value = match default_value {
+        Value::Integer(_) => Value::Integer(raw_value.trim().parse().map_err(|_| invalid())?),
+        Value::Float(_) => Value::Float(raw_value.trim().parse().map_err(|_| invalid())?),
+        Value::Boolean(_) => Value::Boolean(raw_value.trim().parse().map_err(|_| invalid())?),
+        Value::Array(_) => Value::Array(
+            raw_value.split(',')
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect()
+        ),
+        Value::String(_) => Value::String(raw_value.to_string()),
+        _ => return Err(invalid()),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, path::PathBuf};
+
+    use toml::{Table, Value};
+
+    use super::{guess_value, set_dotted, AppConfig, ConfigError, DaemonConfig, TaskConfig, CONFIG_PATH_ENV};
+
+    /// A configuration file of the test, removed on drop.
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("insectsys-{name}-{}.toml", std::process::id()));
+            fs::write(&path, content).unwrap();
+            ConfigFile(path)
+        }
+
+        fn path(&self) -> String {
+            self.0.display().to_string()
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn env_vars(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    fn invalid_key(result: Result<(AppConfig, Vec<String>), ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid(key, _)) => key,
+            other => panic!("expected an invalid value, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn layers_override_defaults_file_environment_and_flags_in_order() {
+        let file = ConfigFile::new("layers", r#"
+            [server]
+            body_limit = 100
+            [database]
+            host = "file-host"
+            port = 1111
+            dbname = "file-db"
+        "#);
+        let env_vars = env_vars(&[
+            ("INSECTSYS__DATABASE__HOST", "env-host"),
+            ("INSECTSYS__DATABASE__PORT", "2222"),
+            ("UNRELATED", "ignored"),
+        ]);
+        let (config, rest) = AppConfig::load_from(
+            &args(&["migrate", "--config", &file.path(), "--set", "database.port=3333", "--set=auth.jwt_expiration=1800", "up"]),
+            &env_vars
+        ).unwrap();
+        assert_eq!(rest, ["migrate", "up"]);
+        assert_eq!(config.server.body_limit, 100);
+        assert_eq!(config.database.dbname, "file-db");
+        assert_eq!(config.database.host, "env-host");
+        assert_eq!(config.database.port, 3333);
+        assert_eq!(config.auth.jwt_expiration, 1800);
+        assert_eq!(config.database.pool_max_size, AppConfig::default().database.pool_max_size);
+
+        // The file may be named by the environment instead, `--config` wins over it.
+        let env_vars = self::env_vars(&[(CONFIG_PATH_ENV, &file.path())]);
+        let (config, _) = AppConfig::load_from(&[], &env_vars).unwrap();
+        assert_eq!(config.database.host, "file-host");
+        let other_file = ConfigFile::new("layers-other", "[database]\nhost = \"other-host\"\n");
+        let (config, _) = AppConfig::load_from(&args(&["--config", &other_file.path()]), &env_vars).unwrap();
+        assert_eq!(config.database.host, "other-host");
+    }
+
+    #[test]
+    fn raw_values_take_the_type_of_their_default() {
+        let defaults = Value::try_from(AppConfig::default()).unwrap();
+        let mut table = Table::new();
+        set_dotted(&mut table, &defaults, "server.body_limit", " 2048 ").unwrap();
+        set_dotted(&mut table, &defaults, "tls.enabled", "true").unwrap();
+        set_dotted(&mut table, &defaults, "cors.allowed_origins", "https://a.org, ,https://b.org").unwrap();
+        set_dotted(&mut table, &defaults, "database.password", "007").unwrap();
+        // Keys without a default are guessed.
+        set_dotted(&mut table, &defaults, "daemon.tasks.auto_rej_fd.interval", "60").unwrap();
+        set_dotted(&mut table, &defaults, "daemon.tasks.auto_rej_fd.cron", "0 3 * * *").unwrap();
+        let config: AppConfig = Value::Table(table).try_into().unwrap();
+        assert_eq!(config.server.body_limit, 2048);
+        assert!(config.tls.enabled);
+        assert_eq!(config.cors.allowed_origins, ["https://a.org", "https://b.org"]);
+        assert_eq!(config.database.password, "007");
+        let task = &config.daemon.tasks["auto_rej_fd"];
+        assert_eq!((task.interval, task.cron.as_deref()), (Some(60), Some("0 3 * * *")));
+
+        assert_eq!(guess_value("-5"), Value::Integer(-5));
+        assert_eq!(guess_value("false"), Value::Boolean(false));
+        assert_eq!(guess_value("5s"), Value::String("5s".to_string()));
 
-// authenticator.rs
-pub const JWT_EXPIRATION: i64 = 3600 + 300; // 1h + 5min
-pub const JWT_REFRESH_PERIOD: i64 = 600;
+        let mut table = Table::new();
+        assert!(matches!(set_dotted(&mut table, &defaults, "server.body_limit", "4MB"),
+            Err(ConfigError::Invalid(key, _)) if key == "server.body_limit"));
+        assert!(matches!(set_dotted(&mut table, &defaults, "tls.enabled", "yes"), Err(ConfigError::Invalid(..))));
+        assert!(matches!(set_dotted(&mut table, &defaults, "server.", "1"), Err(ConfigError::Args(_))));
+        set_dotted(&mut table, &defaults, "server.bind_address", "0.0.0.0:80").unwrap();
+        assert!(matches!(set_dotted(&mut table, &defaults, "server.bind_address.port", "80"), Err(ConfigError::Args(_))));
+    }
 
-// feedback.rs
-pub const FEEDBACK_EXPIRATION: i64 = 3600 * 24 * 7; // 7 days
+    #[test]
+    fn rejected_values_name_their_key() {
+        let no_env = HashMap::new();
+        let file = ConfigFile::new("rejected", "[server]\nbody_limit = 1\n");
+        let load = |flags: &[&str]| {
+            let mut all_args = args(&["--config", &file.path()]);
+            all_args.extend(args(flags));
+            AppConfig::load_from(&all_args, &no_env)
+        };
+        assert!(load(&[]).is_ok());
+        assert_eq!(invalid_key(load(&["--set", "server.bind_address=nowhere"])), "server.bind_address");
+        assert_eq!(invalid_key(load(&["--set", "server.body_limit=0"])), "server.body_limit");
+        assert_eq!(invalid_key(load(&["--set", "cors.allowed_origins=*,https://a.org"])), "cors.allowed_origins");
+        assert_eq!(invalid_key(load(&["--set", "database.backend=mysql"])), "database.backend");
+        assert_eq!(invalid_key(load(&["--set", "auth.refresh_token_expiration=60"])), "auth.refresh_token_expiration");
+        assert_eq!(invalid_key(load(&["--set", "daemon.tasks.auto_rej_fd.interval=0"])), "daemon.tasks.auto_rej_fd.interval");
+        assert_eq!(invalid_key(load(&["--set", "daemon.tasks.auto_rej_fd.cron=61 * * * *"])), "daemon.tasks.auto_rej_fd.cron");
+        assert_eq!(
+            invalid_key(load(&["--set", "daemon.tasks.auto_rej_fd.cron=0 3 * * *", "--set", "daemon.tasks.auto_rej_fd.interval=60"])),
+            "daemon.tasks.auto_rej_fd"
+        );
 
-// daemon.rs
-pub const TIMER_DURATION: u64 = 3600; // 1h
+        // Values out of the range of their type and unknown keys fail deserialization.
+        assert!(matches!(load(&["--set", "database.port=70000"]), Err(ConfigError::Parse(_))));
+        assert!(matches!(load(&["--set", "server.unknown=1"]), Err(ConfigError::Parse(_))));
+        assert!(matches!(load(&["--set"]), Err(ConfigError::Args(_))));
+        assert!(matches!(load(&["--set", "server.body_limit"]), Err(ConfigError::Args(_))));
+        let missing = AppConfig::load_from(&args(&["--config", "/nonexistent/insectsys.toml"]), &no_env);
+        assert!(matches!(missing, Err(ConfigError::Io(..))));
+        let broken = ConfigFile::new("broken", "[server\n");
+        assert!(matches!(AppConfig::load_from(&args(&["--config", &broken.path()]), &no_env), Err(ConfigError::Parse(_))));
+    }
 
-// main.rs + model_manage.rs
-pub const MODEL_STORED_PATH: &str = "./models/";
-pub const MODEL_BACKUP_STORED_PATH: &str = "./.modbak/";
+    #[test]
+    fn tasks_without_a_schedule_run_every_timer_duration() {
+        let mut daemon_config = DaemonConfig { timer_duration: 120, ..Default::default() };
+        daemon_config.tasks.insert("cron".to_string(), TaskConfig { cron: Some("0 3 * * *".to_string()), ..Default::default() });
+        daemon_config.tasks.insert("retrying".to_string(), TaskConfig { max_retries: 3, ..Default::default() });
 
-// dl_svc.rs
-pub const DL_SVC_HOST: &str = "https://localhost:8182";
+        let missing = daemon_config.task("missing");
+        assert_eq!((missing.interval, missing.max_retries), (Some(120), 0));
+        let retrying = daemon_config.task("retrying");
+        assert_eq!((retrying.interval, retrying.max_retries), (Some(120), 3));
+        let cron = daemon_config.task("cron");
+        assert_eq!((cron.interval, cron.cron.as_deref()), (None, Some("0 3 * * *")));
+    }
+}
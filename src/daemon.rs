@@ -1,94 +1,894 @@
-use crate::config::TIMER_DURATION;
-use std::collections::HashMap;
+use crate::{
+    config::{app_config, parse_cron_expression, TaskConfig},
+    error::AppError,
+    metrics::metrics,
+    repository::{Repositories, TaskLeaseRecord, TaskRunRecord, TaskSettingRecord}
+};
+use chrono::{DateTime, Local, TimeZone};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+};
 use tokio::{
-    runtime::Runtime,
-    time::{interval, Duration, MissedTickBehavior}
+    sync::{watch, Notify},
+    task::JoinHandle,
+    time::{sleep, Duration}
 };
+use tokio_util::sync::CancellationToken;
+use utoipa::ToSchema;
 
 type ResponseType = Result<(), String>;
-type ClosureType = dyn Fn() -> ResponseType;
-pub type RefTimer = Box<Timer<ClosureType>>;
-pub type Daemon = HashMap<String, RefTimer>;
-// type AsyncFn = Box<dyn Fn(&Object<Manager>) -> Pin<Box<dyn Future<Output = ResponseType>>>>;
+pub type TaskFuture = BoxFuture<'static, ResponseType>;
+pub type TaskFn = Arc<dyn Fn(Repositories) -> TaskFuture + Send + Sync>;
+
+/// When a task runs: every `n` seconds, or whenever a cron expression matches (local time).
+#[derive(Clone, Debug)]
+pub enum TaskSchedule {
+    Every(u64),
+    Cron(String, Box<cron::Schedule>),
+}
+
+/// How often and how long to wait before running a failed task again.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff: Duration,
+    pub backoff_max: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunOutcome {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// What the admin API reports about a task.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct TaskStatus {
+    pub task_name: String,
+    pub schedule: String,
+    pub paused: bool,
+    pub running: bool,
+    pub last_run: Option<DateTime<Local>>,
+    pub last_finished: Option<DateTime<Local>>,
+    pub last_outcome: Option<String>,
+    pub last_error: Option<String>,
+    pub next_run: Option<DateTime<Local>>,
+    pub lease_holder: Option<String>,
+    pub lease_acquired_at: Option<DateTime<Local>>,
+}
 
-pub struct Timer<T>
-    where T: Fn()->ResponseType + ?Sized
-{
-    runtime: Runtime,
-    duration: u64,
-    task: Box<T>
+pub struct Timer {
+    schedule: watch::Sender<TaskSchedule>,
+    paused: watch::Sender<bool>,
+    trigger: Arc<Notify>,
+    status: Arc<Mutex<TaskStatus>>,
+    retry_policy: RetryPolicy,
+    task: TaskFn,
+    cancel: CancellationToken,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// Scheduler running every registered task repeatedly on its own schedule, concurrently,
+/// on the runtime it is started from. Clones are handles to the same scheduler.
+#[derive(Clone)]
+pub struct Daemon {
+    repositories: Repositories,
+    instance_id: Arc<String>,
+    timers: Arc<Mutex<HashMap<String, Timer>>>,
+    shutdown: CancellationToken,
+    started: Arc<Mutex<bool>>,
 }
 
 pub trait Cronie {
-    fn new() -> Self;
-    fn append_task(&mut self, task_name: &str, task: Box<ClosureType>) -> ResponseType;
-    fn rm_task(&mut self, task_name: &String) -> ResponseType;
-    fn update_duration(&mut self, task_name: &String, duration: u64) -> ResponseType;
+    fn new(repositories: Repositories) -> Self;
+    fn append_task<F, Fut>(&self, task_name: &str, schedule: TaskSchedule, retry_policy: RetryPolicy, task: F) -> ResponseType
+        where F: Fn(Repositories) -> Fut + Send + Sync + 'static,
+              Fut: Future<Output = ResponseType> + Send + 'static;
+    fn rm_task(&self, task_name: &str) -> ResponseType;
+    fn update_duration(&self, task_name: &str, duration: u64) -> ResponseType;
+    fn update_schedule(&self, task_name: &str, schedule: TaskSchedule) -> ResponseType;
+    fn pause_task(&self, task_name: &str) -> ResponseType;
+    fn resume_task(&self, task_name: &str) -> ResponseType;
+    fn trigger_task(&self, task_name: &str) -> ResponseType;
+    fn list_tasks(&self) -> Vec<TaskStatus>;
     fn start(&self) -> ResponseType;
 }
 
+impl TaskSchedule {
+    pub fn cron(expression: &str) -> Result<TaskSchedule, String> {
+        let schedule = parse_cron_expression(expression).map_err(|err| err.to_string())?;
+        Ok(TaskSchedule::Cron(expression.trim().to_string(), Box::new(schedule)))
+    }
+
+    /// Schedule of a task as configured in `daemon.tasks`.
+    pub fn from_config(task_config: &TaskConfig) -> Result<TaskSchedule, String> {
+        match (&task_config.cron, task_config.interval) {
+            (Some(expression), _) => TaskSchedule::cron(expression),
+            (None, Some(interval)) => Ok(TaskSchedule::Every(interval)),
+            (None, None) => Err("Neither interval nor cron is set!".to_string()),
+        }
+    }
+
+    /// Schedule saved in `TaskSetting` through the admin API, `None` for the configured one.
+    pub fn from_setting(setting: &TaskSettingRecord) -> Result<Option<TaskSchedule>, String> {
+        match (&setting.cron_expression, setting.interval_seconds) {
+            (Some(expression), _) => TaskSchedule::cron(expression).map(Some),
+            (None, Some(interval)) if interval > 0 => Ok(Some(TaskSchedule::Every(interval as u64))),
+            (None, Some(interval)) => Err(format!("Invalid interval {interval}!")),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// The first run of an interval schedule happens right away, like `tokio::time::interval`.
+    /// Later runs are aligned to multiples of the interval since the Unix epoch,
+    /// so every instance schedules the same ticks.
+    pub fn next_run(&self, last_run: Option<DateTime<Local>>) -> Option<DateTime<Local>> {
+        match self {
+            TaskSchedule::Every(seconds) => match last_run {
+                Some(last_run) => {
+                    let period = *seconds as i64 * 1000;
+                    let millis = (last_run.timestamp_millis().div_euclid(period) + 1) * period;
+                    Local.timestamp_millis_opt(millis).single()
+                },
+                None => Some(Local::now()),
+            },
+            TaskSchedule::Cron(_, schedule) => schedule.after(&Local::now()).next(),
+        }
+    }
+
+    /// Identifier of the tick a run scheduled at the given time belongs to, in Unix epoch milliseconds.
+    /// Instances running the same schedule agree on it.
+    pub fn tick(&self, scheduled_at: DateTime<Local>) -> i64 {
+        let period = match self {
+            TaskSchedule::Every(seconds) => *seconds as i64 * 1000,
+            TaskSchedule::Cron(_, _) => 1000,
+        };
+        scheduled_at.timestamp_millis().div_euclid(period) * period
+    }
+}
+
+impl fmt::Display for TaskSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskSchedule::Every(seconds) => write!(f, "every {seconds}s"),
+            TaskSchedule::Cron(expression, _) => write!(f, "cron {expression}"),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_config(task_config: &TaskConfig) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: task_config.max_retries,
+            backoff: Duration::from_secs(task_config.retry_backoff),
+            backoff_max: Duration::from_secs(task_config.retry_backoff_max),
+        }
+    }
+
+    /// Delay before the given retry (starting at 1), doubling up to `backoff_max`.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.backoff_max)
+    }
+}
+
+impl RunOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunOutcome::Succeeded => "succeeded",
+            RunOutcome::Failed => "failed",
+            RunOutcome::Cancelled => "cancelled",
+        }
+    }
+}
+
 impl Cronie for Daemon {
-    fn new() -> Self {
-        HashMap::new()
+    fn new(repositories: Repositories) -> Self {
+        Daemon {
+            repositories,
+            instance_id: Arc::new(app_config().daemon.instance_name()),
+            timers: Arc::new(Mutex::new(HashMap::new())),
+            shutdown: CancellationToken::new(),
+            started: Arc::new(Mutex::new(false)),
+        }
     }
 
-    fn append_task(&mut self, task_name: &str, task: Box<ClosureType>) -> ResponseType {
-        let timer = Box::new(Timer {
-            runtime: Runtime::new().unwrap(),
-            duration: TIMER_DURATION,
-            task
-        });
-        self.insert(task_name.to_string(), timer);
+    fn append_task<F, Fut>(&self, task_name: &str, schedule: TaskSchedule, retry_policy: RetryPolicy, task: F) -> ResponseType
+        where F: Fn(Repositories) -> Fut + Send + Sync + 'static,
+              Fut: Future<Output = ResponseType> + Send + 'static
+    {
+        if let TaskSchedule::Every(0) = schedule {
+            return Err(format!("Task: {task_name} needs a duration greater than 0!"));
+        }
+        // Same lock order as `start`, `started` before `timers`.
+        let started = self.started.lock().unwrap();
+        let mut timers = self.timers.lock().unwrap();
+        if timers.contains_key(task_name) {
+            return Err(format!("Task: {task_name} already exists!"));
+        }
+        let status = TaskStatus {
+            task_name: task_name.to_string(),
+            schedule: schedule.to_string(),
+            paused: false,
+            running: false,
+            last_run: None,
+            last_finished: None,
+            last_outcome: None,
+            last_error: None,
+            next_run: None,
+            lease_holder: None,
+            lease_acquired_at: None,
+        };
+        let (schedule, _) = watch::channel(schedule);
+        let (paused, _) = watch::channel(false);
+        let mut timer = Timer {
+            schedule,
+            paused,
+            trigger: Arc::new(Notify::new()),
+            status: Arc::new(Mutex::new(status)),
+            retry_policy,
+            task: Arc::new(move |repositories| Box::pin(task(repositories))),
+            cancel: self.shutdown.child_token(),
+            handle: None,
+        };
+        if *started {
+            timer.handle = Some(self.spawn_timer(task_name, &timer));
+        }
+        timers.insert(task_name.to_string(), timer);
         Ok(())
     }
 
-    fn rm_task(&mut self, task_name: &String) -> ResponseType {
-        if self.contains_key(task_name) {
-            self.remove(task_name);
-            return Ok(());
-        } else {
-            return Err(format!("Task: {task_name} doesn't exist!"));
+    fn rm_task(&self, task_name: &str) -> ResponseType {
+        let timer = self.timers.lock().unwrap().remove(task_name);
+        match timer {
+            Some(timer) => {
+                timer.cancel.cancel();
+                return Ok(());
+            },
+            None => return Err(format!("Task: {task_name} doesn't exist!"))
         }
     }
 
-    fn update_duration(&mut self, task_name: &String, duration: u64) -> ResponseType {
-        if self.contains_key(task_name) {
-            let timer = self.get_mut(task_name).unwrap();
-            timer.duration = duration;
-            return Ok(());
-        } else {
-            return Err(format!("Task: {task_name} doesn't exist!"));
+    fn update_duration(&self, task_name: &str, duration: u64) -> ResponseType {
+        self.update_schedule(task_name, TaskSchedule::Every(duration))
+    }
+
+    fn update_schedule(&self, task_name: &str, schedule: TaskSchedule) -> ResponseType {
+        if let TaskSchedule::Every(0) = schedule {
+            return Err(format!("Task: {task_name} needs a duration greater than 0!"));
         }
+        self.with_timer(task_name, |timer| {
+            timer.status.lock().unwrap().schedule = schedule.to_string();
+            timer.schedule.send_replace(schedule);
+        })
     }
 
-    // async fn __exec_raw(closure: &F, pool: &Object<Manager>) -> ResponseType {
-    //     closure(pool);
-    //     todo!()
-    // }
+    fn pause_task(&self, task_name: &str) -> ResponseType {
+        self.with_timer(task_name, |timer| {
+            timer.paused.send_replace(true);
+        })
+    }
+
+    fn resume_task(&self, task_name: &str) -> ResponseType {
+        self.with_timer(task_name, |timer| {
+            timer.paused.send_replace(false);
+        })
+    }
+
+    /// Run the task as soon as possible, even if it is paused.
+    /// A trigger during a run makes the task run again right after.
+    fn trigger_task(&self, task_name: &str) -> ResponseType {
+        self.with_timer(task_name, |timer| timer.trigger.notify_one())
+    }
+
+    fn list_tasks(&self) -> Vec<TaskStatus> {
+        let mut tasks: Vec<TaskStatus> = self.timers.lock().unwrap()
+            .values()
+            .map(|timer| timer.status.lock().unwrap().clone())
+            .collect();
+        tasks.sort_by(|left, right| left.task_name.cmp(&right.task_name));
+        tasks
+    }
 
     fn start(&self) -> ResponseType {
-        let timers = self.values();
-        for timer in timers {
-            let rt = &timer.runtime;
-            let duration = timer.duration;
-            let task = &timer.task;
-
-            let _ = rt.block_on(async {
-                // let start = Instant::now();
-                let dur = Duration::from_secs(duration);
-                let mut intv = interval(dur);
-                intv.set_missed_tick_behavior(MissedTickBehavior::Delay);
-
-                intv.tick().await;
-                let status = task();
-
-                if let Err(e) = status {
-                    return Err(e);
-                }
-                Ok(())
-            });
+        let mut started = self.started.lock().unwrap();
+        if *started {
+            return Err("Daemon has already been started!".to_string());
+        }
+        let mut timers = self.timers.lock().unwrap();
+        for (task_name, timer) in timers.iter_mut() {
+            timer.handle = Some(self.spawn_timer(task_name, timer));
         }
+        *started = true;
         Ok(())
     }
 }
 
+impl fmt::Debug for Daemon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Daemon")
+            .field("tasks", &self.timers.lock().unwrap().keys().collect::<Vec<&String>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Daemon {
+    /// Name this instance holds task leases under.
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Another instance over the same repositories, as in a test of several instances.
+    #[cfg(test)]
+    fn with_instance_id(mut self, instance_id: &str) -> Self {
+        self.instance_id = Arc::new(instance_id.to_string());
+        self
+    }
+
+    /// Pause or resume the task on every instance. The setting is saved in `TaskSetting`,
+    /// which each instance reads before leasing a tick.
+    pub async fn share_paused(&self, task_name: &str, paused: bool) -> Result<(), AppError> {
+        match paused {
+            true => self.pause_task(task_name),
+            false => self.resume_task(task_name),
+        }.map_err(AppError::NotFound)?;
+        let mut setting = load_setting(&self.repositories, task_name).await?;
+        setting.paused = paused;
+        self.repositories.tasks.save_setting(&setting).await
+    }
+
+    /// Replace the schedule of the task on every instance, like `share_paused`.
+    pub async fn share_schedule(&self, task_name: &str, schedule: TaskSchedule) -> Result<(), AppError> {
+        let mut setting = load_setting(&self.repositories, task_name).await?;
+        (setting.interval_seconds, setting.cron_expression) = match &schedule {
+            TaskSchedule::Every(seconds) => (Some(*seconds as i64), None),
+            TaskSchedule::Cron(expression, _) => (None, Some(expression.clone())),
+        };
+        self.update_schedule(task_name, schedule).map_err(AppError::NotFound)?;
+        self.repositories.tasks.save_setting(&setting).await
+    }
+
+    /// Stop scheduling and wait for the runs in progress.
+    /// Runs still going after `daemon.shutdown_grace_period` seconds are cancelled.
+    pub async fn stop(&self) {
+        self.shutdown.cancel();
+        let handles: Vec<JoinHandle<()>> = self.timers.lock().unwrap()
+            .values_mut()
+            .filter_map(|timer| timer.handle.take())
+            .collect();
+        for handle in handles {
+            let _ = handle.await;
+        }
+        tracing::info!("Daemon stopped.");
+    }
+
+    fn with_timer<F: FnOnce(&Timer)>(&self, task_name: &str, action: F) -> ResponseType {
+        let timers = self.timers.lock().unwrap();
+        match timers.get(task_name) {
+            Some(timer) => {
+                action(timer);
+                return Ok(());
+            },
+            None => return Err(format!("Task: {task_name} doesn't exist!"))
+        }
+    }
+
+    fn spawn_timer(&self, task_name: &str, timer: &Timer) -> JoinHandle<()> {
+        let task_name = task_name.to_string();
+        let task = timer.task.clone();
+        let status = timer.status.clone();
+        let trigger = timer.trigger.clone();
+        let paused_tx = timer.paused.clone();
+        let mut paused_rx = timer.paused.subscribe();
+        let retry_policy = timer.retry_policy.clone();
+        let repositories = self.repositories.clone();
+        let instance_id = self.instance_id.clone();
+        let cancel = timer.cancel.clone();
+        let schedule_tx = timer.schedule.clone();
+        let mut schedule_rx = timer.schedule.subscribe();
+        let grace_period = Duration::from_secs(app_config().daemon.shutdown_grace_period);
+
+        tokio::spawn(async move {
+            let mut last_run: Option<DateTime<Local>> = None;
+            loop {
+                let paused = *paused_rx.borrow_and_update();
+                // Paused tasks still wake up on their ticks, to see a resume through another instance.
+                let next_run = schedule_rx.borrow_and_update().next_run(last_run);
+                {
+                    let mut status = status.lock().unwrap();
+                    status.paused = paused;
+                    status.next_run = next_run.filter(|_| !paused);
+                }
+                let wait = next_run.map(|next_run| (next_run - Local::now()).to_std().unwrap_or_default());
+                if wait.is_none() && !paused {
+                    tracing::warn!("Task: {task_name} will never run again with its schedule.");
+                }
+                let triggered = tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => break,
+                    // A new interval counts from the previous run.
+                    changed = schedule_rx.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        continue;
+                    },
+                    changed = paused_rx.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        continue;
+                    },
+                    _ = trigger.notified() => true,
+                    _ = sleep(wait.unwrap_or_default()), if wait.is_some() => false,
+                };
+
+                // Scheduled runs only go ahead on the instance leasing their tick,
+                // manual triggers run on the instance receiving them.
+                if triggered {
+                    tracing::info!("Task: {task_name} triggered manually.");
+                } else if let Some(scheduled_at) = next_run {
+                    // A setting saved through any instance wins over the local one.
+                    match repositories.tasks.setting(&task_name).await {
+                        Ok(Some(setting)) => {
+                            paused_tx.send_if_modified(|paused| std::mem::replace(paused, setting.paused) != setting.paused);
+                            let current = schedule_rx.borrow().to_string();
+                            match TaskSchedule::from_setting(&setting) {
+                                Ok(Some(schedule)) if schedule.to_string() != current => {
+                                    tracing::info!("Task: {task_name} scheduled {schedule} through another instance.");
+                                    status.lock().unwrap().schedule = schedule.to_string();
+                                    schedule_tx.send_replace(schedule);
+                                    continue;
+                                },
+                                Ok(_) => {},
+                                Err(err) => tracing::error!("Task: {task_name} keeps its schedule, the saved one is invalid! Error: {err}"),
+                            }
+                        },
+                        Ok(None) => {},
+                        Err(err) => tracing::error!("Task: {task_name} failed to read its saved setting! Error: {err}"),
+                    }
+                    if *paused_rx.borrow() {
+                        tracing::debug!("Task: {task_name} skipped, it is paused.");
+                        last_run = Some(scheduled_at);
+                        continue;
+                    }
+
+                    let tick = schedule_rx.borrow().tick(scheduled_at);
+                    let lease = acquire_lease(&repositories, &task_name, &instance_id, tick).await;
+                    let acquired = match lease {
+                        Ok((acquired, holder, acquired_at)) => {
+                            if !acquired {
+                                tracing::debug!("Task: {task_name} skipped, the tick is leased by {holder}.");
+                            }
+                            let mut status = status.lock().unwrap();
+                            status.lease_holder = Some(holder);
+                            status.lease_acquired_at = Local.timestamp_millis_opt(acquired_at).single();
+                            acquired
+                        },
+                        Err(err) => {
+                            tracing::error!("Task: {task_name} skipped, failed to lease the tick! Error: {err}");
+                            false
+                        }
+                    };
+                    if !acquired {
+                        last_run = Some(scheduled_at);
+                        continue;
+                    }
+                }
+
+                let started_at = Local::now();
+                last_run = Some(match (triggered, next_run) {
+                    (false, Some(scheduled_at)) => scheduled_at,
+                    _ => started_at,
+                });
+                {
+                    let mut status = status.lock().unwrap();
+                    status.running = true;
+                    status.last_run = Some(started_at);
+                    status.next_run = None;
+                }
+                tracing::info!("Task: {task_name} started.");
+                let (outcome, attempts, error) = tokio::select! {
+                    result = run_with_retries(&task_name, &task, &repositories, &retry_policy, &cancel) => result,
+                    _ = async { cancel.cancelled().await; sleep(grace_period).await } => {
+                        (RunOutcome::Cancelled, 0, Some("Cancelled by shutdown.".to_string()))
+                    }
+                };
+                let finished_at = Local::now();
+                {
+                    let mut status = status.lock().unwrap();
+                    status.running = false;
+                    status.last_finished = Some(finished_at);
+                    status.last_outcome = Some(outcome.as_str().to_string());
+                    status.last_error = error.clone();
+                }
+                metrics().daemon_task_runs.with_label_values(&[&task_name, outcome.as_str()]).inc();
+                match outcome {
+                    RunOutcome::Succeeded => tracing::info!("Task: {task_name} finished in {}ms.", (finished_at - started_at).num_milliseconds()),
+                    RunOutcome::Failed => tracing::error!("Task: {task_name} failed after {attempts} attempts! Error: {}", error.as_deref().unwrap_or_default()),
+                    RunOutcome::Cancelled => tracing::warn!("Task: {task_name} was cancelled before finishing."),
+                }
+                if let Err(err) = record_run(&repositories, &task_name, started_at, finished_at, outcome, attempts, error.as_deref()).await {
+                    tracing::error!("Failed to record the run of task {task_name}: {err}");
+                }
+                if outcome == RunOutcome::Cancelled {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+/// Run the task, retrying with backoff on `Err`. Returns the outcome, the number of attempts and the last error.
+async fn run_with_retries(
+    task_name: &str,
+    task: &TaskFn,
+    repositories: &Repositories,
+    retry_policy: &RetryPolicy,
+    cancel: &CancellationToken
+) -> (RunOutcome, i32, Option<String>) {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let err = match task(repositories.clone()).await {
+            Ok(_) => return (RunOutcome::Succeeded, attempts, None),
+            Err(err) => err,
+        };
+        let retry = attempts as u32;
+        if retry > retry_policy.max_retries || cancel.is_cancelled() {
+            return (RunOutcome::Failed, attempts, Some(err));
+        }
+        let delay = retry_policy.delay(retry);
+        tracing::warn!("Task: {task_name} failed, retrying in {delay:?}. Error: {err}");
+        tokio::select! {
+            _ = cancel.cancelled() => return (RunOutcome::Failed, attempts, Some(err)),
+            _ = sleep(delay) => {},
+        }
+    }
+}
+
+/// Lease the tick of a task in the `TaskLease` table unless an instance already holds it or a later one.
+/// Returns whether this instance got it, the holder of the tick and when it was acquired, in Unix epoch milliseconds.
+async fn acquire_lease(repositories: &Repositories, task_name: &str, instance_id: &str, tick: i64) -> Result<(bool, String, i64), String> {
+    let (acquired, lease) = repositories.tasks
+        .acquire_lease(&TaskLeaseRecord {
+            task_name: task_name.to_string(),
+            holder: instance_id.to_string(),
+            tick,
+            acquired_at: Local::now().timestamp_millis(),
+        })
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok((acquired, lease.holder, lease.acquired_at))
+}
+
+/// The saved setting of a task to update, or a new one with the configured schedule.
+async fn load_setting(repositories: &Repositories, task_name: &str) -> Result<TaskSettingRecord, AppError> {
+    let setting = repositories.tasks.setting(task_name).await?;
+    Ok(TaskSettingRecord {
+        updated_at: Local::now().timestamp_millis(),
+        ..setting.unwrap_or(TaskSettingRecord {
+            task_name: task_name.to_string(),
+            paused: false,
+            interval_seconds: None,
+            cron_expression: None,
+            updated_at: 0,
+        })
+    })
+}
+
+/// Store one run in the `TaskRun` table, times are Unix epoch milliseconds.
+async fn record_run(
+    repositories: &Repositories,
+    task_name: &str,
+    started_at: DateTime<Local>,
+    finished_at: DateTime<Local>,
+    outcome: RunOutcome,
+    attempts: i32,
+    error: Option<&str>
+) -> Result<(), String> {
+    repositories.tasks
+        .record_run(&TaskRunRecord {
+            task_name: task_name.to_string(),
+            started_at: started_at.timestamp_millis(),
+            finished_at: finished_at.timestamp_millis(),
+            outcome: outcome.as_str().to_string(),
+            attempts,
+            error: error.map(str::to_string),
+        })
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc
+    };
+
+    use chrono::{Local, TimeZone, Timelike};
+    use tokio::time::{sleep, Duration, Instant};
+
+    use super::{Cronie, Daemon, RetryPolicy, TaskSchedule};
+    use crate::{
+        config::{app_config, parse_cron_expression, TaskConfig},
+        repository::Repositories,
+        tests::init_test_config
+    };
+
+    const NO_RETRIES: RetryPolicy = RetryPolicy { max_retries: 0, backoff: Duration::ZERO, backoff_max: Duration::ZERO };
+
+    fn test_daemon() -> (Daemon, Repositories) {
+        init_test_config();
+        let repositories = Repositories::in_memory();
+        (Daemon::new(repositories.clone()), repositories)
+    }
+
+    /// A task counting its runs.
+    fn counting_task(daemon: &Daemon, task_name: &str, schedule: TaskSchedule) -> Arc<AtomicUsize> {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        daemon.append_task(task_name, schedule, NO_RETRIES, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Ok(()) }
+        }).unwrap();
+        runs
+    }
+
+    /// Let the timers catch up, the paused clock jumps to their deadlines meanwhile.
+    async fn settle() {
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    /// Waits out ticks until the condition holds, timers wait for the wall clock which paused tests do not move.
+    async fn eventually(condition: impl Fn() -> bool) {
+        for _ in 0..2880 {
+            if condition() {
+                return;
+            }
+            sleep(Duration::from_secs(60)).await;
+        }
+        panic!("The condition did not hold within two days.");
+    }
+
+    #[test]
+    fn intervals_are_aligned_to_the_epoch() {
+        let schedule = TaskSchedule::Every(60);
+        let at = |hour, minute, second| Local.with_ymd_and_hms(2026, 10, 18, hour, minute, second).unwrap();
+        assert_eq!(schedule.next_run(Some(at(10, 0, 30))), Some(at(10, 1, 0)));
+        assert_eq!(schedule.next_run(Some(at(10, 1, 0))), Some(at(10, 2, 0)));
+        let first_run = schedule.next_run(None).unwrap();
+        assert!((Local::now() - first_run).num_seconds().abs() < 5);
+
+        let scheduled_at = at(10, 1, 0) + chrono::Duration::milliseconds(500);
+        assert_eq!(schedule.tick(scheduled_at), at(10, 1, 0).timestamp_millis());
+        assert_eq!(TaskSchedule::Every(3600).tick(scheduled_at), at(10, 0, 0).timestamp_millis());
+        assert_eq!(schedule.to_string(), "every 60s");
+    }
+
+    #[test]
+    fn cron_expressions_take_an_optional_seconds_field() {
+        let at = |hour, minute, second| Local.with_ymd_and_hms(2026, 10, 18, hour, minute, second).unwrap();
+        // Five fields run at second 0, six or seven fields (with the year) are taken as they are.
+        let five_fields = parse_cron_expression(" */15 * * * * ").unwrap();
+        let six_fields = parse_cron_expression("0 */15 * * * *").unwrap();
+        let upcoming: Vec<_> = five_fields.after(&at(10, 7, 30)).take(3).collect();
+        assert_eq!(upcoming, [at(10, 15, 0), at(10, 30, 0), at(10, 45, 0)]);
+        assert_eq!(six_fields.after(&at(10, 7, 30)).take(3).collect::<Vec<_>>(), upcoming);
+        let seconds = parse_cron_expression("*/20 * * * * *").unwrap();
+        assert_eq!(seconds.after(&at(10, 0, 5)).next(), Some(at(10, 0, 20)));
+        let daily = parse_cron_expression("0 3 * * *").unwrap();
+        assert_eq!(daily.after(&at(10, 0, 0)).next(), Some(at(3, 0, 0) + chrono::Duration::days(1)));
+        assert_eq!(parse_cron_expression("0 0 3 * * * 2020").unwrap().after(&at(10, 0, 0)).next(), None);
+
+        for invalid in ["", "* * * *", "61 * * * *", "0 3 * * * * * *", "every minute"] {
+            assert!(parse_cron_expression(invalid).is_err(), "{invalid:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn cron_schedules_run_at_their_next_match() {
+        let schedule = TaskSchedule::cron("*/15 * * * *").unwrap();
+        assert_eq!(schedule.to_string(), "cron */15 * * * *");
+        let next_run = schedule.next_run(None).unwrap();
+        assert_eq!((next_run.minute() % 15, next_run.second()), (0, 0));
+        assert!(next_run > Local::now() && next_run - Local::now() <= chrono::Duration::minutes(15));
+        assert_eq!(schedule.tick(next_run + chrono::Duration::milliseconds(250)), next_run.timestamp_millis());
+        assert!(TaskSchedule::cron("*/15 * *").is_err());
+
+        // Cron takes precedence over an interval, and one of them is needed.
+        let task_config = TaskConfig { cron: Some("0 3 * * *".to_string()), interval: Some(60), ..Default::default() };
+        assert!(matches!(TaskSchedule::from_config(&task_config), Ok(TaskSchedule::Cron(..))));
+        let task_config = TaskConfig { interval: Some(60), ..Default::default() };
+        assert!(matches!(TaskSchedule::from_config(&task_config), Ok(TaskSchedule::Every(60))));
+        assert!(TaskSchedule::from_config(&TaskConfig::default()).is_err());
+    }
+
+    #[test]
+    fn retries_back_off_up_to_the_limit() {
+        let retry_policy = RetryPolicy {
+            max_retries: 10,
+            backoff: Duration::from_secs(10),
+            backoff_max: Duration::from_secs(60),
+        };
+        let delays: Vec<u64> = (1..=5).map(|retry| retry_policy.delay(retry).as_secs()).collect();
+        assert_eq!(delays, [10, 20, 40, 60, 60]);
+        assert_eq!(retry_policy.delay(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_runs_are_retried_with_backoff() {
+        let (daemon, repositories) = test_daemon();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let retry_policy = RetryPolicy { max_retries: 3, backoff: Duration::from_secs(10), backoff_max: Duration::from_secs(60) };
+        daemon.append_task("flaky", TaskSchedule::Every(3600), retry_policy, move |_| {
+            let attempt = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                match attempt {
+                    1 | 2 => Err(format!("attempt {attempt} failed")),
+                    _ => Ok(()),
+                }
+            }
+        }).unwrap();
+        let started = Instant::now();
+        daemon.start().unwrap();
+        sleep(Duration::from_secs(31)).await;
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        let last_runs = repositories.tasks.last_runs().await.unwrap();
+        assert_eq!((last_runs[0].outcome.as_str(), last_runs[0].attempts), ("succeeded", 3));
+        assert!(started.elapsed() >= Duration::from_secs(30));
+        let status = &daemon.list_tasks()[0];
+        assert_eq!((status.last_outcome.as_deref(), status.last_error.as_deref()), (Some("succeeded"), None));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paused_tasks_run_when_triggered_only() {
+        let (daemon, _) = test_daemon();
+        let runs = counting_task(&daemon, "count", TaskSchedule::Every(3600));
+        daemon.start().unwrap();
+        settle().await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        daemon.pause_task("count").unwrap();
+        settle().await;
+        let status = &daemon.list_tasks()[0];
+        assert!(status.paused && status.next_run.is_none());
+        sleep(Duration::from_secs(7200)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        daemon.trigger_task("count").unwrap();
+        settle().await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        daemon.resume_task("count").unwrap();
+        settle().await;
+        assert!(daemon.list_tasks()[0].next_run.is_some());
+        sleep(Duration::from_secs(3600)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert!(daemon.trigger_task("missing").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn interval_updates_reschedule_waiting_tasks() {
+        let (daemon, _) = test_daemon();
+        let runs = counting_task(&daemon, "count", TaskSchedule::Every(86400));
+        daemon.start().unwrap();
+        settle().await;
+        let daily_run = daemon.list_tasks()[0].next_run.unwrap();
+
+        daemon.update_duration("count", 60).unwrap();
+        settle().await;
+        let status = &daemon.list_tasks()[0];
+        assert_eq!(status.schedule, "every 60s");
+        let next_run = status.next_run.unwrap();
+        assert!(next_run <= daily_run && next_run - Local::now() <= chrono::Duration::seconds(60));
+        sleep(Duration::from_secs(60)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        assert!(daemon.update_duration("count", 0).is_err());
+        assert!(daemon.update_duration("missing", 60).is_err());
+        assert!(daemon.append_task("count", TaskSchedule::Every(60), NO_RETRIES, |_| async { Ok(()) }).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn every_tick_runs_on_one_instance_only() {
+        let (daemon, repositories) = test_daemon();
+        let first = daemon.with_instance_id("first");
+        let second = Daemon::new(repositories.clone()).with_instance_id("second");
+        let first_runs = counting_task(&first, "count", TaskSchedule::Every(3600));
+        let second_runs = counting_task(&second, "count", TaskSchedule::Every(3600));
+        let runs = || first_runs.load(Ordering::SeqCst) + second_runs.load(Ordering::SeqCst);
+        first.start().unwrap();
+        settle().await;
+        second.start().unwrap();
+        settle().await;
+        assert_eq!((first_runs.load(Ordering::SeqCst), second_runs.load(Ordering::SeqCst)), (1, 0));
+        // The instance refused the tick shows who holds it.
+        assert_eq!(second.list_tasks()[0].lease_holder.as_deref(), Some("first"));
+        assert!(second.list_tasks()[0].last_run.is_none());
+
+        // Both wait for the next tick, one of them runs it.
+        sleep(Duration::from_secs(3600)).await;
+        assert_eq!(runs(), 2);
+        let leases = repositories.tasks.leases().await.unwrap();
+        let holder = leases[0].holder.as_str();
+        assert_eq!(first.list_tasks()[0].lease_holder.as_deref(), Some(holder));
+        assert_eq!(second.list_tasks()[0].lease_holder.as_deref(), Some(holder));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pauses_and_schedules_saved_through_one_instance_reach_the_others() {
+        let (daemon, repositories) = test_daemon();
+        let first = daemon.with_instance_id("first");
+        let second = Daemon::new(repositories.clone()).with_instance_id("second");
+        let first_runs = counting_task(&first, "count", TaskSchedule::Every(3600));
+        let second_runs = counting_task(&second, "count", TaskSchedule::Every(3600));
+        let runs = || first_runs.load(Ordering::SeqCst) + second_runs.load(Ordering::SeqCst);
+        first.start().unwrap();
+        second.start().unwrap();
+        settle().await;
+        assert_eq!(runs(), 1);
+
+        // The second instance reads the pause before leasing its next tick and skips it.
+        first.share_paused("count", true).await.unwrap();
+        eventually(|| second.list_tasks()[0].paused).await;
+        sleep(Duration::from_secs(7200)).await;
+        assert_eq!(runs(), 1);
+
+        // The first one picks up a schedule saved through the second at its next tick.
+        second.share_schedule("count", TaskSchedule::Every(60)).await.unwrap();
+        eventually(|| first.list_tasks()[0].schedule == "every 60s").await;
+        assert_eq!(runs(), 1);
+
+        first.share_paused("count", false).await.unwrap();
+        eventually(|| !second.list_tasks()[0].paused && runs() > 1).await;
+        let setting = repositories.tasks.setting("count").await.unwrap().unwrap();
+        assert_eq!((setting.paused, setting.interval_seconds, setting.cron_expression), (false, Some(60), None));
+        assert!(first.share_paused("missing", true).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tasks_added_or_removed_while_running_take_effect() {
+        let (daemon, _) = test_daemon();
+        daemon.start().unwrap();
+        let runs = counting_task(&daemon, "count", TaskSchedule::Every(60));
+        settle().await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        daemon.rm_task("count").unwrap();
+        sleep(Duration::from_secs(120)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(daemon.rm_task("count").is_err());
+        assert!(daemon.start().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_cancels_runs_after_the_grace_period() {
+        let (daemon, repositories) = test_daemon();
+        daemon.append_task("stuck", TaskSchedule::Every(3600), NO_RETRIES, |_| async {
+            std::future::pending::<()>().await;
+            Ok(())
+        }).unwrap();
+        daemon.start().unwrap();
+        settle().await;
+        assert!(daemon.list_tasks()[0].running);
+
+        let stopping = Instant::now();
+        daemon.stop().await;
+        assert!(stopping.elapsed() >= Duration::from_secs(app_config().daemon.shutdown_grace_period));
+        let last_runs = repositories.tasks.last_runs().await.unwrap();
+        assert_eq!(last_runs[0].outcome, "cancelled");
+        let status = &daemon.list_tasks()[0];
+        assert!(!status.running);
+        assert_eq!(status.last_outcome.as_deref(), Some("cancelled"));
+    }
+}
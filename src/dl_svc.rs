@@ -1,25 +1,29 @@
 use std::path::PathBuf;
 
-use axum::{extract::{Path, State}, http::StatusCode, Form};
+use axum::{extract::{Path, State}, Form};
+use chrono::Local;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use tokio_pg_mapper_derive::PostgresMapper;
 use std::process::Command;
 
 use crate::{
-    authenticator::{check_permission, Permission},
-    config::DL_SVC_HOST,
-    io_agent::_obtain_dir,
+    authenticator::{AuthUser, Permission},
+    config::app_config,
+    error::{parse_json_field, AppError, ErrorResponses},
+    io_agent::{_obtain_dir, _path_is_valid},
+    metrics::metrics,
+    repository::{InferenceRecord, Repositories},
     species_vector::SPECIES_VECTOR,
     MultiState
 };
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct RequestInfer {
-    useremail: String,
     file_list: String // JSON Serialized Vec<String>
 }
 
-#[derive(Serialize, Deserialize, PostgresMapper)]
+#[derive(Serialize, Deserialize, PostgresMapper, ToSchema)]
 #[pg_mapper (table = "Wiki")]
 pub struct ResponseInferResultUnit {
     file_name: String,
@@ -27,60 +31,180 @@ pub struct ResponseInferResultUnit {
     content: String
 }
 
-type ResponseInferResult = Vec<ResponseInferResultUnit>;
+pub type ResponseInferResult = Vec<ResponseInferResultUnit>;
 
+/// Records returned when no limit is given, and the most which may be asked for.
+const DEFAULT_HISTORY_LIMIT: i64 = 20;
+const MAX_HISTORY_LIMIT: i64 = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RequestInferenceHistory {
+    /// From 1 to 100, 20 by default.
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResponseInferenceRecord {
+    file_name: String,
+    label: i32,
+    specie_name: String,
+    /// Unix epoch milliseconds.
+    inferred_at: i64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/infer",
+    tag = "user",
+    request_body(content = RequestInfer, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Species on every picture, serialized as JSON text", body = Vec<ResponseInferResultUnit>, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_infer(
     State(multi_state): State<MultiState>,
+    user: AuthUser,
     Form(user_inference): Form<RequestInfer>
-) -> Result<String, (StatusCode, String)> {
-    if !check_permission(&multi_state.db_pool, &user_inference.useremail, Permission::Common).await.unwrap() {
-        return Err(
-            (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-        );
-    }
+) -> Result<String, AppError> {
+    let files_vec: Vec<String> = parse_json_field("file_list", &user_inference.file_list)?;
+    let result_res = _infer(&multi_state.repositories, &user.email, files_vec).await?;
 
-    let files_vec: Vec<String> = serde_json::from_str(&user_inference.file_list).unwrap();
-    tracing::warn!("files_vec: {files_vec:#?}");
+    let json_string = serde_json::to_string(&result_res)
+        .map_err(|err| AppError::Internal(err.to_string()))?;
+    return Ok(json_string);
+    // Example Response
+    // let response : ResponseInferResult = vec![
+    //     ResponseInferResultUnit { file_name: "./39181.jpg".to_string(), specie_name: "odontothrips loti".to_string(), content: "whatever".to_string() },
+    //     ResponseInferResultUnit { file_name: "./58237.jpg".to_string(), specie_name: "Erythroneura apicalis".to_string(), content: "whatever you say".to_string() },
+    //     ResponseInferResultUnit { file_name: "./66871.jpg".to_string(), specie_name: "Dasineura sp".to_string(), content: "whatever I shout".to_string() },
+    // ];
+}
+
+/// Identify the species on pictures the user uploaded.
+pub async fn _infer(repositories: &Repositories, useremail: &str, files_vec: Vec<String>) -> Result<ResponseInferResult, AppError> {
+    if let Some(file_name) = files_vec.iter().find(|file_name| !_path_is_valid(file_name)) {
+        return Err(AppError::BadRequest(format!("Invalid file name: {file_name:?}")));
+    }
+    tracing::debug!(files = ?files_vec, "inferring pictures");
 
     let mut result_res: ResponseInferResult = Vec::new();
 
-    let infer_path = PathBuf::from(_obtain_dir(&user_inference.useremail).unwrap());
+    let infer_path = PathBuf::from(_obtain_dir(useremail)?);
     for file_name in files_vec {
         let image_path = infer_path.join(&file_name);
-        let cmd_output = Command::new("python")
-        .arg("./dl_svc/TransferProcedures/infer_by_tvm.py")
-        .arg("optimized").arg("llvm")
-        .arg(image_path.as_os_str().to_str().unwrap())
-        .output().expect("failed to execute process");
-        tracing::warn!("Command result: {:#?}", cmd_output);
-        let label = String::from_utf8_lossy(&cmd_output.stdout).to_string().parse::<usize>().unwrap();
-        let (_, (specie_name, content)) = SPECIES_VECTOR[label];
+        if !image_path.is_file() {
+            return Err(AppError::NotFound(format!("Couldn't find the picture: {file_name:?}")));
+        }
+        let label = __infer_image(&image_path)?;
+        let (specie_name, content) = match repositories.wiki.find(label as i32).await? {
+            Some(entry) => (entry.specie_name, entry.content),
+            // The Wiki table is only filled by `init seed-species`.
+            None => {
+                let (_, (specie_name, content)) = SPECIES_VECTOR[label];
+                (specie_name.to_string(), content.to_string())
+            }
+        };
+        metrics().species_predictions.with_label_values(&[&specie_name]).inc();
+        let record = InferenceRecord {
+            user_email: useremail.to_string(),
+            file_name: file_name.clone(),
+            label: label as i32,
+            specie_name: specie_name.clone(),
+            inferred_at: Local::now().timestamp_millis(),
+        };
+        if let Err(err) = repositories.inferences.record(&record).await {
+            tracing::warn!("Failed to record the inference of {file_name}: {err}");
+        }
         result_res.push(ResponseInferResultUnit {
             file_name,
-            specie_name: specie_name.to_string(),
-            content: content.to_string()
+            specie_name,
+            content
         });
     }
+    Ok(result_res)
+}
+
+/// The latest inferences of the user, newest first. Only the user and user administrators may see them.
+pub async fn _fetch_inference_history(repositories: &Repositories, user: &AuthUser, useremail: &str, limit: Option<i64>) -> Result<Vec<ResponseInferenceRecord>, AppError> {
+    user.require_self_or(repositories, useremail, Permission::MngUsr).await?;
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    if !(1..=MAX_HISTORY_LIMIT).contains(&limit) {
+        return Err(AppError::BadRequest(format!("The limit should be from 1 to {MAX_HISTORY_LIMIT}!")));
+    }
+    let records = repositories.inferences
+        .list_by_user(useremail, limit)
+        .await?
+        .into_iter()
+        .map(|record| ResponseInferenceRecord {
+            file_name: record.file_name,
+            label: record.label,
+            specie_name: record.specie_name,
+            inferred_at: record.inferred_at,
+        })
+        .collect();
+    Ok(records)
+}
 
-    let json_string = serde_json::to_string(&result_res).unwrap();
-    return Ok(json_string);
-    // Example Response
-    // let response : ResponseInferResult = vec![
-    //     ResponseInferResultUnit { file_name: "./39181.jpg".to_string(), specie_name: "odontothrips loti".to_string(), content: "whatever".to_string() },
-    //     ResponseInferResultUnit { file_name: "./58237.jpg".to_string(), specie_name: "Erythroneura apicalis".to_string(), content: "whatever you say".to_string() },
-    //     ResponseInferResultUnit { file_name: "./66871.jpg".to_string(), specie_name: "Dasineura sp".to_string(), content: "whatever I shout".to_string() },
-    // ];
+/// Run the inference entrypoint on one image and return the predicted label, recording its duration or failure.
+fn __infer_image(image_path: &std::path::Path) -> Result<usize, AppError> {
+    let timer = metrics().inference_duration.start_timer();
+    match __run_inference(image_path) {
+        Ok(label) => {
+            timer.observe_duration();
+            Ok(label)
+        },
+        Err((reason, message)) => {
+            timer.stop_and_discard();
+            metrics().inference_failures.with_label_values(&[reason]).inc();
+            tracing::error!("Inference of {} failed! {message}", image_path.display());
+            Err(AppError::Internal(format!("Inference of {} failed!", image_path.display())))
+        }
+    }
+}
+
+/// Errors carry the failure reason reported in the metrics.
+fn __run_inference(image_path: &std::path::Path) -> Result<usize, (&'static str, String)> {
+    let dl_svc_config = &app_config().dl_svc;
+    let cmd_output = Command::new(&dl_svc_config.python)
+        .arg(&dl_svc_config.infer_script)
+        .arg(&dl_svc_config.model_prefix).arg(&dl_svc_config.target)
+        .arg(image_path.as_os_str())
+        .output()
+        .map_err(|err| ("spawn", err.to_string()))?;
+    tracing::debug!(status = %cmd_output.status, stdout = %String::from_utf8_lossy(&cmd_output.stdout).trim(), "inference command finished");
+    if !cmd_output.status.success() {
+        return Err(("exit_status", String::from_utf8_lossy(&cmd_output.stderr).to_string()));
+    }
+    let label = String::from_utf8_lossy(&cmd_output.stdout).trim().parse::<usize>()
+        .map_err(|err| ("bad_output", err.to_string()))?;
+    if label >= SPECIES_VECTOR.len() {
+        return Err(("unknown_label", format!("Label {label} is out of range.")));
+    }
+    Ok(label)
 }
 
+#[utoipa::path(
+    post,
+    path = "/admin/authenticate_ssh/{useremail}",
+    tag = "model",
+    params(("useremail" = String, Path, description = "Email of the account")),
+    responses(
+        (status = 200, description = "Address of the SSH host of the model server", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_authenticate_ssh(
-    State(multi_state): State<MultiState>,
+    user: AuthUser,
     Path(useremail): Path<String>
-) -> Result<String, (StatusCode, String)> {
-    if !check_permission(&multi_state.db_pool, &useremail, Permission::MngModel).await.unwrap() {
-        return Err(
-            (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-        );
-    }
-    let ssh_addr = String::from(DL_SVC_HOST);
-    return Ok(ssh_addr);
+) -> Result<String, AppError> {
+    user.require_self(&useremail)?;
+    Ok(_ssh_host())
+}
+
+pub fn _ssh_host() -> String {
+    app_config().dl_svc.host.clone()
 }
\ No newline at end of file
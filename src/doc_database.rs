@@ -1,11 +1,7 @@
 use data_encoding::HEXUPPER;
 use serde::{Serialize, Deserialize};
-use std::{fs::{self, File}, io::{BufRead, BufReader, Write}, path::PathBuf};
-use crate::config::{
-    QUEUE_MAX_LENGTH,
-    QUEUE_STORED_PATH,
-    DATASETS_STORED_PATH,
-};
+use std::{fs::{self, File}, io::{self, Write}, path::PathBuf};
+use crate::config::{app_config, QUEUE_MAX_LENGTH};
 
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -33,10 +29,7 @@ impl DatasetTrait for DatasetVec {
     }
 
     fn save(&self) -> Result<usize, std::io::Error> {
-        let json_encoded = HEXUPPER.encode(serde_json::to_string(self).unwrap().as_bytes());
-
-        let mut file = File::create(DATASETS_STORED_PATH).unwrap();
-        file.write(json_encoded.as_bytes())
+        __save_encoded(self, &app_config().storage.datasets_stored_path)
     }
 
     fn append_dset(&mut self, dataset: Dataset) -> Result<(), String> {
@@ -45,25 +38,8 @@ impl DatasetTrait for DatasetVec {
     }
 
     fn load() -> DatasetVec {
-        let file = File::open(DATASETS_STORED_PATH).unwrap();
-        let buffered = BufReader::new(file);
-
-        let mut data_loaded = String::new();
-        for data_stream in buffered.lines() {
-            let line = data_stream.map_err(|err| err.to_string()).unwrap();
-            data_loaded.push_str(line.as_str());
-        }
-
-        let data_vec_decoded: Vec<u8> = match HEXUPPER.decode(data_loaded.as_bytes()) {
-            Ok(data_vec) => data_vec,
-            Err(_) => vec![]
-        };
-        let data_string =
-            String::from_utf8(data_vec_decoded).unwrap();
-        let dataset_vec = match serde_json::from_str(&data_string.as_str()) {
-            Ok(data_json) => data_json,
-            Err(_) => DatasetVec::new()
-        };
+        let data_string = __load_decoded(&app_config().storage.datasets_stored_path);
+        let dataset_vec: DatasetVec = serde_json::from_str(data_string.as_str()).unwrap_or_default();
         dataset_vec
     }
 
@@ -92,7 +68,7 @@ impl DatasetTrait for DatasetVec {
             None => return Err(format!("Dataset: {} does not exist!", dataset_name)),
             Some(index) => {
                 let _ = self.remove(index);
-                let path_buf = PathBuf::from(DATASETS_STORED_PATH).join(dataset_name);
+                let path_buf = PathBuf::from(&app_config().storage.datasets_stored_path).join(dataset_name);
                 let result = fs::remove_file(path_buf);
                 match result {
                     Ok(_) => return Ok(()),
@@ -172,30 +148,13 @@ impl QueueTrait for Queue {
     }
 
     fn save(&self)  -> Result<usize, std::io::Error> {
-        let json_encoded = HEXUPPER.encode(serde_json::to_string(self).unwrap().as_bytes());
-
-        let mut file = File::create(QUEUE_STORED_PATH).unwrap();
-        file.write(json_encoded.as_bytes())
+        __save_encoded(self, &app_config().storage.queue_stored_path)
     }
 
     fn load() -> Queue {
-        let file = File::open(QUEUE_STORED_PATH).unwrap();
-        let buffered = BufReader::new(file);
-
-        let mut data_loaded = String::new();
-        for data_stream in buffered.lines() {
-            let line = data_stream.map_err(|err| err.to_string()).unwrap();
-            data_loaded.push_str(line.as_str());
-        }
-
-        let data_vec_decoded: Vec<u8> = match HEXUPPER.decode(data_loaded.as_bytes()) {
-            Ok(data_vec) => data_vec,
-            Err(_) => vec![]
-        };
-        let data_string =
-            String::from_utf8(data_vec_decoded).unwrap();
+        let data_string = __load_decoded(&app_config().storage.queue_stored_path);
 
-        let queue = match serde_json::from_str(&data_string.as_str()) {
+        let queue = match serde_json::from_str(data_string.as_str()) {
             Ok(data_json) => data_json,
             Err(_) => Queue::init_queue()
         };
@@ -203,3 +162,54 @@ impl QueueTrait for Queue {
     }
 }
 
+/// Write `value` as hex-encoded JSON into a temporary file, then rename it over `path`,
+/// so a failed write never leaves a truncated store behind.
+fn __save_encoded<T: Serialize>(value: &T, path: &str) -> Result<usize, io::Error> {
+    let json_encoded = HEXUPPER.encode(serde_json::to_string(value)?.as_bytes());
+
+    let temp_path = format!("{path}.tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(json_encoded.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    Ok(json_encoded.len())
+}
+
+/// The JSON stored at `path`, empty when the file doesn't exist yet.
+fn __load_decoded(path: &str) -> String {
+    let data_loaded = match fs::read_to_string(path) {
+        Ok(data_loaded) => data_loaded,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return String::new(),
+        Err(err) => panic!("Failed to read {path}: {err}"),
+    };
+    let data_loaded: String = data_loaded.lines().collect();
+
+    let data_vec_decoded: Vec<u8> = HEXUPPER.decode(data_loaded.as_bytes()).unwrap_or_default();
+    String::from_utf8(data_vec_decoded).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{__load_decoded, __save_encoded, Dataset, DatasetVec};
+
+    #[test]
+    fn stores_are_replaced_whole_and_missing_ones_are_empty() {
+        let directory = std::env::temp_dir().join(format!("insectsys-doc-database-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("datasets").display().to_string();
+        assert_eq!(__load_decoded(&path), "");
+
+        let datasets: DatasetVec = vec![Dataset { name: "moths".to_string(), timestamp: 1, available: true }];
+        let written = __save_encoded(&datasets, &path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), written as u64);
+        assert!(!directory.join("datasets.tmp").exists());
+        let loaded: DatasetVec = serde_json::from_str(&__load_decoded(&path)).unwrap();
+        assert_eq!(loaded[0].name, "moths");
+
+        // A store that can't be written is an error for the caller to report.
+        assert!(__save_encoded(&datasets, &directory.join("missing/datasets").display().to_string()).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,324 @@
+use std::fmt;
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json
+};
+use serde::{de::DeserializeOwned, Serialize};
+use utoipa::{IntoResponses, ToSchema};
+
+use crate::logging::current_request_id;
+
+/// Error of every handler, answered as `{"code", "message", "request_id"}`,
+/// plus `fields` for validation failures.
+///
+/// The detail of server side errors (database, io, internal) is logged, not sent to the client.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Validation(Vec<FieldError>),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    TooManyRequests(String, u64), // (detail, seconds until a retry may succeed)
+    Unavailable(String),
+    Database(String),
+    Io(String),
+    Internal(String),
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+/// Why one field of a request was rejected, the field is a path such as `file_list[2]`.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Error statuses a route may answer with, all carrying an `ErrorBody`. Only used by the OpenAPI document.
+#[derive(IntoResponses)]
+#[allow(dead_code)]
+pub enum ErrorResponses {
+    #[response(status = 400, description = "The request is malformed")]
+    BadRequest(ErrorBody),
+    #[response(status = 401, description = "Missing or invalid credentials")]
+    Unauthorized(ErrorBody),
+    #[response(status = 403, description = "The account lacks the permission")]
+    Forbidden(ErrorBody),
+    #[response(status = 404, description = "An account, file or task doesn't exist")]
+    NotFound(ErrorBody),
+    #[response(status = 409, description = "The resource already exists")]
+    Conflict(ErrorBody),
+    #[response(status = 422, description = "Some fields are invalid, see `fields`")]
+    Validation(ErrorBody),
+    #[response(
+        status = 429,
+        description = "The rate limit of the route is exceeded",
+        headers(("Retry-After" = u64, description = "Seconds to wait before retrying"))
+    )]
+    TooManyRequests(ErrorBody),
+    #[response(status = 500, description = "Database, file system or inference failure")]
+    Internal(ErrorBody),
+    #[response(status = 503, description = "No database connection is available, or mail is disabled")]
+    Unavailable(ErrorBody),
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        FieldError { field: field.into(), message: message.into() }
+    }
+}
+
+impl AppError {
+    pub fn forbidden() -> Self {
+        AppError::Forbidden("Not permitted!".to_string())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Database(_) | AppError::Io(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Machine readable code of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Validation(_) => "validation_failed",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::TooManyRequests(..) => "too_many_requests",
+            AppError::Unavailable(_) => "service_unavailable",
+            AppError::Database(_) => "database_error",
+            AppError::Io(_) => "io_error",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Message sent to the client.
+    pub fn message(&self) -> String {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message) => message.clone(),
+            AppError::Validation(_) => "Some fields of the request are invalid.".to_string(),
+            AppError::TooManyRequests(_, retry_after) => format!("Too many requests, please retry in {retry_after} seconds."),
+            AppError::Unavailable(_) => "The service is temporarily unavailable, please retry later.".to_string(),
+            AppError::Database(_) | AppError::Io(_) | AppError::Internal(_) => "Internal server error.".to_string(),
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            AppError::Validation(fields) => fields.iter()
+                .map(|field| format!("{}: {}", field.field, field.message))
+                .collect::<Vec<String>>()
+                .join("; "),
+            AppError::BadRequest(detail)
+            | AppError::Unauthorized(detail)
+            | AppError::Forbidden(detail)
+            | AppError::NotFound(detail)
+            | AppError::Conflict(detail)
+            | AppError::TooManyRequests(detail, _)
+            | AppError::Unavailable(detail)
+            | AppError::Database(detail)
+            | AppError::Io(detail)
+            | AppError::Internal(detail) => detail.clone(),
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.detail())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        match status.is_server_error() {
+            true => tracing::error!(code = self.code(), "{}", self.detail()),
+            false => tracing::info!(code = self.code(), "{}", self.detail()),
+        }
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            request_id: current_request_id(),
+            fields: match self {
+                AppError::Validation(ref fields) => fields.clone(),
+                _ => Vec::new(),
+            },
+        };
+        let mut response = (status, Json(body)).into_response();
+        if let AppError::TooManyRequests(_, retry_after) = self {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for AppError {
+    fn from(err: deadpool_postgres::PoolError) -> Self {
+        AppError::Unavailable(format!("No database connection: {err}"))
+    }
+}
+
+impl From<tokio_postgres::Error> for AppError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        AppError::Database(err.to_string())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for AppError {
+    fn from(err: rusqlite::Error) -> Self {
+        AppError::Database(err.to_string())
+    }
+}
+
+impl From<tokio_pg_mapper::Error> for AppError {
+    fn from(err: tokio_pg_mapper::Error) -> Self {
+        AppError::Database(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        AppError::Io(err.to_string())
+    }
+}
+
+/// Parse a form field holding serialized JSON, a failure is reported on that field.
+pub fn parse_json_field<T: DeserializeOwned>(field: &str, value: &str) -> Result<T, AppError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(value);
+    serde_path_to_error::deserialize(deserializer)
+        .map_err(|err| AppError::Validation(vec![json_field_error(field, &err)]))
+}
+
+/// The field of a deserialization error: the path it occurred at below `root`,
+/// completed with the name of a missing field.
+pub fn json_field_error(root: &str, err: &serde_path_to_error::Error<serde_json::Error>) -> FieldError {
+    let inner = err.inner().to_string();
+    let mut path = match err.path().to_string().as_str() {
+        "." => root.to_string(),
+        path if root.is_empty() => path.to_string(),
+        path if path.starts_with('[') => format!("{root}{path}"),
+        path => format!("{root}.{path}"),
+    };
+    if let Some(missing) = inner.strip_prefix("missing field `").and_then(|rest| rest.split('`').next()) {
+        path = match path.is_empty() {
+            true => missing.to_string(),
+            false => format!("{path}.{missing}"),
+        };
+    }
+    // serde_json appends the position to its messages, which means little to the client.
+    let message = match inner.rfind(" at line ") {
+        Some(position) => inner[..position].to_string(),
+        None => inner,
+    };
+    FieldError::new(path, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request, StatusCode},
+        middleware,
+        response::{IntoResponse, Response},
+        routing::get,
+        Router
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::{AppError, FieldError};
+    use crate::logging::middleware_request_id;
+
+    async fn json_body(response: Response) -> Value {
+        serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn errors_answer_their_code_and_message() {
+        let response = AppError::NotFound("Couldn't find account: \"a@b.cn\"".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+        assert_eq!(
+            json_body(response).await,
+            json!({"code": "not_found", "message": "Couldn't find account: \"a@b.cn\"", "request_id": null})
+        );
+
+        let fields = vec![FieldError::new("file_list[2]", "expected a string"), FieldError::new("label", "must not be empty")];
+        let response = AppError::Validation(fields).into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = json_body(response).await;
+        assert_eq!(body["code"], "validation_failed");
+        assert_eq!(body["fields"], json!([
+            {"field": "file_list[2]", "message": "expected a string"},
+            {"field": "label", "message": "must not be empty"},
+        ]));
+
+        let response = AppError::TooManyRequests("Rate limit of sign_in exceeded by ip".to_string(), 17).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "17");
+        let body = json_body(response).await;
+        assert_eq!((body["code"].as_str(), body["message"].as_str()),
+            (Some("too_many_requests"), Some("Too many requests, please retry in 17 seconds.")));
+    }
+
+    #[tokio::test]
+    async fn server_errors_keep_their_detail_out_of_the_answer() {
+        for (error, code) in [
+            (AppError::Database("password authentication failed for user postgres".to_string()), "database_error"),
+            (AppError::Io("/srv/models/optimized.tar: permission denied".to_string()), "io_error"),
+            (AppError::Internal("Failed to sign the token!".to_string()), "internal_error"),
+        ] {
+            let response = error.into_response();
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(json_body(response).await, json!({"code": code, "message": "Internal server error.", "request_id": null}));
+        }
+        let response = AppError::Unavailable("No database connection: timed out".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(json_body(response).await["message"], "The service is temporarily unavailable, please retry later.");
+    }
+
+    #[tokio::test]
+    async fn errors_carry_the_request_id() {
+        let app = Router::new()
+            .route("/", get(|| async { Err::<(), _>(AppError::forbidden()) }))
+            .layer(middleware::from_fn(middleware_request_id));
+        let request = Request::builder().uri("/").header("x-request-id", "req-42").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(json_body(response).await["request_id"], "req-42");
+
+        let response = app.oneshot(Request::builder().uri("/").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(json_body(response).await["request_id"], Value::Null);
+    }
+}
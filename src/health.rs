@@ -0,0 +1,305 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant}
+};
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use utoipa::ToSchema;
+use tokio::{process::Command, time::timeout};
+
+use crate::{
+    config::app_config,
+    error::{AppError, ErrorResponses},
+    repository::Repositories,
+    MultiState
+};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const ENTRYPOINT_CHECK_TIMEOUT: Duration = Duration::from_secs(60);
+// Importing TVM and torchvision takes seconds, so probes reuse a recent result.
+const ENTRYPOINT_CHECK_TTL: Duration = Duration::from_secs(60);
+const PROBE_FILE_NAME: &str = ".readyz_probe";
+
+static STARTED_AT: OnceLock<(Instant, DateTime<Local>)> = OnceLock::new();
+static ENTRYPOINT_CHECK: Mutex<Option<(Instant, Result<String, String>)>> = Mutex::new(None);
+
+#[derive(Serialize, ToSchema)]
+pub struct ResponseReadiness {
+    ready: bool,
+    checks: Vec<ReadinessCheck>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessCheck {
+    name: String,
+    ok: bool,
+    detail: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ResponseDiagnostics {
+    versions: Versions,
+    started_at: Option<DateTime<Local>>,
+    uptime_secs: u64,
+    pool: Option<PoolStatistics>, // none with SQLite
+    storage: Vec<StorageUsage>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Versions {
+    insects_identifier: String,
+    postgres: Option<String>,
+    sqlite: Option<String>,
+    python: Option<String>,
+    tvm: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PoolStatistics {
+    max_size: usize,
+    size: usize,
+    available: usize,
+    waiting: usize
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StorageUsage {
+    key: String,
+    path: String,
+    exists: bool,
+    files: u64,
+    bytes: u64
+}
+
+/// Remember when the process started, for the uptime in the diagnostics.
+pub fn init_started_at() {
+    STARTED_AT.get_or_init(|| (Instant::now(), Local::now()));
+}
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "health",
+    responses(
+        (status = 200, description = "Greeting", body = String, content_type = "text/plain"),
+    )
+)]
+pub async fn handler_index() -> &'static str {
+    "Hello, World!"
+}
+
+/// The process is alive and serving requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses(
+        (status = 200, description = "The process is alive", body = String, content_type = "text/plain"),
+    )
+)]
+pub async fn handler_healthz() -> &'static str {
+    "OK"
+}
+
+/// Whether this instance can serve traffic: 200 when every check passes, 503 otherwise.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Every check passed", body = ResponseReadiness),
+        (status = 503, description = "Some checks failed", body = ResponseReadiness),
+    )
+)]
+pub async fn handler_readyz(
+    State(multi_state): State<MultiState>
+) -> (StatusCode, Json<ResponseReadiness>) {
+    let mut checks = vec![
+        __readiness_check("database", __check_database(&multi_state.repositories).await)
+    ];
+    for directory in app_config().storage.directories() {
+        checks.push(__readiness_check(
+            &format!("directory {}", directory.display()),
+            __check_directory_writable(directory).await
+        ));
+    }
+    checks.push(__readiness_check("compiled model", __check_compiled_model()));
+    checks.push(__readiness_check("inference entrypoint", __check_entrypoint().await));
+
+    let ready = checks.iter().all(|check| check.ok);
+    if !ready {
+        for check in checks.iter().filter(|check| !check.ok) {
+            tracing::warn!("Readiness check {} failed: {}", check.name, check.detail);
+        }
+    }
+    let status = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(ResponseReadiness { ready, checks }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/diagnostics",
+    tag = "health",
+    responses(
+        (status = 200, description = "Versions, uptime, pool and storage usage", body = ResponseDiagnostics),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_diagnostics(
+    State(multi_state): State<MultiState>
+) -> Result<Json<ResponseDiagnostics>, AppError> {
+    let database = &multi_state.repositories.database;
+    let database_version = database.version().await.ok();
+    let python = &app_config().dl_svc.python;
+    let versions = Versions {
+        insects_identifier: env!("CARGO_PKG_VERSION").to_string(),
+        postgres: database_version.clone().filter(|_| database.backend() == "postgres"),
+        sqlite: database_version.filter(|_| database.backend() == "sqlite"),
+        python: __run_command(python, &["--version"], CHECK_TIMEOUT).await.ok(),
+        tvm: __run_command(python, &["-c", "import tvm; print(tvm.__version__)"], ENTRYPOINT_CHECK_TIMEOUT).await.ok(),
+    };
+
+    let entries: Vec<(String, PathBuf)> = app_config().storage.entries()
+        .into_iter()
+        .map(|(key, path)| (key.to_string(), PathBuf::from(path)))
+        .collect();
+    let storage = tokio::task::spawn_blocking(move || {
+        entries.into_iter()
+            .map(|(key, path)| {
+                let (files, bytes) = __disk_usage(&path);
+                StorageUsage {
+                    key,
+                    path: path.display().to_string(),
+                    exists: path.exists(),
+                    files,
+                    bytes
+                }
+            })
+            .collect::<Vec<StorageUsage>>()
+    }).await.map_err(|err| AppError::Internal(err.to_string()))?;
+
+    Ok(Json(ResponseDiagnostics {
+        versions,
+        started_at: STARTED_AT.get().map(|(_, started_at)| *started_at),
+        uptime_secs: STARTED_AT.get().map(|(started, _)| started.elapsed().as_secs()).unwrap_or_default(),
+        pool: multi_state.db_pool.as_ref().map(|pool| {
+            let pool_status = pool.status();
+            PoolStatistics {
+                max_size: pool_status.max_size,
+                size: pool_status.size,
+                available: pool_status.available,
+                waiting: pool_status.waiting
+            }
+        }),
+        storage
+    }))
+}
+
+fn __readiness_check(name: &str, result: Result<String, String>) -> ReadinessCheck {
+    let (ok, detail) = match result {
+        Ok(detail) => (true, detail),
+        Err(detail) => (false, detail),
+    };
+    ReadinessCheck { name: name.to_string(), ok, detail }
+}
+
+async fn __check_database(repositories: &Repositories) -> Result<String, String> {
+    match timeout(CHECK_TIMEOUT, repositories.database.ping()).await {
+        Ok(Ok(_)) => Ok(format!("connected to {}", repositories.database.backend())),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(format!("no connection within {CHECK_TIMEOUT:?}")),
+    }
+}
+
+async fn __check_directory_writable(directory: &Path) -> Result<String, String> {
+    if !directory.is_dir() {
+        return Err("missing".to_string());
+    }
+    let probe_path = directory.join(PROBE_FILE_NAME);
+    tokio::fs::write(&probe_path, b"").await.map_err(|err| format!("not writable: {err}"))?;
+    tokio::fs::remove_file(&probe_path).await.map_err(|err| format!("not writable: {err}"))?;
+    Ok("writable".to_string())
+}
+
+fn __check_compiled_model() -> Result<String, String> {
+    let dl_svc_config = &app_config().dl_svc;
+    let model_path = Path::new(&dl_svc_config.compiled_model_directory)
+        .join(format!("{}_deploy_lib.tar", dl_svc_config.model_prefix));
+    match model_path.is_file() {
+        true => Ok(model_path.display().to_string()),
+        false => Err(format!("{} is missing", model_path.display())),
+    }
+}
+
+/// Load the inference script without running it, which imports TVM and the other modules it needs.
+async fn __check_entrypoint() -> Result<String, String> {
+    if let Some((checked_at, result)) = ENTRYPOINT_CHECK.lock().unwrap().as_ref() {
+        if checked_at.elapsed() < ENTRYPOINT_CHECK_TTL {
+            return result.clone();
+        }
+    }
+    let dl_svc_config = &app_config().dl_svc;
+    let result = match Path::new(&dl_svc_config.infer_script).is_file() {
+        true => __run_command(&dl_svc_config.python, &[
+            "-c", "import runpy, sys; runpy.run_path(sys.argv[1], run_name='readyz')", &dl_svc_config.infer_script
+        ], ENTRYPOINT_CHECK_TIMEOUT).await
+            .map(|_| format!("{} loads", dl_svc_config.infer_script)),
+        false => Err(format!("{} is missing", dl_svc_config.infer_script)),
+    };
+    *ENTRYPOINT_CHECK.lock().unwrap() = Some((Instant::now(), result.clone()));
+    result
+}
+
+/// Run a command and return its trimmed output, or why it failed.
+async fn __run_command(program: &str, args: &[&str], time_limit: Duration) -> Result<String, String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = timeout(time_limit, output).await
+        .map_err(|_| format!("{program} did not finish within {time_limit:?}"))?
+        .map_err(|err| format!("failed to run {program}: {err}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let last_line = stderr.lines().last().unwrap_or_default();
+        return Err(format!("{program} exited with {}: {last_line}", output.status));
+    }
+    // `python --version` prints to stderr before Python 3.4.
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    match stdout.is_empty() {
+        true => Ok(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        false => Ok(stdout),
+    }
+}
+
+/// Number of files and their total size under a path, following no symlinks.
+fn __disk_usage(path: &Path) -> (u64, u64) {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return (0, 0),
+    };
+    if metadata.is_file() {
+        return (1, metadata.len());
+    }
+    if !metadata.is_dir() {
+        return (0, 0);
+    }
+    let mut usage = (0, 0);
+    if let Ok(entries) = std::fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let (files, bytes) = __disk_usage(&entry.path());
+            usage.0 += files;
+            usage.1 += bytes;
+        }
+    }
+    usage
+}
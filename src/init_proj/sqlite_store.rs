@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use chrono::Local;
+use rusqlite::{params, params_from_iter, types, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::{
+    migrations::{
+        AppliedMigration, Migration, CREATE_SCHEMA_MIGRATIONS, DELETE_SCHEMA_MIGRATION, INSERT_SCHEMA_MIGRATION,
+        SELECT_SCHEMA_MIGRATIONS
+    },
+    species_vector::SPECIES_VECTOR,
+    store::Store,
+    transfer::{Kind, Table, Value}
+};
+
+/// Opens or creates the database file.
+pub fn open(path: &str) -> Result<Connection, String> {
+    let connection = Connection::open(path).map_err(|err| format!("couldn't open {path}: {err}"))?;
+    connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(())).map_err(|err| err.to_string())?;
+    connection.busy_timeout(Duration::from_secs(5)).map_err(|err| err.to_string())?;
+    Ok(connection)
+}
+
+impl Store for Connection {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    // Every migration runs in an IMMEDIATE transaction, so a concurrent `init` waits for it
+    // and then fails on the version it applied.
+    fn lock(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn unlock(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn fetch_applied(&mut self) -> Result<Vec<AppliedMigration>, String> {
+        self.execute_batch(CREATE_SCHEMA_MIGRATIONS).map_err(|err| err.to_string())?;
+        let mut statement = self.prepare(SELECT_SCHEMA_MIGRATIONS).map_err(|err| err.to_string())?;
+        let applied = statement
+            .query_map([], |row| Ok(AppliedMigration {
+                version: row.get("version")?,
+                name: row.get("name")?,
+                checksum: row.get("checksum")?,
+                applied_at: row.get("applied_at")?,
+            }))
+            .and_then(|rows| rows.collect())
+            .map_err(|err| err.to_string())?;
+        Ok(applied)
+    }
+
+    fn apply(&mut self, migration: &Migration) -> Result<(), String> {
+        let transaction = self.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|err| err.to_string())?;
+        transaction.execute_batch(migration.up)
+            .map_err(|err| format!("{:04}_{}: {err}", migration.version, migration.name))?;
+        transaction.execute(INSERT_SCHEMA_MIGRATION, params![
+            migration.version, migration.name, migration.checksum(), Local::now().timestamp_millis()
+        ]).map_err(|err| err.to_string())?;
+        transaction.commit().map_err(|err| err.to_string())
+    }
+
+    fn revert(&mut self, migration: &Migration) -> Result<(), String> {
+        let transaction = self.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|err| err.to_string())?;
+        transaction.execute_batch(migration.down)
+            .map_err(|err| format!("{:04}_{}: {err}", migration.version, migration.name))?;
+        transaction.execute(DELETE_SCHEMA_MIGRATION, params![migration.version]).map_err(|err| err.to_string())?;
+        transaction.commit().map_err(|err| err.to_string())
+    }
+
+    fn find_account_with(&mut self, permissions: i16) -> Result<Option<String>, String> {
+        self.query_row("SELECT email FROM Account WHERE permissions = ?1 LIMIT 1;", params![permissions], |row| row.get(0))
+            .optional()
+            .map_err(|err| err.to_string())
+    }
+
+    fn account_exists(&mut self, email: &str) -> Result<bool, String> {
+        self.query_row("SELECT EXISTS (SELECT 1 FROM Account WHERE email = ?1);", params![email], |row| row.get(0))
+            .map_err(|err| err.to_string())
+    }
+
+    fn insert_account(&mut self, nick_name: &str, password_salt: &str, password_hash: &str, email: &str, permissions: i16)
+        -> Result<(), String> {
+        self.execute("
+            INSERT INTO Account (nick_name, password_salt, password_hash, email, contribution, available, permissions)
+            VALUES (?1, ?2, ?3, ?4, 0, TRUE, ?5)
+            ON CONFLICT (email) DO NOTHING;
+        ", params![nick_name, password_salt, password_hash, email, permissions])
+        .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn seed_species(&mut self) -> Result<(), String> {
+        let transaction = self.transaction().map_err(|err| err.to_string())?;
+        {
+            let mut statement = transaction.prepare("
+                INSERT INTO Wiki (label, english_name, specie_name, content)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (label) DO UPDATE
+                SET english_name = excluded.english_name, specie_name = excluded.specie_name, content = excluded.content;
+            ").map_err(|err| err.to_string())?;
+            for (label, (english_name, (specie_name, content))) in SPECIES_VECTOR.iter().enumerate() {
+                statement.execute(params![label as i32, english_name, specie_name, content])
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        transaction.commit().map_err(|err| err.to_string())
+    }
+
+    fn count_rows(&mut self, table: &Table) -> Result<i64, String> {
+        self.query_row(&format!("SELECT COUNT(*) FROM {};", table.name), [], |row| row.get(0))
+            .map_err(|err| err.to_string())
+    }
+
+    fn read_rows(&mut self, table: &Table) -> Result<Vec<Vec<Value>>, String> {
+        let mut statement = self.prepare(&table.select_statement()).map_err(|err| format!("{}: {err}", table.name))?;
+        let rows = statement
+            .query_map([], |row| table.columns.iter()
+                .enumerate()
+                .map(|(index, (_, kind))| {
+                    let value = match kind {
+                        Kind::SmallInt | Kind::Integer | Kind::BigInt => row.get::<_, Option<i64>>(index)?.map(Value::Integer),
+                        Kind::Boolean => row.get::<_, Option<bool>>(index)?.map(Value::Boolean),
+                        Kind::Text => row.get::<_, Option<String>>(index)?.map(Value::Text),
+                    };
+                    Ok(value.unwrap_or(Value::Null))
+                })
+                .collect())
+            .and_then(|rows| rows.collect())
+            .map_err(|err| format!("{}: {err}", table.name))?;
+        Ok(rows)
+    }
+
+    fn replace_rows(&mut self, data: &[(&Table, Vec<Vec<Value>>)]) -> Result<(), String> {
+        let transaction = self.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|err| err.to_string())?;
+        for (table, rows) in data {
+            transaction.execute(&format!("DELETE FROM {};", table.name), []).map_err(|err| err.to_string())?;
+            let mut statement = transaction.prepare(&table.insert_statement(|index| format!("?{index}")))
+                .map_err(|err| format!("{}: {err}", table.name))?;
+            for row in rows {
+                statement.execute(params_from_iter(row.iter().map(__to_sql)))
+                    .map_err(|err| format!("{}: {err}", table.name))?;
+            }
+        }
+        transaction.commit().map_err(|err| err.to_string())
+    }
+}
+
+fn __to_sql(value: &Value) -> types::Value {
+    match value {
+        Value::Null => types::Value::Null,
+        Value::Integer(value) => types::Value::Integer(*value),
+        Value::Boolean(value) => types::Value::Integer(*value as i64),
+        Value::Text(value) => types::Value::Text(value.clone()),
+    }
+}
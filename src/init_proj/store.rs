@@ -0,0 +1,186 @@
+use chrono::Local;
+use postgres::{types::ToSql, Client};
+
+use crate::{
+    migrations::{
+        embedded, AppliedMigration, Migration, CREATE_SCHEMA_MIGRATIONS, DELETE_SCHEMA_MIGRATION,
+        INSERT_SCHEMA_MIGRATION, MIGRATION_LOCK_KEY, SELECT_SCHEMA_MIGRATIONS
+    },
+    species_vector::SPECIES_VECTOR,
+    transfer::{Kind, Table, Value}
+};
+
+/// What the commands of `init` need from a database, PostgreSQL or SQLite.
+pub trait Store {
+    /// `database.backend` of the store.
+    fn backend(&self) -> &'static str;
+    /// Keep another `init` from migrating at the same time.
+    fn lock(&mut self) -> Result<(), String>;
+    fn unlock(&mut self) -> Result<(), String>;
+    /// Rows of `schema_migrations`, none before the first migration.
+    fn fetch_applied(&mut self) -> Result<Vec<AppliedMigration>, String>;
+    /// Run the up migration and record it, in one transaction.
+    fn apply(&mut self, migration: &Migration) -> Result<(), String>;
+    /// Run the down migration and forget it, in one transaction.
+    fn revert(&mut self, migration: &Migration) -> Result<(), String>;
+    /// Email of an account with exactly these permissions.
+    fn find_account_with(&mut self, permissions: i16) -> Result<Option<String>, String>;
+    fn account_exists(&mut self, email: &str) -> Result<bool, String>;
+    /// Insert an available account without contribution, unless the email is taken.
+    fn insert_account(&mut self, nick_name: &str, password_salt: &str, password_hash: &str, email: &str, permissions: i16)
+        -> Result<(), String>;
+    /// Insert or update every species of the model in the Wiki table, keyed by label.
+    fn seed_species(&mut self) -> Result<(), String>;
+    fn count_rows(&mut self, table: &Table) -> Result<i64, String>;
+    /// Every row of the table, the values in the order of its columns.
+    fn read_rows(&mut self, table: &Table) -> Result<Vec<Vec<Value>>, String>;
+    /// Replace the rows of every given table, all in one transaction.
+    fn replace_rows(&mut self, data: &[(&Table, Vec<Vec<Value>>)]) -> Result<(), String>;
+
+    /// Migrations embedded for the backend.
+    fn migrations(&self) -> &'static [Migration] {
+        embedded(self.backend())
+    }
+}
+
+impl Store for Client {
+    fn backend(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn lock(&mut self) -> Result<(), String> {
+        self.execute("SELECT pg_advisory_lock($1);", &[&MIGRATION_LOCK_KEY]).map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn unlock(&mut self) -> Result<(), String> {
+        self.execute("SELECT pg_advisory_unlock($1);", &[&MIGRATION_LOCK_KEY]).map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn fetch_applied(&mut self) -> Result<Vec<AppliedMigration>, String> {
+        self.batch_execute(CREATE_SCHEMA_MIGRATIONS).map_err(|err| err.to_string())?;
+        let rows = self.query(SELECT_SCHEMA_MIGRATIONS, &[]).map_err(|err| err.to_string())?;
+        Ok(rows.iter()
+            .map(|row| AppliedMigration {
+                version: row.get("version"),
+                name: row.get("name"),
+                checksum: row.get("checksum"),
+                applied_at: row.get("applied_at"),
+            })
+            .collect())
+    }
+
+    fn apply(&mut self, migration: &Migration) -> Result<(), String> {
+        let mut transaction = self.transaction().map_err(|err| err.to_string())?;
+        transaction.batch_execute(migration.up)
+            .map_err(|err| format!("{:04}_{}: {err}", migration.version, migration.name))?;
+        transaction.execute(INSERT_SCHEMA_MIGRATION, &[
+            &migration.version, &migration.name, &migration.checksum(), &Local::now().timestamp_millis()
+        ]).map_err(|err| err.to_string())?;
+        transaction.commit().map_err(|err| err.to_string())
+    }
+
+    fn revert(&mut self, migration: &Migration) -> Result<(), String> {
+        let mut transaction = self.transaction().map_err(|err| err.to_string())?;
+        transaction.batch_execute(migration.down)
+            .map_err(|err| format!("{:04}_{}: {err}", migration.version, migration.name))?;
+        transaction.execute(DELETE_SCHEMA_MIGRATION, &[&migration.version]).map_err(|err| err.to_string())?;
+        transaction.commit().map_err(|err| err.to_string())
+    }
+
+    fn find_account_with(&mut self, permissions: i16) -> Result<Option<String>, String> {
+        Ok(self.query_opt("SELECT email FROM Account WHERE permissions = $1 LIMIT 1;", &[&permissions])
+            .map_err(|err| err.to_string())?
+            .map(|row| row.get(0)))
+    }
+
+    fn account_exists(&mut self, email: &str) -> Result<bool, String> {
+        Ok(self.query_opt("SELECT email FROM Account WHERE email = $1;", &[&email])
+            .map_err(|err| err.to_string())?
+            .is_some())
+    }
+
+    fn insert_account(&mut self, nick_name: &str, password_salt: &str, password_hash: &str, email: &str, permissions: i16)
+        -> Result<(), String> {
+        let contribution: i16 = 0;
+        self.execute("
+            INSERT INTO Account (nick_name, password_salt, password_hash, email, contribution, available, permissions)
+            VALUES ($1, $2, $3, $4, $5, TRUE, $6)
+            ON CONFLICT (email) DO NOTHING;
+        ", &[&nick_name, &password_salt, &password_hash, &email, &contribution, &permissions])
+        .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn seed_species(&mut self) -> Result<(), String> {
+        let mut transaction = self.transaction().map_err(|err| err.to_string())?;
+        let statement = transaction.prepare("
+            INSERT INTO Wiki (label, english_name, specie_name, content)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (label) DO UPDATE
+            SET english_name = EXCLUDED.english_name, specie_name = EXCLUDED.specie_name, content = EXCLUDED.content;
+        ").map_err(|err| err.to_string())?;
+        for (label, (english_name, (specie_name, content))) in SPECIES_VECTOR.iter().enumerate() {
+            transaction.execute(&statement, &[&(label as i32), english_name, specie_name, content])
+                .map_err(|err| err.to_string())?;
+        }
+        transaction.commit().map_err(|err| err.to_string())
+    }
+
+    fn count_rows(&mut self, table: &Table) -> Result<i64, String> {
+        Ok(self.query_one(&format!("SELECT COUNT(*) FROM {};", table.name), &[])
+            .map_err(|err| err.to_string())?
+            .get(0))
+    }
+
+    fn read_rows(&mut self, table: &Table) -> Result<Vec<Vec<Value>>, String> {
+        let rows = self.query(&table.select_statement(), &[]).map_err(|err| format!("{}: {err}", table.name))?;
+        Ok(rows.iter()
+            .map(|row| table.columns.iter()
+                .enumerate()
+                .map(|(index, (_, kind))| {
+                    let value = match kind {
+                        Kind::SmallInt => row.get::<_, Option<i16>>(index).map(|value| Value::Integer(value.into())),
+                        Kind::Integer => row.get::<_, Option<i32>>(index).map(|value| Value::Integer(value.into())),
+                        Kind::BigInt => row.get::<_, Option<i64>>(index).map(Value::Integer),
+                        Kind::Boolean => row.get::<_, Option<bool>>(index).map(Value::Boolean),
+                        Kind::Text => row.get::<_, Option<String>>(index).map(Value::Text),
+                    };
+                    value.unwrap_or(Value::Null)
+                })
+                .collect())
+            .collect())
+    }
+
+    fn replace_rows(&mut self, data: &[(&Table, Vec<Vec<Value>>)]) -> Result<(), String> {
+        let mut transaction = self.transaction().map_err(|err| err.to_string())?;
+        for (table, rows) in data {
+            transaction.execute(&format!("DELETE FROM {};", table.name), &[]).map_err(|err| err.to_string())?;
+            let statement = transaction.prepare(&table.insert_statement(|index| format!("${index}")))
+                .map_err(|err| format!("{}: {err}", table.name))?;
+            for row in rows {
+                let params = table.columns.iter()
+                    .zip(row)
+                    .map(|((_, kind), value)| __to_sql(*kind, value))
+                    .collect::<Result<Vec<Box<dyn ToSql + Sync>>, String>>()
+                    .map_err(|err| format!("{}: {err}", table.name))?;
+                let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|param| param.as_ref()).collect();
+                transaction.execute(&statement, &params).map_err(|err| format!("{}: {err}", table.name))?;
+            }
+        }
+        transaction.commit().map_err(|err| err.to_string())
+    }
+}
+
+/// PostgreSQL checks the type of every parameter against its column, so integers are narrowed.
+fn __to_sql(kind: Kind, value: &Value) -> Result<Box<dyn ToSql + Sync>, String> {
+    let out_of_range = |err: std::num::TryFromIntError| err.to_string();
+    Ok(match kind {
+        Kind::SmallInt => Box::new(value.integer()?.map(i16::try_from).transpose().map_err(out_of_range)?),
+        Kind::Integer => Box::new(value.integer()?.map(i32::try_from).transpose().map_err(out_of_range)?),
+        Kind::BigInt => Box::new(value.integer()?),
+        Kind::Boolean => Box::new(value.boolean()?),
+        Kind::Text => Box::new(value.text()?.map(str::to_string)),
+    })
+}
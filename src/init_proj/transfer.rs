@@ -0,0 +1,189 @@
+use crate::{migrations::check_schema, store::Store};
+
+/// Type of a column as declared for PostgreSQL, SQLite stores the integers alike.
+#[derive(Clone, Copy, Debug)]
+pub enum Kind {
+    SmallInt,
+    Integer,
+    BigInt,
+    Boolean,
+    Text,
+}
+
+/// A table copied by `transfer`. Serial ids are left out, the destination numbers the rows again
+/// in the order of `order_by`.
+pub struct Table {
+    pub name: &'static str,
+    pub columns: &'static [(&'static str, Kind)],
+    pub order_by: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Boolean(bool),
+    Text(String),
+}
+
+/// Every table with data, in the order they are copied.
+/// Refresh tokens, revoked sessions and mailed tokens are left behind, everyone signs in again after the move.
+pub static TABLES: [Table; 11] = [
+    Table {
+        name: "Account",
+        columns: &[
+            ("nick_name", Kind::Text), ("password_salt", Kind::Text), ("password_hash", Kind::Text),
+            ("email", Kind::Text), ("contribution", Kind::SmallInt), ("available", Kind::Boolean),
+            ("permissions", Kind::SmallInt), ("email_verified", Kind::Boolean),
+        ],
+        order_by: "email",
+    },
+    Table {
+        name: "TFeedback",
+        columns: &[
+            ("time_stamp", Kind::BigInt), ("from_user_email", Kind::Text), ("time_out", Kind::BigInt),
+            ("pic_link", Kind::Text), ("real_label", Kind::Text), ("submit_count", Kind::BigInt),
+        ],
+        order_by: "id",
+    },
+    Table {
+        name: "UFeedback",
+        columns: &[("time_stamp", Kind::BigInt), ("from_user_email", Kind::Text), ("pic_link", Kind::Text)],
+        order_by: "id",
+    },
+    Table {
+        name: "Wiki",
+        columns: &[
+            ("label", Kind::Integer), ("english_name", Kind::Text), ("specie_name", Kind::Text), ("content", Kind::Text),
+        ],
+        order_by: "label",
+    },
+    Table {
+        name: "TaskRun",
+        columns: &[
+            ("task_name", Kind::Text), ("started_at", Kind::BigInt), ("finished_at", Kind::BigInt),
+            ("outcome", Kind::Text), ("attempts", Kind::Integer), ("error", Kind::Text),
+        ],
+        order_by: "id",
+    },
+    Table {
+        name: "TaskLease",
+        columns: &[("task_name", Kind::Text), ("holder", Kind::Text), ("tick", Kind::BigInt), ("acquired_at", Kind::BigInt)],
+        order_by: "task_name",
+    },
+    Table {
+        name: "TaskSetting",
+        columns: &[
+            ("task_name", Kind::Text), ("paused", Kind::Boolean), ("interval_seconds", Kind::BigInt),
+            ("cron_expression", Kind::Text), ("updated_at", Kind::BigInt),
+        ],
+        order_by: "task_name",
+    },
+    Table {
+        name: "InferenceHistory",
+        columns: &[
+            ("user_email", Kind::Text), ("file_name", Kind::Text), ("label", Kind::Integer),
+            ("specie_name", Kind::Text), ("inferred_at", Kind::BigInt),
+        ],
+        order_by: "id",
+    },
+    Table {
+        name: "TwoFactor",
+        columns: &[
+            ("user_email", Kind::Text), ("secret", Kind::Text), ("enabled", Kind::Boolean),
+            ("enrolled_at", Kind::BigInt), ("last_step", Kind::BigInt),
+        ],
+        order_by: "user_email",
+    },
+    Table {
+        name: "RecoveryCode",
+        columns: &[("code_hash", Kind::Text), ("user_email", Kind::Text), ("used_at", Kind::BigInt)],
+        order_by: "code_hash",
+    },
+    Table {
+        name: "ApiKey",
+        columns: &[
+            ("key_id", Kind::Text), ("key_hash", Kind::Text), ("user_email", Kind::Text), ("name", Kind::Text),
+            ("scopes", Kind::SmallInt), ("allowed_ips", Kind::Text), ("created_at", Kind::BigInt),
+            ("expires_at", Kind::BigInt), ("last_used_at", Kind::BigInt),
+        ],
+        order_by: "key_id",
+    },
+];
+
+// Seeded by `init --seed-species` on both sides, so it may be replaced.
+const REPLACEABLE_TABLE: &str = "Wiki";
+
+impl Table {
+    pub fn select_statement(&self) -> String {
+        format!("SELECT {} FROM {} ORDER BY {};", self.column_names().join(", "), self.name, self.order_by)
+    }
+
+    /// The insert of one row, `placeholder` numbering the parameters from 1 in the dialect of the backend.
+    pub fn insert_statement(&self, placeholder: fn(usize) -> String) -> String {
+        let placeholders: Vec<String> = (1..=self.columns.len()).map(placeholder).collect();
+        format!("INSERT INTO {} ({}) VALUES ({});", self.name, self.column_names().join(", "), placeholders.join(", "))
+    }
+
+    fn column_names(&self) -> Vec<&'static str> {
+        self.columns.iter().map(|(name, _)| *name).collect()
+    }
+}
+
+impl Value {
+    pub fn integer(&self) -> Result<Option<i64>, String> {
+        match self {
+            Value::Null => Ok(None),
+            Value::Integer(value) => Ok(Some(*value)),
+            value => Err(format!("expected an integer, got {value:?}")),
+        }
+    }
+
+    pub fn boolean(&self) -> Result<Option<bool>, String> {
+        match self {
+            Value::Null => Ok(None),
+            Value::Boolean(value) => Ok(Some(*value)),
+            value => Err(format!("expected a boolean, got {value:?}")),
+        }
+    }
+
+    pub fn text(&self) -> Result<Option<&str>, String> {
+        match self {
+            Value::Null => Ok(None),
+            Value::Text(value) => Ok(Some(value)),
+            value => Err(format!("expected a text, got {value:?}")),
+        }
+    }
+}
+
+/// Copy every row from one database into another one of the same schema version, in one transaction.
+/// The destination must be empty but for the species catalog, which is replaced.
+pub fn transfer(source: &mut dyn Store, destination: &mut dyn Store) -> Result<(), String> {
+    __require_current_schema(source)?;
+    __require_current_schema(destination)?;
+    for table in TABLES.iter().filter(|table| table.name != REPLACEABLE_TABLE) {
+        let rows = destination.count_rows(table)?;
+        if rows > 0 {
+            return Err(format!(
+                "{} of the {} database has {rows} rows, transfer only into an empty database",
+                table.name, destination.backend()
+            ));
+        }
+    }
+
+    let mut data = Vec::new();
+    for table in TABLES.iter() {
+        data.push((table, source.read_rows(table)?));
+    }
+    destination.replace_rows(&data)?;
+    for (table, rows) in data.iter() {
+        println!("Copied {} rows of {}.", rows.len(), table.name);
+    }
+    println!("Transferred the {} database into the {} database.", source.backend(), destination.backend());
+    Ok(())
+}
+
+fn __require_current_schema(store: &mut dyn Store) -> Result<(), String> {
+    let applied = store.fetch_applied()?;
+    check_schema(store.migrations(), &applied).map_err(|err| format!("{} database: {err}", store.backend()))
+}
@@ -1,36 +1,54 @@
-use axum::{extract::{Query, State}, response::{IntoResponse, Response}};
+use axum::{extract::Query, response::{IntoResponse, Response}};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use tokio::{fs::File, io::{AsyncReadExt, AsyncWriteExt}};
 use std::fs::create_dir;
-use axum::{
-    extract::{Multipart, Path as RoutePath},
-    http::StatusCode,
-};
+use axum::extract::{Multipart, Path as RoutePath};
 use std::{io::{Error, ErrorKind}, path::{Path, PathBuf}, slice::Iter};
 
-use crate::config::{MODEL_BACKUP_STORED_PATH, MODEL_STORED_PATH, UFEEDBACK_STORED_DIRECTORY, USER_PIC_PATH};
-use crate::authenticator::{check_permission, Permission};
-use crate::MultiState;
+use crate::config::app_config;
+use crate::authenticator::AuthUser;
+use crate::error::{AppError, ErrorResponses};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct RequestImageFetch {
-    useremail: String,
     image_name: String
 }
 
+/// Multipart body of an upload, only described for the OpenAPI document.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct UploadPicture {
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>
+}
+
+#[utoipa::path(
+    get,
+    path = "/fetch_image",
+    tag = "user",
+    params(RequestImageFetch),
+    responses(
+        (status = 200, description = "Content of the picture", body = Vec<u8>, content_type = "application/octet-stream"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_fetch_image(
-    State(multi_state): State<MultiState>,
     Query(request_image_fetch): Query<RequestImageFetch>
-) -> Result<Response, (StatusCode, String)> {
-    if !check_permission(&multi_state.db_pool, &request_image_fetch.useremail, Permission::Common).await.unwrap() {
-        return Err(
-            (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-        );
+) -> Result<Response, AppError> {
+    if !_path_is_valid(&request_image_fetch.image_name) {
+        return Err(AppError::BadRequest(format!("Invalid image name: {:?}", request_image_fetch.image_name)));
     }
-    let image_pathbuf = PathBuf::from(UFEEDBACK_STORED_DIRECTORY).join(request_image_fetch.image_name);
-    let mut image_file_handle = tokio::fs::File::open(&image_pathbuf).await.unwrap();
+    let image_pathbuf = PathBuf::from(&app_config().storage.ufeedback_stored_directory).join(&request_image_fetch.image_name);
+    let mut image_file_handle = tokio::fs::File::open(&image_pathbuf).await
+        .map_err(|err| match err.kind() {
+            ErrorKind::NotFound => AppError::NotFound(format!("Couldn't find the image: {:?}", request_image_fetch.image_name)),
+            _ => AppError::from(err),
+        })?;
     let mut buffer = vec![];
-    image_file_handle.read_to_end(&mut buffer).await.unwrap();
+    image_file_handle.read_to_end(&mut buffer).await?;
 
     Ok(buffer.into_response())
 }
@@ -48,15 +66,16 @@ pub fn _path_is_valid(path: &str) -> bool {
     components.count() == 1
 }
 
-pub fn _obtain_dir(user_email: &str) -> Result<String, std::ffi::OsString>{
-    let path_buffer = __obtain_dir(user_email).unwrap();
+pub fn _obtain_dir(user_email: &str) -> Result<String, Error> {
+    let path_buffer = __obtain_dir(user_email)?;
     path_buffer.into_os_string().into_string()
+        .map_err(|path| Error::new(ErrorKind::InvalidData, format!("Invalid directory: {path:?}")))
 }
 
-fn __obtain_dir(user_email: &str) -> Result<PathBuf, String> {
+fn __obtain_dir(user_email: &str) -> Result<PathBuf, Error> {
     let user_dir_name = _generate_user_folder_name(user_email);
 
-    let path = Path::new(USER_PIC_PATH);
+    let path = Path::new(&app_config().storage.user_pic_path);
     let user_dir_path = path.join(user_dir_name);
     if !user_dir_path.exists() {
         match create_dir(&user_dir_path) {
@@ -65,7 +84,7 @@ fn __obtain_dir(user_email: &str) -> Result<PathBuf, String> {
             },
             Err(e) => {
                 tracing::error!("Error creating directory: {}", e);
-                return Err(format!("Error creating directory: {}", e));
+                return Err(e);
             }
         }
     }
@@ -78,73 +97,86 @@ pub fn _generate_user_folder_name(user_email: &str) -> String {
 
 pub fn _generate_new_file_name(user_email: &str, file_name: &str) -> String {
     let mut new_file_name = _generate_user_folder_name(user_email);
-    new_file_name.push_str("_");
+    new_file_name.push('_');
     new_file_name.push_str(file_name);
     return new_file_name;
 }
 
+#[utoipa::path(
+    post,
+    path = "/{useremail}/upload_pic",
+    tag = "user",
+    params(("useremail" = String, Path, description = "Email of the account")),
+    request_body(content = UploadPicture, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Name and size of the stored picture", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_upload_pic(
-    State(multi_state): State<MultiState>,
+    user: AuthUser,
     RoutePath(useremail): RoutePath<String>,
     mut multipart: Multipart
-)-> Result<(StatusCode, String), (StatusCode, String)> {
-    if !check_permission(&multi_state.db_pool, &useremail, Permission::Common).await.unwrap() {
-        return Err(
-            (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-        );
-    }
-    if let Some(file) = multipart.next_field().await.unwrap() {
-        let filename = file.file_name().unwrap().to_string();
-        let data = file.bytes().await.unwrap();
-
-        let file_path = __obtain_dir(&useremail).unwrap();
+)-> Result<String, AppError> {
+    // Only into the own directory, the path is kept for the clients.
+    user.require_self(&useremail)?;
+    let field = multipart.next_field().await
+        .map_err(|err| AppError::BadRequest(format!("Invalid multipart body! {err}")))?;
+    if let Some(file) = field {
+        let filename = file.file_name()
+            .filter(|filename| _path_is_valid(filename))
+            .ok_or_else(|| AppError::BadRequest("The uploaded file has no valid file name!".to_string()))?
+            .to_string();
+        let data = file.bytes().await
+            .map_err(|err| AppError::BadRequest(format!("Failed to read the uploaded file! {err}")))?;
+
+        let file_path = __obtain_dir(&useremail)?;
         let upload_path = file_path.join(&filename);
         //std::fs::write(&filename, &data).map_err(|err| err.to_string())?;
-        tokio::fs::write(&upload_path, &data)
-            .await
-            .map_err(|err| (StatusCode::NOT_ACCEPTABLE, err.to_string()))?;
+        tokio::fs::write(&upload_path, &data).await?;
 
-        return Ok((StatusCode::OK, format!(
-            "【上传的文件】文件名：{:?}, 文件大小：{}",
+        return Ok(format!(
+            "Uploaded file: {:?}, size: {} bytes",
             filename,
             data.len()
-        )))
+        ))
     }
-    Err((StatusCode::NOT_ACCEPTABLE, "没有上传文件或上传文件不合法".to_string()))
+    Err(AppError::BadRequest("No file was uploaded!".to_string()))
 }
 
 pub async fn backup_models(files: Iter<'_, String>) -> tokio::io::Result<u64> {
-    let src_dir_path = PathBuf::from(MODEL_STORED_PATH);
-    let dest_dir_path = PathBuf::from(MODEL_BACKUP_STORED_PATH);
+    let src_dir_path = PathBuf::from(&app_config().storage.model_stored_path);
+    let dest_dir_path = PathBuf::from(&app_config().storage.model_backup_stored_path);
     let total_write = __copy_files(files, &src_dir_path, &dest_dir_path).await;
     return total_write;
 }
 
 pub async fn remove_models(files: Iter<'_, String>) -> tokio::io::Result<u64> {
-    let src_dir_path = PathBuf::from(MODEL_STORED_PATH);
+    let src_dir_path = PathBuf::from(&app_config().storage.model_stored_path);
     let count = __remove_files(files, &src_dir_path).await;
     count
 }
 
-pub async fn _remove_file(file_name: &str, src_dir_path: &PathBuf) -> tokio::io::Result<u64> {
+pub async fn _remove_file(file_name: &str, src_dir_path: &Path) -> tokio::io::Result<u64> {
     tokio::fs::remove_file(src_dir_path.join(file_name)).await?;
     Ok(1)
 }
 
-pub async fn _copy_file(file_name: &str, src_dir_path: &PathBuf, dest_dir_path: &PathBuf) -> tokio::io::Result<u64> {
+pub async fn _copy_file(file_name: &str, src_dir_path: &Path, dest_dir_path: &Path) -> tokio::io::Result<u64> {
     let src_path = src_dir_path.join(file_name);
     let dest_path = dest_dir_path.join(file_name);
     let wirte_bytes = tokio::fs::copy(&src_path, dest_path).await?;
     Ok(wirte_bytes)
 }
 
-pub async fn _move_file(file_name: &str, src_dir_path: &PathBuf, dest_dir_path: &PathBuf) -> tokio::io::Result<u64> {
+pub async fn _move_file(file_name: &str, src_dir_path: &Path, dest_dir_path: &Path) -> tokio::io::Result<u64> {
     _copy_file(file_name, src_dir_path, dest_dir_path).await?;
     _remove_file(file_name, src_dir_path).await?;
     Ok(1)
 }
 
-pub async fn _rename_file(current_file_name: &str, new_file_name: &str, src_dir_path: &PathBuf) -> Result<(), Error> {
+pub async fn _rename_file(current_file_name: &str, new_file_name: &str, src_dir_path: &Path) -> Result<(), Error> {
     let src_path = src_dir_path.join(current_file_name);
     let dest_path = src_dir_path.join(new_file_name);
 
@@ -154,17 +186,17 @@ pub async fn _rename_file(current_file_name: &str, new_file_name: &str, src_dir_
 pub fn __generate_pic_label_file(pic_location: &str) -> String {
     let mut label_file_pathbuf = PathBuf::from(pic_location);
     label_file_pathbuf.set_extension("txt");
-    return label_file_pathbuf.to_str().unwrap().to_owned();
+    return label_file_pathbuf.to_string_lossy().into_owned();
 }
 
-pub async fn create_and_write_label_file(file_name: &str, input_data: &[u8], dest_dir_path: &PathBuf)  -> tokio::io::Result<()> {
+pub async fn create_and_write_label_file(file_name: &str, input_data: &[u8], dest_dir_path: &Path)  -> tokio::io::Result<()> {
     let dest_path = dest_dir_path.join(file_name);
     let mut file = File::create(dest_path).await?;
     file.write_all(input_data).await?;
     Ok(())
 }
 
-async fn __copy_files(files: Iter<'_, String>, src_dir_path: &PathBuf, dest_dir_path: &PathBuf) -> tokio::io::Result<u64> {
+async fn __copy_files(files: Iter<'_, String>, src_dir_path: &Path, dest_dir_path: &Path) -> tokio::io::Result<u64> {
     let mut total_write = 0;
     for file in files {
         let write_bytes = _copy_file(file.as_str(), src_dir_path, dest_dir_path).await?;
@@ -173,7 +205,7 @@ async fn __copy_files(files: Iter<'_, String>, src_dir_path: &PathBuf, dest_dir_
     Ok(total_write)
 }
 
-async fn __remove_files(files: Iter<'_, String>, src_dir_path: &PathBuf) -> tokio::io::Result<u64> {
+async fn __remove_files(files: Iter<'_, String>, src_dir_path: &Path) -> tokio::io::Result<u64> {
     let mut count = 0;
     let expected_count = files.len() as u64;
     for file in files {
@@ -195,11 +227,11 @@ async fn __remove_files(files: Iter<'_, String>, src_dir_path: &PathBuf) -> toki
     }
 }
 
-async fn __move_files(files: Iter<'_, String>, src_dir_path: &PathBuf, dest_dir_path: &PathBuf) -> tokio::io::Result<u64> {
+async fn __move_files(files: Iter<'_, String>, src_dir_path: &Path, dest_dir_path: &Path) -> tokio::io::Result<u64> {
     let files_to_remove = files.clone();
     match __copy_files(files, src_dir_path, dest_dir_path).await {
         Ok(_) => {
-            __remove_files(files_to_remove, &src_dir_path).await
+            __remove_files(files_to_remove, src_dir_path).await
         },
         Err(e) => Err(e)
     }
@@ -0,0 +1,275 @@
+use std::{collections::HashMap, env, fs, sync::OnceLock};
+
+use data_encoding::{BASE64, BASE64URL_NOPAD};
+use hmac::{Hmac, Mac};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha384;
+
+use crate::config::{AuthConfig, JwtKeyConfig};
+
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+// RFC 7518 wants HMAC keys at least as long as the hash.
+const MIN_SECRET_LENGTH: usize = 48;
+// Id of the key taken from `JWT_SECRET` when `auth.jwt_keys` is empty.
+const ENV_KEY_ID: &str = "default";
+
+/// Keys signing and verifying the tokens, found by the `kid` header.
+/// One key signs, the others only verify tokens signed before a rotation.
+pub struct Keyring {
+    signing_key_id: String,
+    keys: HashMap<String, Key>,
+}
+
+enum Key {
+    Hs384(Box<Hmac<Sha384>>),
+    EdDsa(Ed25519KeyPair),
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenHeader {
+    alg: String,
+    typ: String,
+    kid: String,
+}
+
+impl Keyring {
+    /// Read the keys of `auth.jwt_keys`, or the `JWT_SECRET` environment variable without any.
+    /// Fails without a key, the service never signs with a default secret.
+    pub fn from_config(auth_config: &AuthConfig) -> Result<Self, String> {
+        let key_configs = match auth_config.jwt_keys.is_empty() {
+            true => match env::var("JWT_SECRET") {
+                Ok(secret) => vec![JwtKeyConfig {
+                    id: ENV_KEY_ID.to_string(),
+                    algorithm: "HS384".to_string(),
+                    secret,
+                    ..Default::default()
+                }],
+                Err(_) => return Err("no signing key, configure auth.jwt_keys or set JWT_SECRET".to_string()),
+            },
+            false => auth_config.jwt_keys.clone(),
+        };
+        let signing_key_id = match auth_config.jwt_signing_key.is_empty() {
+            true => key_configs[0].id.clone(),
+            false => auth_config.jwt_signing_key.clone(),
+        };
+        let mut keys = HashMap::new();
+        for key_config in key_configs.iter() {
+            let key = Key::from_config(key_config).map_err(|err| format!("key {}: {err}", key_config.id))?;
+            keys.insert(key_config.id.clone(), key);
+        }
+        if !keys.contains_key(&signing_key_id) {
+            return Err(format!("no key {signing_key_id} to sign with"));
+        }
+        Ok(Keyring { signing_key_id, keys })
+    }
+
+    pub fn signing_key_id(&self) -> &str {
+        &self.signing_key_id
+    }
+
+    pub fn key_ids(&self) -> Vec<&str> {
+        let mut key_ids: Vec<&str> = self.keys.keys().map(String::as_str).collect();
+        key_ids.sort();
+        key_ids
+    }
+
+    /// The claims as a compact JWS, signed by the signing key and naming it in `kid`.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, String> {
+        let key = &self.keys[&self.signing_key_id];
+        let header = TokenHeader {
+            alg: key.algorithm().to_string(),
+            typ: "JWT".to_string(),
+            kid: self.signing_key_id.clone(),
+        };
+        let signing_input = format!("{}.{}", __encode_json(&header)?, __encode_json(claims)?);
+        let signature = key.sign(signing_input.as_bytes())?;
+        Ok(format!("{signing_input}.{}", BASE64URL_NOPAD.encode(&signature)))
+    }
+
+    /// The claims of a token whose signature the key of its `kid` verifies. Expiry is up to the caller.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T, String> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(claims), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err("malformed token".to_string());
+        };
+        let token_header: TokenHeader = __decode_json(header)?;
+        let key = self.keys.get(&token_header.kid).ok_or_else(|| format!("unknown key {}", token_header.kid))?;
+        // The algorithm belongs to the key, a token can't pick another one.
+        if token_header.alg != key.algorithm() {
+            return Err(format!("key {} doesn't sign with {}", token_header.kid, token_header.alg));
+        }
+        let signature = BASE64URL_NOPAD.decode(signature.as_bytes()).map_err(|err| err.to_string())?;
+        key.verify(format!("{header}.{claims}").as_bytes(), &signature)?;
+        __decode_json(claims)
+    }
+}
+
+impl Key {
+    fn from_config(key_config: &JwtKeyConfig) -> Result<Self, String> {
+        match key_config.algorithm.as_str() {
+            "HS384" => {
+                let secret = match key_config.key_file.is_empty() {
+                    true => key_config.secret.clone(),
+                    false => fs::read_to_string(&key_config.key_file)
+                        .map_err(|err| format!("couldn't read {}: {err}", key_config.key_file))?
+                        .trim_end_matches(['\r', '\n'])
+                        .to_string(),
+                };
+                if secret.len() < MIN_SECRET_LENGTH {
+                    return Err(format!("the secret must be at least {MIN_SECRET_LENGTH} bytes long"));
+                }
+                Ok(Key::Hs384(Box::new(Hmac::new_from_slice(secret.as_bytes()).map_err(|err| err.to_string())?)))
+            },
+            "EdDSA" => {
+                let file = fs::read(&key_config.key_file)
+                    .map_err(|err| format!("couldn't read {}: {err}", key_config.key_file))?;
+                let pkcs8 = __pem_to_der(&file)?;
+                // `openssl genpkey -algorithm ed25519` writes PKCS#8 v1, without the public key.
+                Ok(Key::EdDsa(Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8).map_err(|err| err.to_string())?))
+            },
+            algorithm => Err(format!("unknown algorithm {algorithm}")),
+        }
+    }
+
+    fn algorithm(&self) -> &'static str {
+        match self {
+            Key::Hs384(_) => "HS384",
+            Key::EdDsa(_) => "EdDSA",
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Key::Hs384(key) => {
+                let mut mac = key.as_ref().clone();
+                mac.update(message);
+                Ok(mac.finalize().into_bytes().to_vec())
+            },
+            Key::EdDsa(key_pair) => Ok(key_pair.sign(message).as_ref().to_vec()),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), String> {
+        let verified = match self {
+            Key::Hs384(key) => {
+                let mut mac = key.as_ref().clone();
+                mac.update(message);
+                mac.verify_slice(signature).is_ok()
+            },
+            Key::EdDsa(key_pair) => UnparsedPublicKey::new(&ED25519, key_pair.public_key().as_ref())
+                .verify(message, signature)
+                .is_ok(),
+        };
+        match verified {
+            true => Ok(()),
+            false => Err("invalid signature".to_string()),
+        }
+    }
+}
+
+pub fn init_keyring(keyring: Keyring) {
+    if KEYRING.set(keyring).is_err() {
+        panic!("The keyring has already been initialized!");
+    }
+}
+
+pub fn keyring() -> &'static Keyring {
+    KEYRING.get().expect("The keyring is not initialized!")
+}
+
+fn __encode_json<T: Serialize>(value: &T) -> Result<String, String> {
+    let json = serde_json::to_vec(value).map_err(|err| err.to_string())?;
+    Ok(BASE64URL_NOPAD.encode(&json))
+}
+
+fn __decode_json<T: DeserializeOwned>(part: &str) -> Result<T, String> {
+    let json = BASE64URL_NOPAD.decode(part.as_bytes()).map_err(|err| err.to_string())?;
+    serde_json::from_slice(&json).map_err(|err| err.to_string())
+}
+
+/// The DER inside a PEM file, the file itself when it isn't PEM.
+fn __pem_to_der(file: &[u8]) -> Result<Vec<u8>, String> {
+    let Ok(text) = std::str::from_utf8(file) else {
+        return Ok(file.to_vec());
+    };
+    if !text.contains("-----BEGIN") {
+        return Ok(file.to_vec());
+    }
+    let body: String = text.lines()
+        .skip_while(|line| !line.starts_with("-----BEGIN"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END"))
+        .map(str::trim)
+        .collect();
+    BASE64.decode(body.as_bytes()).map_err(|err| format!("invalid PEM: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+    use serde_json::{json, Value};
+
+    use super::Keyring;
+    use crate::config::{AuthConfig, JwtKeyConfig};
+
+    fn hs384(id: &str, secret: &str) -> JwtKeyConfig {
+        JwtKeyConfig { id: id.to_string(), algorithm: "HS384".to_string(), secret: secret.repeat(48), ..Default::default() }
+    }
+
+    fn keyring(signing_key: &str, jwt_keys: Vec<JwtKeyConfig>) -> Keyring {
+        Keyring::from_config(&AuthConfig {
+            jwt_signing_key: signing_key.to_string(),
+            jwt_keys,
+            ..Default::default()
+        }).unwrap()
+    }
+
+    #[test]
+    fn tokens_outlive_the_rotation_of_their_key() {
+        let claims = json!({"user_email": "a@b.cn"});
+        let before = keyring("old", vec![hs384("old", "a"), hs384("new", "b")]);
+        let token = before.sign(&claims).unwrap();
+
+        let after = keyring("new", vec![hs384("old", "a"), hs384("new", "b")]);
+        assert_eq!(after.verify::<Value>(&token).unwrap(), claims);
+        assert_ne!(after.sign(&claims).unwrap(), token);
+
+        let retired = keyring("new", vec![hs384("new", "b")]);
+        assert!(retired.verify::<Value>(&token).is_err());
+        // Another secret under the same id.
+        let replaced = keyring("old", vec![hs384("old", "c")]);
+        assert!(replaced.verify::<Value>(&token).is_err());
+    }
+
+    #[test]
+    fn ed25519_keys_sign_and_tokens_keep_their_algorithm() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_file = std::env::temp_dir().join(format!("insectsys-ed25519-{}.der", std::process::id()));
+        std::fs::write(&key_file, pkcs8.as_ref()).unwrap();
+        let ed25519 = JwtKeyConfig {
+            id: "ed".to_string(),
+            algorithm: "EdDSA".to_string(),
+            key_file: key_file.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let keyring = keyring("ed", vec![ed25519, hs384("hs", "a")]);
+        std::fs::remove_file(&key_file).unwrap();
+
+        let claims = json!({"user_email": "a@b.cn"});
+        let token = keyring.sign(&claims).unwrap();
+        assert_eq!(keyring.verify::<Value>(&token).unwrap(), claims);
+
+        // The header claims HMAC under the id of the Ed25519 key.
+        let parts: Vec<&str> = token.split('.').collect();
+        let header = data_encoding::BASE64URL_NOPAD.encode(br#"{"alg":"HS384","typ":"JWT","kid":"ed"}"#);
+        assert!(keyring.verify::<Value>(&format!("{header}.{}.{}", parts[1], parts[2])).is_err());
+    }
+
+    #[test]
+    fn no_key_no_keyring() {
+        let short = JwtKeyConfig { secret: "short".to_string(), ..hs384("a", "a") };
+        assert!(Keyring::from_config(&AuthConfig { jwt_keys: vec![short], ..Default::default() }).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use std::{fmt, fs, io, path::Path, time::Duration};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{MatchedPath, Request as AxumRequest},
+    http::{HeaderMap, Request, Uri},
+    middleware::Next,
+    response::Response
+};
+use chrono::Local;
+use tower_http::classify::ServerErrorsFailureClass;
+use tracing::{info_span, Span, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+    fmt::{format::Writer, time::FormatTime},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry
+};
+
+use crate::config::LogConfig;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const REDACTED: &str = "[redacted]";
+// Headers carrying credentials, compared in lowercase.
+const SENSITIVE_HEADERS: [&str; 6] = ["authorization", "proxy-authorization", "auth-token", "cookie", "set-cookie", "x-api-key"];
+// Query parameters whose name contains one of these are hidden.
+const SENSITIVE_PARAMS: [&str; 5] = ["password", "token", "secret", "key", "code"];
+// tokio-postgres logs statement parameters, password hashes included, at debug level.
+const QUERY_LOG_DIRECTIVE: &str = "tokio_postgres::query=info";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+struct LocalTimer;
+
+impl FormatTime for LocalTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        write!(w, "{}", Local::now().format("%FT%T%.3f"))
+    }
+}
+
+/// Install the global subscriber writing to stdout and/or daily rolling files.
+/// Keep the returned guard alive until exit, dropping it flushes the file writer.
+pub fn init_logging(log_config: &LogConfig) -> Result<Option<WorkerGuard>, String> {
+    let directives = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.is_empty() => directives,
+        _ => log_config.filter.clone(),
+    };
+    // Statement parameters are only logged when asked for explicitly.
+    let directives = match directives.contains("tokio_postgres") {
+        true => directives,
+        false => format!("{directives},{QUERY_LOG_DIRECTIVE}"),
+    };
+    let filter = EnvFilter::try_new(&directives).map_err(|err| format!("invalid log filter {directives:?}: {err}"))?;
+    let json = log_config.format == "json";
+
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = Vec::new();
+    if log_config.stdout {
+        layers.push(__format_layer(json, true, io::stdout));
+    }
+    let mut guard = None;
+    if !log_config.directory.is_empty() {
+        fs::create_dir_all(Path::new(&log_config.directory))
+            .map_err(|err| format!("failed to create {}: {err}", log_config.directory))?;
+        let file_appender = tracing_appender::rolling::daily(&log_config.directory, &log_config.file_prefix);
+        let (non_blocking, worker_guard) = tracing_appender::non_blocking(file_appender);
+        layers.push(__format_layer(json, false, non_blocking));
+        guard = Some(worker_guard);
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()
+        .map_err(|err| err.to_string())?;
+    Ok(guard)
+}
+
+fn __format_layer<S, W>(json: bool, ansi: bool, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+    where S: Subscriber + for<'span> LookupSpan<'span>,
+          W: for<'writer> tracing_subscriber::fmt::MakeWriter<'writer> + Send + Sync + 'static
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_timer(LocalTimer)
+        .with_target(true)
+        .with_level(true)
+        .with_writer(writer);
+    match json {
+        true => layer.json().with_current_span(true).with_span_list(false).boxed(),
+        false => layer.with_ansi(ansi).boxed(),
+    }
+}
+
+/// Make the request id readable by the code handling the request, see `current_request_id`.
+pub async fn middleware_request_id(request: AxumRequest, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    REQUEST_ID.scope(request_id, next.run(request)).await
+}
+
+/// Id of the request being handled, if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|request_id| request_id.clone())
+        .ok()
+        .filter(|request_id| !request_id.is_empty())
+}
+
+/// Span of one request, carrying its request id and route. The URI is logged without credentials.
+pub fn make_request_span(request: &Request<Body>) -> Span {
+    let matched_path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str);
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    info_span!(
+        "http_request",
+        request_id,
+        method = %request.method(),
+        uri = %redact_uri(request.uri()),
+        matched_path,
+    )
+}
+
+pub fn on_request(request: &Request<Body>, _span: &Span) {
+    tracing::debug!(headers = ?RedactedHeaders(request.headers()), "started processing request");
+}
+
+pub fn on_response(response: &Response, latency: Duration, _span: &Span) {
+    tracing::info!(
+        status = response.status().as_u16(),
+        latency_ms = latency.as_millis() as u64,
+        "finished processing request"
+    );
+    tracing::debug!(headers = ?RedactedHeaders(response.headers()), "response headers");
+}
+
+/// Only the size of a chunk is logged, bodies hold passwords and pictures.
+pub fn on_body_chunk(chunk: &Bytes, _latency: Duration, _span: &Span) {
+    tracing::trace!(size = chunk.len(), "sent body chunk");
+}
+
+pub fn on_eos(trailers: Option<&HeaderMap>, stream_duration: Duration, _span: &Span) {
+    tracing::debug!(
+        trailers = ?trailers.map(RedactedHeaders),
+        stream_duration_ms = stream_duration.as_millis() as u64,
+        "end of stream"
+    );
+}
+
+pub fn on_failure(error: ServerErrorsFailureClass, latency: Duration, _span: &Span) {
+    tracing::error!(%error, latency_ms = latency.as_millis() as u64, "request failed");
+}
+
+/// Debug view of headers with the values of credentials replaced.
+pub struct RedactedHeaders<'a>(pub &'a HeaderMap);
+
+impl fmt::Debug for RedactedHeaders<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (name, value) in self.0.iter() {
+            if SENSITIVE_HEADERS.contains(&name.as_str()) {
+                map.entry(&name.as_str(), &REDACTED);
+            } else {
+                map.entry(&name.as_str(), &value.to_str().unwrap_or("[binary]"));
+            }
+        }
+        map.finish()
+    }
+}
+
+/// The URI with the values of credential-like query parameters replaced.
+pub fn redact_uri(uri: &Uri) -> String {
+    let query = match uri.query() {
+        Some(query) => query,
+        None => return uri.path().to_string(),
+    };
+    let redacted_query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if __is_sensitive_param(name) => format!("{name}={REDACTED}"),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("&");
+    format!("{}?{redacted_query}", uri.path())
+}
+
+fn __is_sensitive_param(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SENSITIVE_PARAMS.iter().any(|sensitive| name.contains(sensitive))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, sync::{Arc, Mutex}};
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{HeaderMap, HeaderValue, Request, Uri},
+        middleware,
+        routing::get,
+        Router
+    };
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+    use tracing_subscriber::{layer::SubscriberExt, EnvFilter};
+
+    use super::*;
+
+    /// Log output kept in memory.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn credentials_are_redacted() {
+        let uri: Uri = "/reset_password?Token=abc123&new_password=hunter2&page=2".parse().unwrap();
+        assert_eq!(redact_uri(&uri), "/reset_password?Token=[redacted]&new_password=[redacted]&page=2");
+        assert_eq!(redact_uri(&"/wiki".parse().unwrap()), "/wiki");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("auth-token", HeaderValue::from_static("jwt-secret"));
+        headers.insert("authorization", HeaderValue::from_static("ApiKey key-secret"));
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        let logged = format!("{:?}", RedactedHeaders(&headers));
+        assert!(!logged.contains("secret"), "{logged}");
+        assert!(logged.contains(r#""auth-token": "[redacted]""#), "{logged}");
+        assert!(logged.contains(r#""content-type": "application/json""#), "{logged}");
+    }
+
+    #[tokio::test]
+    async fn requests_are_logged_with_their_id() {
+        let logs = Capture::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(__format_layer(false, false, move || writer.clone()))
+            .with(EnvFilter::new("debug"));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route("/id", get(|| async { current_request_id().unwrap_or_default() }))
+            .layer(middleware::from_fn(middleware_request_id))
+            .layer(TraceLayer::new_for_http().make_span_with(make_request_span).on_request(on_request).on_response(on_response));
+        let request = Request::get("/id?password=hunter2")
+            .header(REQUEST_ID_HEADER, "req-7")
+            .header("auth-token", "jwt-secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), "req-7");
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains(r#"request_id="req-7""#), "{logs}");
+        assert!(logs.contains("finished processing request"), "{logs}");
+        assert!(logs.contains("password=[redacted]"), "{logs}");
+        assert!(!logs.contains("hunter2") && !logs.contains("jwt-secret"), "{logs}");
+    }
+}
@@ -0,0 +1,397 @@
+use std::{fmt, fs, sync::Arc, time::Duration};
+
+use axum::async_trait;
+use chrono::Local;
+use data_encoding::BASE64;
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, ServerName},
+    ClientConfig, RootCertStore
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream
+};
+use tokio_rustls::TlsConnector;
+
+use crate::config::MailConfig;
+
+// Built-in templates, replaced by the files of the same name in `mail.template_directory`.
+const TEMPLATES: [(&str, &str); 2] = [
+    ("verify_email", include_str!("../templates/mail/verify_email.txt")),
+    ("reset_password", include_str!("../templates/mail/reset_password.txt")),
+];
+
+/// A plain text message to one recipient.
+#[derive(Clone, Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers the mail of the service.
+#[async_trait]
+pub trait Mailer: Send + Sync + fmt::Debug {
+    async fn send(&self, mail: &Mail) -> Result<(), String>;
+}
+
+impl Mail {
+    /// Render the template `name` for `to`, replacing each `{{key}}` by its value.
+    /// The first line of a template is `Subject: ...`, the body follows a blank line.
+    pub fn from_template(mail_config: &MailConfig, name: &str, to: &str, values: &[(&str, &str)]) -> Result<Self, String> {
+        let template = match mail_config.template_directory.is_empty() {
+            true => TEMPLATES.iter()
+                .find(|(template_name, _)| *template_name == name)
+                .map(|(_, template)| template.to_string())
+                .ok_or_else(|| format!("no template {name}"))?,
+            false => {
+                let path = format!("{}/{name}.txt", mail_config.template_directory.trim_end_matches('/'));
+                fs::read_to_string(&path).map_err(|err| format!("couldn't read {path}: {err}"))?
+            },
+        };
+        let mut rendered = template.replace("\r\n", "\n");
+        for (key, value) in values {
+            rendered = rendered.replace(&format!("{{{{{key}}}}}"), value);
+        }
+        let (subject, body) = rendered.split_once("\n\n")
+            .and_then(|(head, body)| Some((head.strip_prefix("Subject:")?.trim(), body)))
+            .ok_or_else(|| format!("template {name} should start with a `Subject:` line and a blank line"))?;
+        Ok(Mail { to: to.to_string(), subject: subject.to_string(), body: body.to_string() })
+    }
+}
+
+/// SMTP client of `[mail]`, one connection per message.
+pub struct SmtpMailer {
+    mail_config: MailConfig,
+    tls_connector: Option<TlsConnector>, // with "starttls" and "tls"
+}
+
+impl fmt::Debug for SmtpMailer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpMailer")
+            .field("server", &format!("{}:{}", self.mail_config.smtp_host, self.mail_config.smtp_port))
+            .field("security", &self.mail_config.security)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SmtpMailer {
+    /// Fails when the CA file of a TLS connection can't be read.
+    pub fn from_config(mail_config: &MailConfig) -> Result<Self, String> {
+        let tls_connector = match mail_config.security.as_str() {
+            "none" => None,
+            _ => Some(__tls_connector(&mail_config.ca_file)?),
+        };
+        Ok(SmtpMailer { mail_config: mail_config.clone(), tls_connector })
+    }
+
+    async fn deliver(&self, mail: &Mail) -> Result<(), String> {
+        let mail_config = &self.mail_config;
+        let stream = TcpStream::connect((mail_config.smtp_host.as_str(), mail_config.smtp_port))
+            .await
+            .map_err(|err| format!("couldn't connect to {}:{}: {err}", mail_config.smtp_host, mail_config.smtp_port))?;
+        match (mail_config.security.as_str(), &self.tls_connector) {
+            ("tls", Some(tls_connector)) => {
+                let stream = tls_connector.connect(self.server_name()?, stream).await.map_err(|err| err.to_string())?;
+                let mut connection = SmtpConnection::greet(stream, self.hello_name()).await?;
+                self.transaction(&mut connection, mail).await
+            },
+            ("starttls", Some(tls_connector)) => {
+                let mut connection = SmtpConnection::greet(stream, self.hello_name()).await?;
+                connection.command("STARTTLS", 220).await?;
+                let stream = tls_connector.connect(self.server_name()?, connection.into_inner())
+                    .await
+                    .map_err(|err| err.to_string())?;
+                // Whatever the server said before TLS is forgotten, so it is greeted again.
+                let mut connection = SmtpConnection { stream: BufReader::new(stream) };
+                connection.command(&format!("EHLO {}", self.hello_name()), 250).await?;
+                self.transaction(&mut connection, mail).await
+            },
+            _ => {
+                let mut connection = SmtpConnection::greet(stream, self.hello_name()).await?;
+                self.transaction(&mut connection, mail).await
+            },
+        }
+    }
+
+    async fn transaction<S: AsyncRead + AsyncWrite + Unpin>(&self, connection: &mut SmtpConnection<S>, mail: &Mail) -> Result<(), String> {
+        let mail_config = &self.mail_config;
+        if !mail_config.username.is_empty() {
+            let credentials = format!("\0{}\0{}", mail_config.username, mail_config.password);
+            connection.command(&format!("AUTH PLAIN {}", BASE64.encode(credentials.as_bytes())), 235).await?;
+        }
+        connection.command(&format!("MAIL FROM:<{}>", mail_config.from_address), 250).await?;
+        connection.command(&format!("RCPT TO:<{}>", mail.to), 250).await?;
+        connection.command("DATA", 354).await?;
+        connection.command(&format!("{}\r\n.", self.message(mail)?), 250).await?;
+        // The message is accepted already.
+        let _ = connection.command("QUIT", 221).await;
+        Ok(())
+    }
+
+    /// Headers and the base64 body, no line of which starts with a dot.
+    fn message(&self, mail: &Mail) -> Result<String, String> {
+        let mut message_id = [0u8; 16];
+        SystemRandom::new().fill(&mut message_id).map_err(|_| "failed to generate a message id".to_string())?;
+        let body = BASE64.encode(mail.body.replace('\n', "\r\n").as_bytes());
+        let body_lines: Vec<&str> = body.as_bytes()
+            .chunks(76)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect();
+        Ok([
+            format!("Date: {}", Local::now().to_rfc2822()),
+            format!("From: {} <{}>", __encoded_word(&self.mail_config.from_name), self.mail_config.from_address),
+            format!("To: <{}>", mail.to),
+            format!("Subject: {}", __encoded_word(&mail.subject)),
+            format!("Message-ID: <{}@{}>", hex::encode(message_id), self.hello_name()),
+            "MIME-Version: 1.0".to_string(),
+            "Content-Type: text/plain; charset=UTF-8".to_string(),
+            "Content-Transfer-Encoding: base64".to_string(),
+            String::new(),
+            body_lines.join("\r\n"),
+        ].join("\r\n"))
+    }
+
+    fn server_name(&self) -> Result<ServerName<'static>, String> {
+        ServerName::try_from(self.mail_config.smtp_host.clone())
+            .map_err(|err| format!("invalid server name {}: {err}", self.mail_config.smtp_host))
+    }
+
+    /// Domain of the sender, named in `EHLO` and the message ids.
+    fn hello_name(&self) -> &str {
+        self.mail_config.from_address
+            .split_once('@')
+            .map_or("localhost", |(_, domain)| domain)
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        // Both end up in commands and headers, nothing is written unless they are plain addresses.
+        for address in [&mail.to, &self.mail_config.from_address] {
+            if !_address_is_valid(address) {
+                return Err(format!("invalid address {address:?}"));
+            }
+        }
+        tokio::time::timeout(Duration::from_secs(self.mail_config.timeout), self.deliver(mail))
+            .await
+            .map_err(|_| format!("no answer from {} in time", self.mail_config.smtp_host))?
+    }
+}
+
+struct SmtpConnection<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SmtpConnection<S> {
+    /// Wait for the greeting of the server and introduce ourselves.
+    async fn greet(stream: S, hello_name: &str) -> Result<Self, String> {
+        let mut connection = SmtpConnection { stream: BufReader::new(stream) };
+        connection.reply(220).await?;
+        connection.command(&format!("EHLO {hello_name}"), 250).await?;
+        Ok(connection)
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<String, String> {
+        self.stream.get_mut()
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await
+            .map_err(|err| err.to_string())?;
+        self.stream.get_mut().flush().await.map_err(|err| err.to_string())?;
+        self.reply(expected).await
+            .map_err(|err| format!("{} refused: {err}", command.split(' ').next().unwrap_or_default()))
+    }
+
+    /// Read a reply, of several `250-...` lines before the last `250 ...` one.
+    async fn reply(&mut self, expected: u16) -> Result<String, String> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await.map_err(|err| err.to_string())? == 0 {
+                return Err("the server closed the connection".to_string());
+            }
+            reply.push_str(&line);
+            if line.len() < 4 || line.as_bytes()[3] != b'-' {
+                break;
+            }
+        }
+        match reply.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+            Some(code) if code == expected => Ok(reply),
+            _ => Err(reply.trim_end().to_string()),
+        }
+    }
+
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+}
+
+fn __tls_connector(ca_file: &str) -> Result<TlsConnector, String> {
+    let mut roots = RootCertStore::empty();
+    let certificates = CertificateDer::pem_file_iter(ca_file).map_err(|err| format!("couldn't read {ca_file}: {err}"))?;
+    for certificate in certificates {
+        let certificate = certificate.map_err(|err| format!("invalid certificate in {ca_file}: {err}"))?;
+        roots.add(certificate).map_err(|err| format!("invalid certificate in {ca_file}: {err}"))?;
+    }
+    let client_config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(client_config)))
+}
+
+/// Whether the address is a plain `name@domain`, safe to put in an SMTP command or a header.
+pub fn _address_is_valid(address: &str) -> bool {
+    let Some((name, domain)) = address.split_once('@') else {
+        return false;
+    };
+    address.len() <= 254
+        && !name.is_empty()
+        && !domain.is_empty()
+        && !domain.contains('@')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains("..")
+        && !address.chars().any(|c| c.is_whitespace() || c.is_control() || "<>()[],;:\\\"".contains(c))
+}
+
+/// RFC 2047 form of a header text, for the Chinese of the templates.
+fn __encoded_word(text: &str) -> String {
+    match text.is_ascii() {
+        true => text.to_string(),
+        false => format!("=?UTF-8?B?{}?=", BASE64.encode(text.as_bytes())),
+    }
+}
+
+/// Keeps the mail instead of sending it, for the tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryMailer {
+    outbox: std::sync::Mutex<Vec<Mail>>,
+}
+
+#[cfg(test)]
+impl MemoryMailer {
+    pub fn outbox(&self) -> Vec<Mail> {
+        self.outbox.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), String> {
+        self.outbox.lock().unwrap().push(mail.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::TcpListener, task::JoinHandle};
+
+    use super::{_address_is_valid, Mail, Mailer, SmtpMailer};
+    use crate::config::MailConfig;
+
+    #[test]
+    fn templates_render_a_subject_and_a_body() {
+        let mail = Mail::from_template(&MailConfig::default(), "reset_password", "a@b.cn", &[
+            ("nick_name", "tester"), ("link", "http://x/reset_password?token=t"), ("expires_at", "2026-01-01 00:00"),
+        ]).unwrap();
+        assert_eq!(mail.subject, "重置您的密码 / Reset your password");
+        assert!(mail.body.starts_with("tester，您好"));
+        assert!(mail.body.contains("http://x/reset_password?token=t"));
+        assert!(!mail.body.contains("{{"));
+    }
+
+    /// A server accepting one connection, which answers every command and returns the transcript.
+    async fn smtp_server() -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(b"220 test ESMTP\r\n").await.unwrap();
+            let mut transcript = String::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                transcript.push_str(&line);
+                let reply: &[u8] = match line.trim_end() {
+                    "." if in_data => { in_data = false; b"250 queued\r\n" },
+                    _ if in_data => continue,
+                    "DATA" => { in_data = true; b"354 go ahead\r\n" },
+                    "QUIT" => b"221 bye\r\n",
+                    line if line.starts_with("EHLO") => b"250-test\r\n250 8BITMIME\r\n",
+                    line if line.starts_with("AUTH PLAIN") => b"235 ok\r\n",
+                    _ => b"250 ok\r\n",
+                };
+                stream.get_mut().write_all(reply).await.unwrap();
+            }
+            transcript
+        });
+        (port, server)
+    }
+
+    #[tokio::test]
+    async fn smtp_delivers_to_a_plain_server() {
+        let (port, server) = smtp_server().await;
+        let mail_config = MailConfig {
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: port,
+            username: "user".to_string(),
+            password: "pass".to_string(),
+            from_address: "no-reply@insects.cn".to_string(),
+            ..Default::default()
+        };
+        let mailer = SmtpMailer::from_config(&mail_config).unwrap();
+        let mail = Mail { to: "a@b.cn".to_string(), subject: "你好".to_string(), body: "line\n.dot".to_string() };
+        mailer.send(&mail).await.unwrap();
+
+        let transcript = server.await.unwrap();
+        assert!(transcript.starts_with("EHLO insects.cn\r\nAUTH PLAIN AHVzZXIAcGFzcw==\r\nMAIL FROM:<no-reply@insects.cn>\r\n"));
+        assert!(transcript.contains("RCPT TO:<a@b.cn>\r\n"));
+        assert!(transcript.contains("Subject: =?UTF-8?B?5L2g5aW9?=\r\n"));
+        assert!(transcript.ends_with(".\r\nQUIT\r\n"));
+    }
+
+    #[tokio::test]
+    async fn injected_addresses_never_reach_the_server() {
+        let (port, server) = smtp_server().await;
+        let mail_config = MailConfig {
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: port,
+            from_address: "no-reply@insects.cn".to_string(),
+            ..Default::default()
+        };
+        let mailer = SmtpMailer::from_config(&mail_config).unwrap();
+        let injected = Mail {
+            to: "a@b.cn>\r\nRCPT TO:<evil@x.cn".to_string(),
+            subject: "hi".to_string(),
+            body: "body".to_string(),
+        };
+        assert!(mailer.send(&injected).await.is_err());
+        let sender = SmtpMailer::from_config(&MailConfig { from_address: "no-reply@insects.cn>\r\nX: y".to_string(), ..mail_config.clone() })
+            .unwrap();
+        assert!(sender.send(&Mail { to: "a@b.cn".to_string(), ..injected.clone() }).await.is_err());
+
+        // Refused before connecting, the only connection is the valid mail.
+        mailer.send(&Mail { to: "a@b.cn".to_string(), ..injected }).await.unwrap();
+        let transcript = server.await.unwrap();
+        assert!(transcript.contains("RCPT TO:<a@b.cn>\r\n"));
+        assert!(!transcript.contains("evil") && !transcript.contains("X: y"));
+
+        for address in ["a@b.cn", "first.last+tag@mail.insects.cn"] {
+            assert!(_address_is_valid(address), "{address}");
+        }
+        for address in ["", "a", "@b.cn", "a@", "a@b@c.cn", "a@.cn", "a@b..cn", "a b@c.cn", "<a@b.cn>", "a@b.cn\n"] {
+            assert!(!_address_is_valid(address), "{address}");
+        }
+    }
+}
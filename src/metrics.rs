@@ -0,0 +1,172 @@
+use std::{path::Path, sync::OnceLock, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response}
+};
+use deadpool_postgres::Pool;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder
+};
+
+use crate::{config::app_config, error::{AppError, ErrorResponses}, repository::Repositories, MultiState};
+
+/// Every metric exported on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub inference_duration: Histogram,
+    pub inference_failures: IntCounterVec,
+    pub species_predictions: IntCounterVec,
+    pub feedback_queue_depth: IntGaugeVec,
+    pub daemon_task_runs: IntCounterVec,
+    pub rate_limited_requests: IntCounterVec,
+    pub db_pool_max_size: IntGauge,
+    pub db_pool_size: IntGauge,
+    pub db_pool_available: IntGauge,
+    pub db_pool_waiting: IntGauge,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("insectsys".to_string()), None).unwrap();
+        let metrics = Metrics {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route, method and status."),
+                &["method", "route", "status"]
+            ).unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route and method."),
+                &["method", "route"]
+            ).unwrap(),
+            inference_duration: Histogram::with_opts(
+                HistogramOpts::new("inference_duration_seconds", "Duration of one image inference.")
+                    .buckets(vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0])
+            ).unwrap(),
+            inference_failures: IntCounterVec::new(
+                Opts::new("inference_failures_total", "Failed image inferences by reason."),
+                &["reason"]
+            ).unwrap(),
+            species_predictions: IntCounterVec::new(
+                Opts::new("species_predictions_total", "Inference results by predicted species."),
+                &["species"]
+            ).unwrap(),
+            feedback_queue_depth: IntGaugeVec::new(
+                Opts::new("feedback_queue_depth", "Pending items of the feedback pipeline: tfeedback, ufeedback and data2train."),
+                &["queue"]
+            ).unwrap(),
+            daemon_task_runs: IntCounterVec::new(
+                Opts::new("daemon_task_runs_total", "Finished daemon task runs by task and outcome."),
+                &["task", "outcome"]
+            ).unwrap(),
+            rate_limited_requests: IntCounterVec::new(
+                Opts::new("rate_limited_requests_total", "Requests rejected by the rate limiter by route group and exhausted budget: ip or identity."),
+                &["group", "key"]
+            ).unwrap(),
+            db_pool_max_size: IntGauge::new("db_pool_max_size", "Maximum connections of the database pool.").unwrap(),
+            db_pool_size: IntGauge::new("db_pool_size", "Open connections of the database pool.").unwrap(),
+            db_pool_available: IntGauge::new("db_pool_available", "Idle connections of the database pool.").unwrap(),
+            db_pool_waiting: IntGauge::new("db_pool_waiting", "Requests waiting for a database connection.").unwrap(),
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.http_requests.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.http_request_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.inference_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.inference_failures.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.species_predictions.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.feedback_queue_depth.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.daemon_task_runs.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.rate_limited_requests.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.db_pool_max_size.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.db_pool_size.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.db_pool_available.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.db_pool_waiting.clone())).unwrap();
+        metrics
+    }
+}
+
+/// Count and time every request by its route template, next to the TraceLayer.
+pub async fn middleware_track_metrics(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let latency = started.elapsed().as_secs_f64();
+
+    let status = response.status().as_u16().to_string();
+    metrics().http_requests.with_label_values(&[&method, &route, &status]).inc();
+    metrics().http_request_duration.with_label_values(&[&method, &route]).observe(latency);
+    response
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses(
+        (status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    )
+)]
+pub async fn handler_metrics(
+    State(multi_state): State<MultiState>
+) -> Result<Response, AppError> {
+    if let Some(pool) = &multi_state.db_pool {
+        __refresh_pool_gauges(pool);
+    }
+    if let Err(err) = __refresh_queue_depths(&multi_state.repositories).await {
+        tracing::warn!("Failed to refresh the feedback queue depths: {err}");
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&metrics().registry.gather(), &mut buffer)
+        .map_err(|err| AppError::Internal(err.to_string()))?;
+    Ok(([(header::CONTENT_TYPE, encoder.format_type().to_string())], buffer).into_response())
+}
+
+fn __refresh_pool_gauges(pool: &Pool) {
+    let status = pool.status();
+    metrics().db_pool_max_size.set(status.max_size as i64);
+    metrics().db_pool_size.set(status.size as i64);
+    metrics().db_pool_available.set(status.available as i64);
+    metrics().db_pool_waiting.set(status.waiting as i64);
+}
+
+/// Rows waiting in TFeedback and UFeedback, and labelled pictures waiting in the data2train directory.
+async fn __refresh_queue_depths(repositories: &Repositories) -> Result<(), String> {
+    let data_to_train_dir = app_config().storage.data_to_train_directory.clone();
+    let data_to_train = tokio::task::spawn_blocking(move || __count_label_files(Path::new(&data_to_train_dir)))
+        .await
+        .map_err(|err| err.to_string())?;
+    metrics().feedback_queue_depth.with_label_values(&["data2train"]).set(data_to_train);
+
+    let (trainable, unlabelled) = repositories.feedback.count().await.map_err(|err| err.to_string())?;
+    metrics().feedback_queue_depth.with_label_values(&["tfeedback"]).set(trainable);
+    metrics().feedback_queue_depth.with_label_values(&["ufeedback"]).set(unlabelled);
+    Ok(())
+}
+
+/// Every accepted picture comes with a `.txt` label file.
+fn __count_label_files(directory: &Path) -> i64 {
+    std::fs::read_dir(directory)
+        .map(|entries| entries
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "txt"))
+            .count() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+/// One schema change, embedded from `migrations/<version>_<name>.{up,down}.sql`.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+/// Every PostgreSQL migration in the order it is applied. Never edit an applied migration, add a new one.
+pub static MIGRATIONS: [Migration; 9] = [
+    Migration {
+        version: 1,
+        name: "initial",
+        up: include_str!("../migrations/0001_initial.up.sql"),
+        down: include_str!("../migrations/0001_initial.down.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "daemon_tasks",
+        up: include_str!("../migrations/0002_daemon_tasks.up.sql"),
+        down: include_str!("../migrations/0002_daemon_tasks.down.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "wiki",
+        up: include_str!("../migrations/0003_wiki.up.sql"),
+        down: include_str!("../migrations/0003_wiki.down.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "inference_history",
+        up: include_str!("../migrations/0004_inference_history.up.sql"),
+        down: include_str!("../migrations/0004_inference_history.down.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "sessions",
+        up: include_str!("../migrations/0005_sessions.up.sql"),
+        down: include_str!("../migrations/0005_sessions.down.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "account_tokens",
+        up: include_str!("../migrations/0006_account_tokens.up.sql"),
+        down: include_str!("../migrations/0006_account_tokens.down.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "two_factor",
+        up: include_str!("../migrations/0007_two_factor.up.sql"),
+        down: include_str!("../migrations/0007_two_factor.down.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "api_keys",
+        up: include_str!("../migrations/0008_api_keys.up.sql"),
+        down: include_str!("../migrations/0008_api_keys.down.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "task_settings",
+        up: include_str!("../migrations/0009_task_settings.up.sql"),
+        down: include_str!("../migrations/0009_task_settings.down.sql"),
+    },
+];
+
+/// The same schema for SQLite, from `migrations/sqlite/`. Every migration has the version and name
+/// of its PostgreSQL counterpart, so both backends report the same status.
+pub static SQLITE_MIGRATIONS: [Migration; 9] = [
+    Migration {
+        version: 1,
+        name: "initial",
+        up: include_str!("../migrations/sqlite/0001_initial.up.sql"),
+        down: include_str!("../migrations/sqlite/0001_initial.down.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "daemon_tasks",
+        up: include_str!("../migrations/sqlite/0002_daemon_tasks.up.sql"),
+        down: include_str!("../migrations/sqlite/0002_daemon_tasks.down.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "wiki",
+        up: include_str!("../migrations/sqlite/0003_wiki.up.sql"),
+        down: include_str!("../migrations/sqlite/0003_wiki.down.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "inference_history",
+        up: include_str!("../migrations/sqlite/0004_inference_history.up.sql"),
+        down: include_str!("../migrations/sqlite/0004_inference_history.down.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "sessions",
+        up: include_str!("../migrations/sqlite/0005_sessions.up.sql"),
+        down: include_str!("../migrations/sqlite/0005_sessions.down.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "account_tokens",
+        up: include_str!("../migrations/sqlite/0006_account_tokens.up.sql"),
+        down: include_str!("../migrations/sqlite/0006_account_tokens.down.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "two_factor",
+        up: include_str!("../migrations/sqlite/0007_two_factor.up.sql"),
+        down: include_str!("../migrations/sqlite/0007_two_factor.down.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "api_keys",
+        up: include_str!("../migrations/sqlite/0008_api_keys.up.sql"),
+        down: include_str!("../migrations/sqlite/0008_api_keys.down.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "task_settings",
+        up: include_str!("../migrations/sqlite/0009_task_settings.up.sql"),
+        down: include_str!("../migrations/sqlite/0009_task_settings.down.sql"),
+    },
+];
+
+/// Applied migrations, times are Unix epoch milliseconds. The statements below suit both backends.
+pub const CREATE_SCHEMA_MIGRATIONS: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version         BIGINT PRIMARY KEY,
+        name            VARCHAR NOT NULL,
+        checksum        VARCHAR NOT NULL,
+        applied_at      BIGINT NOT NULL
+    );
+";
+pub const SELECT_SCHEMA_MIGRATIONS: &str = "SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version;";
+pub const INSERT_SCHEMA_MIGRATION: &str = "INSERT INTO schema_migrations (version, name, checksum, applied_at) VALUES ($1, $2, $3, $4);";
+pub const DELETE_SCHEMA_MIGRATION: &str = "DELETE FROM schema_migrations WHERE version = $1;";
+/// Key of the advisory lock serializing concurrent `migrate` runs.
+pub const MIGRATION_LOCK_KEY: i64 = 0x696e_7365_6374;
+
+/// A row of `schema_migrations`.
+#[derive(Debug, Clone)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    Modified, // applied, but the embedded SQL changed since
+    Unknown, // applied by a newer version of the service
+}
+
+impl fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::Modified => "modified",
+            MigrationState::Unknown => "unknown",
+        };
+        f.pad(state)
+    }
+}
+
+/// Migrations of a `database.backend`.
+pub fn embedded(backend: &str) -> &'static [Migration] {
+    match backend {
+        "sqlite" => &SQLITE_MIGRATIONS,
+        _ => &MIGRATIONS,
+    }
+}
+
+impl Migration {
+    /// SHA-256 of the up migration, recorded when it is applied.
+    pub fn checksum(&self) -> String {
+        hex::encode(Sha256::digest(self.up.as_bytes()))
+    }
+}
+
+/// State of every embedded and every applied migration as (version, name, state), ordered by version.
+pub fn migration_status(migrations: &[Migration], applied: &[AppliedMigration]) -> Vec<(i64, String, MigrationState)> {
+    let mut status: Vec<(i64, String, MigrationState)> = migrations.iter()
+        .map(|migration| {
+            let state = match applied.iter().find(|row| row.version == migration.version) {
+                Some(row) if row.checksum == migration.checksum() => MigrationState::Applied,
+                Some(_) => MigrationState::Modified,
+                None => MigrationState::Pending,
+            };
+            (migration.version, migration.name.to_string(), state)
+        })
+        .collect();
+    status.extend(applied.iter()
+        .filter(|row| !migrations.iter().any(|migration| migration.version == row.version))
+        .map(|row| (row.version, row.name.clone(), MigrationState::Unknown)));
+    status.sort_by_key(|(version, _, _)| *version);
+    status
+}
+
+/// Whether the service may run against a schema with these migrations applied.
+/// Migrations of a newer version are tolerated, missing or modified ones are not.
+pub fn check_schema(migrations: &[Migration], applied: &[AppliedMigration]) -> Result<(), String> {
+    let status = migration_status(migrations, applied);
+    let describe = |state: MigrationState| status.iter()
+        .filter(|(_, _, migration_state)| *migration_state == state)
+        .map(|(version, name, _)| format!("{version:04}_{name}"))
+        .collect::<Vec<String>>();
+    let modified = describe(MigrationState::Modified);
+    if !modified.is_empty() {
+        return Err(format!("applied migrations differ from the embedded ones: {}", modified.join(", ")));
+    }
+    let pending = describe(MigrationState::Pending);
+    if !pending.is_empty() {
+        return Err(format!("the schema is out of date, run `init migrate up` to apply {}", pending.join(", ")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_schema, migration_status, AppliedMigration, MigrationState, MIGRATIONS, SQLITE_MIGRATIONS};
+
+    fn applied(versions: &[i64]) -> Vec<AppliedMigration> {
+        versions.iter()
+            .map(|version| {
+                let migration = MIGRATIONS.iter().find(|migration| migration.version == *version);
+                AppliedMigration {
+                    version: *version,
+                    name: migration.map_or("newer", |migration| migration.name).to_string(),
+                    checksum: migration.map(|migration| migration.checksum()).unwrap_or_default(),
+                    applied_at: 0,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn migrations_are_ordered_and_reversible() {
+        for pair in MIGRATIONS.windows(2) {
+            assert!(pair[0].version < pair[1].version, "{} comes after {}", pair[0].name, pair[1].name);
+        }
+        for migration in MIGRATIONS.iter().chain(SQLITE_MIGRATIONS.iter()) {
+            assert!(!migration.up.trim().is_empty() && !migration.down.trim().is_empty(), "{} is empty", migration.name);
+        }
+    }
+
+    #[test]
+    fn sqlite_migrations_mirror_postgres() {
+        assert_eq!(MIGRATIONS.len(), SQLITE_MIGRATIONS.len());
+        for (postgres, sqlite) in MIGRATIONS.iter().zip(SQLITE_MIGRATIONS.iter()) {
+            assert_eq!((postgres.version, postgres.name), (sqlite.version, sqlite.name));
+        }
+    }
+
+    #[test]
+    fn schema_check() {
+        let all: Vec<i64> = MIGRATIONS.iter().map(|migration| migration.version).collect();
+        assert!(check_schema(&MIGRATIONS, &applied(&all)).is_ok());
+        assert!(check_schema(&MIGRATIONS, &applied(&all[..all.len() - 1])).unwrap_err().contains("out of date"));
+
+        let mut modified = applied(&all);
+        modified[0].checksum = "0".repeat(64);
+        assert_eq!(migration_status(&MIGRATIONS, &modified)[0].2, MigrationState::Modified);
+        assert!(check_schema(&MIGRATIONS, &modified).is_err());
+
+        let newer = applied(&[all.clone(), vec![9999]].concat());
+        assert_eq!(migration_status(&MIGRATIONS, &newer).last().unwrap().2, MigrationState::Unknown);
+        assert!(check_schema(&MIGRATIONS, &newer).is_ok());
+    }
+}
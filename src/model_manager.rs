@@ -1,19 +1,23 @@
 use std::{env, fs};
 
-use axum::{extract::State, http::StatusCode, Form, Json};
+use axum::{Form, Json};
 use chrono::{DateTime, Local};
 use futures::TryFutureExt;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::{authenticator::{check_permission, Permission}, io_agent::{backup_models, _path_is_valid, remove_models}, MultiState};
+use crate::{
+    error::{parse_json_field, AppError, ErrorResponses},
+    io_agent::{backup_models, _path_is_valid, remove_models}
+};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct RequestFetchModels {
-    useremail: String,
-    request_dir: String,
+    pub request_dir: String,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 pub struct FileMetadata {
     file_name: String,
     file_type: String,
@@ -23,76 +27,108 @@ pub struct FileMetadata {
     creation_date: String,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 pub struct RequestFileOperation {
-    useremail: String,
     operation_type: String,
     files2operate: String // Json String
 }
 
 // #[debug_handler]
+#[utoipa::path(
+    get,
+    path = "/admin/model_manage",
+    tag = "model",
+    params(RequestFetchModels),
+    responses(
+        (status = 200, description = "Files in the directory", body = Vec<FileMetadata>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_fetch_all_models(
-    State(multi_state): State<MultiState>,
     Form(request): Form<RequestFetchModels>
-) -> Result<Json<Vec<FileMetadata>>, (StatusCode, String)> {
-    if !check_permission(&multi_state.db_pool, &request.useremail, Permission::MngModel).await.unwrap() {
-        return Err(
-            (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-        );
-    }
+) -> Result<Json<Vec<FileMetadata>>, AppError> {
+    Ok(Json(_fetch_all_models(&request).await?))
+}
+
+/// Metadata of every file in a directory below the working directory.
+pub async fn _fetch_all_models(request: &RequestFetchModels) -> Result<Vec<FileMetadata>, AppError> {
     if !_path_is_valid(&request.request_dir) {
-        return Err((StatusCode::BAD_REQUEST, "Invalid path".to_owned()));
+        return Err(AppError::BadRequest("Invalid path".to_owned()));
     }
     let mut file_list: Vec<FileMetadata> = Vec::new();
-    let current_dir = env::current_dir().unwrap().join(&request.request_dir);
-    for entry in fs::read_dir(current_dir).unwrap() {
-        let entry = entry.unwrap();
+    let current_dir = env::current_dir()?.join(&request.request_dir);
+    let entries = fs::read_dir(current_dir).map_err(|err| match err.kind() {
+        std::io::ErrorKind::NotFound => AppError::NotFound(format!("Couldn't find the directory: {:?}", request.request_dir)),
+        _ => AppError::from(err),
+    })?;
+    for entry in entries {
+        let entry = entry?;
         let path = entry.path();
 
-        let curr_file_metadata = fs::metadata(&path).unwrap();
-        let access_time: DateTime<Local> = curr_file_metadata.accessed().unwrap().into();
-        let modified_time: DateTime<Local> = curr_file_metadata.modified().unwrap().into();
-        let creation_date: DateTime<Local> = curr_file_metadata.created().unwrap().into();
+        let curr_file_metadata = fs::metadata(&path)?;
+        // Not every file system records every time.
+        let time_string = |time: std::io::Result<std::time::SystemTime>| time
+            .map(|time| DateTime::<Local>::from(time).to_string())
+            .unwrap_or_default();
         let file_metadata = FileMetadata {
-            file_name: entry.file_name().into_string().unwrap(),
-            file_type: path.extension().unwrap().to_owned().into_string().unwrap(),
+            file_name: entry.file_name().to_string_lossy().into_owned(),
+            file_type: path.extension().map(|extension| extension.to_string_lossy().into_owned()).unwrap_or_default(),
             file_size: curr_file_metadata.len(),
-            last_access_time: access_time.to_string(),
-            last_modified_time: modified_time.to_string(),
-            creation_date: creation_date.to_string(),
+            last_access_time: time_string(curr_file_metadata.accessed()),
+            last_modified_time: time_string(curr_file_metadata.modified()),
+            creation_date: time_string(curr_file_metadata.created()),
         };
         file_list.push(file_metadata);
     }
-    Ok(Json(file_list))
+    Ok(file_list)
 }
 
+#[utoipa::path(
+    post,
+    path = "/admin/model_manage",
+    tag = "model",
+    request_body(content = RequestFileOperation, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Bytes written or files removed", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_file_operation(
-    State(multi_state): State<MultiState>,
     Form(file_operation_request): Form<RequestFileOperation>
-) -> Result<String, (StatusCode, String)> {
-    if !check_permission(&multi_state.db_pool, &file_operation_request.useremail, Permission::MngModel).await.unwrap() {
-        return Err(
-            (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-        );
-    }
+) -> Result<String, AppError> {
+    let files2operate: Vec<String> = parse_json_field("files2operate", &file_operation_request.files2operate)?;
+    _operate_files(&file_operation_request.operation_type, &files2operate).await
+}
 
-    let operation_type = file_operation_request.operation_type.as_str();
-    let files2operate: Vec<String> = serde_json::from_str(file_operation_request.files2operate.as_str()).unwrap();
+/// Back up or remove model files.
+pub async fn _operate_files(operation_type: &str, files2operate: &[String]) -> Result<String, AppError> {
+    if let Some(file) = files2operate.iter().find(|file| !_path_is_valid(file)) {
+        return Err(AppError::BadRequest(format!("Invalid file name: {file:?}")));
+    }
     match operation_type {
         "backup" => {
-            let write_bytes = backup_models(files2operate.iter()).map_err(|err| (StatusCode::CONFLICT, format!("Failed to backup files! Get Error: {err}").to_owned())).await?;
+            let write_bytes = backup_models(files2operate.iter()).map_err(|err| __file_operation_error("backup", err)).await?;
             return Ok(format!("File operations finished! Written {write_bytes} bytes!"));
         },
         "remove" => {
-            let count_of_files = remove_models(files2operate.iter()).map_err(|err| (StatusCode::CONFLICT, format!("Failed to backup files! Get Error: {err}").to_owned())).await?;
+            let count_of_files = remove_models(files2operate.iter()).map_err(|err| __file_operation_error("remove", err)).await?;
             return Ok(format!("File operations finished! Removed {count_of_files} files!"));
         },
         _ => {
-            return Err((StatusCode::FORBIDDEN, "Operation was not permitted or implemented! Only support backup and remove files.".to_string()));
+            return Err(AppError::BadRequest("Operation was not permitted or implemented! Only support backup and remove files.".to_string()));
         }
     }
 }
 
+fn __file_operation_error(operation: &str, err: std::io::Error) -> AppError {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => AppError::NotFound(format!("Failed to {operation} files! {err}")),
+        _ => AppError::Io(format!("Failed to {operation} files! {err}")),
+    }
+}
+
 // pub async fn handler_rm_dset(
 //     State(multi_state): State<MultiState>,
 //     Path((user_id, dataset_name)): Path<(String, String)>
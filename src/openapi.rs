@@ -0,0 +1,233 @@
+use axum::Router;
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+    Modify, OpenApi
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{api_key, api_v1, authenticator, dl_svc, error, feedback, health, io_agent, metrics, model_manager, task_manager, two_factor, user_manager, MultiState};
+
+pub const OPENAPI_PATH: &str = "/api/openapi.json";
+pub const DOCS_PATH: &str = "/api/docs";
+
+/// Every route of the server. `tests::every_route_is_documented` fails when a route is missing here.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Insect Identifier", description = "Identification of insects on pictures, feedback and model management."),
+    paths(
+        health::handler_index,
+        health::handler_healthz,
+        health::handler_readyz,
+        health::handler_diagnostics,
+        metrics::handler_metrics,
+        authenticator::handler_sign_in,
+        authenticator::handler_sign_in_two_factor,
+        authenticator::handler_sign_up,
+        authenticator::handler_refresh,
+        authenticator::handler_sign_out,
+        authenticator::handler_verify_email,
+        authenticator::handler_resend_verification,
+        authenticator::handler_forgot_password,
+        authenticator::handler_reset_password,
+        authenticator::handler_transfer_permission_to_role,
+        two_factor::handler_two_factor_status,
+        two_factor::handler_enroll_two_factor,
+        two_factor::handler_enable_two_factor,
+        two_factor::handler_disable_two_factor,
+        two_factor::handler_regenerate_recovery_codes,
+        api_key::handler_fetch_api_keys,
+        api_key::handler_create_api_key,
+        api_key::handler_revoke_api_key,
+        user_manager::handler_user_info,
+        user_manager::handler_fetch_all_users,
+        user_manager::handler_suspend_or_unsuspend_user,
+        user_manager::handler_add_admin,
+        io_agent::handler_upload_pic,
+        io_agent::handler_fetch_image,
+        dl_svc::handler_infer,
+        dl_svc::handler_authenticate_ssh,
+        feedback::handler_subm_fb,
+        feedback::handler_fetch_ufb,
+        feedback::handler_label_pic,
+        feedback::handler_fetch_trainable_fb,
+        feedback::handler_acc_rej_fb,
+        model_manager::handler_fetch_all_models,
+        model_manager::handler_file_operation,
+        task_manager::handler_fetch_all_tasks,
+        task_manager::handler_trigger_task,
+        task_manager::handler_pause_task,
+        task_manager::handler_resume_task,
+        task_manager::handler_update_task_schedule,
+
+        api_v1::handler_sign_in,
+        api_v1::handler_sign_in_two_factor,
+        api_v1::handler_sign_up,
+        api_v1::handler_refresh,
+        api_v1::handler_sign_out,
+        api_v1::handler_verify_email,
+        api_v1::handler_resend_verification,
+        api_v1::handler_forgot_password,
+        api_v1::handler_reset_password,
+        api_v1::handler_two_factor_status,
+        api_v1::handler_enroll_two_factor,
+        api_v1::handler_enable_two_factor,
+        api_v1::handler_disable_two_factor,
+        api_v1::handler_regenerate_recovery_codes,
+        api_v1::handler_fetch_api_keys,
+        api_v1::handler_create_api_key,
+        api_v1::handler_revoke_api_key,
+        api_v1::handler_fetch_user_info,
+        api_v1::handler_fetch_role,
+        api_v1::handler_upload_picture,
+        api_v1::handler_fetch_image,
+        api_v1::handler_infer,
+        api_v1::handler_fetch_inference_history,
+        api_v1::handler_submit_feedback,
+        api_v1::handler_fetch_unlabelled_feedback,
+        api_v1::handler_label_picture,
+        api_v1::handler_fetch_trainable_feedback,
+        api_v1::handler_accept_or_reject_feedback,
+        api_v1::handler_fetch_users,
+        api_v1::handler_add_admin,
+        api_v1::handler_suspend_or_unsuspend_users,
+        api_v1::handler_fetch_models,
+        api_v1::handler_operate_files,
+        api_v1::handler_ssh_host,
+        api_v1::handler_diagnostics,
+        api_v1::handler_fetch_tasks,
+        api_v1::handler_task_trigger,
+        api_v1::handler_task_pause,
+        api_v1::handler_task_resume,
+        api_v1::handler_task_schedule,
+    ),
+    components(schemas(error::ErrorBody, error::FieldError)),
+    modifiers(&SecurityAddon)
+)]
+pub struct ApiDoc;
+
+/// The token from `/sign_in`, sent back in the `auth-token` header, or in its place a key from `/api_keys`.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "auth_token",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("auth-token")))
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "authorization",
+                "`ApiKey <key>`, accepted wherever `auth-token` is, within the scopes of the key"
+            )))
+        );
+    }
+}
+
+/// `/api/openapi.json` and the Swagger UI rendering it at `/api/docs`.
+pub fn router() -> Router<MultiState> {
+    SwaggerUi::new(DOCS_PATH)
+        .url(OPENAPI_PATH, ApiDoc::openapi())
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::{header, Method, Request, StatusCode}};
+    use serde_json::json;
+    use tower::ServiceExt;
+    use utoipa::OpenApi;
+
+    use super::{ApiDoc, DOCS_PATH, OPENAPI_PATH};
+    use crate::tests::{create_api_key, sign_in_root, test_app};
+
+    const METHODS: [Method; 5] = [Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::PATCH];
+
+    /// Routes served by the built router as (method, OpenAPI path), apart from the documentation itself.
+    async fn served_routes() -> Vec<(String, String)> {
+        let (app, repositories) = test_app();
+        let root_token = sign_in_root(&app, &repositories, "openapi@b.cn").await;
+        // axum lists the paths of a router in its Debug output only, the methods are found by asking each of them.
+        let debug = format!("{app:?}");
+        let mut paths: Vec<&str> = debug.split("RouteId(")
+            .skip(1)
+            .filter_map(|entry| entry.split_once("): \"")?.1.split('"').next())
+            .filter(|path| !path.contains('*') && *path != OPENAPI_PATH && !path.starts_with(DOCS_PATH))
+            .collect();
+        paths.sort();
+        paths.dedup();
+
+        let mut routes = Vec::new();
+        for path in paths {
+            let segments: Vec<(String, String)> = path.split('/')
+                .map(|segment| match segment.strip_prefix(':') {
+                    Some(param) => (format!("{{{param}}}"), "placeholder".to_string()),
+                    None => (segment.to_string(), segment.to_string()),
+                })
+                .collect();
+            let documented_path = segments.iter().map(|(documented, _)| documented.as_str()).collect::<Vec<_>>().join("/");
+            let uri = segments.iter().map(|(_, probed)| probed.as_str()).collect::<Vec<_>>().join("/");
+            // Past the authorization, which answers before the router knows the method, without a session to sign out.
+            // A key per path stays within the rate limits, which count each probe.
+            let (api_key, _) = create_api_key(&app, &root_token, json!({"name": "probe", "scopes": ["Common"]})).await;
+            for method in METHODS {
+                let request = Request::builder()
+                    .method(method.clone())
+                    .uri(&uri)
+                    .header(header::AUTHORIZATION, format!("ApiKey {api_key}"))
+                    .body(Body::empty())
+                    .unwrap();
+                if app.clone().oneshot(request).await.unwrap().status() != StatusCode::METHOD_NOT_ALLOWED {
+                    routes.push((method.as_str().to_lowercase(), documented_path.clone()));
+                }
+            }
+        }
+        routes
+    }
+
+    #[tokio::test]
+    async fn every_route_is_documented() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let routes = served_routes().await;
+        assert!(routes.len() > 40, "found only {} routes, did the Debug output of Router change?", routes.len());
+
+        let undocumented: Vec<String> = routes.iter()
+            .filter(|(method, path)| openapi["paths"][path][method].is_null())
+            .map(|(method, path)| format!("{} {path}", method.to_uppercase()))
+            .collect();
+        assert!(undocumented.is_empty(), "routes missing from ApiDoc: {undocumented:?}");
+    }
+
+    #[tokio::test]
+    async fn documented_routes_exist() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let routes = served_routes().await;
+
+        let mut unknown = Vec::new();
+        for (path, operations) in openapi["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                if !routes.iter().any(|route| route.0 == *method && route.1 == *path) {
+                    unknown.push(format!("{} {path}", method.to_uppercase()));
+                }
+            }
+        }
+        assert!(unknown.is_empty(), "documented routes which aren't served: {unknown:?}");
+    }
+
+    #[test]
+    fn operation_ids_are_unique() {
+        let openapi = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut operation_ids: Vec<&str> = openapi["paths"].as_object().unwrap()
+            .values()
+            .flat_map(|operations| operations.as_object().unwrap().values())
+            .filter_map(|operation| operation["operationId"].as_str())
+            .collect();
+        operation_ids.sort();
+        let shared: Vec<&str> = operation_ids.windows(2)
+            .filter(|pair| pair[0] == pair[1])
+            .map(|pair| pair[0])
+            .collect();
+        assert!(shared.is_empty(), "operation ids shared by several routes: {shared:?}");
+    }
+}
@@ -0,0 +1,50 @@
+use std::num::NonZeroU32;
+
+use data_encoding::HEXUPPER;
+use ring::{digest, pbkdf2, rand::{self, SecureRandom}};
+
+const CREDENTIAL_LEN: usize = digest::SHA512_OUTPUT_LEN;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Random salt and PBKDF2-HMAC-SHA512 hash of the password, both in uppercase hex
+/// as stored in `password_salt` and `password_hash` of the Account table.
+pub fn hash_password(password: &str) -> Result<(String, String), String> {
+    let n_iter = NonZeroU32::new(PBKDF2_ITERATIONS).unwrap();
+    let rng = rand::SystemRandom::new();
+
+    let mut salt = [0u8; CREDENTIAL_LEN];
+    rng.fill(&mut salt).map_err(|_| "Failed to generate a salt!".to_string())?;
+
+    let mut pbkdf2_hash = [0u8; CREDENTIAL_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA512,
+        n_iter,
+        &salt,
+        password.as_bytes(),
+        &mut pbkdf2_hash,
+    );
+
+    Ok((
+        HEXUPPER.encode(&salt),
+        HEXUPPER.encode(&pbkdf2_hash)
+    ))
+}
+
+/// Whether the password matches the salt and hash made by `hash_password`.
+pub fn verify_password(password: &str, salt_string: &str, pbkdf2_hash_string: &str) -> bool {
+    let n_iter = NonZeroU32::new(PBKDF2_ITERATIONS).unwrap();
+    let (Ok(salt), Ok(pbkdf2_hash)) = (
+        HEXUPPER.decode(salt_string.as_bytes()),
+        HEXUPPER.decode(pbkdf2_hash_string.as_bytes())
+    ) else {
+        return false;
+    };
+
+    pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA512,
+        n_iter,
+        &salt,
+        password.as_bytes(),
+        &pbkdf2_hash
+    ).is_ok()
+}
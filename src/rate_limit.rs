@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant}
+};
+
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response
+};
+
+use crate::{
+    api_key::get_api_key,
+    authenticator::{get_token, hash_token, verify_jwt},
+    config::{app_config, RateLimitRule},
+    error::AppError,
+    metrics::metrics
+};
+
+// How often buckets which have filled up again are dropped.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Rate limited route templates and their group, each legacy route next to its /api/v1 twin.
+const ROUTE_GROUPS: [(&str, &str); 20] = [
+    ("/sign_in", "sign_in"),
+    ("/api/v1/sign_in", "sign_in"),
+    // Guessing two-factor codes counts as guessing passwords.
+    ("/sign_in/two_factor", "sign_in"),
+    ("/api/v1/sign_in/two_factor", "sign_in"),
+    ("/two_factor/enable", "sign_in"),
+    ("/api/v1/two_factor/enable", "sign_in"),
+    ("/two_factor/disable", "sign_in"),
+    ("/api/v1/two_factor/disable", "sign_in"),
+    ("/two_factor/recovery_codes", "sign_in"),
+    ("/api/v1/two_factor/recovery_codes", "sign_in"),
+    ("/sign_up", "sign_up"),
+    ("/api/v1/sign_up", "sign_up"),
+    ("/:useremail/upload_pic", "upload"),
+    ("/api/v1/users/:useremail/pictures", "upload"),
+    ("/user/infer", "infer"),
+    ("/api/v1/infer", "infer"),
+    ("/resend_verification", "mail"),
+    ("/api/v1/resend_verification", "mail"),
+    ("/forgot_password", "mail"),
+    ("/api/v1/forgot_password", "mail"),
+];
+
+#[derive(Hash, PartialEq, Eq, Clone, Debug)]
+enum BucketKey {
+    Ip(&'static str, IpAddr),
+    Identity(&'static str, String),
+}
+
+impl BucketKey {
+    fn label(&self) -> &'static str {
+        match self {
+            BucketKey::Ip(..) => "ip",
+            BucketKey::Identity(..) => "identity",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    refill_duration: Duration, // from empty to full
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<BucketKey, Bucket>,
+    last_pruned: Instant,
+}
+
+/// Token buckets of every client, kept in memory so each instance counts on its own.
+#[derive(Debug)]
+pub struct RateLimiter {
+    state: Mutex<Buckets>,
+}
+
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+pub fn rate_limiter() -> &'static RateLimiter {
+    RATE_LIMITER.get_or_init(RateLimiter::new)
+}
+
+impl RateLimiter {
+    fn new() -> Self {
+        RateLimiter {
+            state: Mutex::new(Buckets { buckets: HashMap::new(), last_pruned: Instant::now() })
+        }
+    }
+
+    /// Take a token from the bucket of the address and of the identity.
+    /// Nothing is taken unless both have one left, otherwise returns the
+    /// exhausted budget ("ip" or "identity") and the seconds until it has a token again.
+    fn acquire(&self, group: &'static str, rule: &RateLimitRule, ip: Option<IpAddr>, identity: Option<String>)
+        -> Result<(), (&'static str, u64)> {
+        let mut limits = Vec::new();
+        if let (Some(ip), true) = (ip, rule.ip_per_minute > 0) {
+            limits.push((BucketKey::Ip(group, ip), rule.ip_per_minute));
+        }
+        if let (Some(identity), true) = (identity, rule.identity_per_minute > 0) {
+            limits.push((BucketKey::Identity(group, identity), rule.identity_per_minute));
+        }
+        if limits.is_empty() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if now.duration_since(state.last_pruned) >= PRUNE_INTERVAL {
+            state.buckets.retain(|_, bucket| now.duration_since(bucket.updated) < bucket.refill_duration);
+            state.last_pruned = now;
+        }
+
+        let mut exhausted: Option<(&'static str, u64)> = None;
+        for (key, per_minute) in limits.iter() {
+            let capacity = match rule.burst {
+                0 => *per_minute,
+                burst => burst,
+            } as f64;
+            let per_second = *per_minute as f64 / 60.0;
+            let bucket = state.buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: capacity,
+                updated: now,
+                refill_duration: Duration::from_secs_f64(capacity / per_second),
+            });
+            bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second).min(capacity);
+            bucket.updated = now;
+            if bucket.tokens < 1.0 {
+                let retry_after = ((1.0 - bucket.tokens) / per_second).ceil().max(1.0) as u64;
+                if exhausted.is_none_or(|(_, longest)| retry_after > longest) {
+                    exhausted = Some((key.label(), retry_after));
+                }
+            }
+        }
+        if let Some(exhausted) = exhausted {
+            return Err(exhausted);
+        }
+        for (key, _) in limits.iter() {
+            if let Some(bucket) = state.buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Answer 429 with `Retry-After` once a client exceeds the budget of a rate limited route group.
+pub async fn middleware_rate_limit(request: Request, next: Next) -> Result<Response, AppError> {
+    let config = &app_config().rate_limit;
+    let group = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|matched_path| route_group(matched_path.as_str()));
+    let (group, rule) = match (config.enabled, group) {
+        (true, Some(group)) => match config.groups().into_iter().find(|(name, _)| *name == group) {
+            Some(group_rule) => group_rule,
+            None => return Ok(next.run(request).await),
+        },
+        _ => return Ok(next.run(request).await),
+    };
+
+    let ip = client_ip(&request, config.trust_forwarded_for);
+    let identity = __identity(request.headers());
+
+    if let Err((key, retry_after)) = rate_limiter().acquire(group, rule, ip, identity) {
+        metrics().rate_limited_requests.with_label_values(&[group, key]).inc();
+        return Err(AppError::TooManyRequests(format!("Rate limit of {group} exceeded by {key}"), retry_after));
+    }
+    Ok(next.run(request).await)
+}
+
+/// Whom a request counts against: the account of a valid token, or an API key by its hash, checked later by the
+/// authorization middleware. Invalid tokens count by address only, the authorization middleware rejects them anyway.
+fn __identity(headers: &HeaderMap) -> Option<String> {
+    if let Some(api_key) = get_api_key(headers) {
+        return Some(format!("api_key:{}", hash_token(&api_key)));
+    }
+    get_token(headers)
+        .and_then(|token| verify_jwt(&token).ok())
+        .map(|claims| claims.user_email().to_string())
+}
+
+fn route_group(route: &str) -> Option<&'static str> {
+    ROUTE_GROUPS.iter()
+        .find(|(template, _)| *template == route)
+        .map(|(_, group)| *group)
+}
+
+/// Address of the client: the peer of the connection, or the last X-Forwarded-For entry when trusted.
+pub fn client_ip(request: &Request, trust_forwarded_for: bool) -> Option<IpAddr> {
+    if trust_forwarded_for {
+        if let Some(ip) = __forwarded_for(request.headers()) {
+            return Some(ip);
+        }
+    }
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip())
+}
+
+/// The last entry is the one appended by the trusted proxy, those before it come from the client.
+fn __forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .next_back()?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use axum::http::HeaderMap;
+
+    use super::{RateLimiter, __forwarded_for, __identity};
+    use crate::config::RateLimitRule;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[test]
+    fn burst_then_rejected_with_retry_after() {
+        let limiter = RateLimiter::new();
+        let rule = RateLimitRule { ip_per_minute: 6, identity_per_minute: 0, burst: 2 };
+        assert!(limiter.acquire("sign_in", &rule, Some(IP), None).is_ok());
+        assert!(limiter.acquire("sign_in", &rule, Some(IP), None).is_ok());
+        assert_eq!(limiter.acquire("sign_in", &rule, Some(IP), None), Err(("ip", 10)));
+        // Other groups and addresses have their own buckets.
+        assert!(limiter.acquire("infer", &rule, Some(IP), None).is_ok());
+        assert!(limiter.acquire("sign_in", &rule, Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)), None).is_ok());
+    }
+
+    #[test]
+    fn identity_budget_is_shared_across_addresses() {
+        let limiter = RateLimiter::new();
+        let rule = RateLimitRule { ip_per_minute: 60, identity_per_minute: 1, burst: 0 };
+        let identity = || Some("a@b.cn".to_string());
+        assert!(limiter.acquire("infer", &rule, Some(IP), identity()).is_ok());
+        assert_eq!(
+            limiter.acquire("infer", &rule, Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)), identity()),
+            Err(("identity", 60))
+        );
+        // A request rejected by the address takes no token from the identity.
+        let rule = RateLimitRule { ip_per_minute: 1, identity_per_minute: 1, burst: 0 };
+        assert!(limiter.acquire("upload", &rule, Some(IP), Some("c@d.cn".to_string())).is_ok());
+        assert_eq!(limiter.acquire("upload", &rule, Some(IP), identity()), Err(("ip", 60)));
+        assert!(limiter.acquire("upload", &rule, None, identity()).is_ok());
+    }
+
+    #[test]
+    fn forwarded_for_takes_the_entry_of_the_proxy() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(__forwarded_for(&headers), None);
+        // Entries before the last one are sent by the client and may be anything.
+        headers.insert("x-forwarded-for", "1.2.3.4, 10.0.0.1 , 192.168.1.7".parse().unwrap());
+        assert_eq!(__forwarded_for(&headers), Some("192.168.1.7".parse().unwrap()));
+        headers.append("x-forwarded-for", "203.0.113.9".parse().unwrap());
+        assert_eq!(__forwarded_for(&headers), Some("203.0.113.9".parse().unwrap()));
+        headers.insert("x-forwarded-for", "1.2.3.4, unknown".parse().unwrap());
+        assert_eq!(__forwarded_for(&headers), None);
+    }
+
+    #[test]
+    fn api_keys_count_as_identities() {
+        let headers = |api_key: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("authorization", format!("ApiKey {api_key}").parse().unwrap());
+            headers
+        };
+        assert_eq!(__identity(&HeaderMap::new()), None);
+        let identity = __identity(&headers("iik_a_1")).unwrap();
+        assert!(identity.starts_with("api_key:") && !identity.contains("iik_a_1"));
+        assert_eq!(__identity(&headers("iik_a_1")), Some(identity.clone()));
+        assert_ne!(__identity(&headers("iik_b_2")), Some(identity));
+    }
+
+    #[test]
+    fn zero_rate_is_unlimited() {
+        let limiter = RateLimiter::new();
+        let rule = RateLimitRule { ip_per_minute: 0, identity_per_minute: 0, burst: 0 };
+        for _ in 0..100 {
+            assert!(limiter.acquire("sign_up", &rule, Some(IP), Some("a@b.cn".to_string())).is_ok());
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant}
+};
+
+use axum::async_trait;
+use chrono::Local;
+
+use crate::error::AppError;
+
+use super::{Account, AccountRepository, ProofAccount, RefreshTokenRecord, SessionRepository};
+
+/// Accounts of another repository, with the proofs of the permission checks kept for `ttl`.
+/// Every write through it drops the proof of the account, changes made elsewhere
+/// (another instance, `init`, SQL) show after `ttl` at most.
+pub struct CachedAccountRepository {
+    inner: Arc<dyn AccountRepository>,
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    proofs: HashMap<String, (ProofAccount, Instant)>,
+    // Unix seconds of the latest write to each account, for the proofs carried by tokens.
+    changed_at: HashMap<String, i64>,
+}
+
+impl CachedAccountRepository {
+    pub fn new(inner: Arc<dyn AccountRepository>, ttl: Duration) -> Self {
+        CachedAccountRepository { inner, ttl, state: Mutex::new(CacheState::default()) }
+    }
+
+    fn forget(&self, email: &str) {
+        let now = Local::now().timestamp();
+        let ttl = self.ttl.as_secs() as i64;
+        let mut state = self.state.lock().unwrap();
+        state.proofs.remove(email);
+        // Older changes no longer matter, tokens issued before them are too old to be trusted anyway.
+        state.changed_at.retain(|_, changed_at| now - *changed_at <= ttl);
+        state.changed_at.insert(email.to_string(), now);
+    }
+}
+
+#[async_trait]
+impl AccountRepository for CachedAccountRepository {
+    async fn find(&self, email: &str) -> Result<Option<Account>, AppError> {
+        self.inner.find(email).await
+    }
+
+    async fn list(&self) -> Result<Vec<Account>, AppError> {
+        self.inner.list().await
+    }
+
+    async fn insert(&self, account: &Account) -> Result<bool, AppError> {
+        let inserted = self.inner.insert(account).await?;
+        self.forget(&account.email);
+        Ok(inserted)
+    }
+
+    async fn set_available(&self, email: &str, available: bool) -> Result<bool, AppError> {
+        let updated = self.inner.set_available(email, available).await;
+        self.forget(email);
+        updated
+    }
+
+    async fn set_contribution(&self, email: &str, contribution: i16) -> Result<bool, AppError> {
+        self.inner.set_contribution(email, contribution).await
+    }
+
+    async fn set_email_verified(&self, email: &str, email_verified: bool) -> Result<bool, AppError> {
+        self.inner.set_email_verified(email, email_verified).await
+    }
+
+    async fn set_password(&self, email: &str, password_salt: &str, password_hash: &str) -> Result<bool, AppError> {
+        self.inner.set_password(email, password_salt, password_hash).await
+    }
+
+    async fn find_proof(&self, email: &str) -> Result<Option<ProofAccount>, AppError> {
+        if let Some((proof, fetched_at)) = self.state.lock().unwrap().proofs.get(email) {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(Some(*proof));
+            }
+        }
+        let proof = self.inner.find_proof(email).await?;
+        // Unknown accounts aren't cached, they may sign up any moment.
+        if let Some(proof) = proof {
+            self.state.lock().unwrap().proofs.insert(email.to_string(), (proof, Instant::now()));
+        }
+        Ok(proof)
+    }
+
+    fn remember_proof(&self, email: &str, proof: ProofAccount, issued_at: i64) {
+        let age = Local::now().timestamp() - issued_at;
+        let Some(fetched_at) = u64::try_from(age).ok()
+            .map(Duration::from_secs)
+            .filter(|age| *age < self.ttl)
+            .and_then(|age| Instant::now().checked_sub(age)) else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        // A write in the same second may have come after the token.
+        if state.changed_at.get(email).is_some_and(|changed_at| *changed_at >= issued_at) {
+            return;
+        }
+        if state.proofs.get(email).is_none_or(|(_, cached_at)| *cached_at < fetched_at) {
+            state.proofs.insert(email.to_string(), (proof, fetched_at));
+        }
+    }
+}
+
+/// Sessions of another repository, with the sessions found revoked kept for `ttl`.
+/// Live sessions are looked up on every check, so a revocation made anywhere takes effect at once.
+pub struct CachedSessionRepository {
+    inner: Arc<dyn SessionRepository>,
+    ttl: Duration,
+    revoked: Mutex<HashMap<String, Instant>>,
+}
+
+impl CachedSessionRepository {
+    pub fn new(inner: Arc<dyn SessionRepository>, ttl: Duration) -> Self {
+        CachedSessionRepository { inner, ttl, revoked: Mutex::new(HashMap::new()) }
+    }
+}
+
+#[async_trait]
+impl SessionRepository for CachedSessionRepository {
+    async fn insert_refresh_token(&self, record: &RefreshTokenRecord) -> Result<(), AppError> {
+        self.inner.insert_refresh_token(record).await
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AppError> {
+        self.inner.find_refresh_token(token_hash).await
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str, used_at: i64) -> Result<bool, AppError> {
+        self.inner.use_refresh_token(token_hash, used_at).await
+    }
+
+    async fn revoke_session(&self, session_id: &str, user_email: &str, revoked_at: i64, expires_at: i64)
+        -> Result<(), AppError>
+    {
+        self.inner.revoke_session(session_id, user_email, revoked_at, expires_at).await?;
+        self.revoked.lock().unwrap().insert(session_id.to_string(), Instant::now());
+        Ok(())
+    }
+
+    async fn revoke_sessions_of(&self, user_email: &str, revoked_at: i64, expires_at: i64) -> Result<u64, AppError> {
+        self.inner.revoke_sessions_of(user_email, revoked_at, expires_at).await
+    }
+
+    async fn is_revoked(&self, session_id: &str) -> Result<bool, AppError> {
+        if self.revoked.lock().unwrap().get(session_id).is_some_and(|checked_at| checked_at.elapsed() < self.ttl) {
+            return Ok(true);
+        }
+        let revoked = self.inner.is_revoked(session_id).await?;
+        // A revoked session stays revoked, a live one may be signed out through another instance any moment.
+        if revoked {
+            let mut revocations = self.revoked.lock().unwrap();
+            revocations.retain(|_, checked_at| checked_at.elapsed() < self.ttl);
+            revocations.insert(session_id.to_string(), Instant::now());
+        }
+        Ok(revoked)
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        self.inner.delete_expired(now).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use chrono::Local;
+
+    use super::{CachedAccountRepository, CachedSessionRepository};
+    use crate::repository::{Account, AccountRepository, MemoryRepository, ProofAccount, SessionRepository};
+
+    fn account(email: &str, permissions: i16) -> Account {
+        Account {
+            nick_name: "tester".to_string(),
+            password_salt: String::new(),
+            password_hash: String::new(),
+            email: email.to_string(),
+            contribution: 0,
+            available: true,
+            permissions,
+            email_verified: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_drop_the_cached_proof() {
+        let inner = Arc::new(MemoryRepository::new());
+        let cached = CachedAccountRepository::new(inner.clone(), Duration::from_secs(60));
+        cached.insert(&account("a@b.cn", 0b0001)).await.unwrap();
+        assert_eq!(cached.find_proof("a@b.cn").await.unwrap(), Some(ProofAccount { permissions: 0b0001, available: true }));
+
+        // Changed behind its back, the cached proof stays.
+        inner.set_available("a@b.cn", false).await.unwrap();
+        assert!(cached.find_proof("a@b.cn").await.unwrap().unwrap().available);
+
+        cached.set_available("a@b.cn", false).await.unwrap();
+        assert!(!cached.find_proof("a@b.cn").await.unwrap().unwrap().available);
+    }
+
+    #[tokio::test]
+    async fn proofs_of_tokens_are_trusted_until_the_account_changes() {
+        let inner = Arc::new(MemoryRepository::new());
+        let cached = CachedAccountRepository::new(inner.clone(), Duration::from_secs(60));
+        inner.insert(&account("a@b.cn", 0b0001)).await.unwrap();
+        let claimed = ProofAccount { permissions: 0b1111, available: true };
+
+        // Too old to be as good as a lookup.
+        cached.remember_proof("a@b.cn", claimed, Local::now().timestamp() - 60);
+        assert_eq!(cached.find_proof("a@b.cn").await.unwrap().unwrap().permissions, 0b0001);
+
+        let cached = CachedAccountRepository::new(inner.clone(), Duration::from_secs(60));
+        cached.remember_proof("a@b.cn", claimed, Local::now().timestamp() - 1);
+        assert_eq!(cached.find_proof("a@b.cn").await.unwrap(), Some(claimed));
+
+        cached.set_available("a@b.cn", false).await.unwrap();
+        cached.remember_proof("a@b.cn", claimed, Local::now().timestamp() - 1);
+        assert!(!cached.find_proof("a@b.cn").await.unwrap().unwrap().available);
+    }
+
+    #[tokio::test]
+    async fn only_revocations_are_cached() {
+        let inner = Arc::new(MemoryRepository::new());
+        let cached = CachedSessionRepository::new(inner.clone(), Duration::from_secs(60));
+        let expires_at = Local::now().timestamp() + 60;
+        assert!(!cached.is_revoked("s1").await.unwrap());
+
+        // Revoked behind its back, it is seen at once.
+        inner.revoke_session("s1", "a@b.cn", 0, expires_at).await.unwrap();
+        assert!(cached.is_revoked("s1").await.unwrap());
+
+        cached.revoke_session("s2", "a@b.cn", 0, expires_at).await.unwrap();
+        assert!(cached.is_revoked("s2").await.unwrap());
+
+        let uncached = CachedSessionRepository::new(inner.clone(), Duration::ZERO);
+        assert!(!uncached.is_revoked("s3").await.unwrap());
+        inner.revoke_session("s3", "a@b.cn", 0, expires_at).await.unwrap();
+        assert!(uncached.is_revoked("s3").await.unwrap());
+    }
+}
@@ -0,0 +1,533 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use axum::async_trait;
+
+use crate::{error::AppError, migrations::{AppliedMigration, MIGRATIONS}, species_vector::SPECIES_VECTOR};
+
+use super::{
+    Account, AccountRepository, AccountTokenRecord, AccountTokenRepository, ApiKeyRecord, ApiKeyRepository,
+    DatabaseRepository, Feedback,
+    FeedbackRepository, InferenceHistoryRepository, InferenceRecord, RefreshTokenRecord, SessionRepository,
+    TaskHistoryRepository, TaskLeaseRecord, TaskRunRecord, TaskSettingRecord, TwoFactorRecord, TwoFactorRepository, WikiEntry, WikiRepository
+};
+
+/// Every repository in process memory, lost on drop. Behaves like `PostgresRepository`.
+#[derive(Debug)]
+pub struct MemoryRepository {
+    accounts: Mutex<BTreeMap<String, Account>>,
+    trainable: Mutex<Vec<Feedback>>,
+    unlabelled: Mutex<Vec<Feedback>>,
+    wiki: BTreeMap<i32, WikiEntry>,
+    inferences: Mutex<Vec<InferenceRecord>>,
+    task_runs: Mutex<Vec<TaskRunRecord>>,
+    task_leases: Mutex<BTreeMap<String, TaskLeaseRecord>>,
+    task_settings: Mutex<BTreeMap<String, TaskSettingRecord>>,
+    refresh_tokens: Mutex<BTreeMap<String, RefreshTokenRecord>>,
+    // Session id to (user email, expires at).
+    revoked_sessions: Mutex<BTreeMap<String, (String, i64)>>,
+    account_tokens: Mutex<BTreeMap<String, AccountTokenRecord>>,
+    two_factor: Mutex<BTreeMap<String, TwoFactorRecord>>,
+    // Code hash to (user email, used at).
+    recovery_codes: Mutex<BTreeMap<String, (String, Option<i64>)>>,
+    api_keys: Mutex<BTreeMap<String, ApiKeyRecord>>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        let wiki = SPECIES_VECTOR.iter()
+            .enumerate()
+            .map(|(label, (english_name, (specie_name, content)))| (label as i32, WikiEntry {
+                label: label as i32,
+                english_name: english_name.to_string(),
+                specie_name: specie_name.to_string(),
+                content: content.to_string(),
+            }))
+            .collect();
+        MemoryRepository {
+            accounts: Mutex::new(BTreeMap::new()),
+            trainable: Mutex::new(Vec::new()),
+            unlabelled: Mutex::new(Vec::new()),
+            wiki,
+            inferences: Mutex::new(Vec::new()),
+            task_runs: Mutex::new(Vec::new()),
+            task_leases: Mutex::new(BTreeMap::new()),
+            task_settings: Mutex::new(BTreeMap::new()),
+            refresh_tokens: Mutex::new(BTreeMap::new()),
+            revoked_sessions: Mutex::new(BTreeMap::new()),
+            account_tokens: Mutex::new(BTreeMap::new()),
+            two_factor: Mutex::new(BTreeMap::new()),
+            recovery_codes: Mutex::new(BTreeMap::new()),
+            api_keys: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Default for MemoryRepository {
+    fn default() -> Self {
+        MemoryRepository::new()
+    }
+}
+
+#[async_trait]
+impl AccountRepository for MemoryRepository {
+    async fn find(&self, email: &str) -> Result<Option<Account>, AppError> {
+        Ok(self.accounts.lock().unwrap().get(email).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<Account>, AppError> {
+        Ok(self.accounts.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn insert(&self, account: &Account) -> Result<bool, AppError> {
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts.contains_key(&account.email) {
+            return Ok(false);
+        }
+        accounts.insert(account.email.clone(), account.clone());
+        Ok(true)
+    }
+
+    async fn set_available(&self, email: &str, available: bool) -> Result<bool, AppError> {
+        Ok(self.accounts.lock().unwrap()
+            .get_mut(email)
+            .map(|account| account.available = available)
+            .is_some())
+    }
+
+    async fn set_contribution(&self, email: &str, contribution: i16) -> Result<bool, AppError> {
+        Ok(self.accounts.lock().unwrap()
+            .get_mut(email)
+            .map(|account| account.contribution = contribution)
+            .is_some())
+    }
+
+    async fn set_email_verified(&self, email: &str, email_verified: bool) -> Result<bool, AppError> {
+        Ok(self.accounts.lock().unwrap()
+            .get_mut(email)
+            .map(|account| account.email_verified = email_verified)
+            .is_some())
+    }
+
+    async fn set_password(&self, email: &str, password_salt: &str, password_hash: &str) -> Result<bool, AppError> {
+        Ok(self.accounts.lock().unwrap()
+            .get_mut(email)
+            .map(|account| {
+                account.password_salt = password_salt.to_string();
+                account.password_hash = password_hash.to_string();
+            })
+            .is_some())
+    }
+}
+
+#[async_trait]
+impl FeedbackRepository for MemoryRepository {
+    async fn insert(&self, feedback: &Feedback) -> Result<(), AppError> {
+        match feedback.real_label {
+            Some(_) => self.trainable.lock().unwrap().push(feedback.clone()),
+            None => self.unlabelled.lock().unwrap().push(Feedback {
+                time_out: None,
+                submit_count: 0,
+                ..feedback.clone()
+            }),
+        }
+        Ok(())
+    }
+
+    async fn list_trainable(&self) -> Result<Vec<Feedback>, AppError> {
+        Ok(self.trainable.lock().unwrap().clone())
+    }
+
+    async fn list_unlabelled(&self) -> Result<Vec<Feedback>, AppError> {
+        Ok(self.unlabelled.lock().unwrap().clone())
+    }
+
+    async fn find_trainable(&self, pic_link: &str, real_label: &str) -> Result<Option<Feedback>, AppError> {
+        Ok(self.trainable.lock().unwrap()
+            .iter()
+            .rfind(|feedback| feedback.pic_link == pic_link && feedback.real_label.as_deref() == Some(real_label))
+            .cloned())
+    }
+
+    async fn set_submit_count(&self, pic_link: &str, submit_count: i64) -> Result<u64, AppError> {
+        let mut updated = 0;
+        for feedback in self.trainable.lock().unwrap().iter_mut().filter(|feedback| feedback.pic_link == pic_link) {
+            feedback.submit_count = submit_count;
+            updated += 1;
+        }
+        Ok(updated)
+    }
+
+    async fn delete_trainable(&self, pic_link: &str, real_label: &str) -> Result<u64, AppError> {
+        let mut trainable = self.trainable.lock().unwrap();
+        let before = trainable.len();
+        trainable.retain(|feedback| !(feedback.pic_link == pic_link && feedback.real_label.as_deref() == Some(real_label)));
+        Ok((before - trainable.len()) as u64)
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        let mut trainable = self.trainable.lock().unwrap();
+        let before = trainable.len();
+        trainable.retain(|feedback| feedback.time_out.is_none_or(|time_out| time_out > now));
+        Ok((before - trainable.len()) as u64)
+    }
+
+    async fn count(&self) -> Result<(i64, i64), AppError> {
+        Ok((self.trainable.lock().unwrap().len() as i64, self.unlabelled.lock().unwrap().len() as i64))
+    }
+}
+
+#[async_trait]
+impl WikiRepository for MemoryRepository {
+    async fn find(&self, label: i32) -> Result<Option<WikiEntry>, AppError> {
+        Ok(self.wiki.get(&label).cloned())
+    }
+}
+
+#[async_trait]
+impl InferenceHistoryRepository for MemoryRepository {
+    async fn record(&self, record: &InferenceRecord) -> Result<(), AppError> {
+        self.inferences.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+
+    async fn list_by_user(&self, user_email: &str, limit: i64) -> Result<Vec<InferenceRecord>, AppError> {
+        let inferences = self.inferences.lock().unwrap();
+        let mut records: Vec<InferenceRecord> = inferences.iter()
+            .rev()
+            .filter(|record| record.user_email == user_email)
+            .cloned()
+            .collect();
+        // Stable, so records of the same time stay newest first.
+        records.sort_by_key(|record| std::cmp::Reverse(record.inferred_at));
+        records.truncate(limit.max(0) as usize);
+        Ok(records)
+    }
+}
+
+#[async_trait]
+impl TaskHistoryRepository for MemoryRepository {
+    async fn acquire_lease(&self, lease: &TaskLeaseRecord) -> Result<(bool, TaskLeaseRecord), AppError> {
+        let mut leases = self.task_leases.lock().unwrap();
+        match leases.get(&lease.task_name) {
+            Some(held) if held.tick >= lease.tick => Ok((false, held.clone())),
+            _ => {
+                leases.insert(lease.task_name.clone(), lease.clone());
+                Ok((true, lease.clone()))
+            }
+        }
+    }
+
+    async fn record_run(&self, run: &TaskRunRecord) -> Result<(), AppError> {
+        self.task_runs.lock().unwrap().push(run.clone());
+        Ok(())
+    }
+
+    async fn last_runs(&self) -> Result<Vec<TaskRunRecord>, AppError> {
+        let mut last_runs: BTreeMap<&str, &TaskRunRecord> = BTreeMap::new();
+        let task_runs = self.task_runs.lock().unwrap();
+        for run in task_runs.iter() {
+            if last_runs.get(run.task_name.as_str()).is_none_or(|last_run| last_run.started_at < run.started_at) {
+                last_runs.insert(&run.task_name, run);
+            }
+        }
+        Ok(last_runs.into_values().cloned().collect())
+    }
+
+    async fn leases(&self) -> Result<Vec<TaskLeaseRecord>, AppError> {
+        Ok(self.task_leases.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn setting(&self, task_name: &str) -> Result<Option<TaskSettingRecord>, AppError> {
+        Ok(self.task_settings.lock().unwrap().get(task_name).cloned())
+    }
+
+    async fn save_setting(&self, setting: &TaskSettingRecord) -> Result<(), AppError> {
+        self.task_settings.lock().unwrap().insert(setting.task_name.clone(), setting.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionRepository for MemoryRepository {
+    async fn insert_refresh_token(&self, record: &RefreshTokenRecord) -> Result<(), AppError> {
+        self.refresh_tokens.lock().unwrap().insert(record.token_hash.clone(), record.clone());
+        Ok(())
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AppError> {
+        Ok(self.refresh_tokens.lock().unwrap().get(token_hash).cloned())
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str, used_at: i64) -> Result<bool, AppError> {
+        Ok(self.refresh_tokens.lock().unwrap()
+            .get_mut(token_hash)
+            .filter(|record| record.used_at.is_none())
+            .map(|record| record.used_at = Some(used_at))
+            .is_some())
+    }
+
+    async fn revoke_session(&self, session_id: &str, user_email: &str, _revoked_at: i64, expires_at: i64)
+        -> Result<(), AppError> {
+        // Both locks at once, as the transaction of the databases.
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+        let mut revoked_sessions = self.revoked_sessions.lock().unwrap();
+        revoked_sessions.entry(session_id.to_string()).or_insert((user_email.to_string(), expires_at));
+        refresh_tokens.retain(|_, record| record.session_id != session_id);
+        Ok(())
+    }
+
+    async fn revoke_sessions_of(&self, user_email: &str, _revoked_at: i64, expires_at: i64) -> Result<u64, AppError> {
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+        let mut revoked_sessions = self.revoked_sessions.lock().unwrap();
+        let mut revoked = 0;
+        for record in refresh_tokens.values().filter(|record| record.user_email == user_email) {
+            if !revoked_sessions.contains_key(&record.session_id) {
+                revoked_sessions.insert(record.session_id.clone(), (user_email.to_string(), expires_at));
+                revoked += 1;
+            }
+        }
+        refresh_tokens.retain(|_, record| record.user_email != user_email);
+        Ok(revoked)
+    }
+
+    async fn is_revoked(&self, session_id: &str) -> Result<bool, AppError> {
+        Ok(self.revoked_sessions.lock().unwrap().contains_key(session_id))
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+        let mut revoked_sessions = self.revoked_sessions.lock().unwrap();
+        let before = refresh_tokens.len() + revoked_sessions.len();
+        refresh_tokens.retain(|_, record| record.expires_at > now);
+        revoked_sessions.retain(|_, (_, expires_at)| *expires_at > now);
+        Ok((before - refresh_tokens.len() - revoked_sessions.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl AccountTokenRepository for MemoryRepository {
+    async fn issue(&self, record: &AccountTokenRecord) -> Result<(), AppError> {
+        let mut account_tokens = self.account_tokens.lock().unwrap();
+        account_tokens.retain(|_, issued| {
+            !(issued.user_email == record.user_email && issued.purpose == record.purpose && issued.used_at.is_none())
+        });
+        account_tokens.insert(record.token_hash.clone(), record.clone());
+        Ok(())
+    }
+
+    async fn consume(&self, token_hash: &str, purpose: &str, now: i64) -> Result<Option<String>, AppError> {
+        Ok(self.account_tokens.lock().unwrap()
+            .get_mut(token_hash)
+            .filter(|record| record.purpose == purpose && record.used_at.is_none() && record.expires_at > now)
+            .map(|record| {
+                record.used_at = Some(now);
+                record.user_email.clone()
+            }))
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        let mut account_tokens = self.account_tokens.lock().unwrap();
+        let before = account_tokens.len();
+        account_tokens.retain(|_, record| record.expires_at > now);
+        Ok((before - account_tokens.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl TwoFactorRepository for MemoryRepository {
+    async fn find_two_factor(&self, user_email: &str) -> Result<Option<TwoFactorRecord>, AppError> {
+        Ok(self.two_factor.lock().unwrap().get(user_email).cloned())
+    }
+
+    async fn enroll(&self, user_email: &str, secret: &str, enrolled_at: i64) -> Result<bool, AppError> {
+        let mut two_factor = self.two_factor.lock().unwrap();
+        if two_factor.get(user_email).is_some_and(|record| record.enabled) {
+            return Ok(false);
+        }
+        two_factor.insert(user_email.to_string(), TwoFactorRecord {
+            user_email: user_email.to_string(),
+            secret: secret.to_string(),
+            enabled: false,
+            enrolled_at,
+            last_step: 0,
+        });
+        Ok(true)
+    }
+
+    async fn enable(&self, user_email: &str, recovery_code_hashes: &[String]) -> Result<bool, AppError> {
+        match self.two_factor.lock().unwrap().get_mut(user_email).filter(|record| !record.enabled) {
+            Some(record) => record.enabled = true,
+            None => return Ok(false),
+        }
+        self.replace_recovery_codes(user_email, recovery_code_hashes).await?;
+        Ok(true)
+    }
+
+    async fn use_step(&self, user_email: &str, step: i64) -> Result<bool, AppError> {
+        Ok(self.two_factor.lock().unwrap()
+            .get_mut(user_email)
+            .filter(|record| record.last_step < step)
+            .map(|record| record.last_step = step)
+            .is_some())
+    }
+
+    async fn replace_recovery_codes(&self, user_email: &str, recovery_code_hashes: &[String]) -> Result<(), AppError> {
+        let mut recovery_codes = self.recovery_codes.lock().unwrap();
+        recovery_codes.retain(|_, (email, _)| email != user_email);
+        for code_hash in recovery_code_hashes {
+            recovery_codes.insert(code_hash.clone(), (user_email.to_string(), None));
+        }
+        Ok(())
+    }
+
+    async fn use_recovery_code(&self, user_email: &str, code_hash: &str, used_at: i64) -> Result<bool, AppError> {
+        Ok(self.recovery_codes.lock().unwrap()
+            .get_mut(code_hash)
+            .filter(|(email, used)| email == user_email && used.is_none())
+            .map(|(_, used)| *used = Some(used_at))
+            .is_some())
+    }
+
+    async fn count_recovery_codes(&self, user_email: &str) -> Result<u64, AppError> {
+        Ok(self.recovery_codes.lock().unwrap()
+            .values()
+            .filter(|(email, used)| email == user_email && used.is_none())
+            .count() as u64)
+    }
+
+    async fn disable(&self, user_email: &str) -> Result<bool, AppError> {
+        self.recovery_codes.lock().unwrap().retain(|_, (email, _)| email != user_email);
+        Ok(self.two_factor.lock().unwrap().remove(user_email).is_some())
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for MemoryRepository {
+    async fn insert_api_key(&self, record: &ApiKeyRecord) -> Result<(), AppError> {
+        self.api_keys.lock().unwrap().insert(record.key_id.clone(), record.clone());
+        Ok(())
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, AppError> {
+        Ok(self.api_keys.lock().unwrap().values().find(|record| record.key_hash == key_hash).cloned())
+    }
+
+    async fn list_api_keys(&self, user_email: &str) -> Result<Vec<ApiKeyRecord>, AppError> {
+        let mut api_keys: Vec<ApiKeyRecord> = self.api_keys.lock().unwrap()
+            .values()
+            .filter(|record| record.user_email == user_email)
+            .cloned()
+            .collect();
+        api_keys.sort_by_key(|record| std::cmp::Reverse(record.created_at));
+        Ok(api_keys)
+    }
+
+    async fn touch_api_key(&self, key_id: &str, used_at: i64) -> Result<(), AppError> {
+        if let Some(record) = self.api_keys.lock().unwrap().get_mut(key_id) {
+            record.last_used_at = Some(used_at);
+        }
+        Ok(())
+    }
+
+    async fn revoke_api_key(&self, user_email: &str, key_id: &str) -> Result<bool, AppError> {
+        let mut api_keys = self.api_keys.lock().unwrap();
+        if api_keys.get(key_id).is_none_or(|record| record.user_email != user_email) {
+            return Ok(false);
+        }
+        api_keys.remove(key_id);
+        Ok(true)
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        let mut api_keys = self.api_keys.lock().unwrap();
+        let before = api_keys.len();
+        api_keys.retain(|_, record| record.expires_at.is_none_or(|expires_at| expires_at > now));
+        Ok((before - api_keys.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl DatabaseRepository for MemoryRepository {
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        Ok(())
+    }
+
+    async fn version(&self) -> Result<String, AppError> {
+        Ok(env!("CARGO_PKG_VERSION").to_string())
+    }
+
+    /// The schema is always current.
+    async fn applied_migrations(&self) -> Result<Option<Vec<AppliedMigration>>, AppError> {
+        Ok(Some(MIGRATIONS.iter()
+            .map(|migration| AppliedMigration {
+                version: migration.version,
+                name: migration.name.to_string(),
+                checksum: migration.checksum(),
+                applied_at: 0,
+            })
+            .collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryRepository;
+    use crate::repository::{TaskHistoryRepository, TaskLeaseRecord, TaskRunRecord};
+
+    #[tokio::test]
+    async fn task_leases_go_to_the_first_instance_of_a_tick() {
+        let repository = MemoryRepository::new();
+        let lease = |task_name: &str, holder: &str, tick| TaskLeaseRecord {
+            task_name: task_name.to_string(),
+            holder: holder.to_string(),
+            tick,
+            acquired_at: tick + 1,
+        };
+        assert!(repository.acquire_lease(&lease("auto_rej_fd", "a", 1000)).await.unwrap().0);
+        for holder in ["b", "a"] {
+            let (acquired, held) = repository.acquire_lease(&lease("auto_rej_fd", holder, 1000)).await.unwrap();
+            assert!(!acquired);
+            assert_eq!((held.holder.as_str(), held.tick, held.acquired_at), ("a", 1000, 1001));
+        }
+        // A late instance can't take back an earlier tick, other tasks lease on their own.
+        assert!(repository.acquire_lease(&lease("auto_rej_fd", "b", 2000)).await.unwrap().0);
+        assert!(!repository.acquire_lease(&lease("auto_rej_fd", "a", 1000)).await.unwrap().0);
+        assert!(repository.acquire_lease(&lease("auto_bak_mod", "a", 1000)).await.unwrap().0);
+
+        let mut leases = repository.leases().await.unwrap();
+        leases.sort_by(|left, right| left.task_name.cmp(&right.task_name));
+        let holders: Vec<(&str, &str, i64)> = leases.iter()
+            .map(|lease| (lease.task_name.as_str(), lease.holder.as_str(), lease.tick))
+            .collect();
+        assert_eq!(holders, [("auto_bak_mod", "a", 1000), ("auto_rej_fd", "b", 2000)]);
+    }
+
+    #[tokio::test]
+    async fn task_runs_keep_the_latest_of_every_task() {
+        let repository = MemoryRepository::new();
+        assert!(repository.last_runs().await.unwrap().is_empty());
+        let run = |task_name: &str, started_at, outcome: &str, error: Option<&str>| TaskRunRecord {
+            task_name: task_name.to_string(),
+            started_at,
+            finished_at: started_at + 5,
+            outcome: outcome.to_string(),
+            attempts: 2,
+            error: error.map(str::to_string),
+        };
+        // Recorded out of order, as overlapping runs of several instances finish.
+        repository.record_run(&run("auto_bak_mod", 20, "failed", Some("disk full"))).await.unwrap();
+        repository.record_run(&run("auto_bak_mod", 10, "succeeded", None)).await.unwrap();
+        repository.record_run(&run("auto_rej_fd", 15, "cancelled", None)).await.unwrap();
+
+        let last_runs = repository.last_runs().await.unwrap();
+        assert_eq!(last_runs.len(), 2);
+        let backup = last_runs.iter().find(|run| run.task_name == "auto_bak_mod").unwrap();
+        assert_eq!((backup.started_at, backup.finished_at, backup.attempts), (20, 25, 2));
+        assert_eq!((backup.outcome.as_str(), backup.error.as_deref()), ("failed", Some("disk full")));
+        assert!(last_runs.iter().any(|run| run.task_name == "auto_rej_fd" && run.outcome == "cancelled"));
+    }
+}
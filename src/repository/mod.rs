@@ -0,0 +1,367 @@
+mod cached;
+mod memory;
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use axum::async_trait;
+use deadpool_postgres::Pool;
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper_derive::PostgresMapper;
+
+use crate::{error::AppError, migrations::AppliedMigration};
+
+pub use cached::{CachedAccountRepository, CachedSessionRepository};
+pub use memory::MemoryRepository;
+pub use postgres::PostgresRepository;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteRepository;
+
+/// A row of `Account`.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "Account")]
+pub struct Account {
+    pub nick_name: String,
+    pub password_salt: String,
+    pub password_hash: String,
+    pub email: String,
+    pub contribution: i16,
+    pub available: bool,
+    pub permissions: i16,
+    pub email_verified: bool,
+}
+
+/// What the permission checks need of an account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProofAccount {
+    pub permissions: i16,
+    pub available: bool,
+}
+
+impl From<&Account> for ProofAccount {
+    fn from(account: &Account) -> Self {
+        ProofAccount { permissions: account.permissions, available: account.available }
+    }
+}
+
+/// A row of `TFeedback` when labelled, of `UFeedback` otherwise.
+/// Unlabelled feedback has neither `time_out` nor `real_label` and a `submit_count` of 0.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Feedback {
+    pub time_stamp: i64,
+    pub from_user_email: String,
+    pub time_out: Option<i64>,
+    pub pic_link: String,
+    pub real_label: Option<String>,
+    pub submit_count: i64,
+}
+
+/// A row of `Wiki`, the label is the output of the model.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "Wiki")]
+pub struct WikiEntry {
+    pub label: i32,
+    pub english_name: String,
+    pub specie_name: String,
+    pub content: String,
+}
+
+/// A row of `InferenceHistory`, `inferred_at` is Unix epoch milliseconds.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "InferenceHistory")]
+pub struct InferenceRecord {
+    pub user_email: String,
+    pub file_name: String,
+    pub label: i32,
+    pub specie_name: String,
+    pub inferred_at: i64,
+}
+
+/// A row of `TaskRun`, times are Unix epoch milliseconds.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "TaskRun")]
+pub struct TaskRunRecord {
+    pub task_name: String,
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub outcome: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+}
+
+/// A row of `TaskLease`, the tick and `acquired_at` are Unix epoch milliseconds.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "TaskLease")]
+pub struct TaskLeaseRecord {
+    pub task_name: String,
+    pub holder: String,
+    pub tick: i64,
+    pub acquired_at: i64,
+}
+
+/// A row of `TaskSetting`, a schedule of `None`s is the configured one. `updated_at` is Unix epoch milliseconds.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "TaskSetting")]
+pub struct TaskSettingRecord {
+    pub task_name: String,
+    pub paused: bool,
+    pub interval_seconds: Option<i64>,
+    pub cron_expression: Option<String>,
+    pub updated_at: i64,
+}
+
+/// A row of `RefreshToken`, times are Unix epoch seconds. The token itself is never stored.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "RefreshToken")]
+pub struct RefreshTokenRecord {
+    pub token_hash: String,
+    pub session_id: String,
+    pub user_email: String,
+    pub issued_at: i64,
+    pub expires_at: i64,
+    pub used_at: Option<i64>,
+}
+
+/// A row of `AccountToken`, times are Unix epoch seconds. The token itself is never stored.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "AccountToken")]
+pub struct AccountTokenRecord {
+    pub token_hash: String,
+    pub user_email: String,
+    pub purpose: String,
+    pub issued_at: i64,
+    pub expires_at: i64,
+    pub used_at: Option<i64>,
+}
+
+/// A row of `TwoFactor`, the TOTP secret in base32.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "TwoFactor")]
+pub struct TwoFactorRecord {
+    pub user_email: String,
+    pub secret: String,
+    pub enabled: bool,
+    pub enrolled_at: i64,
+    pub last_step: i64,
+}
+
+/// A row of `ApiKey`. `scopes` are `Permission` bits, `allowed_ips` comma separated, empty for any address.
+#[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
+#[pg_mapper(table = "ApiKey")]
+pub struct ApiKeyRecord {
+    pub key_id: String,
+    pub key_hash: String,
+    pub user_email: String,
+    pub name: String,
+    pub scopes: i16,
+    pub allowed_ips: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+}
+
+#[async_trait]
+pub trait AccountRepository: Send + Sync {
+    async fn find(&self, email: &str) -> Result<Option<Account>, AppError>;
+    async fn list(&self) -> Result<Vec<Account>, AppError>;
+    /// Returns false, inserting nothing, when the email is taken.
+    async fn insert(&self, account: &Account) -> Result<bool, AppError>;
+    /// Returns false when there is no such account, likewise below.
+    async fn set_available(&self, email: &str, available: bool) -> Result<bool, AppError>;
+    async fn set_contribution(&self, email: &str, contribution: i16) -> Result<bool, AppError>;
+    async fn set_email_verified(&self, email: &str, email_verified: bool) -> Result<bool, AppError>;
+    async fn set_password(&self, email: &str, password_salt: &str, password_hash: &str) -> Result<bool, AppError>;
+
+    async fn find_proof(&self, email: &str) -> Result<Option<ProofAccount>, AppError> {
+        Ok(self.find(email).await?.as_ref().map(ProofAccount::from))
+    }
+
+    /// Offer the proof a token claims, issued at `issued_at` (Unix seconds), in place of a lookup.
+    /// Ignored but by the cache.
+    fn remember_proof(&self, _email: &str, _proof: ProofAccount, _issued_at: i64) {}
+}
+
+#[async_trait]
+pub trait FeedbackRepository: Send + Sync {
+    /// Into `TFeedback` when the feedback has a label, into `UFeedback` otherwise.
+    async fn insert(&self, feedback: &Feedback) -> Result<(), AppError>;
+    async fn list_trainable(&self) -> Result<Vec<Feedback>, AppError>;
+    async fn list_unlabelled(&self) -> Result<Vec<Feedback>, AppError>;
+    async fn find_trainable(&self, pic_link: &str, real_label: &str) -> Result<Option<Feedback>, AppError>;
+    /// Sets the count of every label of the picture, returns the rows updated.
+    async fn set_submit_count(&self, pic_link: &str, submit_count: i64) -> Result<u64, AppError>;
+    async fn delete_trainable(&self, pic_link: &str, real_label: &str) -> Result<u64, AppError>;
+    /// Removes the trainable feedback whose `time_out` (Unix seconds) is not after `now`.
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError>;
+    /// Rows of (`TFeedback`, `UFeedback`).
+    async fn count(&self) -> Result<(i64, i64), AppError>;
+}
+
+#[async_trait]
+pub trait WikiRepository: Send + Sync {
+    async fn find(&self, label: i32) -> Result<Option<WikiEntry>, AppError>;
+}
+
+#[async_trait]
+pub trait InferenceHistoryRepository: Send + Sync {
+    async fn record(&self, record: &InferenceRecord) -> Result<(), AppError>;
+    /// The latest `limit` records of the user, newest first.
+    async fn list_by_user(&self, user_email: &str, limit: i64) -> Result<Vec<InferenceRecord>, AppError>;
+}
+
+#[async_trait]
+pub trait TaskHistoryRepository: Send + Sync {
+    /// Take the lease of the tick unless an instance holds it or a later one already.
+    /// Returns whether it was taken and the lease held now.
+    async fn acquire_lease(&self, lease: &TaskLeaseRecord) -> Result<(bool, TaskLeaseRecord), AppError>;
+    async fn record_run(&self, run: &TaskRunRecord) -> Result<(), AppError>;
+    /// The latest run of every task.
+    async fn last_runs(&self) -> Result<Vec<TaskRunRecord>, AppError>;
+    async fn leases(&self) -> Result<Vec<TaskLeaseRecord>, AppError>;
+    /// The setting shared by every instance, `None` until one is saved.
+    async fn setting(&self, task_name: &str) -> Result<Option<TaskSettingRecord>, AppError>;
+    async fn save_setting(&self, setting: &TaskSettingRecord) -> Result<(), AppError>;
+}
+
+/// Refresh tokens and revoked sessions, times are Unix epoch seconds.
+#[async_trait]
+pub trait SessionRepository: Send + Sync {
+    async fn insert_refresh_token(&self, record: &RefreshTokenRecord) -> Result<(), AppError>;
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AppError>;
+    /// Mark the token used, returns false when it was used already.
+    async fn use_refresh_token(&self, token_hash: &str, used_at: i64) -> Result<bool, AppError>;
+    /// Revoke the session until `expires_at` and drop its refresh tokens, in one transaction.
+    async fn revoke_session(&self, session_id: &str, user_email: &str, revoked_at: i64, expires_at: i64)
+        -> Result<(), AppError>;
+    /// Revoke every session of the account holding a refresh token, returns the sessions revoked.
+    async fn revoke_sessions_of(&self, user_email: &str, revoked_at: i64, expires_at: i64) -> Result<u64, AppError>;
+    async fn is_revoked(&self, session_id: &str) -> Result<bool, AppError>;
+    /// Removes the refresh tokens and revocations expired by `now`, returns the rows removed.
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError>;
+}
+
+/// Single-use tokens mailed to the accounts, times are Unix epoch seconds.
+#[async_trait]
+pub trait AccountTokenRepository: Send + Sync {
+    /// Store the token, dropping the unused tokens of the account for the same purpose.
+    async fn issue(&self, record: &AccountTokenRecord) -> Result<(), AppError>;
+    /// Mark the token used if it has the purpose, is unused and not expired by `now`. Returns its account.
+    async fn consume(&self, token_hash: &str, purpose: &str, now: i64) -> Result<Option<String>, AppError>;
+    /// Removes the tokens expired by `now`, returns the rows removed.
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError>;
+}
+
+/// TOTP secrets and recovery codes of the accounts. Codes are SHA-256 hashes, times Unix epoch seconds.
+#[async_trait]
+pub trait TwoFactorRepository: Send + Sync {
+    async fn find_two_factor(&self, user_email: &str) -> Result<Option<TwoFactorRecord>, AppError>;
+    /// Store a pending secret, replacing the one of an unfinished enrollment. False when one is enabled already.
+    async fn enroll(&self, user_email: &str, secret: &str, enrolled_at: i64) -> Result<bool, AppError>;
+    /// Enable the pending secret along with new recovery codes. False without a pending secret.
+    async fn enable(&self, user_email: &str, recovery_code_hashes: &[String]) -> Result<bool, AppError>;
+    /// Accept a TOTP time step if it is later than the last one accepted, so a code works once.
+    async fn use_step(&self, user_email: &str, step: i64) -> Result<bool, AppError>;
+    /// Replace every recovery code of the account.
+    async fn replace_recovery_codes(&self, user_email: &str, recovery_code_hashes: &[String]) -> Result<(), AppError>;
+    /// Mark the recovery code used, false if it isn't an unused code of the account.
+    async fn use_recovery_code(&self, user_email: &str, code_hash: &str, used_at: i64) -> Result<bool, AppError>;
+    async fn count_recovery_codes(&self, user_email: &str) -> Result<u64, AppError>;
+    /// Remove the secret and the recovery codes, false if there was no secret.
+    async fn disable(&self, user_email: &str) -> Result<bool, AppError>;
+}
+
+/// Keys acting for the accounts, stored as SHA-256 hashes. Times are Unix epoch seconds.
+#[async_trait]
+pub trait ApiKeyRepository: Send + Sync {
+    async fn insert_api_key(&self, record: &ApiKeyRecord) -> Result<(), AppError>;
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, AppError>;
+    /// Keys of the account, newest first.
+    async fn list_api_keys(&self, user_email: &str) -> Result<Vec<ApiKeyRecord>, AppError>;
+    async fn touch_api_key(&self, key_id: &str, used_at: i64) -> Result<(), AppError>;
+    /// Remove the key, false if the account has no such key.
+    async fn revoke_api_key(&self, user_email: &str, key_id: &str) -> Result<bool, AppError>;
+    /// Removes the keys expired by `now`, returns the rows removed.
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError>;
+}
+
+/// The database behind the other repositories, for the health checks and the schema check.
+#[async_trait]
+pub trait DatabaseRepository: Send + Sync {
+    /// `database.backend` of the repositories.
+    fn backend(&self) -> &'static str;
+    async fn ping(&self) -> Result<(), AppError>;
+    async fn version(&self) -> Result<String, AppError>;
+    /// Rows of `schema_migrations`, None before `init migrate up` created it.
+    async fn applied_migrations(&self) -> Result<Option<Vec<AppliedMigration>>, AppError>;
+}
+
+/// Every repository the handlers use, shared through `MultiState`.
+/// Postgres or SQLite in production, in process memory in the tests of the router.
+#[derive(Clone)]
+pub struct Repositories {
+    pub accounts: Arc<dyn AccountRepository>,
+    pub feedback: Arc<dyn FeedbackRepository>,
+    pub wiki: Arc<dyn WikiRepository>,
+    pub inferences: Arc<dyn InferenceHistoryRepository>,
+    pub tasks: Arc<dyn TaskHistoryRepository>,
+    pub sessions: Arc<dyn SessionRepository>,
+    pub account_tokens: Arc<dyn AccountTokenRepository>,
+    pub two_factor: Arc<dyn TwoFactorRepository>,
+    pub api_keys: Arc<dyn ApiKeyRepository>,
+    pub database: Arc<dyn DatabaseRepository>,
+}
+
+impl Repositories {
+    pub fn postgres(pool: Pool) -> Self {
+        Repositories::from_backend(Arc::new(PostgresRepository::new(pool)))
+    }
+
+    /// Opens or creates the database file.
+    #[cfg(feature = "sqlite")]
+    pub fn sqlite(path: &str) -> Result<Self, AppError> {
+        Ok(Repositories::from_backend(Arc::new(SqliteRepository::open(path)?)))
+    }
+
+    /// Empty tables but the species catalog, as after `init --seed-species`.
+    pub fn in_memory() -> Self {
+        Repositories::from_backend(Arc::new(MemoryRepository::new()))
+    }
+
+    /// Keep the proofs of the permission checks and the revocations of the sessions for `ttl`, none when it is zero.
+    pub fn with_permission_cache(mut self, ttl: Duration) -> Self {
+        if !ttl.is_zero() {
+            self.accounts = Arc::new(CachedAccountRepository::new(self.accounts, ttl));
+            self.sessions = Arc::new(CachedSessionRepository::new(self.sessions, ttl));
+        }
+        self
+    }
+
+    fn from_backend<R>(backend: Arc<R>) -> Self
+        where R: AccountRepository + FeedbackRepository + WikiRepository + InferenceHistoryRepository
+            + TaskHistoryRepository + SessionRepository + AccountTokenRepository + TwoFactorRepository
+            + ApiKeyRepository + DatabaseRepository + 'static
+    {
+        Repositories {
+            accounts: backend.clone(),
+            feedback: backend.clone(),
+            wiki: backend.clone(),
+            inferences: backend.clone(),
+            tasks: backend.clone(),
+            sessions: backend.clone(),
+            account_tokens: backend.clone(),
+            two_factor: backend.clone(),
+            api_keys: backend.clone(),
+            database: backend,
+        }
+    }
+}
+
+impl fmt::Debug for Repositories {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Repositories")
+            .field("backend", &self.database.backend())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,689 @@
+use axum::async_trait;
+use deadpool_postgres::{Object, Pool};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_postgres::Row;
+
+use crate::{error::AppError, migrations::{AppliedMigration, SELECT_SCHEMA_MIGRATIONS}};
+
+use super::{
+    Account, AccountRepository, AccountTokenRecord, AccountTokenRepository, ApiKeyRecord, ApiKeyRepository,
+    DatabaseRepository, Feedback,
+    FeedbackRepository, InferenceHistoryRepository, InferenceRecord, RefreshTokenRecord, SessionRepository,
+    TaskHistoryRepository, TaskLeaseRecord, TaskRunRecord, TaskSettingRecord, TwoFactorRecord, TwoFactorRepository, WikiEntry, WikiRepository
+};
+
+/// Every repository over one pool. Statements are prepared once per connection and
+/// cached by deadpool, so a recycled connection reuses them.
+#[derive(Clone, Debug)]
+pub struct PostgresRepository {
+    pool: Pool,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: Pool) -> Self {
+        PostgresRepository { pool }
+    }
+
+    async fn client(&self) -> Result<Object, AppError> {
+        Ok(self.pool.get().await?)
+    }
+}
+
+const SELECT_ACCOUNT: &str = "
+    SELECT nick_name, password_salt, password_hash, email, contribution, available, permissions, email_verified
+    FROM Account WHERE email=$1;
+";
+const SELECT_ACCOUNTS: &str = "
+    SELECT nick_name, password_salt, password_hash, email, contribution, available, permissions, email_verified FROM Account;
+";
+const INSERT_ACCOUNT: &str = "
+    INSERT INTO Account (nick_name, password_salt, password_hash, email, contribution, available, permissions, email_verified)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    ON CONFLICT (email) DO NOTHING;
+";
+const UPDATE_ACCOUNT_AVAILABLE: &str = "UPDATE Account SET available=$1 WHERE email=$2;";
+const UPDATE_ACCOUNT_CONTRIBUTION: &str = "UPDATE Account SET contribution=$1 WHERE email=$2;";
+const UPDATE_ACCOUNT_EMAIL_VERIFIED: &str = "UPDATE Account SET email_verified=$1 WHERE email=$2;";
+const UPDATE_ACCOUNT_PASSWORD: &str = "UPDATE Account SET password_salt=$1, password_hash=$2 WHERE email=$3;";
+
+const INSERT_TFEEDBACK: &str = "
+    INSERT INTO TFeedback (time_stamp, from_user_email, time_out, pic_link, real_label, submit_count)
+    VALUES ($1, $2, $3, $4, $5, $6);
+";
+const INSERT_UFEEDBACK: &str = "INSERT INTO UFeedback (time_stamp, from_user_email, pic_link) VALUES ($1, $2, $3);";
+const SELECT_TFEEDBACKS: &str = "
+    SELECT time_stamp, from_user_email, time_out, pic_link, real_label, submit_count FROM TFeedback;
+";
+const SELECT_UFEEDBACKS: &str = "SELECT time_stamp, from_user_email, pic_link FROM UFeedback;";
+const SELECT_TFEEDBACK: &str = "
+    SELECT time_stamp, from_user_email, time_out, pic_link, real_label, submit_count FROM TFeedback
+    WHERE pic_link=$1 AND real_label=$2;
+";
+const UPDATE_TFEEDBACK_SUBMIT_COUNT: &str = "UPDATE TFeedback SET submit_count=$1 WHERE pic_link=$2;";
+const DELETE_TFEEDBACK: &str = "DELETE FROM TFeedback WHERE pic_link=$1 AND real_label=$2;";
+const DELETE_EXPIRED_TFEEDBACK: &str = "DELETE FROM TFeedback WHERE time_out <= $1;";
+const COUNT_FEEDBACK: &str = "SELECT (SELECT COUNT(*) FROM TFeedback), (SELECT COUNT(*) FROM UFeedback);";
+
+const SELECT_WIKI: &str = "SELECT label, english_name, specie_name, content FROM Wiki WHERE label=$1;";
+
+const INSERT_INFERENCE: &str = "
+    INSERT INTO InferenceHistory (user_email, file_name, label, specie_name, inferred_at)
+    VALUES ($1, $2, $3, $4, $5);
+";
+const SELECT_INFERENCES: &str = "
+    SELECT user_email, file_name, label, specie_name, inferred_at FROM InferenceHistory
+    WHERE user_email=$1 ORDER BY inferred_at DESC, id DESC LIMIT $2;
+";
+
+const UPSERT_TASK_LEASE: &str = "
+    INSERT INTO TaskLease (task_name, holder, tick, acquired_at)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (task_name) DO UPDATE
+    SET holder = EXCLUDED.holder, tick = EXCLUDED.tick, acquired_at = EXCLUDED.acquired_at
+    WHERE TaskLease.tick < EXCLUDED.tick
+    RETURNING task_name, holder, tick, acquired_at;
+";
+const SELECT_TASK_LEASE: &str = "SELECT task_name, holder, tick, acquired_at FROM TaskLease WHERE task_name = $1;";
+const SELECT_TASK_LEASES: &str = "SELECT task_name, holder, tick, acquired_at FROM TaskLease;";
+const SELECT_TASK_SETTING: &str = "
+    SELECT task_name, paused, interval_seconds, cron_expression, updated_at FROM TaskSetting WHERE task_name = $1;
+";
+const UPSERT_TASK_SETTING: &str = "
+    INSERT INTO TaskSetting (task_name, paused, interval_seconds, cron_expression, updated_at)
+    VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT (task_name) DO UPDATE
+    SET paused = EXCLUDED.paused, interval_seconds = EXCLUDED.interval_seconds,
+        cron_expression = EXCLUDED.cron_expression, updated_at = EXCLUDED.updated_at;
+";
+const INSERT_TASK_RUN: &str = "
+    INSERT INTO TaskRun (task_name, started_at, finished_at, outcome, attempts, error)
+    VALUES ($1, $2, $3, $4, $5, $6);
+";
+const SELECT_LAST_TASK_RUNS: &str = "
+    SELECT DISTINCT ON (task_name) task_name, started_at, finished_at, outcome, attempts, error
+    FROM TaskRun
+    ORDER BY task_name, started_at DESC;
+";
+
+const INSERT_REFRESH_TOKEN: &str = "
+    INSERT INTO RefreshToken (token_hash, session_id, user_email, issued_at, expires_at, used_at)
+    VALUES ($1, $2, $3, $4, $5, $6);
+";
+const SELECT_REFRESH_TOKEN: &str = "
+    SELECT token_hash, session_id, user_email, issued_at, expires_at, used_at FROM RefreshToken WHERE token_hash=$1;
+";
+const UPDATE_REFRESH_TOKEN_USED: &str = "UPDATE RefreshToken SET used_at=$1 WHERE token_hash=$2 AND used_at IS NULL;";
+const INSERT_REVOKED_SESSION: &str = "
+    INSERT INTO RevokedSession (session_id, user_email, revoked_at, expires_at)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (session_id) DO NOTHING;
+";
+const DELETE_SESSION_REFRESH_TOKENS: &str = "DELETE FROM RefreshToken WHERE session_id=$1;";
+const INSERT_REVOKED_SESSIONS_OF: &str = "
+    INSERT INTO RevokedSession (session_id, user_email, revoked_at, expires_at)
+    SELECT DISTINCT session_id, user_email, $2::BIGINT, $3::BIGINT FROM RefreshToken WHERE user_email=$1
+    ON CONFLICT (session_id) DO NOTHING;
+";
+const DELETE_USER_REFRESH_TOKENS: &str = "DELETE FROM RefreshToken WHERE user_email=$1;";
+const SELECT_SESSION_REVOKED: &str = "SELECT EXISTS (SELECT 1 FROM RevokedSession WHERE session_id=$1);";
+const DELETE_EXPIRED_REFRESH_TOKENS: &str = "DELETE FROM RefreshToken WHERE expires_at <= $1;";
+const DELETE_EXPIRED_REVOKED_SESSIONS: &str = "DELETE FROM RevokedSession WHERE expires_at <= $1;";
+
+const DELETE_UNUSED_ACCOUNT_TOKENS: &str = "
+    DELETE FROM AccountToken WHERE user_email=$1 AND purpose=$2 AND used_at IS NULL;
+";
+const INSERT_ACCOUNT_TOKEN: &str = "
+    INSERT INTO AccountToken (token_hash, user_email, purpose, issued_at, expires_at, used_at)
+    VALUES ($1, $2, $3, $4, $5, $6);
+";
+const UPDATE_ACCOUNT_TOKEN_USED: &str = "
+    UPDATE AccountToken SET used_at=$1
+    WHERE token_hash=$2 AND purpose=$3 AND used_at IS NULL AND expires_at > $1
+    RETURNING user_email;
+";
+const DELETE_EXPIRED_ACCOUNT_TOKENS: &str = "DELETE FROM AccountToken WHERE expires_at <= $1;";
+
+const SELECT_TWO_FACTOR: &str = "
+    SELECT user_email, secret, enabled, enrolled_at, last_step FROM TwoFactor WHERE user_email=$1;
+";
+const UPSERT_PENDING_TWO_FACTOR: &str = "
+    INSERT INTO TwoFactor (user_email, secret, enabled, enrolled_at, last_step) VALUES ($1, $2, FALSE, $3, 0)
+    ON CONFLICT (user_email) DO UPDATE SET secret=excluded.secret, enrolled_at=excluded.enrolled_at, last_step=0
+    WHERE NOT TwoFactor.enabled;
+";
+const UPDATE_TWO_FACTOR_ENABLED: &str = "UPDATE TwoFactor SET enabled=TRUE WHERE user_email=$1 AND NOT enabled;";
+const UPDATE_TWO_FACTOR_STEP: &str = "UPDATE TwoFactor SET last_step=$2 WHERE user_email=$1 AND last_step < $2;";
+const DELETE_TWO_FACTOR: &str = "DELETE FROM TwoFactor WHERE user_email=$1;";
+const DELETE_RECOVERY_CODES: &str = "DELETE FROM RecoveryCode WHERE user_email=$1;";
+const INSERT_RECOVERY_CODE: &str = "INSERT INTO RecoveryCode (code_hash, user_email, used_at) VALUES ($1, $2, NULL);";
+const UPDATE_RECOVERY_CODE_USED: &str = "
+    UPDATE RecoveryCode SET used_at=$3 WHERE code_hash=$2 AND user_email=$1 AND used_at IS NULL;
+";
+const COUNT_RECOVERY_CODES: &str = "SELECT COUNT(*) AS count FROM RecoveryCode WHERE user_email=$1 AND used_at IS NULL;";
+
+const SELECT_API_KEY: &str = "
+    SELECT key_id, key_hash, user_email, name, scopes, allowed_ips, created_at, expires_at, last_used_at
+    FROM ApiKey WHERE key_hash=$1;
+";
+const SELECT_API_KEYS: &str = "
+    SELECT key_id, key_hash, user_email, name, scopes, allowed_ips, created_at, expires_at, last_used_at
+    FROM ApiKey WHERE user_email=$1 ORDER BY created_at DESC;
+";
+const INSERT_API_KEY: &str = "
+    INSERT INTO ApiKey (key_id, key_hash, user_email, name, scopes, allowed_ips, created_at, expires_at, last_used_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NULL);
+";
+const UPDATE_API_KEY_USED: &str = "UPDATE ApiKey SET last_used_at=$2 WHERE key_id=$1;";
+const DELETE_API_KEY: &str = "DELETE FROM ApiKey WHERE user_email=$1 AND key_id=$2;";
+const DELETE_EXPIRED_API_KEYS: &str = "DELETE FROM ApiKey WHERE expires_at <= $1;";
+
+const SCHEMA_MIGRATIONS_EXISTS: &str = "SELECT to_regclass('schema_migrations') IS NOT NULL;";
+
+fn __feedback_from_row(row: &Row, trainable: bool) -> Feedback {
+    Feedback {
+        time_stamp: row.get("time_stamp"),
+        from_user_email: row.get("from_user_email"),
+        time_out: match trainable {
+            true => row.get("time_out"),
+            false => None,
+        },
+        pic_link: row.get("pic_link"),
+        real_label: match trainable {
+            true => row.get("real_label"),
+            false => None,
+        },
+        submit_count: match trainable {
+            true => row.get("submit_count"),
+            false => 0,
+        },
+    }
+}
+
+#[async_trait]
+impl AccountRepository for PostgresRepository {
+    async fn find(&self, email: &str) -> Result<Option<Account>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_ACCOUNT).await?;
+        Ok(client.query_opt(&statement, &[&email])
+            .await?
+            .map(|row| Account::from_row_ref(&row))
+            .transpose()?)
+    }
+
+    async fn list(&self) -> Result<Vec<Account>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_ACCOUNTS).await?;
+        Ok(client.query(&statement, &[])
+            .await?
+            .iter()
+            .map(Account::from_row_ref)
+            .collect::<Result<Vec<Account>, _>>()?)
+    }
+
+    async fn insert(&self, account: &Account) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(INSERT_ACCOUNT).await?;
+        let rows = client.execute(&statement, &[
+            &account.nick_name, &account.password_salt, &account.password_hash, &account.email,
+            &account.contribution, &account.available, &account.permissions, &account.email_verified
+        ]).await?;
+        Ok(rows > 0)
+    }
+
+    async fn set_available(&self, email: &str, available: bool) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_ACCOUNT_AVAILABLE).await?;
+        Ok(client.execute(&statement, &[&available, &email]).await? > 0)
+    }
+
+    async fn set_contribution(&self, email: &str, contribution: i16) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_ACCOUNT_CONTRIBUTION).await?;
+        Ok(client.execute(&statement, &[&contribution, &email]).await? > 0)
+    }
+
+    async fn set_email_verified(&self, email: &str, email_verified: bool) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_ACCOUNT_EMAIL_VERIFIED).await?;
+        Ok(client.execute(&statement, &[&email_verified, &email]).await? > 0)
+    }
+
+    async fn set_password(&self, email: &str, password_salt: &str, password_hash: &str) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_ACCOUNT_PASSWORD).await?;
+        Ok(client.execute(&statement, &[&password_salt, &password_hash, &email]).await? > 0)
+    }
+}
+
+#[async_trait]
+impl FeedbackRepository for PostgresRepository {
+    async fn insert(&self, feedback: &Feedback) -> Result<(), AppError> {
+        let client = self.client().await?;
+        let rows = match feedback.real_label {
+            Some(_) => {
+                let statement = client.prepare_cached(INSERT_TFEEDBACK).await?;
+                client.execute(&statement, &[
+                    &feedback.time_stamp, &feedback.from_user_email, &feedback.time_out,
+                    &feedback.pic_link, &feedback.real_label, &feedback.submit_count
+                ]).await?
+            },
+            None => {
+                let statement = client.prepare_cached(INSERT_UFEEDBACK).await?;
+                client.execute(&statement, &[&feedback.time_stamp, &feedback.from_user_email, &feedback.pic_link]).await?
+            }
+        };
+        if rows < 1 {
+            return Err(AppError::Database("Insert feedback failed".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn list_trainable(&self) -> Result<Vec<Feedback>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_TFEEDBACKS).await?;
+        Ok(client.query(&statement, &[])
+            .await?
+            .iter()
+            .map(|row| __feedback_from_row(row, true))
+            .collect())
+    }
+
+    async fn list_unlabelled(&self) -> Result<Vec<Feedback>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_UFEEDBACKS).await?;
+        Ok(client.query(&statement, &[])
+            .await?
+            .iter()
+            .map(|row| __feedback_from_row(row, false))
+            .collect())
+    }
+
+    async fn find_trainable(&self, pic_link: &str, real_label: &str) -> Result<Option<Feedback>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_TFEEDBACK).await?;
+        Ok(client.query(&statement, &[&pic_link, &real_label])
+            .await?
+            .last()
+            .map(|row| __feedback_from_row(row, true)))
+    }
+
+    async fn set_submit_count(&self, pic_link: &str, submit_count: i64) -> Result<u64, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_TFEEDBACK_SUBMIT_COUNT).await?;
+        Ok(client.execute(&statement, &[&submit_count, &pic_link]).await?)
+    }
+
+    async fn delete_trainable(&self, pic_link: &str, real_label: &str) -> Result<u64, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(DELETE_TFEEDBACK).await?;
+        Ok(client.execute(&statement, &[&pic_link, &real_label]).await?)
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(DELETE_EXPIRED_TFEEDBACK).await?;
+        Ok(client.execute(&statement, &[&now]).await?)
+    }
+
+    async fn count(&self) -> Result<(i64, i64), AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(COUNT_FEEDBACK).await?;
+        let row = client.query_one(&statement, &[]).await?;
+        Ok((row.get(0), row.get(1)))
+    }
+}
+
+#[async_trait]
+impl WikiRepository for PostgresRepository {
+    async fn find(&self, label: i32) -> Result<Option<WikiEntry>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_WIKI).await?;
+        Ok(client.query_opt(&statement, &[&label])
+            .await?
+            .map(|row| WikiEntry::from_row_ref(&row))
+            .transpose()?)
+    }
+}
+
+#[async_trait]
+impl InferenceHistoryRepository for PostgresRepository {
+    async fn record(&self, record: &InferenceRecord) -> Result<(), AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(INSERT_INFERENCE).await?;
+        client.execute(&statement, &[
+            &record.user_email, &record.file_name, &record.label, &record.specie_name, &record.inferred_at
+        ]).await?;
+        Ok(())
+    }
+
+    async fn list_by_user(&self, user_email: &str, limit: i64) -> Result<Vec<InferenceRecord>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_INFERENCES).await?;
+        Ok(client.query(&statement, &[&user_email, &limit])
+            .await?
+            .iter()
+            .map(InferenceRecord::from_row_ref)
+            .collect::<Result<Vec<InferenceRecord>, _>>()?)
+    }
+}
+
+#[async_trait]
+impl TaskHistoryRepository for PostgresRepository {
+    async fn acquire_lease(&self, lease: &TaskLeaseRecord) -> Result<(bool, TaskLeaseRecord), AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPSERT_TASK_LEASE).await?;
+        let acquired = client
+            .query_opt(&statement, &[&lease.task_name, &lease.holder, &lease.tick, &lease.acquired_at])
+            .await?;
+        if let Some(row) = acquired {
+            return Ok((true, TaskLeaseRecord::from_row_ref(&row)?));
+        }
+        let statement = client.prepare_cached(SELECT_TASK_LEASE).await?;
+        let row = client.query_one(&statement, &[&lease.task_name]).await?;
+        Ok((false, TaskLeaseRecord::from_row_ref(&row)?))
+    }
+
+    async fn record_run(&self, run: &TaskRunRecord) -> Result<(), AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(INSERT_TASK_RUN).await?;
+        client.execute(&statement, &[
+            &run.task_name, &run.started_at, &run.finished_at, &run.outcome, &run.attempts, &run.error
+        ]).await?;
+        Ok(())
+    }
+
+    async fn last_runs(&self) -> Result<Vec<TaskRunRecord>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_LAST_TASK_RUNS).await?;
+        Ok(client.query(&statement, &[])
+            .await?
+            .iter()
+            .map(TaskRunRecord::from_row_ref)
+            .collect::<Result<Vec<TaskRunRecord>, _>>()?)
+    }
+
+    async fn leases(&self) -> Result<Vec<TaskLeaseRecord>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_TASK_LEASES).await?;
+        Ok(client.query(&statement, &[])
+            .await?
+            .iter()
+            .map(TaskLeaseRecord::from_row_ref)
+            .collect::<Result<Vec<TaskLeaseRecord>, _>>()?)
+    }
+
+    async fn setting(&self, task_name: &str) -> Result<Option<TaskSettingRecord>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_TASK_SETTING).await?;
+        Ok(client.query_opt(&statement, &[&task_name])
+            .await?
+            .map(|row| TaskSettingRecord::from_row_ref(&row))
+            .transpose()?)
+    }
+
+    async fn save_setting(&self, setting: &TaskSettingRecord) -> Result<(), AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPSERT_TASK_SETTING).await?;
+        client.execute(&statement, &[
+            &setting.task_name, &setting.paused, &setting.interval_seconds, &setting.cron_expression, &setting.updated_at
+        ]).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionRepository for PostgresRepository {
+    async fn insert_refresh_token(&self, record: &RefreshTokenRecord) -> Result<(), AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(INSERT_REFRESH_TOKEN).await?;
+        client.execute(&statement, &[
+            &record.token_hash, &record.session_id, &record.user_email, &record.issued_at, &record.expires_at,
+            &record.used_at
+        ]).await?;
+        Ok(())
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_REFRESH_TOKEN).await?;
+        Ok(client.query_opt(&statement, &[&token_hash])
+            .await?
+            .map(|row| RefreshTokenRecord::from_row_ref(&row))
+            .transpose()?)
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str, used_at: i64) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_REFRESH_TOKEN_USED).await?;
+        Ok(client.execute(&statement, &[&used_at, &token_hash]).await? > 0)
+    }
+
+    async fn revoke_session(&self, session_id: &str, user_email: &str, revoked_at: i64, expires_at: i64)
+        -> Result<(), AppError> {
+        let mut client = self.client().await?;
+        let transaction = client.transaction().await?;
+        let statement = transaction.prepare_cached(INSERT_REVOKED_SESSION).await?;
+        transaction.execute(&statement, &[&session_id, &user_email, &revoked_at, &expires_at]).await?;
+        let statement = transaction.prepare_cached(DELETE_SESSION_REFRESH_TOKENS).await?;
+        transaction.execute(&statement, &[&session_id]).await?;
+        Ok(transaction.commit().await?)
+    }
+
+    async fn revoke_sessions_of(&self, user_email: &str, revoked_at: i64, expires_at: i64) -> Result<u64, AppError> {
+        let mut client = self.client().await?;
+        let transaction = client.transaction().await?;
+        let statement = transaction.prepare_cached(INSERT_REVOKED_SESSIONS_OF).await?;
+        let revoked = transaction.execute(&statement, &[&user_email, &revoked_at, &expires_at]).await?;
+        let statement = transaction.prepare_cached(DELETE_USER_REFRESH_TOKENS).await?;
+        transaction.execute(&statement, &[&user_email]).await?;
+        transaction.commit().await?;
+        Ok(revoked)
+    }
+
+    async fn is_revoked(&self, session_id: &str) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_SESSION_REVOKED).await?;
+        Ok(client.query_one(&statement, &[&session_id]).await?.get(0))
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(DELETE_EXPIRED_REFRESH_TOKENS).await?;
+        let tokens = client.execute(&statement, &[&now]).await?;
+        let statement = client.prepare_cached(DELETE_EXPIRED_REVOKED_SESSIONS).await?;
+        Ok(tokens + client.execute(&statement, &[&now]).await?)
+    }
+}
+
+#[async_trait]
+impl AccountTokenRepository for PostgresRepository {
+    async fn issue(&self, record: &AccountTokenRecord) -> Result<(), AppError> {
+        let mut client = self.client().await?;
+        let transaction = client.transaction().await?;
+        let statement = transaction.prepare_cached(DELETE_UNUSED_ACCOUNT_TOKENS).await?;
+        transaction.execute(&statement, &[&record.user_email, &record.purpose]).await?;
+        let statement = transaction.prepare_cached(INSERT_ACCOUNT_TOKEN).await?;
+        transaction.execute(&statement, &[
+            &record.token_hash, &record.user_email, &record.purpose, &record.issued_at, &record.expires_at,
+            &record.used_at
+        ]).await?;
+        Ok(transaction.commit().await?)
+    }
+
+    async fn consume(&self, token_hash: &str, purpose: &str, now: i64) -> Result<Option<String>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_ACCOUNT_TOKEN_USED).await?;
+        Ok(client.query_opt(&statement, &[&now, &token_hash, &purpose])
+            .await?
+            .map(|row| row.get("user_email")))
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(DELETE_EXPIRED_ACCOUNT_TOKENS).await?;
+        Ok(client.execute(&statement, &[&now]).await?)
+    }
+}
+
+#[async_trait]
+impl TwoFactorRepository for PostgresRepository {
+    async fn find_two_factor(&self, user_email: &str) -> Result<Option<TwoFactorRecord>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_TWO_FACTOR).await?;
+        Ok(client.query_opt(&statement, &[&user_email])
+            .await?
+            .map(|row| TwoFactorRecord::from_row_ref(&row))
+            .transpose()?)
+    }
+
+    async fn enroll(&self, user_email: &str, secret: &str, enrolled_at: i64) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPSERT_PENDING_TWO_FACTOR).await?;
+        Ok(client.execute(&statement, &[&user_email, &secret, &enrolled_at]).await? > 0)
+    }
+
+    async fn enable(&self, user_email: &str, recovery_code_hashes: &[String]) -> Result<bool, AppError> {
+        let mut client = self.client().await?;
+        let transaction = client.transaction().await?;
+        let statement = transaction.prepare_cached(UPDATE_TWO_FACTOR_ENABLED).await?;
+        if transaction.execute(&statement, &[&user_email]).await? == 0 {
+            return Ok(false);
+        }
+        let statement = transaction.prepare_cached(DELETE_RECOVERY_CODES).await?;
+        transaction.execute(&statement, &[&user_email]).await?;
+        let statement = transaction.prepare_cached(INSERT_RECOVERY_CODE).await?;
+        for code_hash in recovery_code_hashes {
+            transaction.execute(&statement, &[code_hash, &user_email]).await?;
+        }
+        transaction.commit().await?;
+        Ok(true)
+    }
+
+    async fn use_step(&self, user_email: &str, step: i64) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_TWO_FACTOR_STEP).await?;
+        Ok(client.execute(&statement, &[&user_email, &step]).await? > 0)
+    }
+
+    async fn replace_recovery_codes(&self, user_email: &str, recovery_code_hashes: &[String]) -> Result<(), AppError> {
+        let mut client = self.client().await?;
+        let transaction = client.transaction().await?;
+        let statement = transaction.prepare_cached(DELETE_RECOVERY_CODES).await?;
+        transaction.execute(&statement, &[&user_email]).await?;
+        let statement = transaction.prepare_cached(INSERT_RECOVERY_CODE).await?;
+        for code_hash in recovery_code_hashes {
+            transaction.execute(&statement, &[code_hash, &user_email]).await?;
+        }
+        Ok(transaction.commit().await?)
+    }
+
+    async fn use_recovery_code(&self, user_email: &str, code_hash: &str, used_at: i64) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_RECOVERY_CODE_USED).await?;
+        Ok(client.execute(&statement, &[&user_email, &code_hash, &used_at]).await? > 0)
+    }
+
+    async fn count_recovery_codes(&self, user_email: &str) -> Result<u64, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(COUNT_RECOVERY_CODES).await?;
+        let count: i64 = client.query_one(&statement, &[&user_email]).await?.get("count");
+        Ok(count as u64)
+    }
+
+    async fn disable(&self, user_email: &str) -> Result<bool, AppError> {
+        let mut client = self.client().await?;
+        let transaction = client.transaction().await?;
+        let statement = transaction.prepare_cached(DELETE_RECOVERY_CODES).await?;
+        transaction.execute(&statement, &[&user_email]).await?;
+        let statement = transaction.prepare_cached(DELETE_TWO_FACTOR).await?;
+        let removed = transaction.execute(&statement, &[&user_email]).await? > 0;
+        transaction.commit().await?;
+        Ok(removed)
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for PostgresRepository {
+    async fn insert_api_key(&self, record: &ApiKeyRecord) -> Result<(), AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(INSERT_API_KEY).await?;
+        client.execute(&statement, &[
+            &record.key_id, &record.key_hash, &record.user_email, &record.name, &record.scopes, &record.allowed_ips,
+            &record.created_at, &record.expires_at
+        ]).await?;
+        Ok(())
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_API_KEY).await?;
+        Ok(client.query_opt(&statement, &[&key_hash])
+            .await?
+            .map(|row| ApiKeyRecord::from_row_ref(&row))
+            .transpose()?)
+    }
+
+    async fn list_api_keys(&self, user_email: &str) -> Result<Vec<ApiKeyRecord>, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(SELECT_API_KEYS).await?;
+        Ok(client.query(&statement, &[&user_email])
+            .await?
+            .iter()
+            .map(ApiKeyRecord::from_row_ref)
+            .collect::<Result<Vec<ApiKeyRecord>, _>>()?)
+    }
+
+    async fn touch_api_key(&self, key_id: &str, used_at: i64) -> Result<(), AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(UPDATE_API_KEY_USED).await?;
+        client.execute(&statement, &[&key_id, &used_at]).await?;
+        Ok(())
+    }
+
+    async fn revoke_api_key(&self, user_email: &str, key_id: &str) -> Result<bool, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(DELETE_API_KEY).await?;
+        Ok(client.execute(&statement, &[&user_email, &key_id]).await? > 0)
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        let client = self.client().await?;
+        let statement = client.prepare_cached(DELETE_EXPIRED_API_KEYS).await?;
+        Ok(client.execute(&statement, &[&now]).await?)
+    }
+}
+
+#[async_trait]
+impl DatabaseRepository for PostgresRepository {
+    fn backend(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        self.client().await?.simple_query("SELECT 1;").await?;
+        Ok(())
+    }
+
+    async fn version(&self) -> Result<String, AppError> {
+        let client = self.client().await?;
+        Ok(client.query_one("SHOW server_version;", &[]).await?.get(0))
+    }
+
+    async fn applied_migrations(&self) -> Result<Option<Vec<AppliedMigration>>, AppError> {
+        let client = self.client().await?;
+        let exists: bool = client.query_one(SCHEMA_MIGRATIONS_EXISTS, &[]).await?.get(0);
+        if !exists {
+            return Ok(None);
+        }
+        Ok(Some(client.query(SELECT_SCHEMA_MIGRATIONS, &[])
+            .await?
+            .iter()
+            .map(|row| AppliedMigration {
+                version: row.get("version"),
+                name: row.get("name"),
+                checksum: row.get("checksum"),
+                applied_at: row.get("applied_at"),
+            })
+            .collect()))
+    }
+}
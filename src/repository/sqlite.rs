@@ -0,0 +1,1012 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use axum::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use crate::{error::AppError, migrations::{AppliedMigration, SELECT_SCHEMA_MIGRATIONS}};
+
+use super::{
+    Account, AccountRepository, AccountTokenRecord, AccountTokenRepository, ApiKeyRecord, ApiKeyRepository,
+    DatabaseRepository, Feedback,
+    FeedbackRepository, InferenceHistoryRepository, InferenceRecord, RefreshTokenRecord, SessionRepository,
+    TaskHistoryRepository, TaskLeaseRecord, TaskRunRecord, TaskSettingRecord, TwoFactorRecord, TwoFactorRepository, WikiEntry, WikiRepository
+};
+
+// Writers wait for each other this long before failing with SQLITE_BUSY.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Every repository over one SQLite file, for deployments on a single machine.
+/// The connection is shared and used from the blocking thread pool, one statement at a time.
+#[derive(Clone, Debug)]
+pub struct SqliteRepository {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteRepository {
+    /// Opens or creates the database file, `:memory:` for a private in-memory database.
+    pub fn open(path: &str) -> Result<Self, AppError> {
+        let connection = Connection::open(path)?;
+        // Readers don't wait for the writer, other processes such as `init` may read meanwhile.
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(SqliteRepository { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    async fn run<T, F>(&self, query: F) -> Result<T, AppError>
+        where F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+              T: Send + 'static
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || query(&connection.lock().unwrap()))
+            .await
+            .map_err(|err| AppError::Internal(err.to_string()))?
+            .map_err(AppError::from)
+    }
+}
+
+const SELECT_ACCOUNT: &str = "
+    SELECT nick_name, password_salt, password_hash, email, contribution, available, permissions, email_verified
+    FROM Account WHERE email=?1;
+";
+const SELECT_ACCOUNTS: &str = "
+    SELECT nick_name, password_salt, password_hash, email, contribution, available, permissions, email_verified FROM Account;
+";
+const INSERT_ACCOUNT: &str = "
+    INSERT INTO Account (nick_name, password_salt, password_hash, email, contribution, available, permissions, email_verified)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+    ON CONFLICT (email) DO NOTHING;
+";
+const UPDATE_ACCOUNT_AVAILABLE: &str = "UPDATE Account SET available=?1 WHERE email=?2;";
+const UPDATE_ACCOUNT_CONTRIBUTION: &str = "UPDATE Account SET contribution=?1 WHERE email=?2;";
+const UPDATE_ACCOUNT_EMAIL_VERIFIED: &str = "UPDATE Account SET email_verified=?1 WHERE email=?2;";
+const UPDATE_ACCOUNT_PASSWORD: &str = "UPDATE Account SET password_salt=?1, password_hash=?2 WHERE email=?3;";
+
+const INSERT_TFEEDBACK: &str = "
+    INSERT INTO TFeedback (time_stamp, from_user_email, time_out, pic_link, real_label, submit_count)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+";
+const INSERT_UFEEDBACK: &str = "INSERT INTO UFeedback (time_stamp, from_user_email, pic_link) VALUES (?1, ?2, ?3);";
+const SELECT_TFEEDBACKS: &str = "
+    SELECT time_stamp, from_user_email, time_out, pic_link, real_label, submit_count FROM TFeedback;
+";
+const SELECT_UFEEDBACKS: &str = "SELECT time_stamp, from_user_email, pic_link FROM UFeedback;";
+const SELECT_TFEEDBACK: &str = "
+    SELECT time_stamp, from_user_email, time_out, pic_link, real_label, submit_count FROM TFeedback
+    WHERE pic_link=?1 AND real_label=?2 ORDER BY id DESC LIMIT 1;
+";
+const UPDATE_TFEEDBACK_SUBMIT_COUNT: &str = "UPDATE TFeedback SET submit_count=?1 WHERE pic_link=?2;";
+const DELETE_TFEEDBACK: &str = "DELETE FROM TFeedback WHERE pic_link=?1 AND real_label=?2;";
+const DELETE_EXPIRED_TFEEDBACK: &str = "DELETE FROM TFeedback WHERE time_out <= ?1;";
+const COUNT_FEEDBACK: &str = "SELECT (SELECT COUNT(*) FROM TFeedback), (SELECT COUNT(*) FROM UFeedback);";
+
+const SELECT_WIKI: &str = "SELECT label, english_name, specie_name, content FROM Wiki WHERE label=?1;";
+
+const INSERT_INFERENCE: &str = "
+    INSERT INTO InferenceHistory (user_email, file_name, label, specie_name, inferred_at)
+    VALUES (?1, ?2, ?3, ?4, ?5);
+";
+const SELECT_INFERENCES: &str = "
+    SELECT user_email, file_name, label, specie_name, inferred_at FROM InferenceHistory
+    WHERE user_email=?1 ORDER BY inferred_at DESC, id DESC LIMIT ?2;
+";
+
+const UPSERT_TASK_LEASE: &str = "
+    INSERT INTO TaskLease (task_name, holder, tick, acquired_at)
+    VALUES (?1, ?2, ?3, ?4)
+    ON CONFLICT (task_name) DO UPDATE
+    SET holder = excluded.holder, tick = excluded.tick, acquired_at = excluded.acquired_at
+    WHERE TaskLease.tick < excluded.tick
+    RETURNING task_name, holder, tick, acquired_at;
+";
+const SELECT_TASK_LEASE: &str = "SELECT task_name, holder, tick, acquired_at FROM TaskLease WHERE task_name = ?1;";
+const SELECT_TASK_LEASES: &str = "SELECT task_name, holder, tick, acquired_at FROM TaskLease;";
+const SELECT_TASK_SETTING: &str = "
+    SELECT task_name, paused, interval_seconds, cron_expression, updated_at FROM TaskSetting WHERE task_name = ?1;
+";
+const UPSERT_TASK_SETTING: &str = "
+    INSERT INTO TaskSetting (task_name, paused, interval_seconds, cron_expression, updated_at)
+    VALUES (?1, ?2, ?3, ?4, ?5)
+    ON CONFLICT (task_name) DO UPDATE
+    SET paused = excluded.paused, interval_seconds = excluded.interval_seconds,
+        cron_expression = excluded.cron_expression, updated_at = excluded.updated_at;
+";
+const INSERT_TASK_RUN: &str = "
+    INSERT INTO TaskRun (task_name, started_at, finished_at, outcome, attempts, error)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+";
+// The other columns of a MAX() aggregate come from the row holding the maximum.
+const SELECT_LAST_TASK_RUNS: &str = "
+    SELECT task_name, MAX(started_at) AS started_at, finished_at, outcome, attempts, error
+    FROM TaskRun
+    GROUP BY task_name;
+";
+
+const INSERT_REFRESH_TOKEN: &str = "
+    INSERT INTO RefreshToken (token_hash, session_id, user_email, issued_at, expires_at, used_at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+";
+const SELECT_REFRESH_TOKEN: &str = "
+    SELECT token_hash, session_id, user_email, issued_at, expires_at, used_at FROM RefreshToken WHERE token_hash=?1;
+";
+const UPDATE_REFRESH_TOKEN_USED: &str = "UPDATE RefreshToken SET used_at=?1 WHERE token_hash=?2 AND used_at IS NULL;";
+const INSERT_REVOKED_SESSION: &str = "
+    INSERT INTO RevokedSession (session_id, user_email, revoked_at, expires_at)
+    VALUES (?1, ?2, ?3, ?4)
+    ON CONFLICT (session_id) DO NOTHING;
+";
+const DELETE_SESSION_REFRESH_TOKENS: &str = "DELETE FROM RefreshToken WHERE session_id=?1;";
+// The WHERE keeps SQLite from reading ON CONFLICT as a join constraint.
+const INSERT_REVOKED_SESSIONS_OF: &str = "
+    INSERT INTO RevokedSession (session_id, user_email, revoked_at, expires_at)
+    SELECT DISTINCT session_id, user_email, ?2, ?3 FROM RefreshToken WHERE user_email=?1
+    ON CONFLICT (session_id) DO NOTHING;
+";
+const DELETE_USER_REFRESH_TOKENS: &str = "DELETE FROM RefreshToken WHERE user_email=?1;";
+const SELECT_SESSION_REVOKED: &str = "SELECT EXISTS (SELECT 1 FROM RevokedSession WHERE session_id=?1);";
+const DELETE_EXPIRED_REFRESH_TOKENS: &str = "DELETE FROM RefreshToken WHERE expires_at <= ?1;";
+const DELETE_EXPIRED_REVOKED_SESSIONS: &str = "DELETE FROM RevokedSession WHERE expires_at <= ?1;";
+
+const DELETE_UNUSED_ACCOUNT_TOKENS: &str = "
+    DELETE FROM AccountToken WHERE user_email=?1 AND purpose=?2 AND used_at IS NULL;
+";
+const INSERT_ACCOUNT_TOKEN: &str = "
+    INSERT INTO AccountToken (token_hash, user_email, purpose, issued_at, expires_at, used_at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+";
+const UPDATE_ACCOUNT_TOKEN_USED: &str = "
+    UPDATE AccountToken SET used_at=?1
+    WHERE token_hash=?2 AND purpose=?3 AND used_at IS NULL AND expires_at > ?1
+    RETURNING user_email;
+";
+const DELETE_EXPIRED_ACCOUNT_TOKENS: &str = "DELETE FROM AccountToken WHERE expires_at <= ?1;";
+
+const SELECT_TWO_FACTOR: &str = "
+    SELECT user_email, secret, enabled, enrolled_at, last_step FROM TwoFactor WHERE user_email=?1;
+";
+const UPSERT_PENDING_TWO_FACTOR: &str = "
+    INSERT INTO TwoFactor (user_email, secret, enabled, enrolled_at, last_step) VALUES (?1, ?2, FALSE, ?3, 0)
+    ON CONFLICT (user_email) DO UPDATE SET secret=excluded.secret, enrolled_at=excluded.enrolled_at, last_step=0
+    WHERE NOT TwoFactor.enabled;
+";
+const UPDATE_TWO_FACTOR_ENABLED: &str = "UPDATE TwoFactor SET enabled=TRUE WHERE user_email=?1 AND NOT enabled;";
+const UPDATE_TWO_FACTOR_STEP: &str = "UPDATE TwoFactor SET last_step=?2 WHERE user_email=?1 AND last_step < ?2;";
+const DELETE_TWO_FACTOR: &str = "DELETE FROM TwoFactor WHERE user_email=?1;";
+const DELETE_RECOVERY_CODES: &str = "DELETE FROM RecoveryCode WHERE user_email=?1;";
+const INSERT_RECOVERY_CODE: &str = "INSERT INTO RecoveryCode (code_hash, user_email, used_at) VALUES (?1, ?2, NULL);";
+const UPDATE_RECOVERY_CODE_USED: &str = "
+    UPDATE RecoveryCode SET used_at=?3 WHERE code_hash=?2 AND user_email=?1 AND used_at IS NULL;
+";
+const COUNT_RECOVERY_CODES: &str = "SELECT COUNT(*) AS count FROM RecoveryCode WHERE user_email=?1 AND used_at IS NULL;";
+
+const INSERT_API_KEY: &str = "
+    INSERT INTO ApiKey (key_id, key_hash, user_email, name, scopes, allowed_ips, created_at, expires_at, last_used_at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, NULL);
+";
+const SELECT_API_KEY: &str = "
+    SELECT key_id, key_hash, user_email, name, scopes, allowed_ips, created_at, expires_at, last_used_at
+    FROM ApiKey WHERE key_hash=?1;
+";
+const SELECT_API_KEYS: &str = "
+    SELECT key_id, key_hash, user_email, name, scopes, allowed_ips, created_at, expires_at, last_used_at
+    FROM ApiKey WHERE user_email=?1 ORDER BY created_at DESC;
+";
+const UPDATE_API_KEY_USED: &str = "UPDATE ApiKey SET last_used_at=?2 WHERE key_id=?1;";
+const DELETE_API_KEY: &str = "DELETE FROM ApiKey WHERE user_email=?1 AND key_id=?2;";
+const DELETE_EXPIRED_API_KEYS: &str = "DELETE FROM ApiKey WHERE expires_at <= ?1;";
+
+const SCHEMA_MIGRATIONS_EXISTS: &str = "
+    SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations');
+";
+
+fn __account_from_row(row: &Row) -> rusqlite::Result<Account> {
+    Ok(Account {
+        nick_name: row.get("nick_name")?,
+        password_salt: row.get("password_salt")?,
+        password_hash: row.get("password_hash")?,
+        email: row.get("email")?,
+        contribution: row.get("contribution")?,
+        available: row.get("available")?,
+        permissions: row.get("permissions")?,
+        email_verified: row.get("email_verified")?,
+    })
+}
+
+fn __feedback_from_row(row: &Row, trainable: bool) -> rusqlite::Result<Feedback> {
+    Ok(Feedback {
+        time_stamp: row.get("time_stamp")?,
+        from_user_email: row.get("from_user_email")?,
+        time_out: match trainable {
+            true => row.get("time_out")?,
+            false => None,
+        },
+        pic_link: row.get("pic_link")?,
+        real_label: match trainable {
+            true => row.get("real_label")?,
+            false => None,
+        },
+        submit_count: match trainable {
+            true => row.get("submit_count")?,
+            false => 0,
+        },
+    })
+}
+
+fn __inference_from_row(row: &Row) -> rusqlite::Result<InferenceRecord> {
+    Ok(InferenceRecord {
+        user_email: row.get("user_email")?,
+        file_name: row.get("file_name")?,
+        label: row.get("label")?,
+        specie_name: row.get("specie_name")?,
+        inferred_at: row.get("inferred_at")?,
+    })
+}
+
+fn __task_run_from_row(row: &Row) -> rusqlite::Result<TaskRunRecord> {
+    Ok(TaskRunRecord {
+        task_name: row.get("task_name")?,
+        started_at: row.get("started_at")?,
+        finished_at: row.get("finished_at")?,
+        outcome: row.get("outcome")?,
+        attempts: row.get("attempts")?,
+        error: row.get("error")?,
+    })
+}
+
+fn __task_lease_from_row(row: &Row) -> rusqlite::Result<TaskLeaseRecord> {
+    Ok(TaskLeaseRecord {
+        task_name: row.get("task_name")?,
+        holder: row.get("holder")?,
+        tick: row.get("tick")?,
+        acquired_at: row.get("acquired_at")?,
+    })
+}
+
+fn __task_setting_from_row(row: &Row) -> rusqlite::Result<TaskSettingRecord> {
+    Ok(TaskSettingRecord {
+        task_name: row.get("task_name")?,
+        paused: row.get("paused")?,
+        interval_seconds: row.get("interval_seconds")?,
+        cron_expression: row.get("cron_expression")?,
+        updated_at: row.get("updated_at")?,
+    })
+}
+
+#[async_trait]
+impl AccountRepository for SqliteRepository {
+    async fn find(&self, email: &str) -> Result<Option<Account>, AppError> {
+        let email = email.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_ACCOUNT)?
+                .query_row(params![email], __account_from_row)
+                .optional()
+        }).await
+    }
+
+    async fn list(&self) -> Result<Vec<Account>, AppError> {
+        self.run(|connection| {
+            connection.prepare_cached(SELECT_ACCOUNTS)?
+                .query_map([], __account_from_row)?
+                .collect()
+        }).await
+    }
+
+    async fn insert(&self, account: &Account) -> Result<bool, AppError> {
+        let account = account.clone();
+        self.run(move |connection| {
+            let rows = connection.prepare_cached(INSERT_ACCOUNT)?.execute(params![
+                account.nick_name, account.password_salt, account.password_hash, account.email,
+                account.contribution, account.available, account.permissions, account.email_verified
+            ])?;
+            Ok(rows > 0)
+        }).await
+    }
+
+    async fn set_available(&self, email: &str, available: bool) -> Result<bool, AppError> {
+        let email = email.to_string();
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(UPDATE_ACCOUNT_AVAILABLE)?.execute(params![available, email])? > 0)
+        }).await
+    }
+
+    async fn set_contribution(&self, email: &str, contribution: i16) -> Result<bool, AppError> {
+        let email = email.to_string();
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(UPDATE_ACCOUNT_CONTRIBUTION)?.execute(params![contribution, email])? > 0)
+        }).await
+    }
+
+    async fn set_email_verified(&self, email: &str, email_verified: bool) -> Result<bool, AppError> {
+        let email = email.to_string();
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(UPDATE_ACCOUNT_EMAIL_VERIFIED)?.execute(params![email_verified, email])? > 0)
+        }).await
+    }
+
+    async fn set_password(&self, email: &str, password_salt: &str, password_hash: &str) -> Result<bool, AppError> {
+        let (email, password_salt, password_hash) = (email.to_string(), password_salt.to_string(), password_hash.to_string());
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(UPDATE_ACCOUNT_PASSWORD)?.execute(params![password_salt, password_hash, email])? > 0)
+        }).await
+    }
+}
+
+#[async_trait]
+impl FeedbackRepository for SqliteRepository {
+    async fn insert(&self, feedback: &Feedback) -> Result<(), AppError> {
+        let feedback = feedback.clone();
+        let rows = self.run(move |connection| match feedback.real_label {
+            Some(_) => connection.prepare_cached(INSERT_TFEEDBACK)?.execute(params![
+                feedback.time_stamp, feedback.from_user_email, feedback.time_out,
+                feedback.pic_link, feedback.real_label, feedback.submit_count
+            ]),
+            None => connection.prepare_cached(INSERT_UFEEDBACK)?
+                .execute(params![feedback.time_stamp, feedback.from_user_email, feedback.pic_link]),
+        }).await?;
+        if rows < 1 {
+            return Err(AppError::Database("Insert feedback failed".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn list_trainable(&self) -> Result<Vec<Feedback>, AppError> {
+        self.run(|connection| {
+            connection.prepare_cached(SELECT_TFEEDBACKS)?
+                .query_map([], |row| __feedback_from_row(row, true))?
+                .collect()
+        }).await
+    }
+
+    async fn list_unlabelled(&self) -> Result<Vec<Feedback>, AppError> {
+        self.run(|connection| {
+            connection.prepare_cached(SELECT_UFEEDBACKS)?
+                .query_map([], |row| __feedback_from_row(row, false))?
+                .collect()
+        }).await
+    }
+
+    async fn find_trainable(&self, pic_link: &str, real_label: &str) -> Result<Option<Feedback>, AppError> {
+        let (pic_link, real_label) = (pic_link.to_string(), real_label.to_string());
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_TFEEDBACK)?
+                .query_row(params![pic_link, real_label], |row| __feedback_from_row(row, true))
+                .optional()
+        }).await
+    }
+
+    async fn set_submit_count(&self, pic_link: &str, submit_count: i64) -> Result<u64, AppError> {
+        let pic_link = pic_link.to_string();
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(UPDATE_TFEEDBACK_SUBMIT_COUNT)?.execute(params![submit_count, pic_link])? as u64)
+        }).await
+    }
+
+    async fn delete_trainable(&self, pic_link: &str, real_label: &str) -> Result<u64, AppError> {
+        let (pic_link, real_label) = (pic_link.to_string(), real_label.to_string());
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(DELETE_TFEEDBACK)?.execute(params![pic_link, real_label])? as u64)
+        }).await
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(DELETE_EXPIRED_TFEEDBACK)?.execute(params![now])? as u64)
+        }).await
+    }
+
+    async fn count(&self) -> Result<(i64, i64), AppError> {
+        self.run(|connection| {
+            connection.prepare_cached(COUNT_FEEDBACK)?
+                .query_row([], |row| Ok((row.get(0)?, row.get(1)?)))
+        }).await
+    }
+}
+
+#[async_trait]
+impl WikiRepository for SqliteRepository {
+    async fn find(&self, label: i32) -> Result<Option<WikiEntry>, AppError> {
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_WIKI)?
+                .query_row(params![label], |row| Ok(WikiEntry {
+                    label: row.get("label")?,
+                    english_name: row.get("english_name")?,
+                    specie_name: row.get("specie_name")?,
+                    content: row.get("content")?,
+                }))
+                .optional()
+        }).await
+    }
+}
+
+#[async_trait]
+impl InferenceHistoryRepository for SqliteRepository {
+    async fn record(&self, record: &InferenceRecord) -> Result<(), AppError> {
+        let record = record.clone();
+        self.run(move |connection| {
+            connection.prepare_cached(INSERT_INFERENCE)?.execute(params![
+                record.user_email, record.file_name, record.label, record.specie_name, record.inferred_at
+            ])?;
+            Ok(())
+        }).await
+    }
+
+    async fn list_by_user(&self, user_email: &str, limit: i64) -> Result<Vec<InferenceRecord>, AppError> {
+        let user_email = user_email.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_INFERENCES)?
+                .query_map(params![user_email, limit], __inference_from_row)?
+                .collect()
+        }).await
+    }
+}
+
+fn __refresh_token_from_row(row: &Row) -> rusqlite::Result<RefreshTokenRecord> {
+    Ok(RefreshTokenRecord {
+        token_hash: row.get("token_hash")?,
+        session_id: row.get("session_id")?,
+        user_email: row.get("user_email")?,
+        issued_at: row.get("issued_at")?,
+        expires_at: row.get("expires_at")?,
+        used_at: row.get("used_at")?,
+    })
+}
+
+fn __two_factor_from_row(row: &Row) -> rusqlite::Result<TwoFactorRecord> {
+    Ok(TwoFactorRecord {
+        user_email: row.get("user_email")?,
+        secret: row.get("secret")?,
+        enabled: row.get("enabled")?,
+        enrolled_at: row.get("enrolled_at")?,
+        last_step: row.get("last_step")?,
+    })
+}
+
+fn __api_key_from_row(row: &Row) -> rusqlite::Result<ApiKeyRecord> {
+    Ok(ApiKeyRecord {
+        key_id: row.get("key_id")?,
+        key_hash: row.get("key_hash")?,
+        user_email: row.get("user_email")?,
+        name: row.get("name")?,
+        scopes: row.get("scopes")?,
+        allowed_ips: row.get("allowed_ips")?,
+        created_at: row.get("created_at")?,
+        expires_at: row.get("expires_at")?,
+        last_used_at: row.get("last_used_at")?,
+    })
+}
+
+fn __replace_recovery_codes(connection: &Connection, user_email: &str, recovery_code_hashes: &[String]) -> rusqlite::Result<()> {
+    connection.prepare_cached(DELETE_RECOVERY_CODES)?.execute(params![user_email])?;
+    let mut statement = connection.prepare_cached(INSERT_RECOVERY_CODE)?;
+    for code_hash in recovery_code_hashes {
+        statement.execute(params![code_hash, user_email])?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl TaskHistoryRepository for SqliteRepository {
+    async fn acquire_lease(&self, lease: &TaskLeaseRecord) -> Result<(bool, TaskLeaseRecord), AppError> {
+        let lease = lease.clone();
+        self.run(move |connection| {
+            let acquired = connection.prepare_cached(UPSERT_TASK_LEASE)?
+                .query_row(params![lease.task_name, lease.holder, lease.tick, lease.acquired_at], __task_lease_from_row)
+                .optional()?;
+            if let Some(acquired) = acquired {
+                return Ok((true, acquired));
+            }
+            let held = connection.prepare_cached(SELECT_TASK_LEASE)?
+                .query_row(params![lease.task_name], __task_lease_from_row)?;
+            Ok((false, held))
+        }).await
+    }
+
+    async fn record_run(&self, run: &TaskRunRecord) -> Result<(), AppError> {
+        let run = run.clone();
+        self.run(move |connection| {
+            connection.prepare_cached(INSERT_TASK_RUN)?.execute(params![
+                run.task_name, run.started_at, run.finished_at, run.outcome, run.attempts, run.error
+            ])?;
+            Ok(())
+        }).await
+    }
+
+    async fn last_runs(&self) -> Result<Vec<TaskRunRecord>, AppError> {
+        self.run(|connection| {
+            connection.prepare_cached(SELECT_LAST_TASK_RUNS)?
+                .query_map([], __task_run_from_row)?
+                .collect()
+        }).await
+    }
+
+    async fn leases(&self) -> Result<Vec<TaskLeaseRecord>, AppError> {
+        self.run(|connection| {
+            connection.prepare_cached(SELECT_TASK_LEASES)?
+                .query_map([], __task_lease_from_row)?
+                .collect()
+        }).await
+    }
+
+    async fn setting(&self, task_name: &str) -> Result<Option<TaskSettingRecord>, AppError> {
+        let task_name = task_name.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_TASK_SETTING)?
+                .query_row(params![task_name], __task_setting_from_row)
+                .optional()
+        }).await
+    }
+
+    async fn save_setting(&self, setting: &TaskSettingRecord) -> Result<(), AppError> {
+        let setting = setting.clone();
+        self.run(move |connection| {
+            connection.prepare_cached(UPSERT_TASK_SETTING)?.execute(params![
+                setting.task_name, setting.paused, setting.interval_seconds, setting.cron_expression, setting.updated_at
+            ])?;
+            Ok(())
+        }).await
+    }
+}
+
+#[async_trait]
+impl SessionRepository for SqliteRepository {
+    async fn insert_refresh_token(&self, record: &RefreshTokenRecord) -> Result<(), AppError> {
+        let record = record.clone();
+        self.run(move |connection| {
+            connection.prepare_cached(INSERT_REFRESH_TOKEN)?.execute(params![
+                record.token_hash, record.session_id, record.user_email, record.issued_at, record.expires_at,
+                record.used_at
+            ])?;
+            Ok(())
+        }).await
+    }
+
+    async fn find_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshTokenRecord>, AppError> {
+        let token_hash = token_hash.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_REFRESH_TOKEN)?
+                .query_row(params![token_hash], __refresh_token_from_row)
+                .optional()
+        }).await
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str, used_at: i64) -> Result<bool, AppError> {
+        let token_hash = token_hash.to_string();
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(UPDATE_REFRESH_TOKEN_USED)?.execute(params![used_at, token_hash])? > 0)
+        }).await
+    }
+
+    async fn revoke_session(&self, session_id: &str, user_email: &str, revoked_at: i64, expires_at: i64)
+        -> Result<(), AppError> {
+        let (session_id, user_email) = (session_id.to_string(), user_email.to_string());
+        self.run(move |connection| {
+            let transaction = connection.unchecked_transaction()?;
+            transaction.prepare_cached(INSERT_REVOKED_SESSION)?
+                .execute(params![session_id, user_email, revoked_at, expires_at])?;
+            transaction.prepare_cached(DELETE_SESSION_REFRESH_TOKENS)?.execute(params![session_id])?;
+            transaction.commit()
+        }).await
+    }
+
+    async fn revoke_sessions_of(&self, user_email: &str, revoked_at: i64, expires_at: i64) -> Result<u64, AppError> {
+        let user_email = user_email.to_string();
+        self.run(move |connection| {
+            let transaction = connection.unchecked_transaction()?;
+            let revoked = transaction.prepare_cached(INSERT_REVOKED_SESSIONS_OF)?
+                .execute(params![user_email, revoked_at, expires_at])?;
+            transaction.prepare_cached(DELETE_USER_REFRESH_TOKENS)?.execute(params![user_email])?;
+            transaction.commit()?;
+            Ok(revoked as u64)
+        }).await
+    }
+
+    async fn is_revoked(&self, session_id: &str) -> Result<bool, AppError> {
+        let session_id = session_id.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_SESSION_REVOKED)?.query_row(params![session_id], |row| row.get(0))
+        }).await
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        self.run(move |connection| {
+            let tokens = connection.prepare_cached(DELETE_EXPIRED_REFRESH_TOKENS)?.execute(params![now])?;
+            let sessions = connection.prepare_cached(DELETE_EXPIRED_REVOKED_SESSIONS)?.execute(params![now])?;
+            Ok((tokens + sessions) as u64)
+        }).await
+    }
+}
+
+#[async_trait]
+impl AccountTokenRepository for SqliteRepository {
+    async fn issue(&self, record: &AccountTokenRecord) -> Result<(), AppError> {
+        let record = record.clone();
+        self.run(move |connection| {
+            let transaction = connection.unchecked_transaction()?;
+            transaction.prepare_cached(DELETE_UNUSED_ACCOUNT_TOKENS)?.execute(params![record.user_email, record.purpose])?;
+            transaction.prepare_cached(INSERT_ACCOUNT_TOKEN)?.execute(params![
+                record.token_hash, record.user_email, record.purpose, record.issued_at, record.expires_at, record.used_at
+            ])?;
+            transaction.commit()
+        }).await
+    }
+
+    async fn consume(&self, token_hash: &str, purpose: &str, now: i64) -> Result<Option<String>, AppError> {
+        let (token_hash, purpose) = (token_hash.to_string(), purpose.to_string());
+        self.run(move |connection| {
+            connection.prepare_cached(UPDATE_ACCOUNT_TOKEN_USED)?
+                .query_row(params![now, token_hash, purpose], |row| row.get("user_email"))
+                .optional()
+        }).await
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(DELETE_EXPIRED_ACCOUNT_TOKENS)?.execute(params![now])? as u64)
+        }).await
+    }
+}
+
+#[async_trait]
+impl TwoFactorRepository for SqliteRepository {
+    async fn find_two_factor(&self, user_email: &str) -> Result<Option<TwoFactorRecord>, AppError> {
+        let user_email = user_email.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_TWO_FACTOR)?
+                .query_row(params![user_email], __two_factor_from_row)
+                .optional()
+        }).await
+    }
+
+    async fn enroll(&self, user_email: &str, secret: &str, enrolled_at: i64) -> Result<bool, AppError> {
+        let (user_email, secret) = (user_email.to_string(), secret.to_string());
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(UPSERT_PENDING_TWO_FACTOR)?.execute(params![user_email, secret, enrolled_at])? > 0)
+        }).await
+    }
+
+    async fn enable(&self, user_email: &str, recovery_code_hashes: &[String]) -> Result<bool, AppError> {
+        let (user_email, recovery_code_hashes) = (user_email.to_string(), recovery_code_hashes.to_vec());
+        self.run(move |connection| {
+            let transaction = connection.unchecked_transaction()?;
+            if transaction.prepare_cached(UPDATE_TWO_FACTOR_ENABLED)?.execute(params![user_email])? == 0 {
+                return Ok(false);
+            }
+            __replace_recovery_codes(&transaction, &user_email, &recovery_code_hashes)?;
+            transaction.commit()?;
+            Ok(true)
+        }).await
+    }
+
+    async fn use_step(&self, user_email: &str, step: i64) -> Result<bool, AppError> {
+        let user_email = user_email.to_string();
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(UPDATE_TWO_FACTOR_STEP)?.execute(params![user_email, step])? > 0)
+        }).await
+    }
+
+    async fn replace_recovery_codes(&self, user_email: &str, recovery_code_hashes: &[String]) -> Result<(), AppError> {
+        let (user_email, recovery_code_hashes) = (user_email.to_string(), recovery_code_hashes.to_vec());
+        self.run(move |connection| {
+            let transaction = connection.unchecked_transaction()?;
+            __replace_recovery_codes(&transaction, &user_email, &recovery_code_hashes)?;
+            transaction.commit()
+        }).await
+    }
+
+    async fn use_recovery_code(&self, user_email: &str, code_hash: &str, used_at: i64) -> Result<bool, AppError> {
+        let (user_email, code_hash) = (user_email.to_string(), code_hash.to_string());
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(UPDATE_RECOVERY_CODE_USED)?.execute(params![user_email, code_hash, used_at])? > 0)
+        }).await
+    }
+
+    async fn count_recovery_codes(&self, user_email: &str) -> Result<u64, AppError> {
+        let user_email = user_email.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(COUNT_RECOVERY_CODES)?.query_row(params![user_email], |row| row.get("count"))
+        }).await
+    }
+
+    async fn disable(&self, user_email: &str) -> Result<bool, AppError> {
+        let user_email = user_email.to_string();
+        self.run(move |connection| {
+            let transaction = connection.unchecked_transaction()?;
+            transaction.prepare_cached(DELETE_RECOVERY_CODES)?.execute(params![user_email])?;
+            let removed = transaction.prepare_cached(DELETE_TWO_FACTOR)?.execute(params![user_email])? > 0;
+            transaction.commit()?;
+            Ok(removed)
+        }).await
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for SqliteRepository {
+    async fn insert_api_key(&self, record: &ApiKeyRecord) -> Result<(), AppError> {
+        let record = record.clone();
+        self.run(move |connection| {
+            connection.prepare_cached(INSERT_API_KEY)?.execute(params![
+                record.key_id, record.key_hash, record.user_email, record.name, record.scopes, record.allowed_ips,
+                record.created_at, record.expires_at
+            ])?;
+            Ok(())
+        }).await
+    }
+
+    async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, AppError> {
+        let key_hash = key_hash.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_API_KEY)?
+                .query_row(params![key_hash], __api_key_from_row)
+                .optional()
+        }).await
+    }
+
+    async fn list_api_keys(&self, user_email: &str) -> Result<Vec<ApiKeyRecord>, AppError> {
+        let user_email = user_email.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(SELECT_API_KEYS)?
+                .query_map(params![user_email], __api_key_from_row)?
+                .collect()
+        }).await
+    }
+
+    async fn touch_api_key(&self, key_id: &str, used_at: i64) -> Result<(), AppError> {
+        let key_id = key_id.to_string();
+        self.run(move |connection| {
+            connection.prepare_cached(UPDATE_API_KEY_USED)?.execute(params![key_id, used_at])?;
+            Ok(())
+        }).await
+    }
+
+    async fn revoke_api_key(&self, user_email: &str, key_id: &str) -> Result<bool, AppError> {
+        let (user_email, key_id) = (user_email.to_string(), key_id.to_string());
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(DELETE_API_KEY)?.execute(params![user_email, key_id])? > 0)
+        }).await
+    }
+
+    async fn delete_expired(&self, now: i64) -> Result<u64, AppError> {
+        self.run(move |connection| {
+            Ok(connection.prepare_cached(DELETE_EXPIRED_API_KEYS)?.execute(params![now])? as u64)
+        }).await
+    }
+}
+
+#[async_trait]
+impl DatabaseRepository for SqliteRepository {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn ping(&self) -> Result<(), AppError> {
+        self.run(|connection| connection.query_row("SELECT 1;", [], |_| Ok(()))).await
+    }
+
+    async fn version(&self) -> Result<String, AppError> {
+        Ok(rusqlite::version().to_string())
+    }
+
+    async fn applied_migrations(&self) -> Result<Option<Vec<AppliedMigration>>, AppError> {
+        self.run(|connection| {
+            let exists: bool = connection.query_row(SCHEMA_MIGRATIONS_EXISTS, [], |row| row.get(0))?;
+            if !exists {
+                return Ok(None);
+            }
+            let applied = connection.prepare(SELECT_SCHEMA_MIGRATIONS)?
+                .query_map([], |row| Ok(AppliedMigration {
+                    version: row.get("version")?,
+                    name: row.get("name")?,
+                    checksum: row.get("checksum")?,
+                    applied_at: row.get("applied_at")?,
+                }))?
+                .collect::<rusqlite::Result<Vec<AppliedMigration>>>()?;
+            Ok(Some(applied))
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteRepository;
+    use crate::{
+        migrations::SQLITE_MIGRATIONS,
+        repository::{
+            Account, AccountRepository, AccountTokenRecord, AccountTokenRepository, ApiKeyRecord, ApiKeyRepository,
+            DatabaseRepository, Feedback, FeedbackRepository, RefreshTokenRecord,
+            SessionRepository, TaskHistoryRepository, TaskLeaseRecord, TaskRunRecord, TaskSettingRecord, TwoFactorRepository
+        }
+    };
+
+    fn migrated() -> SqliteRepository {
+        let repository = SqliteRepository::open(":memory:").unwrap();
+        for migration in SQLITE_MIGRATIONS.iter() {
+            repository.connection.lock().unwrap().execute_batch(migration.up).unwrap();
+        }
+        repository
+    }
+
+    #[tokio::test]
+    async fn accounts_and_feedback() {
+        let repository = migrated();
+        let account = Account {
+            nick_name: "tester".to_string(),
+            password_salt: "salt".to_string(),
+            password_hash: "hash".to_string(),
+            email: "a@b.cn".to_string(),
+            contribution: 0,
+            available: true,
+            permissions: 0b0001,
+            email_verified: true,
+        };
+        assert!(AccountRepository::insert(&repository, &account).await.unwrap());
+        assert!(!AccountRepository::insert(&repository, &account).await.unwrap());
+        assert!(repository.set_available("a@b.cn", false).await.unwrap());
+        assert!(!AccountRepository::find(&repository, "a@b.cn").await.unwrap().unwrap().available);
+
+        let feedback = Feedback {
+            time_stamp: 1,
+            from_user_email: "a@b.cn".to_string(),
+            time_out: Some(10),
+            pic_link: "a@b.cn_1.jpg".to_string(),
+            real_label: Some("3".to_string()),
+            submit_count: 1,
+        };
+        FeedbackRepository::insert(&repository, &feedback).await.unwrap();
+        FeedbackRepository::insert(&repository, &Feedback { real_label: None, ..feedback.clone() }).await.unwrap();
+        assert_eq!(repository.count().await.unwrap(), (1, 1));
+        assert_eq!(repository.set_submit_count("a@b.cn_1.jpg", 2).await.unwrap(), 1);
+        assert_eq!(repository.find_trainable("a@b.cn_1.jpg", "3").await.unwrap().unwrap().submit_count, 2);
+        assert_eq!(FeedbackRepository::delete_expired(&repository, 9).await.unwrap(), 0);
+        assert_eq!(FeedbackRepository::delete_expired(&repository, 10).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn task_leases_and_runs() {
+        let repository = migrated();
+        let lease = |holder: &str, tick| TaskLeaseRecord {
+            task_name: "auto_rej_fd".to_string(),
+            holder: holder.to_string(),
+            tick,
+            acquired_at: tick,
+        };
+        assert!(repository.acquire_lease(&lease("a", 1000)).await.unwrap().0);
+        let (acquired, held) = repository.acquire_lease(&lease("b", 1000)).await.unwrap();
+        assert!(!acquired);
+        assert_eq!(held.holder, "a");
+        assert!(repository.acquire_lease(&lease("b", 2000)).await.unwrap().0);
+
+        for (started_at, outcome) in [(1, "failed"), (2, "succeeded")] {
+            repository.record_run(&TaskRunRecord {
+                task_name: "auto_rej_fd".to_string(),
+                started_at,
+                finished_at: started_at,
+                outcome: outcome.to_string(),
+                attempts: 1,
+                error: None,
+            }).await.unwrap();
+        }
+        let last_runs = repository.last_runs().await.unwrap();
+        assert_eq!(last_runs.len(), 1);
+        assert_eq!(last_runs[0].outcome, "succeeded");
+
+        assert!(repository.setting("auto_rej_fd").await.unwrap().is_none());
+        for (paused, cron_expression) in [(true, None), (false, Some("0 3 * * *".to_string()))] {
+            repository.save_setting(&TaskSettingRecord {
+                task_name: "auto_rej_fd".to_string(),
+                paused,
+                interval_seconds: cron_expression.is_none().then_some(60),
+                cron_expression,
+                updated_at: 1,
+            }).await.unwrap();
+        }
+        let setting = repository.setting("auto_rej_fd").await.unwrap().unwrap();
+        assert_eq!((setting.paused, setting.interval_seconds, setting.cron_expression.as_deref()), (false, None, Some("0 3 * * *")));
+        // Applied above without `init`, which records them.
+        assert!(repository.applied_migrations().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_and_revoked_sessions() {
+        let repository = migrated();
+        let token = |token_hash: &str, session_id: &str| RefreshTokenRecord {
+            token_hash: token_hash.to_string(),
+            session_id: session_id.to_string(),
+            user_email: "a@b.cn".to_string(),
+            issued_at: 0,
+            expires_at: 100,
+            used_at: None,
+        };
+        repository.insert_refresh_token(&token("h1", "s1")).await.unwrap();
+        repository.insert_refresh_token(&token("h2", "s1")).await.unwrap();
+        repository.insert_refresh_token(&token("h3", "s2")).await.unwrap();
+        assert!(repository.use_refresh_token("h1", 5).await.unwrap());
+        assert!(!repository.use_refresh_token("h1", 6).await.unwrap());
+        assert_eq!(repository.find_refresh_token("h1").await.unwrap().unwrap().used_at, Some(5));
+
+        repository.revoke_session("s1", "a@b.cn", 10, 50).await.unwrap();
+        assert!(repository.is_revoked("s1").await.unwrap());
+        assert!(repository.find_refresh_token("h2").await.unwrap().is_none());
+        assert_eq!(repository.revoke_sessions_of("a@b.cn", 20, 60).await.unwrap(), 1);
+        assert!(repository.is_revoked("s2").await.unwrap());
+
+        assert_eq!(SessionRepository::delete_expired(&repository, 50).await.unwrap(), 1);
+        assert!(!repository.is_revoked("s1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn account_tokens_are_single_use() {
+        let repository = migrated();
+        let token = |token_hash: &str, purpose: &str, expires_at: i64| AccountTokenRecord {
+            token_hash: token_hash.to_string(),
+            user_email: "a@b.cn".to_string(),
+            purpose: purpose.to_string(),
+            issued_at: 0,
+            expires_at,
+            used_at: None,
+        };
+        repository.issue(&token("h1", "verify_email", 100)).await.unwrap();
+        repository.issue(&token("h2", "reset_password", 100)).await.unwrap();
+        // Replaces the unused token of the same purpose only.
+        repository.issue(&token("h3", "verify_email", 100)).await.unwrap();
+        assert_eq!(repository.consume("h1", "verify_email", 5).await.unwrap(), None);
+        assert_eq!(repository.consume("h2", "verify_email", 5).await.unwrap(), None);
+        assert_eq!(repository.consume("h3", "verify_email", 5).await.unwrap().as_deref(), Some("a@b.cn"));
+        assert_eq!(repository.consume("h3", "verify_email", 6).await.unwrap(), None);
+        assert_eq!(repository.consume("h2", "reset_password", 100).await.unwrap(), None);
+
+        assert_eq!(AccountTokenRepository::delete_expired(&repository, 100).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn two_factor_steps_and_recovery_codes_are_used_once() {
+        let repository = migrated();
+        assert!(repository.enroll("a@b.cn", "first", 1).await.unwrap());
+        // Enrolling again before enabling starts over with the new secret.
+        assert!(repository.enroll("a@b.cn", "second", 2).await.unwrap());
+        assert!(repository.enable("a@b.cn", &["r1".to_string(), "r2".to_string()]).await.unwrap());
+        assert!(!repository.enroll("a@b.cn", "third", 3).await.unwrap());
+        let two_factor = repository.find_two_factor("a@b.cn").await.unwrap().unwrap();
+        assert_eq!((two_factor.secret.as_str(), two_factor.enabled), ("second", true));
+
+        assert!(repository.use_step("a@b.cn", 10).await.unwrap());
+        assert!(!repository.use_step("a@b.cn", 10).await.unwrap());
+        assert!(!repository.use_step("a@b.cn", 9).await.unwrap());
+        assert!(repository.use_recovery_code("a@b.cn", "r1", 5).await.unwrap());
+        assert!(!repository.use_recovery_code("a@b.cn", "r1", 6).await.unwrap());
+        assert!(!repository.use_recovery_code("c@d.cn", "r2", 6).await.unwrap());
+        assert_eq!(repository.count_recovery_codes("a@b.cn").await.unwrap(), 1);
+        repository.replace_recovery_codes("a@b.cn", &["r3".to_string()]).await.unwrap();
+        assert!(!repository.use_recovery_code("a@b.cn", "r2", 7).await.unwrap());
+
+        assert!(repository.disable("a@b.cn").await.unwrap());
+        assert!(repository.find_two_factor("a@b.cn").await.unwrap().is_none());
+        assert_eq!(repository.count_recovery_codes("a@b.cn").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn api_keys_are_found_by_hash_and_revoked_by_their_account() {
+        let repository = migrated();
+        let api_key = |key_id: &str, created_at: i64, expires_at: Option<i64>| ApiKeyRecord {
+            key_id: key_id.to_string(),
+            key_hash: format!("hash-{key_id}"),
+            user_email: "a@b.cn".to_string(),
+            name: "trap".to_string(),
+            scopes: 1,
+            allowed_ips: "10.0.0.0/8".to_string(),
+            created_at,
+            expires_at,
+            last_used_at: None,
+        };
+        repository.insert_api_key(&api_key("k1", 1, None)).await.unwrap();
+        repository.insert_api_key(&api_key("k2", 2, Some(100))).await.unwrap();
+        repository.touch_api_key("k1", 50).await.unwrap();
+        let found = repository.find_api_key("hash-k1").await.unwrap().unwrap();
+        assert_eq!((found.allowed_ips.as_str(), found.last_used_at), ("10.0.0.0/8", Some(50)));
+        let keys = repository.list_api_keys("a@b.cn").await.unwrap();
+        assert_eq!(keys.iter().map(|key| key.key_id.as_str()).collect::<Vec<&str>>(), ["k2", "k1"]);
+
+        assert!(!repository.revoke_api_key("c@d.cn", "k1").await.unwrap());
+        assert!(repository.revoke_api_key("a@b.cn", "k1").await.unwrap());
+        assert!(repository.find_api_key("hash-k1").await.unwrap().is_none());
+        assert_eq!(ApiKeyRepository::delete_expired(&repository, 100).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,158 @@
+use axum::{extract::{Path, State}, Form, Json};
+use chrono::{DateTime, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    daemon::{Cronie, TaskSchedule, TaskStatus},
+    error::{AppError, ErrorResponses},
+    MultiState
+};
+
+#[derive(Deserialize, ToSchema)]
+pub struct RequestTaskSchedule {
+    pub interval: Option<u64>, // seconds
+    pub cron: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ResponseTaskAction {
+    task_name: String,
+    message: String
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/tasks",
+    tag = "tasks",
+    responses(
+        (status = 200, description = "Every registered daemon task", body = Vec<TaskStatus>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_fetch_all_tasks(
+    State(multi_state): State<MultiState>
+) -> Result<Json<Vec<TaskStatus>>, AppError> {
+    let mut tasks = multi_state.daemon.list_tasks();
+    // Tasks which have not run since this process started report their latest recorded run.
+    if tasks.iter().any(|task| task.last_run.is_none()) {
+        let last_runs = multi_state.repositories.tasks.last_runs().await?;
+        for task in tasks.iter_mut().filter(|task| task.last_run.is_none()) {
+            if let Some(last_run) = last_runs.iter().find(|last_run| last_run.task_name == task.task_name) {
+                task.last_run = __millis_to_datetime(last_run.started_at);
+                task.last_finished = __millis_to_datetime(last_run.finished_at);
+                task.last_outcome = Some(last_run.outcome.clone());
+                task.last_error = last_run.error.clone();
+            }
+        }
+    }
+    // Leases are shared by every instance, the table knows who holds them now.
+    let leases = multi_state.repositories.tasks.leases().await?;
+    for task in tasks.iter_mut() {
+        if let Some(lease) = leases.iter().find(|lease| lease.task_name == task.task_name) {
+            task.lease_holder = Some(lease.holder.clone());
+            task.lease_acquired_at = __millis_to_datetime(lease.acquired_at);
+        }
+    }
+    Ok(Json(tasks))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/tasks/{task_name}/trigger",
+    tag = "tasks",
+    params(("task_name" = String, Path, description = "Name of the daemon task")),
+    responses(
+        (status = 200, description = "Run started", body = ResponseTaskAction),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_trigger_task(
+    State(multi_state): State<MultiState>,
+    Path(task_name): Path<String>
+) -> Result<Json<ResponseTaskAction>, AppError> {
+    multi_state.daemon.trigger_task(&task_name)
+        .map_err(AppError::NotFound)?;
+    Ok(__task_response(task_name, "Task triggered!"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/tasks/{task_name}/pause",
+    tag = "tasks",
+    params(("task_name" = String, Path, description = "Name of the daemon task")),
+    responses(
+        (status = 200, description = "Task paused", body = ResponseTaskAction),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_pause_task(
+    State(multi_state): State<MultiState>,
+    Path(task_name): Path<String>
+) -> Result<Json<ResponseTaskAction>, AppError> {
+    multi_state.daemon.share_paused(&task_name, true).await?;
+    Ok(__task_response(task_name, "Task paused!"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/tasks/{task_name}/resume",
+    tag = "tasks",
+    params(("task_name" = String, Path, description = "Name of the daemon task")),
+    responses(
+        (status = 200, description = "Task resumed", body = ResponseTaskAction),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_resume_task(
+    State(multi_state): State<MultiState>,
+    Path(task_name): Path<String>
+) -> Result<Json<ResponseTaskAction>, AppError> {
+    multi_state.daemon.share_paused(&task_name, false).await?;
+    Ok(__task_response(task_name, "Task resumed!"))
+}
+
+/// Replace the schedule of a task with either an interval in seconds or a cron expression.
+#[utoipa::path(
+    post,
+    path = "/admin/tasks/{task_name}/schedule",
+    tag = "tasks",
+    params(("task_name" = String, Path, description = "Name of the daemon task")),
+    request_body(content = RequestTaskSchedule, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Schedule replaced", body = ResponseTaskAction),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_update_task_schedule(
+    State(multi_state): State<MultiState>,
+    Path(task_name): Path<String>,
+    Form(request): Form<RequestTaskSchedule>
+) -> Result<Json<ResponseTaskAction>, AppError> {
+    let schedule = match (request.interval, request.cron) {
+        (Some(0), None) => return Err(AppError::BadRequest("The interval should be greater than 0!".to_string())),
+        (Some(interval), None) => TaskSchedule::Every(interval),
+        (None, Some(expression)) => TaskSchedule::cron(&expression)
+            .map_err(|err| AppError::BadRequest(format!("Invalid cron expression! {err}")))?,
+        _ => return Err(AppError::BadRequest("Either interval or cron should be given!".to_string())),
+    };
+    let message = format!("Task scheduled {schedule}!");
+    multi_state.daemon.share_schedule(&task_name, schedule).await?;
+    Ok(__task_response(task_name, &message))
+}
+
+fn __task_response(task_name: String, message: &str) -> Json<ResponseTaskAction> {
+    Json(ResponseTaskAction {
+        task_name,
+        message: message.to_string()
+    })
+}
+
+fn __millis_to_datetime(millis: i64) -> Option<DateTime<Local>> {
+    Local.timestamp_millis_opt(millis).single()
+}
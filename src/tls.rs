@@ -0,0 +1,137 @@
+use std::{fs, time::{Duration, SystemTime}};
+
+use axum_server::tls_rustls::RustlsConfig;
+
+use crate::config::TlsConfig;
+
+/// Load the certificate chain and private key of `[tls]`.
+pub async fn load_rustls_config(tls_config: &TlsConfig) -> Result<RustlsConfig, String> {
+    // Fails only when a provider has been installed already.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    RustlsConfig::from_pem_file(&tls_config.cert_path, &tls_config.key_path)
+        .await
+        .map_err(|err| format!("failed to load {} and {}: {err}", tls_config.cert_path, tls_config.key_path))
+}
+
+/// Reload the PEM files on SIGHUP and, every `reload_interval`, when one of them was modified.
+/// New connections use the new certificate, a failed reload keeps the current one.
+pub fn spawn_reloader(rustls_config: RustlsConfig, tls_config: TlsConfig) {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(err) => {
+                tracing::warn!("Failed to install the SIGHUP handler, certificates reload on change only: {err}");
+                None
+            }
+        };
+        let mut interval = tokio::time::interval(Duration::from_secs(tls_config.reload_interval.max(1)));
+        let mut last_modified = __modified(&tls_config);
+
+        loop {
+            #[cfg(unix)]
+            let hangup_received = async {
+                match hangup.as_mut() {
+                    Some(hangup) => hangup.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup_received = std::future::pending::<Option<()>>();
+
+            tokio::select! {
+                _ = hangup_received => tracing::info!("Received SIGHUP, reloading the certificate..."),
+                _ = interval.tick(), if tls_config.reload_interval > 0 => {
+                    let modified = __modified(&tls_config);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    tracing::info!("The certificate files changed, reloading...");
+                },
+            }
+            last_modified = __modified(&tls_config);
+            match rustls_config.reload_from_pem_file(&tls_config.cert_path, &tls_config.key_path).await {
+                Ok(()) => tracing::info!("Reloaded the certificate from {}.", tls_config.cert_path),
+                Err(err) => tracing::error!("Failed to reload the certificate, keeping the current one: {err}"),
+            }
+        }
+    });
+}
+
+fn __modified(tls_config: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let cert_modified = fs::metadata(&tls_config.cert_path).and_then(|metadata| metadata.modified()).ok()?;
+    let key_modified = fs::metadata(&tls_config.key_path).and_then(|metadata| metadata.modified()).ok()?;
+    Some((cert_modified, key_modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+    use super::{load_rustls_config, spawn_reloader};
+    use crate::config::TlsConfig;
+
+    const CERT_PEM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/cert.pem");
+    const KEY_PEM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls/cert.key");
+
+    /// Copies of the test certificate, removed on drop.
+    struct CertFiles(PathBuf);
+
+    impl CertFiles {
+        fn new(name: &str) -> Self {
+            let directory = std::env::temp_dir().join(format!("insectsys-{name}-{}", std::process::id()));
+            fs::create_dir_all(&directory).unwrap();
+            fs::copy(CERT_PEM, directory.join("cert.pem")).unwrap();
+            fs::copy(KEY_PEM, directory.join("cert.key")).unwrap();
+            CertFiles(directory)
+        }
+
+        fn tls_config(&self, reload_interval: u64) -> TlsConfig {
+            TlsConfig {
+                enabled: true,
+                cert_path: self.0.join("cert.pem").display().to_string(),
+                key_path: self.0.join("cert.key").display().to_string(),
+                reload_interval,
+            }
+        }
+    }
+
+    impl Drop for CertFiles {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn certificates_load_from_pem_files() {
+        let files = CertFiles::new("tls-load");
+        assert!(load_rustls_config(&files.tls_config(0)).await.is_ok());
+
+        let mut missing = files.tls_config(0);
+        missing.cert_path = files.0.join("missing.pem").display().to_string();
+        let err = load_rustls_config(&missing).await.err().unwrap();
+        assert!(err.contains("missing.pem"), "{err}");
+
+        fs::write(files.0.join("cert.key"), "not a key").unwrap();
+        assert!(load_rustls_config(&files.tls_config(0)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_reloads_keep_the_current_certificate() {
+        let files = CertFiles::new("tls-reload");
+        let tls_config = files.tls_config(1);
+        let rustls_config = load_rustls_config(&tls_config).await.unwrap();
+        let loaded = rustls_config.get_inner();
+        spawn_reloader(rustls_config.clone(), tls_config.clone());
+        // The reloader notes the times the files were modified first.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        fs::write(&tls_config.key_path, "not a key").unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(Arc::ptr_eq(&loaded, &rustls_config.get_inner()));
+
+        fs::copy(KEY_PEM, &tls_config.key_path).unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!Arc::ptr_eq(&loaded, &rustls_config.get_inner()));
+    }
+}
@@ -0,0 +1,83 @@
+use data_encoding::BASE32_NOPAD;
+use ring::{hmac, rand::{SecureRandom, SystemRandom}};
+
+// RFC 6238 defaults, the only parameters every authenticator app supports.
+const PERIOD: i64 = 30;
+const DIGITS: u32 = 6;
+// Steps accepted before and after the current one, for clocks a little off.
+const SKEW: i64 = 1;
+// Bytes of a secret, the length of the HMAC-SHA-1 key RFC 4226 recommends.
+const SECRET_LENGTH: usize = 20;
+
+/// A new random secret, in base32 as authenticator apps take it.
+pub fn generate_secret() -> Result<String, String> {
+    let mut secret = [0u8; SECRET_LENGTH];
+    SystemRandom::new().fill(&mut secret).map_err(|_| "failed to generate a secret".to_string())?;
+    Ok(BASE32_NOPAD.encode(&secret))
+}
+
+/// The `otpauth://` URI of the secret, rendered as a QR code for authenticator apps to scan.
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={secret}&issuer={}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}",
+        __percent_encode(issuer), __percent_encode(account), __percent_encode(issuer)
+    )
+}
+
+/// The time step of `now` the code belongs to, none when it is wrong or the secret is invalid.
+pub fn verify(secret: &str, code: &str, now: i64) -> Option<i64> {
+    let code: u32 = match code.trim() {
+        code if code.len() == DIGITS as usize && code.bytes().all(|byte| byte.is_ascii_digit()) => code.parse().ok()?,
+        _ => return None,
+    };
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let step = now / PERIOD;
+    (step - SKEW..=step + SKEW).find(|step| code_at(&key, *step) == code)
+}
+
+/// The HOTP code of RFC 4226 for the time step.
+pub fn code_at(key: &[u8], step: i64) -> u32 {
+    let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key), &step.to_be_bytes());
+    let digest = tag.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]]) & 0x7fff_ffff;
+    binary % 10u32.pow(DIGITS)
+}
+
+fn __percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::BASE32_NOPAD;
+
+    use super::{code_at, provisioning_uri, verify};
+
+    #[test]
+    fn codes_match_the_rfc_6238_vectors() {
+        let key = b"12345678901234567890";
+        // The 8 digit codes of the RFC, cut to 6 digits.
+        for (time, code) in [(59, 287082), (1111111109, 81804), (1234567890, 5924), (2000000000, 279037)] {
+            assert_eq!(code_at(key, time / 30), code);
+        }
+    }
+
+    #[test]
+    fn codes_of_neighbouring_steps_are_accepted() {
+        let secret = BASE32_NOPAD.encode(b"12345678901234567890");
+        assert_eq!(verify(&secret, "081804", 1111111109), Some(37037036));
+        assert_eq!(verify(&secret, "081804", 1111111109 + 30), Some(37037036));
+        assert_eq!(verify(&secret, "081804", 1111111109 + 90), None);
+        assert_eq!(verify(&secret, "81804", 1111111109), None);
+        assert_eq!(
+            provisioning_uri(&secret, "Insect Identifier", "a@b.cn"),
+            format!("otpauth://totp/Insect%20Identifier:a%40b.cn?secret={secret}&issuer=Insect%20Identifier&algorithm=SHA1&digits=6&period=30")
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use axum::{extract::State, Form, Json};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    authenticator::{generate_token, hash_token, AuthUser, Permission},
+    config::app_config,
+    error::{AppError, ErrorResponses},
+    repository::{Repositories, TwoFactorRecord},
+    totp,
+    MultiState
+};
+
+// Recovery codes handed out at once, each of 10 hex digits.
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RequestTwoFactorCode {
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResponseTwoFactorStatus {
+    enabled: bool,
+    // Whether `auth.two_factor_required` applies to the role of the account.
+    required: bool,
+    recovery_codes_left: u64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResponseTwoFactorEnrollment {
+    secret: String, // base32, for typing into the app
+    provisioning_uri: String, // otpauth:// URI to show as a QR code
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ResponseRecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+/// Roles which manage users or models, those `auth.two_factor_required` applies to.
+pub fn two_factor_required_for(permissions: i16) -> bool {
+    let admin_permissions = Permission::MngUsr as i16 | Permission::MngModel as i16;
+    permissions & admin_permissions != 0
+}
+
+/// Whether the code is a current TOTP code of the secret, or with `recovery` an unused recovery code.
+/// Either is used up.
+pub async fn check_second_factor(repositories: &Repositories, two_factor: &TwoFactorRecord, code: &str, recovery: bool) -> Result<bool, AppError> {
+    let now = Local::now().timestamp();
+    if let Some(step) = totp::verify(&two_factor.secret, code, now) {
+        return repositories.two_factor.use_step(&two_factor.user_email, step).await;
+    }
+    if !recovery {
+        return Ok(false);
+    }
+    let used = repositories.two_factor
+        .use_recovery_code(&two_factor.user_email, &hash_token(&__normalize_recovery_code(code)), now)
+        .await?;
+    if used {
+        tracing::info!("{} used a recovery code.", two_factor.user_email);
+    }
+    Ok(used)
+}
+
+#[utoipa::path(
+    get,
+    path = "/two_factor",
+    tag = "two factor",
+    responses(
+        (status = 200, description = "Two-factor authentication of the caller", body = ResponseTwoFactorStatus),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_two_factor_status(
+    State(multi_state): State<MultiState>,
+    user: AuthUser
+) -> Result<Json<ResponseTwoFactorStatus>, AppError> {
+    Ok(Json(_two_factor_status(&multi_state.repositories, &user).await?))
+}
+
+pub async fn _two_factor_status(repositories: &Repositories, user: &AuthUser) -> Result<ResponseTwoFactorStatus, AppError> {
+    let proof = repositories.accounts
+        .find_proof(&user.email)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Couldn't find account: {:?}", user.email)))?;
+    let enabled = repositories.two_factor.find_two_factor(&user.email).await?.is_some_and(|two_factor| two_factor.enabled);
+    let recovery_codes_left = match enabled {
+        true => repositories.two_factor.count_recovery_codes(&user.email).await?,
+        false => 0,
+    };
+    Ok(ResponseTwoFactorStatus {
+        enabled,
+        required: app_config().auth.two_factor_required && two_factor_required_for(proof.permissions),
+        recovery_codes_left,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/two_factor/enroll",
+    tag = "two factor",
+    responses(
+        (status = 200, description = "A new secret, enabled by `/two_factor/enable` with a first code", body = ResponseTwoFactorEnrollment),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_enroll_two_factor(
+    State(multi_state): State<MultiState>,
+    user: AuthUser
+) -> Result<Json<ResponseTwoFactorEnrollment>, AppError> {
+    Ok(Json(_enroll_two_factor(&multi_state.repositories, &user).await?))
+}
+
+/// Start over with a new secret, unless one is enabled already.
+pub async fn _enroll_two_factor(repositories: &Repositories, user: &AuthUser) -> Result<ResponseTwoFactorEnrollment, AppError> {
+    user.require_session()?;
+    let secret = totp::generate_secret().map_err(AppError::Internal)?;
+    if !repositories.two_factor.enroll(&user.email, &secret, Local::now().timestamp()).await? {
+        return Err(AppError::Conflict("Two-factor authentication is enabled already!".to_string()));
+    }
+    let provisioning_uri = totp::provisioning_uri(&secret, &app_config().auth.two_factor_issuer, &user.email);
+    Ok(ResponseTwoFactorEnrollment { secret, provisioning_uri })
+}
+
+#[utoipa::path(
+    post,
+    path = "/two_factor/enable",
+    tag = "two factor",
+    request_body(content = RequestTwoFactorCode, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Enabled, the recovery codes are shown this time only", body = ResponseRecoveryCodes),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_enable_two_factor(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    Form(code_form): Form<RequestTwoFactorCode>
+) -> Result<Json<ResponseRecoveryCodes>, AppError> {
+    Ok(Json(_enable_two_factor(&multi_state.repositories, &user, code_form).await?))
+}
+
+/// Enable the enrolled secret once the app shows a right code for it.
+pub async fn _enable_two_factor(repositories: &Repositories, user: &AuthUser, user_request: RequestTwoFactorCode) -> Result<ResponseRecoveryCodes, AppError> {
+    user.require_session()?;
+    let two_factor = match repositories.two_factor.find_two_factor(&user.email).await? {
+        Some(two_factor) if two_factor.enabled =>
+            return Err(AppError::Conflict("Two-factor authentication is enabled already!".to_string())),
+        Some(two_factor) => two_factor,
+        None => return Err(AppError::BadRequest("Enroll in two-factor authentication first!".to_string())),
+    };
+    if !check_second_factor(repositories, &two_factor, &user_request.code, false).await? {
+        return Err(AppError::BadRequest("Wrong two-factor code!".to_string()));
+    }
+    let (recovery_codes, recovery_code_hashes) = __generate_recovery_codes()?;
+    if !repositories.two_factor.enable(&user.email, &recovery_code_hashes).await? {
+        return Err(AppError::Conflict("Two-factor authentication is enabled already!".to_string()));
+    }
+    tracing::info!("{} enabled two-factor authentication.", user.email);
+    Ok(ResponseRecoveryCodes { recovery_codes })
+}
+
+#[utoipa::path(
+    post,
+    path = "/two_factor/disable",
+    tag = "two factor",
+    request_body(content = RequestTwoFactorCode, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Disabled, signing in takes the password only", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_disable_two_factor(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    Form(code_form): Form<RequestTwoFactorCode>
+) -> Result<String, AppError> {
+    _disable_two_factor(&multi_state.repositories, &user, code_form).await
+}
+
+/// Remove the secret and the recovery codes, given a code of either. Refused where `auth.two_factor_required` applies.
+pub async fn _disable_two_factor(repositories: &Repositories, user: &AuthUser, user_request: RequestTwoFactorCode) -> Result<String, AppError> {
+    user.require_session()?;
+    let two_factor = __enabled_two_factor(repositories, &user.email).await?;
+    let proof = repositories.accounts
+        .find_proof(&user.email)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Couldn't find account: {:?}", user.email)))?;
+    if app_config().auth.two_factor_required && two_factor_required_for(proof.permissions) {
+        return Err(AppError::Forbidden("Two-factor authentication is required for your role!".to_string()));
+    }
+    if !check_second_factor(repositories, &two_factor, &user_request.code, true).await? {
+        return Err(AppError::BadRequest("Wrong two-factor code!".to_string()));
+    }
+    repositories.two_factor.disable(&user.email).await?;
+    tracing::info!("{} disabled two-factor authentication.", user.email);
+    Ok("Succeeded to disable two-factor authentication!".to_string())
+}
+
+#[utoipa::path(
+    post,
+    path = "/two_factor/recovery_codes",
+    tag = "two factor",
+    request_body(content = RequestTwoFactorCode, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "New recovery codes, the earlier ones no longer work", body = ResponseRecoveryCodes),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
+pub async fn handler_regenerate_recovery_codes(
+    State(multi_state): State<MultiState>,
+    user: AuthUser,
+    Form(code_form): Form<RequestTwoFactorCode>
+) -> Result<Json<ResponseRecoveryCodes>, AppError> {
+    Ok(Json(_regenerate_recovery_codes(&multi_state.repositories, &user, code_form).await?))
+}
+
+/// Replace the recovery codes, given a code of the authenticator app.
+pub async fn _regenerate_recovery_codes(repositories: &Repositories, user: &AuthUser, user_request: RequestTwoFactorCode) -> Result<ResponseRecoveryCodes, AppError> {
+    user.require_session()?;
+    let two_factor = __enabled_two_factor(repositories, &user.email).await?;
+    if !check_second_factor(repositories, &two_factor, &user_request.code, false).await? {
+        return Err(AppError::BadRequest("Wrong two-factor code!".to_string()));
+    }
+    let (recovery_codes, recovery_code_hashes) = __generate_recovery_codes()?;
+    repositories.two_factor.replace_recovery_codes(&user.email, &recovery_code_hashes).await?;
+    Ok(ResponseRecoveryCodes { recovery_codes })
+}
+
+async fn __enabled_two_factor(repositories: &Repositories, useremail: &str) -> Result<TwoFactorRecord, AppError> {
+    repositories.two_factor
+        .find_two_factor(useremail)
+        .await?
+        .filter(|two_factor| two_factor.enabled)
+        .ok_or_else(|| AppError::BadRequest("Two-factor authentication isn't enabled!".to_string()))
+}
+
+/// Codes as shown to the user, `xxxxx-xxxxx`, and the hashes stored of them.
+fn __generate_recovery_codes() -> Result<(Vec<String>, Vec<String>), AppError> {
+    let mut recovery_codes = Vec::new();
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let code = generate_token(5)?;
+        recovery_codes.push(format!("{}-{}", &code[..5], &code[5..]));
+    }
+    let recovery_code_hashes = recovery_codes.iter()
+        .map(|code| hash_token(&__normalize_recovery_code(code)))
+        .collect();
+    Ok((recovery_codes, recovery_code_hashes))
+}
+
+/// Recovery codes are typed in with or without the dash, in either case.
+fn __normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|char| char.is_ascii_alphanumeric())
+        .map(|char| char.to_ascii_lowercase())
+        .collect()
+}
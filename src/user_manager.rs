@@ -1,26 +1,20 @@
-use axum::{extract::{Path, State}, http::StatusCode, Form, Json};
+use axum::{extract::{Path, State}, Form, Json};
 use serde::{Deserialize, Serialize};
-use tokio_pg_mapper::FromTokioPostgresRow;
-use tokio_pg_mapper_derive::PostgresMapper;
+use utoipa::ToSchema;
 
-use crate::{authenticator::{check_permission, encrypt_password, role_to_string, string_to_role, Permission, AccountUnit}, MultiState};
+use crate::{
+    authenticator::{encrypt_password, revoke_sessions_of, role_to_string, string_to_role, AuthUser, Permission},
+    error::{parse_json_field, AppError, ErrorResponses},
+    repository::{Account, Repositories},
+    MultiState
+};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct RequestUserManagement {
-    admin_email: String,
     user_emails: String, // Json String
 }
 
-#[derive(Serialize, Deserialize, PostgresMapper)]
-#[pg_mapper(table = "Account")]
-pub struct UserInfo {
-    nick_name: String,
-    email: String,
-    contribution: i16,
-    permissions: i16
-}
-
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ResponseUserInfo {
     nick_name: String,
     email: String,
@@ -28,22 +22,7 @@ pub struct ResponseUserInfo {
     role: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RequestUserManageUnit {
-    useremail: String
-}
-
-#[derive(Serialize, Deserialize, PostgresMapper)]
-#[pg_mapper(table = "Account")]
-pub struct UserManageUnit {
-    nick_name: String,
-    email: String,
-    contribution: i16,
-    permissions: i16,
-    available: bool
-}
-
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct ResponseUserManageUnit {
     username: String,
     useremail: String,
@@ -52,57 +31,37 @@ pub struct ResponseUserManageUnit {
     available: bool
 }
 
-#[derive(Serialize, Deserialize, PostgresMapper)]
-#[pg_mapper (table = "Account")]
-struct User2Operate {
-    email: String,
-    available: bool
-}
-
-#[derive(Serialize, Deserialize, PostgresMapper)]
-#[pg_mapper (table = "Account")]
-struct Admin2Add {
-    nick_name: String,
-    password_salt: String,
-    password_hash: String,
-    email: String,
-    permissions: i16
-}
-
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct RequestAdminAdd {
-    admin_email: String,
-    username: String,
-    useremail: String,
-    password: String,
-    repassword: String,
-    role: String
+    pub username: String,
+    pub useremail: String,
+    pub password: String,
+    pub repassword: String,
+    pub role: String
 }
 
+#[utoipa::path(
+    get,
+    path = "/admin/user_manage",
+    tag = "user management",
+    responses(
+        (status = 200, description = "Every other account", body = Vec<ResponseUserManageUnit>),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_fetch_all_users(
     State(multi_state): State<MultiState>,
-    Form(request): Form<RequestUserManageUnit>
-) -> Result<Json<Vec<ResponseUserManageUnit>>, (StatusCode, String)> {
-    let useremail = request.useremail;
-    if !check_permission(&multi_state.db_pool, &useremail, Permission::MngUsr).await.unwrap() {
-        return  Err(
-            (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-        );
-    }
-    let client = multi_state.db_pool.get().await.unwrap();
-    let query_statement = client
-        .prepare("
-            SELECT nick_name, email, contribution, permissions, available FROM account;
-        ").await.map_err(|err| (StatusCode::BAD_REQUEST, format!("Bad query! {}", err)))?;
+    user: AuthUser
+) -> Result<Json<Vec<ResponseUserManageUnit>>, AppError> {
+    let user_list = _fetch_all_users(&multi_state.repositories, &user.email).await?;
+    Ok(Json(user_list))
+}
 
+/// Every account but the one asking.
+pub async fn _fetch_all_users(repositories: &Repositories, useremail: &str) -> Result<Vec<ResponseUserManageUnit>, AppError> {
     let mut user_list: Vec<ResponseUserManageUnit> = Vec::new();
-    let users = client
-        .query(&query_statement, &[])
-        .await
-        .map_err(|err| (StatusCode::NOT_FOUND, err.to_string()))?
-        .iter()
-        .map(|row| UserManageUnit::from_row_ref(row).unwrap())
-        .collect::<Vec<UserManageUnit>>();
+    let users = repositories.accounts.list().await?;
 
     for user in users {
         if user.email == useremail {
@@ -116,86 +75,94 @@ pub async fn handler_fetch_all_users(
             available: user.available
         })
     }
-    return Ok(Json(user_list));
+    Ok(user_list)
 }
 
+#[utoipa::path(
+    post,
+    path = "/admin/user_manage",
+    tag = "user management",
+    request_body(content = RequestUserManagement, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Availability of the accounts flipped", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_suspend_or_unsuspend_user(
     State(multi_state): State<MultiState>,
+    user: AuthUser,
     Form(action_request): Form<RequestUserManagement>
-) -> Result<String, (StatusCode, String)> {
-    if !check_permission(&multi_state.db_pool, &action_request.admin_email, Permission::MngUsr).await.unwrap() {
-        return  Err(
-            (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-        );
-    }
-
-    let users_to_operate: Vec<String> = serde_json::from_str(action_request.user_emails.as_str()).unwrap();
-
-    let tracing_string = format!("Gained deserialized obj is: {users_to_operate:#?}");
-    tracing::warn!(tracing_string);
+) -> Result<String, AppError> {
+    let users_to_operate: Vec<String> = parse_json_field("user_emails", &action_request.user_emails)?;
+    _suspend_or_unsuspend_users(&multi_state.repositories, &user, users_to_operate).await
+}
 
+/// Flip the availability of every given account, none if one of them is the caller or has a permission the caller lacks.
+pub async fn _suspend_or_unsuspend_users(repositories: &Repositories, user: &AuthUser, users_to_operate: Vec<String>) -> Result<String, AppError> {
+    let caller_permissions = __caller_permissions(repositories, user).await?;
     let expected_total_count = users_to_operate.len() as u64;
-    let client = multi_state.db_pool.get().await.unwrap();
-    let query_statement = client
-    .prepare("
-        SELECT email, available FROM account WHERE email=$1;
-    ").await.map_err(|err| (StatusCode::BAD_REQUEST, format!("Bad query! {}", err)))?;
-
-    let mut count_of_operation = 0;
+    let mut accounts = Vec::new();
+    let mut missing_users = Vec::new();
     for useremail in users_to_operate {
-        let user_to_operate = client
-            .query(&query_statement, &[&useremail])
-            .await
-            .map_err(|err| (StatusCode::NOT_FOUND, err.to_string()))?
-            .iter()
-            .map(|row| User2Operate::from_row_ref(row).unwrap())
-            .collect::<Vec<User2Operate>>()
-            .pop()
-            .ok_or((StatusCode::NOT_FOUND, format!("Couldn't find account: {:?}", useremail)));
-
-        if let Ok(user) = user_to_operate {
-            let user_status = user.available;
-            let update_statement = client
-                .prepare("
-                    UPDATE account SET available=$1 WHERE email=$2
-                ").await.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
-
-            let modified_count = client
-                .execute(&update_statement, &[&(!user_status), &user.email])
-                .await
-                .map_err(|err| (StatusCode::NOT_MODIFIED, err.to_string()))?;
+        match repositories.accounts.find(&useremail).await? {
+            Some(account) => accounts.push(account),
+            None => missing_users.push(useremail),
+        }
+    }
+    if !missing_users.is_empty() {
+        return Err(AppError::NotFound(format!("Couldn't find accounts: {missing_users:?}")));
+    }
+    if accounts.iter().any(|account| account.email == user.email) {
+        return Err(AppError::Forbidden("You can't suspend your own account!".to_string()));
+    }
+    if let Some(account) = accounts.iter().find(|account| account.permissions & !caller_permissions != 0) {
+        return Err(AppError::Forbidden(format!("{} has permissions you don't have!", account.email)));
+    }
 
-            count_of_operation += modified_count;
+    let mut count_of_operation = 0;
+    for account in accounts {
+        if repositories.accounts.set_available(&account.email, !account.available).await? {
+            count_of_operation += 1;
+        }
+        if account.available {
+            revoke_sessions_of(repositories, &account.email).await?;
         }
     }
     if count_of_operation == expected_total_count {
         return Ok("Operation finished!".to_string());
     } else {
-        return Err((StatusCode::BAD_REQUEST,
+        return Err(AppError::Database(
             format!(
-                "There is something worng! Need to process {expected_total_count} accounts, but only {count_of_operation} were done.",)));
+                "Need to process {expected_total_count} accounts, but only {count_of_operation} were done.",)));
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/user/info/{useremail}",
+    tag = "user",
+    params(("useremail" = String, Path, description = "Email of the account")),
+    responses(
+        (status = 200, description = "Profile of the account", body = ResponseUserInfo),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_user_info(
     State(multi_state): State<MultiState>,
+    user: AuthUser,
     Path(useremail): Path<String>,
-) -> Result<axum::Json<ResponseUserInfo>, (StatusCode, String)> {
-    let client = multi_state.db_pool.get().await.unwrap();
-    let query_statement = client
-    .prepare("
-        SELECT nick_name, email, contribution, permissions FROM account WHERE email=$1;
-    ").await.map_err(|err| (StatusCode::BAD_REQUEST, format!("Bad query! {}", err)))?;
+) -> Result<Json<ResponseUserInfo>, AppError> {
+    user.require_self_or(&multi_state.repositories, &useremail, Permission::MngUsr).await?;
+    Ok(Json(_fetch_user_info(&multi_state.repositories, &useremail).await?))
+}
 
-    let user = client
-        .query(&query_statement, &[&useremail])
-        .await
-        .map_err(|err| (StatusCode::NOT_FOUND, err.to_string()))?
-        .iter()
-        .map(|row| UserInfo::from_row_ref(row).unwrap())
-        .collect::<Vec<UserInfo>>()
-        .pop()
-        .ok_or((StatusCode::NOT_FOUND, format!("Couldn't find account: {:?}", useremail)))?;
+pub async fn _fetch_user_info(repositories: &Repositories, useremail: &str) -> Result<ResponseUserInfo, AppError> {
+    let user = repositories.accounts
+        .find(useremail)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Couldn't find account: {:?}", useremail)))?;
 
     let response = ResponseUserInfo {
         nick_name: user.nick_name,
@@ -204,77 +171,65 @@ pub async fn handler_user_info(
         role: role_to_string(user.permissions)
     };
 
-    Ok(Json(response))
+    Ok(response)
 }
 
+#[utoipa::path(
+    post,
+    path = "/admin/user_manage/add_admin",
+    tag = "user management",
+    request_body(content = RequestAdminAdd, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Administrator signed up", body = String, content_type = "text/plain"),
+        ErrorResponses,
+    ),
+    security(("auth_token" = []))
+)]
 pub async fn handler_add_admin(
     State(multi_state): State<MultiState>,
+    user: AuthUser,
     Form(request_add_admin): Form<RequestAdminAdd>
-) -> Result<String, (StatusCode, String)> {
-    if !check_permission(&multi_state.db_pool, &request_add_admin.admin_email, Permission::MngUsr).await.unwrap() {
-        return Err(
-            (StatusCode::FORBIDDEN, "Not permitted!".to_string())
-        );
-    }
-
-    let client = multi_state.db_pool.get().await.unwrap();
+) -> Result<String, AppError> {
+    _add_admin(&multi_state.repositories, &user, request_add_admin).await
+}
 
+/// Sign up an account of the role, which mustn't have a permission the caller lacks.
+pub async fn _add_admin(repositories: &Repositories, user: &AuthUser, request_add_admin: RequestAdminAdd) -> Result<String, AppError> {
     if request_add_admin.password != request_add_admin.repassword {
-        return Err((StatusCode::NOT_ACCEPTABLE, "The passwords should be the same!".to_string()))
+        return Err(AppError::BadRequest("The passwords should be the same!".to_string()))
+    }
+    let permissions = string_to_role(request_add_admin.role) as i16;
+    if permissions & !__caller_permissions(repositories, user).await? != 0 {
+        return Err(AppError::Forbidden("You can't grant permissions you don't have!".to_string()));
     }
 
-    let query_statement = client
-    .prepare("
-        SELECT email FROM account WHERE email=$1;
-    ")
-    .await.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
-
-    let account = client
-    .query(&query_statement, &[&request_add_admin.useremail])
-    .await
-    .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?
-    .iter()
-    .map(|row| AccountUnit::from_row_ref(row).unwrap())
-    .collect::<Vec<AccountUnit>>()
-    .pop();
-
-    match account {
-        None => {
-            let contribute: i16 = 0;
-            let available = true;
-            let permissions = string_to_role(request_add_admin.role) as i16;
-            let (passwd_salt, passwd_hash) =
-                encrypt_password(request_add_admin.password);
-
-            let insert_statement = client
-            .prepare("
-                INSERT INTO account (nick_name, password_salt, password_hash, email, contribution, available, permissions)
-                VALUES
-                ($1, $2, $3, $4, $5, $6, $7)
-            ").await.map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
-
-            // let rows = client
-            // .execute(&insert_statement,
-            //     &[ &user_request.username, &passwd_salt, &passwd_hash, &user_request.email,
-            //         &contribute, &available, &permissions]
-            // )
-            // .await
-            // .map_err(|err| (StatusCode::NOT_MODIFIED, err.to_string()))?;
-            let rows = client
-            .execute(&insert_statement,
-                &[ &request_add_admin.username, &passwd_salt, &passwd_hash, &request_add_admin.useremail,
-                    &contribute, &available, &permissions]
-            )
-            .await
-            .map_err(|err| (StatusCode::NOT_MODIFIED, err.to_string()))?;
-
-            if rows < 1 {
-                return Err((StatusCode::NOT_MODIFIED, "Register account failed".to_string()));
-            }
-            Ok("Succeeded to sign up an admin!".to_string())
-        },
-        Some(_) => {
-            Err((StatusCode::CONFLICT, "The email has been used!".to_string()))
-        }
+    if repositories.accounts.find(&request_add_admin.useremail).await?.is_some() {
+        return Err(AppError::Conflict("The email has been used!".to_string()));
+    }
+    let (passwd_salt, passwd_hash) =
+        encrypt_password(request_add_admin.password)?;
+    let account = Account {
+        nick_name: request_add_admin.username,
+        password_salt: passwd_salt,
+        password_hash: passwd_hash,
+        email: request_add_admin.useremail,
+        contribution: 0,
+        available: true,
+        permissions,
+        // Administrators are added by someone who knows them.
+        email_verified: true,
+    };
+    if !repositories.accounts.insert(&account).await? {
+        return Err(AppError::Conflict("The email has been used!".to_string()));
     }
-}
\ No newline at end of file
+    Ok("Succeeded to sign up an admin!".to_string())
+}
+
+/// Permissions of the caller, limited to the scopes of its API key if it came with one.
+async fn __caller_permissions(repositories: &Repositories, user: &AuthUser) -> Result<i16, AppError> {
+    let proof = repositories.accounts
+        .find_proof(&user.email)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Couldn't find account: {:?}", user.email)))?;
+    Ok(proof.permissions & user.scopes.unwrap_or(!0))
+}